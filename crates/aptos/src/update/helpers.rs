@@ -2,7 +2,36 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use anyhow::{anyhow, Context, Result};
+use clap::ValueEnum;
 use self_update::{backends::github::ReleaseList, cargo_crate_version, version::bump_is_greater};
+use sha2::{Digest, Sha256};
+use std::{
+    fmt,
+    fs::{File, OpenOptions},
+    path::Path,
+};
+use sysinfo::{DiskExt, RefreshKind, System, SystemExt};
+
+const CLI_RELEASE_TAG_PREFIX: &str = "aptos-cli-";
+
+/// Releases the CLI can be updated to
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, ValueEnum)]
+pub enum UpdateChannel {
+    /// Stable releases only
+    #[default]
+    Stable,
+    /// The newest release, including prereleases and nightly builds
+    Nightly,
+}
+
+impl fmt::Display for UpdateChannel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            UpdateChannel::Stable => "stable",
+            UpdateChannel::Nightly => "nightly",
+        })
+    }
+}
 
 #[derive(Debug)]
 pub struct UpdateRequiredInfo {
@@ -12,8 +41,12 @@ pub struct UpdateRequiredInfo {
     pub latest_version_tag: String,
 }
 
-/// Return information about whether an update is required.
-pub fn check_if_update_required(repo_owner: &str, repo_name: &str) -> Result<UpdateRequiredInfo> {
+/// Return information about whether an update to the latest release of `channel` is required.
+pub fn check_if_update_required(
+    repo_owner: &str,
+    repo_name: &str,
+    channel: UpdateChannel,
+) -> Result<UpdateRequiredInfo> {
     // Build a configuration for determining the latest release.
     let config = ReleaseList::configure()
         .repo_owner(repo_owner)
@@ -26,21 +59,21 @@ pub fn check_if_update_required(repo_owner: &str, repo_name: &str) -> Result<Upd
         .fetch()
         .map_err(|e| anyhow!("Failed to fetch releases: {:#}", e))?;
 
-    // Find the latest release of the CLI, in which we filter for the CLI tag.
+    // Find the latest release of the CLI on the channel, in which we filter for the CLI tag.
     // If the release isn't in the last 30 items (the default API page size)
     // this will fail. See https://github.com/aptos-labs/aptos-core/issues/6411.
-    let mut releases = releases.into_iter();
-    let latest_release = loop {
-        let release = match releases.next() {
-            Some(release) => release,
-            None => return Err(anyhow!("Failed to find latest CLI release")),
-        };
-        if release.version.starts_with("aptos-cli-") {
-            break release;
-        }
-    };
-    let latest_version_tag = latest_release.version;
-    let latest_version = latest_version_tag.split("-v").last().unwrap();
+    let latest_version_tag = select_release_tag(
+        releases.iter().map(|release| release.version.as_str()),
+        channel,
+    )
+    .ok_or_else(|| {
+        anyhow!(
+            "Failed to find latest CLI release on the {} channel",
+            channel
+        )
+    })?
+    .to_string();
+    let latest_version = release_version(&latest_version_tag);
 
     // Return early if we're up to date already.
     let current_version = cargo_crate_version!();
@@ -55,6 +88,161 @@ pub fn check_if_update_required(repo_owner: &str, repo_name: &str) -> Result<Upd
     })
 }
 
+/// Return the first tag of a CLI release on `channel` among `tags`, which are sorted newest first.
+fn select_release_tag<'a>(
+    tags: impl IntoIterator<Item = &'a str>,
+    channel: UpdateChannel,
+) -> Option<&'a str> {
+    tags.into_iter()
+        .filter(|tag| tag.starts_with(CLI_RELEASE_TAG_PREFIX))
+        .find(|tag| channel == UpdateChannel::Nightly || !is_prerelease(tag))
+}
+
+/// Return the version of a CLI release tag, e.g., `2.0.0` for `aptos-cli-v2.0.0`.
+fn release_version(tag: &str) -> &str {
+    tag.split("-v").last().unwrap()
+}
+
+/// Prereleases and nightly builds have a suffix after their version, e.g.,
+/// `aptos-cli-v2.0.0-rc.1` or `aptos-cli-v2.1.0-nightly.20240501`.
+fn is_prerelease(tag: &str) -> bool {
+    release_version(tag).contains('-')
+}
+
+/// Release asset, as reported by the GitHub API
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ReleaseAsset {
+    pub name: String,
+    pub size: u64,
+    pub download_url: String,
+    /// Hex encoded SHA-256 of the asset, if GitHub computed one
+    pub sha256: Option<String>,
+}
+
+/// Return the asset for `target` of the release tagged `tag`, as reported by the GitHub API.
+pub fn get_release_asset(
+    repo_owner: &str,
+    repo_name: &str,
+    tag: &str,
+    target: &str,
+) -> Result<ReleaseAsset> {
+    let url = format!(
+        "https://api.github.com/repos/{}/{}/releases/tags/{}",
+        repo_owner, repo_name, tag
+    );
+    let release: serde_json::Value = reqwest::blocking::Client::new()
+        .get(&url)
+        .header(reqwest::header::USER_AGENT, "aptos-cli")
+        .send()
+        .and_then(|response| response.error_for_status())
+        .and_then(|response| response.json())
+        .with_context(|| format!("Failed to fetch release {}", tag))?;
+    // Like the updater, pick the asset whose name contains the target.
+    release["assets"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .find(|asset| {
+            asset["name"]
+                .as_str()
+                .map_or(false, |name| name.contains(target))
+        })
+        .and_then(|asset| {
+            Some(ReleaseAsset {
+                name: asset["name"].as_str()?.to_string(),
+                size: asset["size"].as_u64()?,
+                download_url: asset["browser_download_url"].as_str()?.to_string(),
+                sha256: asset["digest"]
+                    .as_str()
+                    .and_then(|digest| digest.strip_prefix("sha256:"))
+                    .map(str::to_string),
+            })
+        })
+        .ok_or_else(|| anyhow!("Release {} has no asset for {}", tag, target))
+}
+
+/// Download `url` to `path`, resuming from the bytes already there, e.g., after an interrupted
+/// download, with a range request. If the server ignores the range, the whole file is downloaded
+/// again. The file is then checked against `sha256` if given, and removed if it doesn't match.
+pub fn download_with_resume(url: &str, path: &Path, sha256: Option<&str>) -> Result<()> {
+    let downloaded = std::fs::metadata(path).map_or(0, |metadata| metadata.len());
+    let client = reqwest::blocking::Client::new();
+    let mut request = client
+        .get(url)
+        .header(reqwest::header::USER_AGENT, "aptos-cli");
+    if downloaded > 0 {
+        request = request.header(reqwest::header::RANGE, format!("bytes={}-", downloaded));
+    }
+    let mut response = request
+        .send()
+        .with_context(|| format!("Failed to download {}", url))?;
+    if response.status() == reqwest::StatusCode::RANGE_NOT_SATISFIABLE {
+        // The file is already complete, or larger than what the server has.
+        response = client
+            .get(url)
+            .header(reqwest::header::USER_AGENT, "aptos-cli")
+            .send()
+            .with_context(|| format!("Failed to download {}", url))?;
+    }
+    let mut response = response
+        .error_for_status()
+        .with_context(|| format!("Failed to download {}", url))?;
+    let mut file = if response.status() == reqwest::StatusCode::PARTIAL_CONTENT {
+        OpenOptions::new().append(true).open(path)?
+    } else {
+        File::create(path)?
+    };
+    response
+        .copy_to(&mut file)
+        .with_context(|| format!("Download of {} was interrupted", url))?;
+    drop(file);
+
+    if let Some(sha256) = sha256 {
+        let actual = hex::encode(Sha256::digest(&std::fs::read(path)?));
+        if !actual.eq_ignore_ascii_case(sha256) {
+            std::fs::remove_file(path)?;
+            return Err(anyhow!(
+                "Checksum mismatch for {} (expected {}, got {}), please try again",
+                url,
+                sha256,
+                actual
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Return the space available in bytes on the disk holding `path`, if it can be determined.
+pub fn get_available_disk_space(path: &Path) -> Option<u64> {
+    let path = path.canonicalize().ok()?;
+    let mut system = System::new_with_specifics(RefreshKind::new().with_disks_list());
+    system.refresh_disks();
+    // The disk mounted closest to the path holds it.
+    system
+        .disks()
+        .iter()
+        .filter(|disk| path.starts_with(disk.mount_point()))
+        .max_by_key(|disk| disk.mount_point().as_os_str().len())
+        .map(|disk| disk.available_space())
+}
+
+/// Fail if `available` bytes in `directory` are fewer than the `required` ones.
+pub fn check_disk_space(directory: &Path, required: u64, available: u64) -> Result<()> {
+    if available < required {
+        return Err(anyhow!(
+            "Insufficient disk space in {} to download the update (need {}, have {})",
+            directory.display(),
+            format_size(required),
+            format_size(available)
+        ));
+    }
+    Ok(())
+}
+
+fn format_size(size_in_bytes: u64) -> String {
+    format!("{:.1} MiB", size_in_bytes as f64 / (1024.0 * 1024.0))
+}
+
 pub enum InstallationMethod {
     Source,
     Homebrew,
@@ -75,3 +263,142 @@ impl InstallationMethod {
         Ok(installation_method)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TAGS: [&str; 6] = [
+        "aptos-node-v1.10.0",
+        "aptos-cli-v2.1.0-nightly.20240501",
+        "aptos-cli-v2.1.0-rc.1",
+        "aptos-framework-v1.10.0",
+        "aptos-cli-v2.0.1",
+        "aptos-cli-v2.0.0",
+    ];
+
+    #[test]
+    fn stable_channel_skips_prereleases() {
+        let tag = select_release_tag(TAGS, UpdateChannel::Stable).unwrap();
+        assert_eq!(tag, "aptos-cli-v2.0.1");
+        assert_eq!(release_version(tag), "2.0.1");
+    }
+
+    #[test]
+    fn nightly_channel_picks_the_newest_release() {
+        let tag = select_release_tag(TAGS, UpdateChannel::Nightly).unwrap();
+        assert_eq!(tag, "aptos-cli-v2.1.0-nightly.20240501");
+        assert_eq!(release_version(tag), "2.1.0-nightly.20240501");
+        // Stable releases are on the nightly channel too.
+        assert_eq!(
+            select_release_tag(TAGS[4..].iter().copied(), UpdateChannel::Nightly),
+            Some("aptos-cli-v2.0.1")
+        );
+    }
+
+    #[test]
+    fn disk_space_check_fails_when_space_is_short() {
+        let directory = Path::new("/tmp");
+        assert!(check_disk_space(directory, 100 * 1024 * 1024, 200 * 1024 * 1024).is_ok());
+        assert!(check_disk_space(directory, 100 * 1024 * 1024, 100 * 1024 * 1024).is_ok());
+        let err = check_disk_space(directory, 150 * 1024 * 1024, 20 * 1024 * 1024).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Insufficient disk space in /tmp to download the update (need 150.0 MiB, have 20.0 MiB)"
+        );
+    }
+
+    /// Serve `content` to a single request, honoring its range if `support_ranges`, and return the
+    /// URL to request along with the range header received.
+    fn serve_once(
+        content: Vec<u8>,
+        support_ranges: bool,
+    ) -> (String, std::thread::JoinHandle<Option<String>>) {
+        use std::io::{BufRead, BufReader, Write};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/aptos-cli.zip", listener.local_addr().unwrap());
+        let handle = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut range = None;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                let line = line.trim_end();
+                if line.is_empty() {
+                    break;
+                }
+                if let Some((name, value)) = line.split_once(": ") {
+                    if name.eq_ignore_ascii_case("range") {
+                        range = Some(value.to_string());
+                    }
+                }
+            }
+            let start = match (&range, support_ranges) {
+                (Some(range), true) => range
+                    .trim_start_matches("bytes=")
+                    .trim_end_matches('-')
+                    .parse::<usize>()
+                    .unwrap(),
+                _ => 0,
+            };
+            let status = if start > 0 {
+                "206 Partial Content"
+            } else {
+                "200 OK"
+            };
+            let mut stream = reader.into_inner();
+            write!(
+                stream,
+                "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                status,
+                content.len() - start
+            )
+            .unwrap();
+            stream.write_all(&content[start..]).unwrap();
+            range
+        });
+        (url, handle)
+    }
+
+    #[test]
+    fn interrupted_downloads_are_resumed() {
+        let content: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+        let sha256 = hex::encode(Sha256::digest(&content));
+        for support_ranges in [true, false] {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("aptos-cli.zip.part");
+            std::fs::write(&path, &content[..40_000]).unwrap();
+
+            let (url, server) = serve_once(content.clone(), support_ranges);
+            download_with_resume(&url, &path, Some(&sha256)).unwrap();
+            assert_eq!(server.join().unwrap().as_deref(), Some("bytes=40000-"));
+            // Without range support, the whole file is downloaded again.
+            assert_eq!(std::fs::read(&path).unwrap(), content);
+        }
+    }
+
+    #[test]
+    fn downloads_not_matching_the_checksum_are_discarded() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("aptos-cli.zip.part");
+        std::fs::write(&path, b"stale").unwrap();
+
+        let (url, server) = serve_once(b"corrupted download".to_vec(), true);
+        let sha256 = hex::encode(Sha256::digest(b"expected download"));
+        assert!(download_with_resume(&url, &path, Some(&sha256)).is_err());
+        server.join().unwrap();
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn channels_without_cli_releases_have_no_tag() {
+        let tags = ["aptos-node-v1.10.0", "aptos-cli-v2.1.0-rc.1"];
+        assert_eq!(select_release_tag(tags, UpdateChannel::Stable), None);
+        assert_eq!(
+            select_release_tag(tags, UpdateChannel::Nightly),
+            Some("aptos-cli-v2.1.0-rc.1")
+        );
+    }
+}
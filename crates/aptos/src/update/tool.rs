@@ -1,7 +1,13 @@
 // Copyright © Aptos Foundation
 // SPDX-License-Identifier: Apache-2.0
 
-use super::{check_if_update_required, helpers::InstallationMethod};
+use super::{
+    check_if_update_required,
+    helpers::{
+        check_disk_space, download_with_resume, get_available_disk_space, get_release_asset,
+        InstallationMethod, ReleaseAsset, UpdateChannel,
+    },
+};
 use crate::common::{
     types::{CliCommand, CliTypedResult},
     utils::cli_build_information,
@@ -10,8 +16,8 @@ use anyhow::{anyhow, Context};
 use aptos_build_info::BUILD_OS;
 use async_trait::async_trait;
 use clap::Parser;
-use self_update::{backends::github::Update, cargo_crate_version, Status};
-use std::process::Command;
+use self_update::{ArchiveKind, Extract};
+use std::{path::Path, process::Command};
 
 /// Update the CLI itself
 ///
@@ -26,6 +32,11 @@ pub struct UpdateTool {
     /// The name of the repo to download the binary from.
     #[clap(long, default_value = "aptos-core")]
     repo_name: String,
+
+    /// The releases to update to: stable ones only, or the newest, prereleases and nightly
+    /// builds included.
+    #[clap(long, value_enum, default_value_t = UpdateChannel::Stable)]
+    channel: UpdateChannel,
 }
 
 impl UpdateTool {
@@ -34,8 +45,8 @@ impl UpdateTool {
     // do this with our releases, we have other GitHub releases beyond just the CLI,
     // and we don't build for all major target triples, so we have to do some of the
     // work ourselves first to figure out what the latest version of the CLI is and
-    // which binary to download based on the current OS. Then we can plug that into
-    // the library which takes care of the rest.
+    // which binary to download based on the current OS. We download it ourselves so
+    // that an interrupted download can be resumed, then let the library install it.
     fn update(&self) -> CliTypedResult<String> {
         let installation_method =
             InstallationMethod::from_env().context("Failed to determine installation method")?;
@@ -54,9 +65,12 @@ impl UpdateTool {
             InstallationMethod::Other => {},
         }
 
-        let info = check_if_update_required(&self.repo_owner, &self.repo_name)?;
+        let info = check_if_update_required(&self.repo_owner, &self.repo_name, self.channel)?;
         if !info.update_required {
-            return Ok(format!("CLI already up to date (v{})", info.latest_version));
+            return Ok(format!(
+                "CLI already up to date (v{}, latest on the {} channel: v{})",
+                info.current_version, self.channel, info.latest_version
+            ));
         }
 
         // Determine the target we should download. This is necessary because we don't
@@ -102,32 +116,64 @@ impl UpdateTool {
             wildcard => return Err(anyhow!("Self-updating is not supported on your OS right now, please download the binary manually: {}", wildcard).into()),
         };
 
-        // Build a new configuration that will direct the library to download the
-        // binary with the target version tag and target that we determined above.
-        let config = Update::configure()
-            .repo_owner(&self.repo_owner)
-            .repo_name(&self.repo_name)
-            .bin_name("aptos")
-            .current_version(cargo_crate_version!())
-            .target_version_tag(&info.latest_version_tag)
-            .target(target)
-            .build()
-            .map_err(|e| anyhow!("Failed to build self-update configuration: {:#}", e))?;
-
-        // Update the binary.
-        let result = config
-            .update()
+        let asset = get_release_asset(
+            &self.repo_owner,
+            &self.repo_name,
+            &info.latest_version_tag,
+            target,
+        )?;
+        // The archive keeps a fixed name in the temp directory, so that running the
+        // command again after an interrupted download resumes it.
+        let archive_path = std::env::temp_dir().join(format!("{}.part", asset.name));
+        self.check_disk_space_for_update(&asset, &archive_path)?;
+        download_with_resume(&asset.download_url, &archive_path, asset.sha256.as_deref())?;
+        install_binary(&archive_path)
             .map_err(|e| anyhow!("Failed to update Aptos CLI: {:#}", e))?;
+        // The archive is only removed once installed, since it can't be resumed anymore.
+        let _ = std::fs::remove_file(&archive_path);
 
-        let message = match result {
-            Status::UpToDate(_) => panic!("We should have caught this already"),
-            Status::Updated(_) => format!(
-                "Successfully updated from v{} to v{}",
-                info.current_version, info.latest_version
-            ),
-        };
+        Ok(format!(
+            "Successfully updated from v{} to v{} ({} channel)",
+            info.current_version, info.latest_version, self.channel
+        ))
+    }
+}
+
+/// Extract the binary from the downloaded archive and replace the running one with it.
+fn install_binary(archive_path: &Path) -> anyhow::Result<()> {
+    let bin_name = if cfg!(windows) { "aptos.exe" } else { "aptos" };
+    let extract_dir = tempfile::tempdir()?;
+    Extract::from_source(archive_path)
+        .archive(ArchiveKind::Zip)
+        .extract_file(extract_dir.path(), bin_name)?;
+    self_update::self_replace::self_replace(extract_dir.path().join(bin_name))?;
+    Ok(())
+}
 
-        Ok(message)
+impl UpdateTool {
+    // The archive is downloaded to the temp directory and the binary extracted next to it,
+    // so we make sure both fit before starting, rather than failing halfway. The part of
+    // the archive already downloaded is accounted for, and the new binary is assumed to
+    // be about the size of the current one. If the space can't be determined, we go
+    // ahead without the check.
+    fn check_disk_space_for_update(
+        &self,
+        asset: &ReleaseAsset,
+        archive_path: &Path,
+    ) -> CliTypedResult<()> {
+        let downloaded = std::fs::metadata(archive_path).map_or(0, |metadata| metadata.len());
+        let binary_size = std::env::current_exe()
+            .and_then(std::fs::metadata)
+            .map_or(0, |metadata| metadata.len());
+        let temp_dir = std::env::temp_dir();
+        if let Some(available) = get_available_disk_space(&temp_dir) {
+            check_disk_space(
+                &temp_dir,
+                asset.size.saturating_sub(downloaded) + binary_size,
+                available,
+            )?;
+        }
+        Ok(())
     }
 }
 
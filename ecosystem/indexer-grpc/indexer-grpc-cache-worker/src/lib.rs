@@ -4,9 +4,12 @@
 pub mod metrics;
 pub mod worker;
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use aptos_indexer_grpc_server_framework::RunnableConfig;
-use aptos_indexer_grpc_utils::{config::IndexerGrpcFileStoreConfig, types::RedisUrl};
+use aptos_indexer_grpc_utils::{
+    cache_operator::CacheRetentionPolicy, config::IndexerGrpcFileStoreConfig,
+    redis_tls::RedisTlsConfig, types::RedisUrl,
+};
 use serde::{Deserialize, Serialize};
 use url::Url;
 use worker::Worker;
@@ -17,8 +20,20 @@ pub struct IndexerGrpcCacheWorkerConfig {
     pub fullnode_grpc_address: Url,
     pub file_store_config: IndexerGrpcFileStoreConfig,
     pub redis_main_instance_address: RedisUrl,
+    /// If not empty, the cache is a Redis Cluster reached through these nodes and the main instance.
+    #[serde(default)]
+    pub redis_cluster_seed_addresses: Vec<RedisUrl>,
+    /// TLS options of the connections to rediss:// addresses, e.g., the CA of a managed Redis.
+    #[serde(default)]
+    pub redis_tls_config: RedisTlsConfig,
     #[serde(default = "default_enable_cache_compression")]
     pub enable_cache_compression: bool,
+    /// If set, the cache is compressed with zstd at this level instead of gzip.
+    #[serde(default)]
+    pub cache_zstd_compression_level: Option<i32>,
+    /// How far behind the head transactions are kept. Readers of the cache must use the same one.
+    #[serde(default)]
+    pub cache_retention_policy: CacheRetentionPolicy,
 }
 
 const fn default_enable_cache_compression() -> bool {
@@ -30,25 +45,52 @@ impl IndexerGrpcCacheWorkerConfig {
         fullnode_grpc_address: Url,
         file_store_config: IndexerGrpcFileStoreConfig,
         redis_main_instance_address: RedisUrl,
+        redis_cluster_seed_addresses: Vec<RedisUrl>,
+        redis_tls_config: RedisTlsConfig,
         enable_cache_compression: bool,
+        cache_zstd_compression_level: Option<i32>,
+        cache_retention_policy: CacheRetentionPolicy,
     ) -> Self {
         Self {
             fullnode_grpc_address,
             file_store_config,
             redis_main_instance_address,
+            redis_cluster_seed_addresses,
+            redis_tls_config,
             enable_cache_compression,
+            cache_zstd_compression_level,
+            cache_retention_policy,
         }
     }
 }
 
 #[async_trait::async_trait]
 impl RunnableConfig for IndexerGrpcCacheWorkerConfig {
+    fn validate(&self) -> Result<()> {
+        if self.redis_tls_config.is_set() && !self.redis_main_instance_address.is_tls() {
+            bail!("redis_tls_config requires a rediss:// redis_main_instance_address");
+        }
+        if self.redis_tls_config.ca_cert_path.is_some()
+            && !self.redis_cluster_seed_addresses.is_empty()
+        {
+            bail!("redis_tls_config.ca_cert_path can't be used with redis_cluster_seed_addresses");
+        }
+        self.cache_retention_policy
+            .validate()
+            .context("Invalid cache_retention_policy")?;
+        Ok(())
+    }
+
     async fn run(&self) -> Result<()> {
         let mut worker = Worker::new(
             self.fullnode_grpc_address.clone(),
             self.redis_main_instance_address.clone(),
+            self.redis_cluster_seed_addresses.clone(),
+            self.redis_tls_config.clone(),
             self.file_store_config.clone(),
             self.enable_cache_compression,
+            self.cache_zstd_compression_level,
+            self.cache_retention_policy,
         )
         .await
         .context("Failed to create cache worker")?;
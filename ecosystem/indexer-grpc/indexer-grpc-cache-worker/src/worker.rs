@@ -7,15 +7,17 @@ use crate::metrics::{
 };
 use anyhow::{bail, Context, Result};
 use aptos_indexer_grpc_utils::{
-    cache_operator::CacheOperator,
-    compression_util::{FileStoreMetadata, StorageFormat},
+    cache_operator::{CacheOperator, CacheRetentionPolicy},
+    compression_util::{FileStoreMetadata, StorageFormat, DEFAULT_ZSTD_COMPRESSION_LEVEL},
     config::IndexerGrpcFileStoreConfig,
     counters::{log_grpc_step, IndexerGrpcStep},
     create_grpc_client,
     file_store_operator::FileStoreOperator,
-    types::RedisUrl,
+    moving_average::MovingAverage,
+    redis_cluster::CacheConnection,
+    redis_tls::RedisTlsConfig,
+    types::{ChainId, RedisUrl},
 };
-use aptos_moving_average::MovingAverage;
 use aptos_protos::internal::fullnode::v1::{
     stream_status::StatusType, transactions_from_node_response::Response,
     GetTransactionsFromNodeRequest, TransactionsFromNodeResponse,
@@ -41,14 +43,22 @@ const FILE_STORE_METADATA_WAIT_MS: u64 = 2000;
 const SERVICE_TYPE: &str = "cache_worker";
 
 pub struct Worker {
-    /// Redis client.
-    redis_client: redis::Client,
+    /// Redis main instance address.
+    redis_main_instance_address: RedisUrl,
+    /// If not empty, the cache is a Redis Cluster reached through these nodes and the main instance.
+    redis_cluster_seed_addresses: Vec<RedisUrl>,
+    /// TLS options of the connections to rediss:// addresses.
+    redis_tls_config: RedisTlsConfig,
     /// Fullnode grpc address.
     fullnode_grpc_address: Url,
     /// File store config
     file_store: IndexerGrpcFileStoreConfig,
     /// Cache storage format.
     cache_storage_format: StorageFormat,
+    /// Zstd compression level used when the cache is zstd compressed.
+    cache_compression_level: i32,
+    /// How far behind the head transactions are kept.
+    cache_retention_policy: CacheRetentionPolicy,
 }
 
 /// GRPC data status enum is to identify the data frame.
@@ -76,26 +86,31 @@ impl Worker {
     pub async fn new(
         fullnode_grpc_address: Url,
         redis_main_instance_address: RedisUrl,
+        redis_cluster_seed_addresses: Vec<RedisUrl>,
+        redis_tls_config: RedisTlsConfig,
         file_store: IndexerGrpcFileStoreConfig,
         enable_cache_compression: bool,
+        cache_zstd_compression_level: Option<i32>,
+        cache_retention_policy: CacheRetentionPolicy,
     ) -> Result<Self> {
-        let cache_storage_format = if enable_cache_compression {
-            StorageFormat::GzipCompressedProto
-        } else {
-            StorageFormat::Base64UncompressedProto
-        };
-        let redis_client = redis::Client::open(redis_main_instance_address.0.clone())
-            .with_context(|| {
-                format!(
-                    "[Indexer Cache] Failed to create redis client for {}",
-                    redis_main_instance_address
-                )
-            })?;
+        let cache_storage_format =
+            StorageFormat::for_cache(enable_cache_compression, cache_zstd_compression_level);
+        redis::Client::open(redis_main_instance_address.0.clone()).with_context(|| {
+            format!(
+                "[Indexer Cache] Failed to create redis client for {}",
+                redis_main_instance_address
+            )
+        })?;
         Ok(Self {
-            redis_client,
+            redis_main_instance_address,
+            redis_cluster_seed_addresses,
+            redis_tls_config,
             file_store,
             fullnode_grpc_address,
             cache_storage_format,
+            cache_compression_level: cache_zstd_compression_level
+                .unwrap_or(DEFAULT_ZSTD_COMPRESSION_LEVEL),
+            cache_retention_policy,
         })
     }
 
@@ -112,11 +127,13 @@ impl Worker {
     pub async fn run(&mut self) -> Result<()> {
         // Re-connect if lost.
         loop {
-            let conn = self
-                .redis_client
-                .get_tokio_connection_manager()
-                .await
-                .context("Get redis connection failed.")?;
+            let conn = CacheConnection::connect(
+                &self.redis_main_instance_address,
+                &self.redis_cluster_seed_addresses,
+                &self.redis_tls_config,
+            )
+            .await
+            .context("Get redis connection failed.")?;
             let mut rpc_client = create_grpc_client(self.fullnode_grpc_address.clone()).await;
 
             // 1. Fetch metadata.
@@ -170,6 +187,8 @@ impl Worker {
             process_streaming_response(
                 conn,
                 self.cache_storage_format,
+                self.cache_compression_level,
+                self.cache_retention_policy,
                 file_store_metadata,
                 response.into_inner(),
             )
@@ -185,7 +204,7 @@ impl Worker {
 
 async fn process_transactions_from_node_response(
     response: TransactionsFromNodeResponse,
-    cache_operator: &mut CacheOperator<redis::aio::ConnectionManager>,
+    cache_operator: &mut CacheOperator<CacheConnection>,
     download_start_time: std::time::Instant,
 ) -> Result<GrpcDataStatus> {
     let size_in_bytes = response.encoded_len();
@@ -287,7 +306,7 @@ async fn process_transactions_from_node_response(
 
 // Setup the cache operator with init signal, including chain id and starting version from fullnode.
 async fn verify_fullnode_init_signal(
-    cache_operator: &mut CacheOperator<redis::aio::ConnectionManager>,
+    cache_operator: &mut CacheOperator<CacheConnection>,
     init_signal: TransactionsFromNodeResponse,
     file_store_metadata: FileStoreMetadata,
 ) -> Result<(ChainID, StartingVersion)> {
@@ -312,7 +331,7 @@ async fn verify_fullnode_init_signal(
 
     // Guaranteed that chain id is here at this point because we already ensure that fileworker did the set up
     let chain_id = cache_operator.get_chain_id().await?.unwrap();
-    if chain_id != fullnode_chain_id as u64 {
+    if chain_id != ChainId(fullnode_chain_id as u64) {
         bail!("[Indexer Cache] Chain ID mismatch between fullnode init signal and cache.");
     }
 
@@ -320,7 +339,7 @@ async fn verify_fullnode_init_signal(
     if file_store_metadata.version != starting_version {
         bail!("[Indexer Cache] Starting version mismatch between filestore metadata and fullnode init signal.");
     }
-    if file_store_metadata.chain_id != fullnode_chain_id as u64 {
+    if file_store_metadata.chain_id != ChainId(fullnode_chain_id as u64) {
         bail!("[Indexer Cache] Chain id mismatch between filestore metadata and fullnode.");
     }
 
@@ -329,8 +348,10 @@ async fn verify_fullnode_init_signal(
 
 /// Infinite streaming processing. Retry if error happens; crash if fatal.
 async fn process_streaming_response(
-    conn: redis::aio::ConnectionManager,
+    conn: CacheConnection,
     cache_storage_format: StorageFormat,
+    cache_compression_level: i32,
+    cache_retention_policy: CacheRetentionPolicy,
     file_store_metadata: FileStoreMetadata,
     mut resp_stream: impl futures_core::Stream<Item = Result<TransactionsFromNodeResponse, tonic::Status>>
         + std::marker::Unpin,
@@ -344,7 +365,9 @@ async fn process_streaming_response(
             bail!("[Indexer Cache] Streaming error: no response.");
         },
     };
-    let mut cache_operator = CacheOperator::new(conn, cache_storage_format);
+    let mut cache_operator = CacheOperator::new(conn, cache_storage_format)
+        .with_compression_level(cache_compression_level)
+        .with_retention_policy(cache_retention_policy);
 
     let (fullnode_chain_id, starting_version) =
         verify_fullnode_init_signal(&mut cache_operator, init_signal, file_store_metadata)
@@ -2,10 +2,11 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use crate::service::RawDataServerWrapper;
-use anyhow::{bail, Result};
+use anyhow::{bail, Context, Result};
 use aptos_indexer_grpc_server_framework::RunnableConfig;
 use aptos_indexer_grpc_utils::{
-    compression_util::StorageFormat, config::IndexerGrpcFileStoreConfig, types::RedisUrl,
+    cache_operator::CacheRetentionPolicy, compression_util::StorageFormat,
+    config::IndexerGrpcFileStoreConfig, redis_tls::RedisTlsConfig, types::RedisUrl,
 };
 use aptos_protos::{
     indexer::v1::FILE_DESCRIPTOR_SET as INDEXER_V1_FILE_DESCRIPTOR_SET,
@@ -69,9 +70,18 @@ pub struct IndexerGrpcDataServiceConfig {
     pub file_store_config: IndexerGrpcFileStoreConfig,
     /// Redis read replica address.
     pub redis_read_replica_address: RedisUrl,
+    /// TLS options of the connections to a rediss:// read replica, e.g., the CA of a managed Redis.
+    #[serde(default)]
+    pub redis_tls_config: RedisTlsConfig,
     /// Support compressed cache data.
     #[serde(default = "IndexerGrpcDataServiceConfig::default_enable_cache_compression")]
     pub enable_cache_compression: bool,
+    /// Support zstd compressed cache data; takes precedence over `enable_cache_compression`.
+    #[serde(default)]
+    pub cache_zstd_compression_level: Option<i32>,
+    /// Retention of the cache; has to match the one of the cache worker.
+    #[serde(default)]
+    pub cache_retention_policy: CacheRetentionPolicy,
 }
 
 impl IndexerGrpcDataServiceConfig {
@@ -83,7 +93,10 @@ impl IndexerGrpcDataServiceConfig {
         disable_auth_check: bool,
         file_store_config: IndexerGrpcFileStoreConfig,
         redis_read_replica_address: RedisUrl,
+        redis_tls_config: RedisTlsConfig,
         enable_cache_compression: bool,
+        cache_zstd_compression_level: Option<i32>,
+        cache_retention_policy: CacheRetentionPolicy,
     ) -> Self {
         Self {
             data_service_grpc_tls_config,
@@ -94,7 +107,10 @@ impl IndexerGrpcDataServiceConfig {
             disable_auth_check,
             file_store_config,
             redis_read_replica_address,
+            redis_tls_config,
             enable_cache_compression,
+            cache_zstd_compression_level,
+            cache_retention_policy,
         }
     }
 
@@ -121,6 +137,12 @@ impl RunnableConfig for IndexerGrpcDataServiceConfig {
         {
             bail!("At least one of data_service_grpc_non_tls_config and data_service_grpc_tls_config must be set");
         }
+        if self.redis_tls_config.is_set() && !self.redis_read_replica_address.is_tls() {
+            bail!("redis_tls_config requires a rediss:// redis_read_replica_address");
+        }
+        self.cache_retention_policy
+            .validate()
+            .context("Invalid cache_retention_policy")?;
         Ok(())
     }
 
@@ -157,17 +179,18 @@ impl RunnableConfig for IndexerGrpcDataServiceConfig {
             .build()
             .map_err(|e| anyhow::anyhow!("Failed to build reflection service: {}", e))?;
 
-        let cache_storage_format: StorageFormat = if self.enable_cache_compression {
-            StorageFormat::GzipCompressedProto
-        } else {
-            StorageFormat::Base64UncompressedProto
-        };
+        let cache_storage_format: StorageFormat = StorageFormat::for_cache(
+            self.enable_cache_compression,
+            self.cache_zstd_compression_level,
+        );
         // Add authentication interceptor.
         let server = RawDataServerWrapper::new(
             self.redis_read_replica_address.clone(),
+            self.redis_tls_config.clone(),
             self.file_store_config.clone(),
             self.data_service_response_channel_size,
             cache_storage_format,
+            self.cache_retention_policy,
         )?;
         let svc = aptos_protos::indexer::v1::raw_data_server::RawDataServer::new(server)
             .send_compressed(CompressionEncoding::Gzip)
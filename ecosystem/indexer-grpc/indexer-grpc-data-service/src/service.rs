@@ -9,7 +9,9 @@ use crate::metrics::{
 };
 use anyhow::{Context, Result};
 use aptos_indexer_grpc_utils::{
-    cache_operator::{CacheBatchGetStatus, CacheCoverageStatus, CacheOperator},
+    cache_operator::{
+        CacheAvailableBatch, CacheCoverageStatus, CacheOperator, CacheRetentionPolicy,
+    },
     chunk_transactions,
     compression_util::{CacheEntry, StorageFormat},
     config::IndexerGrpcFileStoreConfig,
@@ -19,17 +21,18 @@ use aptos_indexer_grpc_utils::{
     },
     counters::{log_grpc_step, IndexerGrpcStep, NUM_MULTI_FETCH_OVERLAPPED_VERSIONS},
     file_store_operator::FileStoreOperator,
+    moving_average::MovingAverage,
+    redis_cluster::CacheConnection,
+    redis_tls::RedisTlsConfig,
     time_diff_since_pb_timestamp_in_secs,
     types::RedisUrl,
 };
-use aptos_moving_average::MovingAverage;
 use aptos_protos::{
     indexer::v1::{raw_data_server::RawData, GetTransactionsRequest, TransactionsResponse},
     transaction::v1::Transaction,
 };
 use futures::Stream;
 use prost::Message;
-use redis::Client;
 use std::{
     collections::HashMap,
     pin::Pin,
@@ -76,28 +79,36 @@ const MAX_FETCH_TASKS_PER_REQUEST: u64 = 5;
 const TRANSACTIONS_PER_STORAGE_BLOCK: u64 = 1000;
 
 pub struct RawDataServerWrapper {
-    pub redis_client: Arc<redis::Client>,
+    pub redis_address: RedisUrl,
+    pub redis_tls_config: RedisTlsConfig,
     pub file_store_config: IndexerGrpcFileStoreConfig,
     pub data_service_response_channel_size: usize,
     pub cache_storage_format: StorageFormat,
+    pub cache_retention_policy: CacheRetentionPolicy,
 }
 
 impl RawDataServerWrapper {
     pub fn new(
         redis_address: RedisUrl,
+        redis_tls_config: RedisTlsConfig,
         file_store_config: IndexerGrpcFileStoreConfig,
         data_service_response_channel_size: usize,
         cache_storage_format: StorageFormat,
+        cache_retention_policy: CacheRetentionPolicy,
     ) -> anyhow::Result<Self> {
+        // Every request connects on its own; a bad CA file fails here rather than on each of them.
+        if redis_address.is_tls() {
+            redis_tls_config
+                .tls_connector()
+                .context("Invalid redis_tls_config")?;
+        }
         Ok(Self {
-            redis_client: Arc::new(
-                redis::Client::open(redis_address.0.clone()).with_context(|| {
-                    format!("Failed to create redis client for {}", redis_address)
-                })?,
-            ),
+            redis_address,
+            redis_tls_config,
             file_store_config,
             data_service_response_channel_size,
             cache_storage_format,
+            cache_retention_policy,
         })
     }
 }
@@ -169,16 +180,20 @@ impl RawData for RawDataServerWrapper {
             Some(&request_metadata),
         );
 
-        let redis_client = self.redis_client.clone();
+        let redis_address = self.redis_address.clone();
+        let redis_tls_config = self.redis_tls_config.clone();
         let cache_storage_format = self.cache_storage_format;
+        let cache_retention_policy = self.cache_retention_policy;
         let request_metadata = Arc::new(request_metadata);
         tokio::spawn({
             let request_metadata = request_metadata.clone();
             async move {
                 data_fetcher_task(
-                    redis_client,
+                    redis_address,
+                    redis_tls_config,
                     file_store_operator,
                     cache_storage_format,
+                    cache_retention_policy,
                     request_metadata,
                     transactions_count,
                     tx,
@@ -210,7 +225,7 @@ async fn get_data_with_tasks(
     start_version: u64,
     transactions_count: Option<u64>,
     chain_id: u64,
-    cache_operator: &mut CacheOperator<redis::aio::ConnectionManager>,
+    cache_operator: &mut CacheOperator<CacheConnection>,
     file_store_operator: Arc<Box<dyn FileStoreOperator>>,
     request_metadata: Arc<IndexerGrpcRequestMetadata>,
     cache_storage_format: StorageFormat,
@@ -297,7 +312,7 @@ async fn get_data_with_tasks(
 async fn get_data_in_task(
     start_version: u64,
     chain_id: u64,
-    cache_operator: &mut CacheOperator<redis::aio::ConnectionManager>,
+    cache_operator: &mut CacheOperator<CacheConnection>,
     file_store_operator: Arc<Box<dyn FileStoreOperator>>,
     request_metadata: Arc<IndexerGrpcRequestMetadata>,
     cache_storage_format: StorageFormat,
@@ -344,9 +359,11 @@ async fn get_data_in_task(
 
 // This is a task spawned off for servicing a users' request
 async fn data_fetcher_task(
-    redis_client: Arc<Client>,
+    redis_address: RedisUrl,
+    redis_tls_config: RedisTlsConfig,
     file_store_operator: Arc<Box<dyn FileStoreOperator>>,
     cache_storage_format: StorageFormat,
+    cache_retention_policy: CacheRetentionPolicy,
     request_metadata: Arc<IndexerGrpcRequestMetadata>,
     transactions_count: Option<u64>,
     tx: tokio::sync::mpsc::Sender<Result<TransactionsResponse, Status>>,
@@ -356,7 +373,7 @@ async fn data_fetcher_task(
     let mut transactions_count = transactions_count;
 
     // Establish redis connection
-    let conn = match redis_client.get_tokio_connection_manager().await {
+    let conn = match CacheConnection::connect(&redis_address, &[], &redis_tls_config).await {
         Ok(conn) => conn,
         Err(e) => {
             ERROR_COUNT
@@ -378,7 +395,8 @@ async fn data_fetcher_task(
             return;
         },
     };
-    let mut cache_operator = CacheOperator::new(conn, cache_storage_format);
+    let mut cache_operator = CacheOperator::new(conn, cache_storage_format)
+        .with_retention_policy(cache_retention_policy);
 
     // Validate chain id
     let mut metadata = file_store_operator.get_file_store_metadata().await;
@@ -439,7 +457,7 @@ async fn data_fetcher_task(
         let transaction_data = match get_data_with_tasks(
             current_version,
             transactions_count,
-            chain_id,
+            chain_id.0,
             &mut cache_operator,
             file_store_operator.clone(),
             request_metadata.clone(),
@@ -486,7 +504,7 @@ async fn data_fetcher_task(
         // 2. Push the data to the response channel, i.e. stream the data to the client.
         let current_batch_size = transaction_data.as_slice().len();
         let end_of_batch_version = transaction_data.as_slice().last().unwrap().version;
-        let resp_items = get_transactions_responses_builder(transaction_data, chain_id as u32);
+        let resp_items = get_transactions_responses_builder(transaction_data, chain_id.0 as u32);
         let data_latency_in_secs = resp_items
             .last()
             .unwrap()
@@ -682,30 +700,37 @@ async fn deserialize_cached_transactions(
                 let cache_entry = CacheEntry::new(transaction, storage_format);
                 cache_entry.into_transaction()
             })
-            .collect::<Vec<Transaction>>()
+            .collect::<anyhow::Result<Vec<Transaction>>>()
     })
     .await;
-    task.context("Transaction bytes to CacheEntry deserialization task failed")
+    task.context("Transaction bytes to CacheEntry deserialization task failed")?
 }
 
 /// Fetches data from cache or the file store. It returns the data if it is ready in the cache or file store.
 /// Otherwise, it returns the status of the data fetching.
 async fn data_fetch(
     starting_version: u64,
-    cache_operator: &mut CacheOperator<redis::aio::ConnectionManager>,
+    cache_operator: &mut CacheOperator<CacheConnection>,
     file_store_operator: Arc<Box<dyn FileStoreOperator>>,
     request_metadata: Arc<IndexerGrpcRequestMetadata>,
     storage_format: StorageFormat,
 ) -> anyhow::Result<TransactionsDataStatus> {
     let current_batch_start_time = std::time::Instant::now();
+    // Whatever is available is served right away, even short of a full batch at the head.
     let batch_get_result = cache_operator
-        .batch_get_encoded_proto_data(starting_version)
+        .batch_get_available_encoded_proto_data(starting_version, TRANSACTIONS_PER_STORAGE_BLOCK)
         .await;
 
     match batch_get_result {
         // Data is not ready yet in the cache.
-        Ok(CacheBatchGetStatus::NotReady) => Ok(TransactionsDataStatus::AheadOfCache),
-        Ok(CacheBatchGetStatus::Ok(transactions)) => {
+        Ok(CacheAvailableBatch::Ok {
+            encoded_transactions,
+            ..
+        }) if encoded_transactions.is_empty() => Ok(TransactionsDataStatus::AheadOfCache),
+        Ok(CacheAvailableBatch::Ok {
+            encoded_transactions: transactions,
+            ..
+        }) => {
             let decoding_start_time = std::time::Instant::now();
             let size_in_bytes = transactions
                 .iter()
@@ -746,7 +771,7 @@ async fn data_fetch(
 
             Ok(TransactionsDataStatus::Success(transactions))
         },
-        Ok(CacheBatchGetStatus::EvictedFromCache) => {
+        Ok(CacheAvailableBatch::EvictedFromCache { .. }) => {
             let transactions =
                 data_fetch_from_filestore(starting_version, file_store_operator, request_metadata)
                     .await?;
@@ -0,0 +1,317 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::transaction_buffer::BufferedBatch;
+use anyhow::{anyhow, Result};
+use aptos_indexer_grpc_utils::compression_util::FILE_ENTRY_TRANSACTION_COUNT;
+use aptos_protos::transaction::v1::Transaction;
+use futures::{Future, StreamExt};
+use prost::Message;
+use std::time::Duration;
+use tokio::{sync::mpsc, task::JoinHandle};
+
+/// Transactions of a batch, fetched for upload.
+pub struct FetchedBatch {
+    pub start_version: u64,
+    pub transactions: BatchTransactions,
+    /// Read back from a file store that already has it, so it isn't uploaded again.
+    pub is_evicted_batch: bool,
+    pub fetch_duration: Duration,
+    /// Room of the batch in the `TransactionBuffer`, released once the batch is dropped.
+    pub buffered_batch: BufferedBatch,
+}
+
+/// Transactions of a fetched batch, either decoded or, with raw transaction pass-through, as
+/// their protobuf encodings read from the cache.
+#[derive(Clone, Debug)]
+pub enum BatchTransactions {
+    Decoded(Vec<Transaction>),
+    Encoded {
+        encoded_transactions: Vec<Vec<u8>>,
+        // Decoded to check the batch and to record the progress of the processor.
+        last_transaction: Transaction,
+    },
+}
+
+impl BatchTransactions {
+    pub fn transaction_count(&self) -> usize {
+        match self {
+            Self::Decoded(transactions) => transactions.len(),
+            Self::Encoded {
+                encoded_transactions,
+                ..
+            } => encoded_transactions.len(),
+        }
+    }
+
+    /// Version of the first transaction; the batch must not be empty.
+    pub fn first_version(&self) -> u64 {
+        match self {
+            Self::Decoded(transactions) => transactions.first().unwrap().version,
+            Self::Encoded {
+                encoded_transactions,
+                last_transaction,
+            } => last_transaction.version + 1 - encoded_transactions.len() as u64,
+        }
+    }
+
+    /// The batch must not be empty.
+    pub fn last_transaction(&self) -> &Transaction {
+        match self {
+            Self::Decoded(transactions) => transactions.last().unwrap(),
+            Self::Encoded {
+                last_transaction, ..
+            } => last_transaction,
+        }
+    }
+
+    /// Sum of the sizes of the protobuf encodings of the transactions.
+    pub fn encoded_size_in_bytes(&self) -> usize {
+        match self {
+            Self::Decoded(transactions) => transactions
+                .iter()
+                .map(|transaction| transaction.encoded_len())
+                .sum(),
+            Self::Encoded {
+                encoded_transactions,
+                ..
+            } => encoded_transactions.iter().map(Vec::len).sum(),
+        }
+    }
+}
+
+/// BatchFetcher fetches the aligned batches following the last one handed over from a spawned
+/// task, and hands them over in order through a bounded channel, so that slow uploads don't stall
+/// fetching and the other way around. The channel bound is the backpressure: once `capacity`
+/// batches wait for their upload, fetching pauses.
+pub struct BatchFetcher {
+    capacity: usize,
+    // Batches fetched at the same time; they're still handed over in order.
+    concurrency: usize,
+    // How long to wait for a batch that isn't in the cache yet.
+    retry_delay: Duration,
+    // Number of versions of each batch.
+    blob_size: u64,
+    // Start version of the next batch handed over, while `receiver` is set.
+    next_version: u64,
+    receiver: Option<mpsc::Receiver<Result<FetchedBatch>>>,
+    task: Option<JoinHandle<()>>,
+}
+
+impl BatchFetcher {
+    pub fn new(capacity: usize, concurrency: usize, retry_delay: Duration) -> Self {
+        Self {
+            capacity,
+            concurrency: concurrency.max(1),
+            retry_delay,
+            blob_size: FILE_ENTRY_TRANSACTION_COUNT,
+            next_version: 0,
+            receiver: None,
+            task: None,
+        }
+    }
+
+    /// Fetches batches of `blob_size` versions, the blob size of the file store.
+    pub fn with_blob_size(mut self, blob_size: u64) -> Self {
+        self.blob_size = blob_size;
+        self
+    }
+
+    /// Returns the batch starting at `start_version`. Unless the fetcher is already there, i.e., at
+    /// the first call, after a failure or once a round is abandoned, the batches fetched ahead are
+    /// dropped and fetching starts over from `start_version` with `fetch`, which returns `None`
+    /// while the batch isn't in the cache yet. Fetching stops at the first failure.
+    pub async fn next_batch<F, Fut>(&mut self, start_version: u64, fetch: F) -> Result<FetchedBatch>
+    where
+        F: Fn(u64) -> Fut + Clone + Send + 'static,
+        Fut: Future<Output = Result<Option<FetchedBatch>>> + Send + 'static,
+    {
+        if self.receiver.is_none() || self.next_version != start_version {
+            self.restart(start_version, fetch);
+        }
+        let batch = match self.receiver.as_mut().unwrap().recv().await {
+            Some(batch) => batch,
+            None => Err(anyhow!("The batch fetcher stopped unexpectedly.")),
+        };
+        match &batch {
+            Ok(batch) => {
+                debug_assert_eq!(batch.start_version, start_version);
+                self.next_version += self.blob_size;
+            },
+            Err(_) => self.stop(),
+        }
+        batch
+    }
+
+    fn restart<F, Fut>(&mut self, start_version: u64, fetch: F)
+    where
+        F: Fn(u64) -> Fut + Clone + Send + 'static,
+        Fut: Future<Output = Result<Option<FetchedBatch>>> + Send + 'static,
+    {
+        self.stop();
+        let (sender, receiver) = mpsc::channel(self.capacity);
+        self.task = Some(tokio::spawn(fetch_batches(
+            start_version,
+            sender,
+            fetch,
+            self.concurrency,
+            self.blob_size,
+            self.retry_delay,
+        )));
+        self.receiver = Some(receiver);
+        self.next_version = start_version;
+    }
+
+    fn stop(&mut self) {
+        if let Some(task) = self.task.take() {
+            task.abort();
+        }
+        self.receiver = None;
+    }
+}
+
+impl Drop for BatchFetcher {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// Sends the batches of `blob_size` versions from `start_version` on, in order, until the receiver
+/// is dropped or a fetch fails.
+async fn fetch_batches<F, Fut>(
+    start_version: u64,
+    sender: mpsc::Sender<Result<FetchedBatch>>,
+    fetch: F,
+    concurrency: usize,
+    blob_size: u64,
+    retry_delay: Duration,
+) where
+    F: Fn(u64) -> Fut + Clone + Send + 'static,
+    Fut: Future<Output = Result<Option<FetchedBatch>>> + Send + 'static,
+{
+    let mut batches = futures::stream::iter((start_version..).step_by(blob_size as usize))
+        .map(move |version| {
+            let fetch = fetch.clone();
+            async move {
+                loop {
+                    match fetch(version).await {
+                        Ok(Some(batch)) => return Ok(batch),
+                        Ok(None) => tokio::time::sleep(retry_delay).await,
+                        Err(err) => return Err(err),
+                    }
+                }
+            }
+        })
+        .buffered(concurrency);
+    while let Some(batch) = batches.next().await {
+        let failed = batch.is_err();
+        if sender.send(batch).await.is_err() || failed {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction_buffer::TransactionBuffer;
+    use std::sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    };
+
+    /// Fetches every batch below `end_version`, later ones faster, counting the fetches.
+    fn fetch_fn(
+        end_version: u64,
+        fetch_count: Arc<AtomicU64>,
+    ) -> impl Fn(u64) -> futures::future::BoxFuture<'static, Result<Option<FetchedBatch>>>
+           + Clone
+           + Send
+           + 'static {
+        let transaction_buffer = Arc::new(TransactionBuffer::new(None));
+        move |start_version| {
+            let fetch_count = fetch_count.clone();
+            let transaction_buffer = transaction_buffer.clone();
+            Box::pin(async move {
+                if start_version >= end_version {
+                    return Ok(None);
+                }
+                fetch_count.fetch_add(1, Ordering::SeqCst);
+                let delay = 20 - (start_version / FILE_ENTRY_TRANSACTION_COUNT % 4) * 5;
+                tokio::time::sleep(Duration::from_millis(delay)).await;
+                let transactions = (start_version..start_version + FILE_ENTRY_TRANSACTION_COUNT)
+                    .map(|version| Transaction {
+                        version,
+                        ..Default::default()
+                    })
+                    .collect();
+                Ok(Some(FetchedBatch {
+                    start_version,
+                    transactions: BatchTransactions::Decoded(transactions),
+                    is_evicted_batch: false,
+                    fetch_duration: Duration::from_millis(delay),
+                    buffered_batch: transaction_buffer.reserve().await,
+                }))
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn batches_are_handed_over_in_order() {
+        let fetch_count = Arc::new(AtomicU64::new(0));
+        let fetch = fetch_fn(20_000, fetch_count.clone());
+        let mut fetcher = BatchFetcher::new(4, 4, Duration::from_millis(5));
+        for start_version in (0..10_000).step_by(FILE_ENTRY_TRANSACTION_COUNT as usize) {
+            let batch = fetcher
+                .next_batch(start_version, fetch.clone())
+                .await
+                .unwrap();
+            assert_eq!(batch.start_version, start_version);
+            assert_eq!(batch.transactions.first_version(), start_version);
+        }
+
+        // Asking for another batch, e.g., after an abandoned round, starts over from there.
+        let batch = fetcher.next_batch(3_000, fetch.clone()).await.unwrap();
+        assert_eq!(batch.start_version, 3_000);
+        let batch = fetcher.next_batch(4_000, fetch).await.unwrap();
+        assert_eq!(batch.start_version, 4_000);
+    }
+
+    #[tokio::test]
+    async fn fetching_pauses_while_the_channel_is_full() {
+        let fetch_count = Arc::new(AtomicU64::new(0));
+        let fetch = fetch_fn(100_000, fetch_count.clone());
+        let (capacity, concurrency) = (2, 1);
+        let mut fetcher = BatchFetcher::new(capacity, concurrency, Duration::from_millis(5));
+        fetcher.next_batch(0, fetch.clone()).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        // The batch handed over, the ones in the channel, and the ones waiting for room in it.
+        let bound = (1 + capacity + concurrency) as u64;
+        assert_eq!(fetch_count.load(Ordering::SeqCst), bound);
+
+        // Handing batches over makes room for more.
+        fetcher.next_batch(1_000, fetch.clone()).await.unwrap();
+        fetcher.next_batch(2_000, fetch).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(fetch_count.load(Ordering::SeqCst), bound + 2);
+    }
+
+    #[tokio::test]
+    async fn fetching_waits_for_the_cache_and_stops_at_failures() {
+        let fetch_count = Arc::new(AtomicU64::new(0));
+        let mut fetcher = BatchFetcher::new(2, 2, Duration::from_millis(5));
+        let fetch = fetch_fn(1_000, fetch_count.clone());
+        fetcher.next_batch(0, fetch.clone()).await.unwrap();
+        // The next batch isn't in the cache yet.
+        assert!(
+            tokio::time::timeout(Duration::from_millis(100), fetcher.next_batch(1_000, fetch))
+                .await
+                .is_err()
+        );
+
+        let failing_fetch =
+            |_: u64| async { Err::<Option<FetchedBatch>, _>(anyhow!("Redis is down")) };
+        assert!(fetcher.next_batch(0, failing_fetch).await.is_err());
+        assert!(fetcher.receiver.is_none());
+    }
+}
@@ -0,0 +1,52 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+use anyhow::Result;
+use aptos_indexer_grpc_file_store::{cache_import, compaction, export, migration, verifier};
+use aptos_indexer_grpc_server_framework::setup_logging;
+use clap::{Parser, Subcommand};
+
+#[derive(Clone, Debug, Subcommand)]
+pub enum Command {
+    /// Copy the blobs of a file store to another one, in the storage format of the destination.
+    Migrate(migration::MigrateArgs),
+    /// Scan a version range of a file store for missing or corrupt blobs, optionally fixing them
+    /// from a secondary file store.
+    Verify(verifier::VerifyArgs),
+    /// Rewrite the blobs of a version range in the canonical storage format of a file store and
+    /// delete their legacy copies.
+    Compact(compaction::CompactArgs),
+    /// Write the blobs of a version range to a local directory, or a tarball, with a manifest of
+    /// their checksums.
+    Export(export::ExportArgs),
+    /// Write the transactions of a version range of a file store into the cache, e.g., to hydrate
+    /// the cache of a new region.
+    Import(cache_import::ImportArgs),
+}
+
+/// Operational tools for file stores; the file store processor itself is a separate binary.
+#[derive(Clone, Debug, Parser)]
+#[clap(author, version, about, long_about = None)]
+pub struct RootArgs {
+    #[clap(subcommand)]
+    pub command: Command,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let root_args = RootArgs::parse();
+    setup_logging(None);
+    match root_args.command {
+        Command::Migrate(args) => migration::run_migration(args).await,
+        Command::Verify(args) => verifier::run_verifier(args).await,
+        Command::Compact(args) => compaction::run_compaction(args).await,
+        Command::Export(args) => export::run_export(args).await,
+        Command::Import(args) => cache_import::run_import(args).await,
+    }
+}
+
+#[test]
+fn verify_tool() {
+    use clap::CommandFactory;
+    RootArgs::command().debug_assert()
+}
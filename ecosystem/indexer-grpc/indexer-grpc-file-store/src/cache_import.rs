@@ -0,0 +1,305 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::migration::verify_blob;
+use anyhow::{bail, ensure, Result};
+use aptos_indexer_grpc_server_framework::load;
+use aptos_indexer_grpc_utils::{
+    cache_operator::{CacheOperator, CacheRetentionPolicy},
+    compression_util::StorageFormat,
+    config::IndexerGrpcFileStoreConfig,
+    file_store_operator::FileStoreOperator,
+    redis_cluster::CacheConnection,
+    redis_tls::RedisTlsConfig,
+    types::RedisUrl,
+};
+use clap::Parser;
+use serde::{Deserialize, Serialize};
+use std::{path::PathBuf, time::Instant};
+
+// Number of retries when reading a blob.
+const IMPORT_DOWNLOAD_RETRIES: u8 = 3;
+// Progress is logged every this many imported blobs.
+const PROGRESS_LOG_INTERVAL_IN_BLOBS: u64 = 10;
+
+/// Writes the transactions of a version range of a file store into the cache, e.g., to hydrate the
+/// cache of a new region without waiting for its cache worker to catch up.
+#[derive(Clone, Debug, Parser)]
+pub struct ImportArgs {
+    /// Path to the import config, with the file store and the cache.
+    #[clap(short, long, value_parser)]
+    pub config_path: PathBuf,
+    /// First version to import; a multiple of the blob size, 1000 by default. Defaults to the first blob the cache serves
+    /// once the import is done, per its retention policy.
+    #[clap(long)]
+    pub start_version: Option<u64>,
+    /// Version to stop at, exclusive; a multiple of the blob size. Defaults to the file store
+    /// version.
+    #[clap(long)]
+    pub end_version: Option<u64>,
+    /// Only check the file store and the cache, and log what would be imported.
+    #[clap(long)]
+    pub dry_run: bool,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct CacheImportConfig {
+    pub file_store_config: IndexerGrpcFileStoreConfig,
+    pub redis_main_instance_address: RedisUrl,
+    // If not empty, the cache is a Redis Cluster reached through these nodes and the main instance.
+    #[serde(default)]
+    pub redis_cluster_seed_addresses: Vec<RedisUrl>,
+    #[serde(default)]
+    pub redis_tls_config: RedisTlsConfig,
+    // The storage format and retention of the cache; they have to match the ones of the cache worker.
+    #[serde(default)]
+    pub enable_cache_compression: bool,
+    #[serde(default)]
+    pub cache_zstd_compression_level: Option<i32>,
+    #[serde(default)]
+    pub cache_retention_policy: CacheRetentionPolicy,
+}
+
+pub async fn run_import(args: ImportArgs) -> Result<()> {
+    let config: CacheImportConfig = load(&args.config_path)?;
+    config.cache_retention_policy.validate()?;
+    let operator = config.file_store_config.create();
+    operator.verify_storage_bucket_existence().await;
+    let conn = CacheConnection::connect(
+        &config.redis_main_instance_address,
+        &config.redis_cluster_seed_addresses,
+        &config.redis_tls_config,
+    )
+    .await?;
+    let storage_format = StorageFormat::for_cache(
+        config.enable_cache_compression,
+        config.cache_zstd_compression_level,
+    );
+    let mut cache_operator = CacheOperator::new(conn, storage_format)
+        .with_retention_policy(config.cache_retention_policy);
+    if let Some(compression_level) = config.cache_zstd_compression_level {
+        cache_operator = cache_operator.with_compression_level(compression_level);
+    }
+    let (start_version, end_version) = import_into_cache(
+        operator.as_ref(),
+        &mut cache_operator,
+        args.start_version,
+        args.end_version,
+        args.dry_run,
+    )
+    .await?;
+    tracing::info!(
+        start_version = start_version,
+        end_version = end_version,
+        dry_run = args.dry_run,
+        "[Cache import] Import is done."
+    );
+    Ok(())
+}
+
+/// Writes the transactions of the blobs in `[start_version, end_version)` into the cache, then
+/// advances the cache head to `end_version` unless it's already past it. Fails if the cache is on
+/// another chain than the file store, or if its head is before `start_version`, since the versions
+/// in between would be missing. With `dry_run`, only the checks are done. Returns the range.
+pub async fn import_into_cache<T: redis::aio::ConnectionLike + Send + Clone>(
+    operator: &dyn FileStoreOperator,
+    cache_operator: &mut CacheOperator<T>,
+    start_version: Option<u64>,
+    end_version: Option<u64>,
+    dry_run: bool,
+) -> Result<(u64, u64)> {
+    let metadata = match operator.get_file_store_metadata().await {
+        Some(metadata) => metadata,
+        None => bail!("The file store has no metadata."),
+    };
+    let cache_chain_id = cache_operator.get_chain_id().await?;
+    if let Some(cache_chain_id) = cache_chain_id {
+        cache_chain_id.ensure_matches("the cache", metadata.chain_id, "the file store")?;
+    }
+    let end_version = end_version.unwrap_or(metadata.version);
+    let blob_size = metadata.blob_size();
+    let start_version = start_version.unwrap_or_else(|| {
+        cache_operator
+            .retention_policy()
+            .low_watermark_version(end_version)
+            .div_ceil(blob_size)
+            * blob_size
+    });
+    ensure!(
+        start_version % blob_size == 0 && end_version % blob_size == 0,
+        "Start and end versions have to be multiples of the blob size {}.",
+        blob_size
+    );
+    ensure!(
+        start_version < end_version && end_version <= metadata.version,
+        "Versions {}-{} are not behind the file store version {}.",
+        start_version,
+        end_version,
+        metadata.version
+    );
+    let cache_latest_version = cache_operator.get_latest_version().await?.unwrap_or(0);
+    ensure!(
+        cache_latest_version == 0 || cache_latest_version >= start_version,
+        "The cache ends at version {}; importing from {} would leave a gap.",
+        cache_latest_version,
+        start_version
+    );
+    tracing::info!(
+        chain_id = metadata.chain_id.0,
+        start_version = start_version,
+        end_version = end_version,
+        blob_count = (end_version - start_version) / blob_size,
+        cache_latest_version = cache_latest_version,
+        storage_format = ?cache_operator.storage_format(),
+        dry_run = dry_run,
+        "[Cache import] Importing the versions into the cache."
+    );
+    if dry_run {
+        return Ok((start_version, end_version));
+    }
+
+    if cache_chain_id.is_none() {
+        cache_operator.set_chain_id(metadata.chain_id).await?;
+    }
+    let import_start_time = Instant::now();
+    for (index, version) in (start_version..end_version)
+        .step_by(blob_size as usize)
+        .enumerate()
+    {
+        let transactions = operator
+            .get_transactions(version, IMPORT_DOWNLOAD_RETRIES)
+            .await?;
+        verify_blob(&transactions, version, blob_size)?;
+        cache_operator
+            .update_cache_transactions(transactions)
+            .await?;
+        let imported_blobs = index as u64 + 1;
+        if imported_blobs % PROGRESS_LOG_INTERVAL_IN_BLOBS == 0 {
+            let next_version = version + blob_size;
+            tracing::info!(
+                next_version = next_version,
+                end_version = end_version,
+                tps = (next_version - start_version) as f64
+                    / import_start_time.elapsed().as_secs_f64(),
+                "[Cache import] Imported blobs."
+            );
+        }
+    }
+    // Only moves the head forward, so a cache that is already ahead keeps serving its versions.
+    if cache_latest_version < end_version {
+        cache_operator
+            .update_cache_latest_version(end_version - cache_latest_version, end_version)
+            .await?;
+    }
+    let file_store_latest_version = cache_operator.get_file_store_latest_version().await?;
+    if file_store_latest_version.map_or(true, |version| version < metadata.version) {
+        cache_operator
+            .update_file_store_latest_version(metadata.version)
+            .await?;
+    }
+    Ok((start_version, end_version))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aptos_indexer_grpc_utils::{
+        compression_util::FILE_ENTRY_TRANSACTION_COUNT,
+        file_store_operator::InMemoryFileStoreOperator, types::ChainId,
+    };
+    use aptos_protos::transaction::v1::Transaction;
+    use redis_test::{MockCmd, MockRedisConnection};
+
+    async fn file_store(blob_count: u64) -> InMemoryFileStoreOperator {
+        let mut operator = InMemoryFileStoreOperator::new(false, None);
+        for i in 0..blob_count {
+            let transactions = (i * FILE_ENTRY_TRANSACTION_COUNT
+                ..(i + 1) * FILE_ENTRY_TRANSACTION_COUNT)
+                .map(|version| Transaction {
+                    version,
+                    ..Default::default()
+                })
+                .collect();
+            operator
+                .upload_transaction_batch(ChainId(1), transactions)
+                .await
+                .unwrap();
+        }
+        operator
+            .update_file_store_metadata_with_timeout(
+                ChainId(1),
+                blob_count * FILE_ENTRY_TRANSACTION_COUNT,
+            )
+            .await
+            .unwrap();
+        operator
+    }
+
+    fn mock_cache(
+        chain_id: Option<u64>,
+        latest_version: Option<u64>,
+    ) -> CacheOperator<MockRedisConnection> {
+        let value = |value: Option<u64>| match value {
+            Some(value) => redis::Value::Data(value.to_string().into_bytes()),
+            None => redis::Value::Nil,
+        };
+        CacheOperator::new(
+            MockRedisConnection::new(vec![
+                MockCmd::new(redis::cmd("GET").arg("chain_id"), Ok(value(chain_id))),
+                MockCmd::new(
+                    redis::cmd("GET").arg("latest_version"),
+                    Ok(value(latest_version)),
+                ),
+            ]),
+            StorageFormat::Base64UncompressedProto,
+        )
+    }
+
+    #[tokio::test]
+    async fn dry_run_only_checks_the_range() {
+        let operator = file_store(3).await;
+        // Nothing is written to the cache, which has no scripted responses past the reads.
+        let mut cache_operator = mock_cache(None, None);
+        assert_eq!(
+            import_into_cache(&operator, &mut cache_operator, None, None, true)
+                .await
+                .unwrap(),
+            (0, 3_000)
+        );
+        let mut cache_operator = mock_cache(Some(1), Some(2_000));
+        assert_eq!(
+            import_into_cache(
+                &operator,
+                &mut cache_operator,
+                Some(1_000),
+                Some(2_000),
+                true
+            )
+            .await
+            .unwrap(),
+            (1_000, 2_000)
+        );
+    }
+
+    #[tokio::test]
+    async fn cache_on_another_chain_is_refused() {
+        let operator = file_store(1).await;
+        let mut cache_operator = mock_cache(Some(2), Some(0));
+        let err = import_into_cache(&operator, &mut cache_operator, None, None, false)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("Chain ID mismatch"), "{:?}", err);
+    }
+
+    #[tokio::test]
+    async fn gap_before_the_range_is_refused() {
+        let operator = file_store(3).await;
+        let mut cache_operator = mock_cache(Some(1), Some(1_000));
+        assert!(
+            import_into_cache(&operator, &mut cache_operator, Some(2_000), None, false)
+                .await
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,213 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::metrics::CACHE_REPLICA_READ_SKIP_COUNT;
+use anyhow::Result;
+use aptos_indexer_grpc_utils::cache_operator::CacheOperator;
+use aptos_protos::transaction::v1::Transaction;
+use futures::{future::BoxFuture, FutureExt};
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+/// CacheReader spreads transaction reads round-robin across the read replicas of the cache.
+/// Replicas that fail or lag behind the requested versions are skipped; if none can serve the
+/// read, it falls back to the primary.
+#[derive(Clone)]
+pub struct CacheReader<T: redis::aio::ConnectionLike + Send> {
+    primary: CacheOperator<T>,
+    replicas: Vec<CacheOperator<T>>,
+    next_replica: Arc<AtomicUsize>,
+}
+
+impl<T: redis::aio::ConnectionLike + Send + Clone> CacheReader<T> {
+    pub fn new(primary: CacheOperator<T>, replicas: Vec<CacheOperator<T>>) -> Self {
+        Self {
+            primary,
+            replicas,
+            next_replica: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Replaces the primary, e.g., after reconnecting to it.
+    pub fn set_primary(&mut self, primary: CacheOperator<T>) {
+        self.primary = primary;
+    }
+
+    /// Fails if not all transactions requested are returned.
+    pub async fn get_transactions(
+        &mut self,
+        start_version: u64,
+        transaction_count: u64,
+    ) -> Result<Vec<Transaction>> {
+        self.read(start_version, transaction_count, move |cache_operator| {
+            cache_operator
+                .get_transactions(start_version, transaction_count)
+                .boxed()
+        })
+        .await
+    }
+
+    /// Same as `get_transactions`, without decoding the transactions.
+    pub async fn get_encoded_transactions(
+        &mut self,
+        start_version: u64,
+        transaction_count: u64,
+    ) -> Result<Vec<Vec<u8>>> {
+        self.read(start_version, transaction_count, move |cache_operator| {
+            cache_operator
+                .get_encoded_transactions(start_version, transaction_count)
+                .boxed()
+        })
+        .await
+    }
+
+    /// Reads the versions with `read` from the next replica that has them, or the primary.
+    async fn read<R>(
+        &mut self,
+        start_version: u64,
+        transaction_count: u64,
+        read: impl for<'a> Fn(&'a mut CacheOperator<T>) -> BoxFuture<'a, Result<R>>,
+    ) -> Result<R> {
+        let end_version = start_version + transaction_count;
+        for _ in 0..self.replicas.len() {
+            let index = self.next_replica.fetch_add(1, Ordering::Relaxed) % self.replicas.len();
+            let replica = &mut self.replicas[index];
+            match replica.get_latest_version().await {
+                Ok(Some(latest_version)) if latest_version >= end_version => {},
+                Ok(latest_version) => {
+                    CACHE_REPLICA_READ_SKIP_COUNT
+                        .with_label_values(&["lagging"])
+                        .inc();
+                    tracing::debug!(
+                        replica = index,
+                        start_version = start_version,
+                        replica_latest_version = ?latest_version,
+                        "[Filestore] Cache replica is lagging behind; skipping it."
+                    );
+                    continue;
+                },
+                Err(err) => {
+                    CACHE_REPLICA_READ_SKIP_COUNT
+                        .with_label_values(&["error"])
+                        .inc();
+                    tracing::warn!(
+                        replica = index,
+                        error = ?err,
+                        "[Filestore] Cache replica is unreachable; skipping it."
+                    );
+                    continue;
+                },
+            }
+            match read(replica).await {
+                Ok(transactions) => return Ok(transactions),
+                Err(err) => {
+                    CACHE_REPLICA_READ_SKIP_COUNT
+                        .with_label_values(&["error"])
+                        .inc();
+                    tracing::warn!(
+                        replica = index,
+                        start_version = start_version,
+                        error = ?err,
+                        "[Filestore] Failed to read from cache replica; skipping it."
+                    );
+                },
+            }
+        }
+        read(&mut self.primary).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aptos_indexer_grpc_utils::compression_util::{CacheEntry, StorageFormat};
+    use prost::Message;
+    use redis_test::{MockCmd, MockRedisConnection};
+
+    const STORAGE_FORMAT: StorageFormat = StorageFormat::Base64UncompressedProto;
+
+    fn cache_operator(cmds: Vec<MockCmd>) -> CacheOperator<MockRedisConnection> {
+        CacheOperator::new(MockRedisConnection::new(cmds), STORAGE_FORMAT)
+    }
+
+    fn latest_version_cmd(latest_version: u64) -> MockCmd {
+        MockCmd::new(
+            redis::cmd("GET").arg("latest_version"),
+            Ok(latest_version.to_string()),
+        )
+    }
+
+    fn mget_cmd(start_version: u64, transaction_count: u64) -> MockCmd {
+        let versions = start_version..start_version + transaction_count;
+        let keys: Vec<String> = versions
+            .clone()
+            .map(|version| CacheEntry::build_key(version, STORAGE_FORMAT))
+            .collect();
+        let values = versions
+            .map(|version| {
+                let transaction = Transaction {
+                    version,
+                    ..Default::default()
+                };
+                redis::Value::Data(
+                    CacheEntry::from_transaction(transaction, STORAGE_FORMAT).into_inner(),
+                )
+            })
+            .collect();
+        MockCmd::new(redis::cmd("MGET").arg(keys), Ok(redis::Value::Bulk(values)))
+    }
+
+    #[tokio::test]
+    async fn reads_are_spread_across_replicas() {
+        let mut cache_reader = CacheReader::new(cache_operator(vec![]), vec![
+            cache_operator(vec![latest_version_cmd(100), mget_cmd(0, 10)]),
+            cache_operator(vec![latest_version_cmd(100), mget_cmd(10, 10)]),
+        ]);
+        assert_eq!(
+            cache_reader.get_transactions(0, 10).await.unwrap().len(),
+            10
+        );
+        assert_eq!(
+            cache_reader.get_transactions(10, 10).await.unwrap().len(),
+            10
+        );
+    }
+
+    #[tokio::test]
+    async fn failing_replica_falls_back_to_primary() {
+        // The replica has no scripted responses, so every command fails.
+        let mut cache_reader =
+            CacheReader::new(cache_operator(vec![mget_cmd(0, 10)]), vec![cache_operator(
+                vec![],
+            )]);
+        let transactions = cache_reader.get_transactions(0, 10).await.unwrap();
+        assert_eq!(transactions.len(), 10);
+        assert_eq!(transactions.last().unwrap().version, 9);
+    }
+
+    #[tokio::test]
+    async fn encoded_reads_fall_back_to_primary() {
+        let mut cache_reader =
+            CacheReader::new(cache_operator(vec![mget_cmd(0, 10)]), vec![cache_operator(
+                vec![latest_version_cmd(100)],
+            )]);
+        let encoded_transactions = cache_reader.get_encoded_transactions(0, 10).await.unwrap();
+        assert_eq!(encoded_transactions.len(), 10);
+        let transaction = Transaction::decode(encoded_transactions[9].as_slice()).unwrap();
+        assert_eq!(transaction.version, 9);
+    }
+
+    #[tokio::test]
+    async fn lagging_replica_is_skipped() {
+        let mut cache_reader =
+            CacheReader::new(cache_operator(vec![mget_cmd(0, 10)]), vec![cache_operator(
+                vec![latest_version_cmd(5)],
+            )]);
+        assert_eq!(
+            cache_reader.get_transactions(0, 10).await.unwrap().len(),
+            10
+        );
+    }
+}
@@ -0,0 +1,109 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::CircuitBreakerConfig;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum CircuitState {
+    /// Operations go through.
+    Closed,
+    /// Too many consecutive failures; operations are held off until the cooldown ends.
+    Open,
+    /// The cooldown ended; the next operation probes whether the dependency recovered.
+    HalfOpen,
+}
+
+/// CircuitBreaker opens after `failure_threshold` consecutive failures and stays open for
+/// `cooldown`, so a struggling dependency isn't hammered with retries.
+pub struct CircuitBreaker {
+    failure_threshold: u32,
+    cooldown: Duration,
+    consecutive_failures: u32,
+    opened_at: Option<Instant>,
+}
+
+impl CircuitBreaker {
+    pub fn new(config: &CircuitBreakerConfig) -> Self {
+        Self {
+            failure_threshold: config.failure_threshold,
+            cooldown: Duration::from_millis(config.cooldown_in_millis),
+            consecutive_failures: 0,
+            opened_at: None,
+        }
+    }
+
+    pub fn state(&self) -> CircuitState {
+        match self.opened_at {
+            None => CircuitState::Closed,
+            Some(opened_at) if opened_at.elapsed() < self.cooldown => CircuitState::Open,
+            Some(_) => CircuitState::HalfOpen,
+        }
+    }
+
+    /// Time left until the breaker half-opens; `None` if it isn't open.
+    pub fn remaining_cooldown(&self) -> Option<Duration> {
+        self.opened_at
+            .and_then(|opened_at| self.cooldown.checked_sub(opened_at.elapsed()))
+            .filter(|remaining| !remaining.is_zero())
+    }
+
+    pub fn record_success(&mut self) {
+        self.consecutive_failures = 0;
+        self.opened_at = None;
+    }
+
+    /// Records a failure; a failed probe in half-open state opens the breaker again.
+    pub fn record_failure(&mut self) {
+        self.consecutive_failures = self.consecutive_failures.saturating_add(1);
+        if self.consecutive_failures >= self.failure_threshold {
+            self.opened_at = Some(Instant::now());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn circuit_breaker(cooldown_in_millis: u64) -> CircuitBreaker {
+        CircuitBreaker::new(&CircuitBreakerConfig {
+            failure_threshold: 3,
+            cooldown_in_millis,
+        })
+    }
+
+    #[test]
+    fn breaker_opens_after_consecutive_failures() {
+        let mut breaker = circuit_breaker(60_000);
+        breaker.record_failure();
+        breaker.record_failure();
+        assert_eq!(breaker.state(), CircuitState::Closed);
+        // A success resets the count.
+        breaker.record_success();
+        breaker.record_failure();
+        breaker.record_failure();
+        assert_eq!(breaker.state(), CircuitState::Closed);
+        breaker.record_failure();
+        assert_eq!(breaker.state(), CircuitState::Open);
+        assert!(breaker.remaining_cooldown().unwrap() <= Duration::from_secs(60));
+    }
+
+    #[test]
+    fn breaker_half_opens_after_cooldown_and_recovers() {
+        let mut breaker = circuit_breaker(10);
+        for _ in 0..3 {
+            breaker.record_failure();
+        }
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+        assert_eq!(breaker.remaining_cooldown(), None);
+
+        // A failed probe opens the breaker again.
+        breaker.record_failure();
+        assert_eq!(breaker.state(), CircuitState::Open);
+        std::thread::sleep(Duration::from_millis(20));
+        breaker.record_success();
+        assert_eq!(breaker.state(), CircuitState::Closed);
+    }
+}
@@ -0,0 +1,436 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::migration::{read_checkpoint, verify_blob, write_checkpoint, MigrationCheckpoint};
+use anyhow::{bail, ensure, Context, Result};
+use aptos_indexer_grpc_server_framework::load;
+use aptos_indexer_grpc_utils::{
+    config::IndexerGrpcFileStoreConfig, file_store_operator::FileStoreOperator, types::ChainId,
+};
+use aptos_protos::transaction::v1::Transaction;
+use clap::Parser;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+// Number of retries when reading a blob.
+const COMPACTION_DOWNLOAD_RETRIES: u8 = 3;
+
+/// Rewrites the blobs of a version range in the canonical size and storage format of a file
+/// store, then deletes their copies in legacy formats.
+#[derive(Clone, Debug, Parser)]
+pub struct CompactArgs {
+    /// Path to the compaction config, with the file store and its legacy copies.
+    #[clap(short, long, value_parser)]
+    pub config_path: PathBuf,
+    /// First version to compact; a multiple of the blob size, 1000 by default.
+    #[clap(long, default_value_t = 0)]
+    pub start_version: u64,
+    /// Version to stop at, exclusive; a multiple of the blob size. Defaults to the file store
+    /// version.
+    #[clap(long)]
+    pub end_version: Option<u64>,
+    /// Number of blobs compacted concurrently.
+    #[clap(long, default_value_t = 10)]
+    pub parallelism: usize,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct FileStoreCompactionConfig {
+    // The compacted file store; blobs are rewritten in its storage format.
+    pub file_store_config: IndexerGrpcFileStoreConfig,
+    // Copies of the blobs in other storage formats, e.g., left in the bucket by a migration. Blobs
+    // missing from the file store are read from them, and they are deleted once compacted.
+    #[serde(default)]
+    pub legacy_file_store_configs: Vec<IndexerGrpcFileStoreConfig>,
+    // Local file recording the progress, so an interrupted compaction resumes where it stopped.
+    pub checkpoint_path: PathBuf,
+}
+
+#[derive(Debug, Default, Eq, PartialEq)]
+pub struct CompactionReport {
+    // Blobs written in the canonical encoding, e.g., read from a legacy file store.
+    pub rewritten_blob_count: usize,
+    // Blobs already in the canonical encoding.
+    pub unchanged_blob_count: usize,
+    pub deleted_legacy_blob_count: usize,
+}
+
+pub async fn run_compaction(args: CompactArgs) -> Result<()> {
+    let config: FileStoreCompactionConfig = load(&args.config_path)?;
+    let mut operator = config.file_store_config.create();
+    operator.verify_storage_bucket_existence().await;
+    let mut legacy_operators = vec![];
+    for legacy_config in &config.legacy_file_store_configs {
+        let legacy_operator = legacy_config.create();
+        legacy_operator.verify_storage_bucket_existence().await;
+        legacy_operators.push(legacy_operator);
+    }
+    let report = compact_file_store(
+        operator.as_mut(),
+        &mut legacy_operators,
+        args.start_version,
+        args.end_version,
+        args.parallelism,
+        &config.checkpoint_path,
+    )
+    .await?;
+    tracing::info!(
+        rewritten_blob_count = report.rewritten_blob_count,
+        unchanged_blob_count = report.unchanged_blob_count,
+        deleted_legacy_blob_count = report.deleted_legacy_blob_count,
+        "[File store compaction] Compaction is done."
+    );
+    Ok(())
+}
+
+/// Compacts the blobs in `[start_version, end_version)`, `parallelism` at a time. The range has
+/// to be behind the file store version, which the live writer never writes below, and legacy
+/// copies of a round are only deleted once every blob of the round is verified.
+pub async fn compact_file_store(
+    operator: &mut dyn FileStoreOperator,
+    legacy_operators: &mut [Box<dyn FileStoreOperator>],
+    start_version: u64,
+    end_version: Option<u64>,
+    parallelism: usize,
+    checkpoint_path: &Path,
+) -> Result<CompactionReport> {
+    ensure!(parallelism > 0, "Parallelism has to be positive.");
+    for legacy_operator in legacy_operators.iter() {
+        // Deleting a legacy copy in the same format would delete the compacted blob.
+        ensure!(
+            legacy_operator.storage_format() != operator.storage_format(),
+            "Legacy file stores have to be in another storage format than {:?}.",
+            operator.storage_format()
+        );
+    }
+    let metadata = match operator.get_file_store_metadata().await {
+        Some(metadata) => metadata,
+        None => bail!("The file store has no metadata."),
+    };
+    let blob_size = metadata.blob_size();
+    let end_version = end_version.unwrap_or(metadata.version);
+    ensure!(
+        start_version % blob_size == 0 && end_version % blob_size == 0,
+        "Start and end versions have to be multiples of {}.",
+        blob_size
+    );
+    ensure!(
+        start_version <= end_version && end_version <= metadata.version,
+        "Versions {}-{} are not behind the file store version {}.",
+        start_version,
+        end_version,
+        metadata.version
+    );
+
+    let mut next_version = match read_checkpoint(checkpoint_path)? {
+        Some(checkpoint) => {
+            ensure!(
+                checkpoint.start_version == start_version && checkpoint.end_version == end_version,
+                "The checkpoint at {:?} is for versions {}-{}; remove it to compact {}-{}.",
+                checkpoint_path,
+                checkpoint.start_version,
+                checkpoint.end_version,
+                start_version,
+                end_version
+            );
+            checkpoint.next_version
+        },
+        None => start_version,
+    };
+
+    let mut report = CompactionReport::default();
+    let round_size = parallelism as u64 * blob_size;
+    while next_version < end_version {
+        let round_end_version = (next_version + round_size).min(end_version);
+        let legacy_operators_ref: &[Box<dyn FileStoreOperator>] = legacy_operators;
+        let tasks = (next_version..round_end_version)
+            .step_by(blob_size as usize)
+            .map(|version| {
+                let mut operator = operator.clone_box();
+                async move {
+                    compact_blob(
+                        operator.as_mut(),
+                        legacy_operators_ref,
+                        metadata.chain_id,
+                        version,
+                        blob_size,
+                    )
+                    .await
+                }
+            });
+        let compacted_blobs = futures::future::try_join_all(tasks).await?;
+
+        for (version, rewritten, transactions) in compacted_blobs {
+            if rewritten {
+                report.rewritten_blob_count += 1;
+            } else {
+                report.unchanged_blob_count += 1;
+            }
+            for legacy_operator in legacy_operators.iter_mut() {
+                if delete_legacy_blob(legacy_operator.as_mut(), version, &transactions).await? {
+                    report.deleted_legacy_blob_count += 1;
+                }
+            }
+        }
+        next_version = round_end_version;
+        write_checkpoint(checkpoint_path, &MigrationCheckpoint {
+            start_version,
+            end_version,
+            next_version,
+        })?;
+        tracing::info!(
+            next_version = next_version,
+            end_version = end_version,
+            "[File store compaction] Compacted blobs."
+        );
+    }
+    Ok(report)
+}
+
+/// Rewrites the blob of `blob_size` versions at `version` in the canonical encoding, reading it
+/// from the first legacy file store that has it if the file store doesn't. Returns whether the blob
+/// was rewritten, with its transactions.
+async fn compact_blob(
+    operator: &mut dyn FileStoreOperator,
+    legacy_operators: &[Box<dyn FileStoreOperator>],
+    chain_id: ChainId,
+    version: u64,
+    blob_size: u64,
+) -> Result<(u64, bool, Vec<Transaction>)> {
+    let original_bytes = operator.get_raw_file(version).await.ok();
+    let transactions = match original_bytes {
+        Some(_) => operator
+            .get_transactions(version, COMPACTION_DOWNLOAD_RETRIES)
+            .await
+            .with_context(|| format!("Failed to read the blob at {}", version))?,
+        None => read_legacy_blob(legacy_operators, version).await?,
+    };
+    verify_blob(&transactions, version, blob_size)
+        .context("The blob is invalid; not compacting it")?;
+
+    // A no-op if the blob is already in the canonical encoding.
+    operator
+        .upload_transaction_batch(chain_id, transactions.clone())
+        .await?;
+    let compacted_bytes = operator
+        .get_raw_file_with_retries(version, COMPACTION_DOWNLOAD_RETRIES)
+        .await
+        .with_context(|| format!("Failed to read back the compacted blob at {}", version))?;
+    let compacted_transactions = operator
+        .get_transactions(version, COMPACTION_DOWNLOAD_RETRIES)
+        .await?;
+    ensure!(
+        compacted_transactions == transactions,
+        "The compacted blob at {} holds different transactions than the original",
+        version
+    );
+    ensure!(
+        operator.verify_blob_digest(version).await? != Some(false),
+        "The compacted blob at {} mismatches its digest",
+        version
+    );
+    let rewritten = original_bytes.as_ref() != Some(&compacted_bytes);
+    Ok((version, rewritten, transactions))
+}
+
+async fn read_legacy_blob(
+    legacy_operators: &[Box<dyn FileStoreOperator>],
+    version: u64,
+) -> Result<Vec<Transaction>> {
+    for legacy_operator in legacy_operators {
+        if let Ok(transactions) = legacy_operator
+            .get_transactions(version, COMPACTION_DOWNLOAD_RETRIES)
+            .await
+        {
+            return Ok(transactions);
+        }
+    }
+    bail!(
+        "The blob at {} is missing from the file store and its legacy copies",
+        version
+    )
+}
+
+/// Deletes the legacy copy of the blob at `version`, once checked it holds the compacted
+/// transactions. Returns whether there was a copy; one that can't be read is kept.
+async fn delete_legacy_blob(
+    legacy_operator: &mut dyn FileStoreOperator,
+    version: u64,
+    transactions: &[Transaction],
+) -> Result<bool> {
+    let legacy_transactions = match legacy_operator.get_transactions(version, 0).await {
+        Ok(legacy_transactions) => legacy_transactions,
+        Err(_) => return Ok(false),
+    };
+    ensure!(
+        legacy_transactions == transactions,
+        "The legacy blob at {} holds different transactions; not deleting it",
+        version
+    );
+    legacy_operator.delete_blob(version).await?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aptos_indexer_grpc_utils::{
+        compression_util::FILE_ENTRY_TRANSACTION_COUNT,
+        file_store_operator::{blob_transactions, InMemoryFileStoreOperator},
+    };
+
+    async fn upload_blobs(operator: &mut InMemoryFileStoreOperator, blobs: std::ops::Range<u64>) {
+        for i in blobs {
+            operator
+                .upload_transaction_batch(
+                    ChainId(1),
+                    blob_transactions(i * FILE_ENTRY_TRANSACTION_COUNT),
+                )
+                .await
+                .unwrap();
+        }
+    }
+
+    // A zstd file store with the first 3 of 5 blobs; the others, and blob 2, are in a legacy
+    // gzip copy.
+    async fn file_stores() -> (InMemoryFileStoreOperator, Box<dyn FileStoreOperator>) {
+        let mut operator = InMemoryFileStoreOperator::new(true, Some(3));
+        upload_blobs(&mut operator, 0..3).await;
+        operator
+            .update_file_store_metadata_with_timeout(ChainId(1), 5 * FILE_ENTRY_TRANSACTION_COUNT)
+            .await
+            .unwrap();
+        let mut legacy_operator = InMemoryFileStoreOperator::new(true, None);
+        upload_blobs(&mut legacy_operator, 2..5).await;
+        (operator, Box::new(legacy_operator))
+    }
+
+    #[tokio::test]
+    async fn compaction_preserves_the_transaction_sequence_and_is_safe_to_rerun() {
+        let (mut operator, legacy_operator) = file_stores().await;
+        let mut legacy_operators = vec![legacy_operator.clone_box()];
+        let checkpoint_dir = tempfile::tempdir().unwrap();
+        let checkpoint_path = checkpoint_dir.path().join("checkpoint.json");
+
+        let report = compact_file_store(
+            &mut operator,
+            &mut legacy_operators,
+            0,
+            None,
+            2,
+            &checkpoint_path,
+        )
+        .await
+        .unwrap();
+        assert_eq!(report, CompactionReport {
+            rewritten_blob_count: 2,
+            unchanged_blob_count: 3,
+            deleted_legacy_blob_count: 3,
+        });
+        let expected: Vec<Transaction> = (0..5)
+            .flat_map(|i| blob_transactions(i * FILE_ENTRY_TRANSACTION_COUNT))
+            .collect();
+        assert_eq!(
+            operator
+                .get_transactions_in_range(0, 5 * FILE_ENTRY_TRANSACTION_COUNT, 0)
+                .await
+                .unwrap(),
+            expected
+        );
+        assert!(legacy_operator.get_transactions(2_000, 0).await.is_err());
+
+        // A rerun of the checkpointed range does nothing; without the checkpoint, it finds every
+        // blob compacted.
+        assert_eq!(
+            compact_file_store(
+                &mut operator,
+                &mut legacy_operators,
+                0,
+                None,
+                2,
+                &checkpoint_path,
+            )
+            .await
+            .unwrap(),
+            CompactionReport::default()
+        );
+        std::fs::remove_file(&checkpoint_path).unwrap();
+        assert_eq!(
+            compact_file_store(
+                &mut operator,
+                &mut legacy_operators,
+                0,
+                None,
+                2,
+                &checkpoint_path,
+            )
+            .await
+            .unwrap(),
+            CompactionReport {
+                unchanged_blob_count: 5,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            operator
+                .get_transactions_in_range(0, 5 * FILE_ENTRY_TRANSACTION_COUNT, 0)
+                .await
+                .unwrap(),
+            expected
+        );
+    }
+
+    #[tokio::test]
+    async fn compaction_stays_behind_the_writer_and_keeps_conflicting_copies() {
+        let (mut operator, legacy_operator) = file_stores().await;
+        let checkpoint_dir = tempfile::tempdir().unwrap();
+        let checkpoint_path = checkpoint_dir.path().join("checkpoint.json");
+
+        // Versions at or after the file store version may still be written by the live writer.
+        assert!(compact_file_store(
+            &mut operator,
+            &mut [legacy_operator.clone_box()],
+            0,
+            Some(6_000),
+            2,
+            &checkpoint_path,
+        )
+        .await
+        .is_err());
+        // A legacy copy in the canonical format would be deleted along with the compacted blob.
+        assert!(compact_file_store(
+            &mut operator,
+            &mut [Box::new(InMemoryFileStoreOperator::new(true, Some(3)))],
+            0,
+            None,
+            2,
+            &checkpoint_path,
+        )
+        .await
+        .is_err());
+
+        // A legacy copy with other transactions than the file store is kept.
+        let mut conflicting_operator = InMemoryFileStoreOperator::new(false, None);
+        let mut conflicting_transactions = blob_transactions(0);
+        conflicting_transactions[10].epoch = 7;
+        conflicting_operator
+            .upload_transaction_batch(ChainId(1), conflicting_transactions.clone())
+            .await
+            .unwrap();
+        assert!(compact_file_store(
+            &mut operator,
+            &mut [conflicting_operator.clone_box()],
+            0,
+            Some(1_000),
+            1,
+            &checkpoint_path,
+        )
+        .await
+        .is_err());
+        assert_eq!(
+            conflicting_operator.get_transactions(0, 0).await.unwrap(),
+            conflicting_transactions
+        );
+    }
+}
@@ -0,0 +1,146 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::processor::UploadVerificationError;
+use aptos_indexer_grpc_utils::file_store_operator::BlobConflictError;
+use std::fmt;
+
+/// Fatal error of the file store processor, by what it takes to recover from it. Each variant
+/// keeps the underlying error, so logs get the same context as before.
+#[derive(Debug)]
+pub enum ProcessorError {
+    /// The configuration can't work, e.g., a starting version that isn't a blob boundary.
+    Config(anyhow::Error),
+    /// The cache, the file store, or another store or fullnode is on another chain.
+    ChainIdMismatch(anyhow::Error),
+    /// Versions to upload are evicted from the cache, and no file store or fullnode has them.
+    CacheEviction(anyhow::Error),
+    /// The cache or the file store failed, e.g., Redis is down or GCS keeps rejecting requests.
+    Storage(anyhow::Error),
+    /// Stored or fetched data isn't what it should be, e.g., a blob holds other transactions or
+    /// the cache has a gap.
+    Integrity(anyhow::Error),
+    /// Another processor holds, or took over, the lease on writing to the file store.
+    Lease(anyhow::Error),
+}
+
+/// What the entrypoint does once the processor stops with a `ProcessorError`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum FailurePolicy {
+    /// Runs the processor again after a backoff, in the same process.
+    RetryWithBackoff,
+    /// Exits with a failure, so that the supervisor restarts the process.
+    CrashLoop,
+    /// Exits with `PERMANENT_FAILURE_EXIT_CODE`; restarting won't help until an operator steps in.
+    ExitPermanently,
+}
+
+/// Exit code of permanent failures, i.e., `EX_CONFIG` of sysexits.h; supervisors can be told not
+/// to restart on it.
+pub const PERMANENT_FAILURE_EXIT_CODE: i32 = 78;
+
+impl ProcessorError {
+    pub fn failure_policy(&self) -> FailurePolicy {
+        match self {
+            ProcessorError::Storage(_) => FailurePolicy::RetryWithBackoff,
+            ProcessorError::CacheEviction(_) | ProcessorError::Lease(_) => FailurePolicy::CrashLoop,
+            ProcessorError::Config(_)
+            | ProcessorError::ChainIdMismatch(_)
+            | ProcessorError::Integrity(_) => FailurePolicy::ExitPermanently,
+        }
+    }
+
+    /// Label of the variant, used in logs.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ProcessorError::Config(_) => "config",
+            ProcessorError::ChainIdMismatch(_) => "chain_id_mismatch",
+            ProcessorError::CacheEviction(_) => "cache_eviction",
+            ProcessorError::Storage(_) => "storage",
+            ProcessorError::Integrity(_) => "integrity",
+            ProcessorError::Lease(_) => "lease",
+        }
+    }
+
+    fn inner(&self) -> &anyhow::Error {
+        match self {
+            ProcessorError::Config(err)
+            | ProcessorError::ChainIdMismatch(err)
+            | ProcessorError::CacheEviction(err)
+            | ProcessorError::Storage(err)
+            | ProcessorError::Integrity(err)
+            | ProcessorError::Lease(err) => err,
+        }
+    }
+}
+
+impl fmt::Display for ProcessorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self.inner(), f)
+    }
+}
+
+impl std::error::Error for ProcessorError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.inner().source()
+    }
+}
+
+/// Errors raised as a `ProcessorError` keep their variant. Others come from the cache and the file
+/// store: data errors are integrity violations, the rest storage failures.
+impl From<anyhow::Error> for ProcessorError {
+    fn from(err: anyhow::Error) -> Self {
+        let err = match err.downcast::<ProcessorError>() {
+            Ok(err) => return err,
+            Err(err) => err,
+        };
+        let is_integrity_violation = err.chain().any(|cause| {
+            cause.is::<UploadVerificationError>()
+                || cause.is::<BlobConflictError>()
+                || cause.is::<prost::DecodeError>()
+                || cause.is::<std::num::ParseIntError>()
+        });
+        if is_integrity_violation {
+            ProcessorError::Integrity(err)
+        } else {
+            ProcessorError::Storage(err)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::{anyhow, Context};
+
+    #[test]
+    fn errors_keep_their_variant_and_context() {
+        let err: anyhow::Error = ProcessorError::ChainIdMismatch(anyhow!(
+            "Chain ID mismatch: the cache has chain id 2, but the file store has chain id 1."
+        ))
+        .into();
+        let err = ProcessorError::from(err);
+        assert_eq!(err.kind(), "chain_id_mismatch");
+        assert_eq!(err.failure_policy(), FailurePolicy::ExitPermanently);
+        assert_eq!(
+            err.to_string(),
+            "Chain ID mismatch: the cache has chain id 2, but the file store has chain id 1."
+        );
+
+        let err = ProcessorError::from(
+            Err::<(), _>(BlobConflictError { version: 1_000 })
+                .context("Failed to upload the batch starting at 1000")
+                .unwrap_err(),
+        );
+        assert_eq!(err.kind(), "integrity");
+        // The context is kept for logs.
+        let err = anyhow::Error::from(err);
+        assert_eq!(
+            format!("{:#}", err),
+            "Failed to upload the batch starting at 1000: [Indexer File] The blob at 1000 already holds different transactions."
+        );
+
+        let err = ProcessorError::from(anyhow!("Redis connection refused"));
+        assert_eq!(err.failure_policy(), FailurePolicy::RetryWithBackoff);
+    }
+}
@@ -0,0 +1,514 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::migration::verify_blob;
+use anyhow::{bail, ensure, Context, Result};
+use aptos_indexer_grpc_server_framework::load;
+use aptos_indexer_grpc_utils::{
+    compression_util::{StorageFormat, FILE_ENTRY_TRANSACTION_COUNT},
+    config::IndexerGrpcFileStoreConfig,
+    file_store_operator::{
+        blob_byte_stream_from_bytes, compute_blob_digest, decode_transaction_stream,
+        FileStoreOperator,
+    },
+    types::ChainId,
+};
+use clap::Parser;
+use futures::{StreamExt, TryStreamExt};
+use serde::{Deserialize, Serialize};
+use std::{
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+// Number of retries when reading a blob.
+const EXPORT_DOWNLOAD_RETRIES: u8 = 3;
+const MANIFEST_FILE_NAME: &str = "manifest.json";
+const BLOBS_DIRECTORY_NAME: &str = "blobs";
+
+/// Writes the blobs of a version range to a local directory, or a tarball, with a manifest, so it
+/// can be shared without access to the file store.
+#[derive(Clone, Debug, Parser)]
+pub struct ExportArgs {
+    /// Path to the config of the file store to export from.
+    #[clap(short, long, value_parser)]
+    pub config_path: PathBuf,
+    /// Directory the blobs and the manifest are written to. An interrupted export resumes from the
+    /// blobs already in it.
+    #[clap(short, long, value_parser)]
+    pub output_path: PathBuf,
+    /// First version to export; a multiple of the blob size, 1000 by default.
+    #[clap(long, default_value_t = 0)]
+    pub start_version: u64,
+    /// Version to stop at, exclusive; a multiple of the blob size. Defaults to the file store
+    /// version.
+    #[clap(long)]
+    pub end_version: Option<u64>,
+    /// Number of blobs downloaded concurrently.
+    #[clap(long, default_value_t = 10)]
+    pub parallelism: usize,
+    /// If set, downloads are slowed down to stay under this many bytes per second on average.
+    #[clap(long)]
+    pub max_bytes_per_second: Option<u64>,
+    /// Also packs the directory into `<output_path>.tar` once the export is verified.
+    #[clap(long)]
+    pub tarball: bool,
+}
+
+/// Describes an export; written as `manifest.json` next to the blobs.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct ExportManifest {
+    pub chain_id: ChainId,
+    pub start_version: u64,
+    // Exclusive.
+    pub end_version: u64,
+    // Blobs are written as stored, i.e., in this format, decrypted.
+    pub storage_format: StorageFormat,
+    // Number of versions of each blob; absent from manifests of exports before it was recorded.
+    #[serde(default = "default_blob_size")]
+    pub blob_size: u64,
+    // Set once every blob is written and verified; an incomplete export is resumed.
+    pub complete: bool,
+    pub blobs: Vec<ExportedBlob>,
+}
+
+fn default_blob_size() -> u64 {
+    FILE_ENTRY_TRANSACTION_COUNT
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct ExportedBlob {
+    pub start_version: u64,
+    // Path of the blob, relative to the manifest.
+    pub path: String,
+    pub size_in_bytes: u64,
+    // Hex encoded SHA-256 digest of the blob.
+    pub sha256: String,
+}
+
+pub async fn run_export(args: ExportArgs) -> Result<()> {
+    let config: IndexerGrpcFileStoreConfig = load(&args.config_path)?;
+    let operator = config.create();
+    operator.verify_storage_bucket_existence().await;
+    let manifest = export_file_store(
+        operator.as_ref(),
+        &args.output_path,
+        args.start_version,
+        args.end_version,
+        args.parallelism,
+        args.max_bytes_per_second,
+    )
+    .await?;
+    if args.tarball {
+        let tarball_path = write_tarball(&args.output_path).await?;
+        tracing::info!(
+            tarball_path = tarball_path.display().to_string(),
+            "[File store export] Tarball is written."
+        );
+    }
+    tracing::info!(
+        start_version = manifest.start_version,
+        end_version = manifest.end_version,
+        blob_count = manifest.blobs.len(),
+        "[File store export] Export is done."
+    );
+    Ok(())
+}
+
+/// Exports the blobs in `[start_version, end_version)` to `output_path`, `parallelism` at a time,
+/// and returns the manifest once every blob is verified. Blobs already in `output_path` from an
+/// interrupted export of the same range are kept rather than downloaded again.
+pub async fn export_file_store(
+    operator: &dyn FileStoreOperator,
+    output_path: &Path,
+    start_version: u64,
+    end_version: Option<u64>,
+    parallelism: usize,
+    max_bytes_per_second: Option<u64>,
+) -> Result<ExportManifest> {
+    ensure!(parallelism > 0, "Parallelism has to be positive.");
+    ensure!(
+        max_bytes_per_second != Some(0),
+        "The bandwidth limit has to be positive."
+    );
+    let metadata = match operator.get_file_store_metadata().await {
+        Some(metadata) => metadata,
+        None => bail!("The file store has no metadata."),
+    };
+    let end_version = end_version.unwrap_or(metadata.version);
+    let blob_size = metadata.blob_size();
+    ensure!(
+        start_version % blob_size == 0 && end_version % blob_size == 0,
+        "Start and end versions have to be multiples of the blob size {}.",
+        blob_size
+    );
+    ensure!(
+        start_version < end_version && end_version <= metadata.version,
+        "Versions {}-{} are not behind the file store version {}.",
+        start_version,
+        end_version,
+        metadata.version
+    );
+
+    let mut manifest = ExportManifest {
+        chain_id: metadata.chain_id,
+        start_version,
+        end_version,
+        storage_format: operator.storage_format(),
+        blob_size,
+        complete: false,
+        blobs: vec![],
+    };
+    let manifest_path = output_path.join(MANIFEST_FILE_NAME);
+    if let Some(previous_manifest) = read_manifest(&manifest_path)? {
+        ensure!(
+            previous_manifest.chain_id == manifest.chain_id
+                && previous_manifest.start_version == start_version
+                && previous_manifest.end_version == end_version
+                && previous_manifest.storage_format == manifest.storage_format
+                && previous_manifest.blob_size == blob_size,
+            "{:?} holds an export of versions {}-{} of chain {}; remove it to export {}-{}.",
+            output_path,
+            previous_manifest.start_version,
+            previous_manifest.end_version,
+            previous_manifest.chain_id,
+            start_version,
+            end_version
+        );
+    }
+    tokio::fs::create_dir_all(output_path.join(BLOBS_DIRECTORY_NAME))
+        .await
+        .with_context(|| format!("Failed to create {:?}", output_path))?;
+    write_manifest(&manifest_path, &manifest)?;
+
+    let bandwidth_limiter = max_bytes_per_second.map(BandwidthLimiter::new);
+    let blobs: Vec<ExportedBlob> =
+        futures::stream::iter((start_version..end_version).step_by(blob_size as usize))
+            .map(|version| export_blob(operator, output_path, version, bandwidth_limiter.as_ref()))
+            .buffered(parallelism)
+            .try_collect()
+            .await?;
+    manifest.blobs = blobs;
+
+    verify_export(output_path, &manifest).await?;
+    manifest.complete = true;
+    write_manifest(&manifest_path, &manifest)?;
+    Ok(manifest)
+}
+
+/// Writes the blob at `version` to the export, unless an earlier run already did, and returns its
+/// manifest entry.
+async fn export_blob(
+    operator: &dyn FileStoreOperator,
+    output_path: &Path,
+    version: u64,
+    bandwidth_limiter: Option<&BandwidthLimiter>,
+) -> Result<ExportedBlob> {
+    let relative_path = blob_path(version);
+    let path = output_path.join(&relative_path);
+    // Blobs are renamed into place once complete, so an existing one is whole.
+    let bytes = match tokio::fs::read(&path).await {
+        Ok(bytes) => bytes,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            let bytes = operator
+                .get_raw_file_with_retries(version, EXPORT_DOWNLOAD_RETRIES)
+                .await
+                .with_context(|| format!("Failed to read the blob at {}", version))?;
+            if let Some(bandwidth_limiter) = bandwidth_limiter {
+                bandwidth_limiter.consume(bytes.len() as u64).await;
+            }
+            if let Some(digest) = operator.get_blob_digest(version).await? {
+                ensure!(
+                    compute_blob_digest(&bytes) == digest,
+                    "The blob at {} mismatches its recorded digest",
+                    version
+                );
+            }
+            let temp_path = path.with_extension("tmp");
+            tokio::fs::write(&temp_path, &bytes)
+                .await
+                .with_context(|| format!("Failed to write {:?}", temp_path))?;
+            tokio::fs::rename(&temp_path, &path)
+                .await
+                .with_context(|| format!("Failed to write {:?}", path))?;
+            bytes
+        },
+        Err(err) => return Err(err).with_context(|| format!("Failed to read {:?}", path)),
+    };
+    Ok(ExportedBlob {
+        start_version: version,
+        path: relative_path,
+        size_in_bytes: bytes.len() as u64,
+        sha256: compute_blob_digest(&bytes),
+    })
+}
+
+/// Checks the export holds every blob of its range, each matching its checksum and decoding to the
+/// versions it's for.
+async fn verify_export(output_path: &Path, manifest: &ExportManifest) -> Result<()> {
+    let expected_versions: Vec<u64> = (manifest.start_version..manifest.end_version)
+        .step_by(manifest.blob_size as usize)
+        .collect();
+    let versions: Vec<u64> = manifest
+        .blobs
+        .iter()
+        .map(|blob| blob.start_version)
+        .collect();
+    ensure!(
+        versions == expected_versions,
+        "The export is missing blobs of versions {}-{}.",
+        manifest.start_version,
+        manifest.end_version
+    );
+    for blob in &manifest.blobs {
+        let bytes = tokio::fs::read(output_path.join(&blob.path))
+            .await
+            .with_context(|| {
+                format!(
+                    "The blob at {} is missing from the export",
+                    blob.start_version
+                )
+            })?;
+        ensure!(
+            compute_blob_digest(&bytes) == blob.sha256,
+            "The exported blob at {} mismatches its checksum",
+            blob.start_version
+        );
+        let transactions: Vec<_> = decode_transaction_stream(
+            blob_byte_stream_from_bytes(bytes),
+            manifest.storage_format,
+            0,
+        )
+        .try_collect()
+        .await?;
+        verify_blob(&transactions, blob.start_version, manifest.blob_size)
+            .with_context(|| format!("The exported blob at {} is invalid", blob.start_version))?;
+    }
+    Ok(())
+}
+
+/// Packs the export directory into `<output_path>.tar`, next to it.
+async fn write_tarball(output_path: &Path) -> Result<PathBuf> {
+    let tarball_path = output_path.with_extension("tar");
+    let (source, destination) = (output_path.to_path_buf(), tarball_path.clone());
+    tokio::task::spawn_blocking(move || -> Result<()> {
+        let temp_path = destination.with_extension("tar.tmp");
+        let mut builder = tar::Builder::new(std::fs::File::create(&temp_path)?);
+        builder.append_dir_all(".", &source)?;
+        builder.into_inner()?.sync_all()?;
+        std::fs::rename(&temp_path, &destination)?;
+        Ok(())
+    })
+    .await
+    .context("Writing the tarball panicked")?
+    .with_context(|| format!("Failed to write {:?}", tarball_path))?;
+    Ok(tarball_path)
+}
+
+fn blob_path(version: u64) -> String {
+    format!("{}/{}", BLOBS_DIRECTORY_NAME, version)
+}
+
+fn read_manifest(path: &Path) -> Result<Option<ExportManifest>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let bytes = std::fs::read(path)
+        .with_context(|| format!("Failed to read the manifest at {:?}", path))?;
+    Ok(Some(serde_json::from_slice(&bytes).with_context(|| {
+        format!("Failed to parse the manifest at {:?}", path)
+    })?))
+}
+
+/// Writes the manifest to a temporary file first, so a crash never leaves a partial manifest.
+fn write_manifest(path: &Path, manifest: &ExportManifest) -> Result<()> {
+    let temp_path = path.with_extension("tmp");
+    std::fs::write(&temp_path, serde_json::to_vec_pretty(manifest)?)
+        .with_context(|| format!("Failed to write the manifest at {:?}", temp_path))?;
+    std::fs::rename(&temp_path, path)
+        .with_context(|| format!("Failed to write the manifest at {:?}", path))
+}
+
+/// Keeps the average download rate under a number of bytes per second, across concurrent
+/// downloads, by delaying the ones that get ahead of it.
+struct BandwidthLimiter {
+    max_bytes_per_second: u64,
+    started_at: Instant,
+    consumed_bytes: AtomicU64,
+}
+
+impl BandwidthLimiter {
+    fn new(max_bytes_per_second: u64) -> Self {
+        Self {
+            max_bytes_per_second,
+            started_at: Instant::now(),
+            consumed_bytes: AtomicU64::new(0),
+        }
+    }
+
+    /// Records `bytes` as downloaded, and waits until the average rate is back under the limit.
+    async fn consume(&self, bytes: u64) {
+        let consumed_bytes = self.consumed_bytes.fetch_add(bytes, Ordering::SeqCst) + bytes;
+        let due = Duration::from_secs_f64(consumed_bytes as f64 / self.max_bytes_per_second as f64);
+        if let Some(delay) = due.checked_sub(self.started_at.elapsed()) {
+            tokio::time::sleep(delay).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aptos_indexer_grpc_utils::file_store_operator::{
+        blob_transactions, InMemoryFileStoreOperator,
+    };
+
+    async fn file_store(blob_count: u64) -> InMemoryFileStoreOperator {
+        let mut operator = InMemoryFileStoreOperator::new(true, None);
+        for i in 0..blob_count {
+            operator
+                .upload_transaction_batch(
+                    ChainId(1),
+                    blob_transactions(i * FILE_ENTRY_TRANSACTION_COUNT),
+                )
+                .await
+                .unwrap();
+        }
+        operator
+            .update_file_store_metadata_with_timeout(
+                ChainId(1),
+                blob_count * FILE_ENTRY_TRANSACTION_COUNT,
+            )
+            .await
+            .unwrap();
+        operator
+    }
+
+    #[tokio::test]
+    async fn version_range_is_exported_with_a_manifest() {
+        let operator = file_store(5).await;
+        let dir = tempfile::tempdir().unwrap();
+        let output_path = dir.path().join("export");
+
+        let manifest = export_file_store(&operator, &output_path, 1_000, Some(4_000), 2, None)
+            .await
+            .unwrap();
+        assert!(manifest.complete);
+        assert_eq!(manifest.chain_id, ChainId(1));
+        assert_eq!(manifest.storage_format, operator.storage_format());
+        assert_eq!(
+            manifest
+                .blobs
+                .iter()
+                .map(|blob| blob.start_version)
+                .collect::<Vec<_>>(),
+            vec![1_000, 2_000, 3_000]
+        );
+        assert_eq!(
+            read_manifest(&output_path.join(MANIFEST_FILE_NAME)).unwrap(),
+            Some(manifest.clone())
+        );
+        let bytes = std::fs::read(output_path.join(&manifest.blobs[0].path)).unwrap();
+        assert_eq!(bytes, operator.get_raw_file(1_000).await.unwrap());
+        assert_eq!(manifest.blobs[0].sha256, compute_blob_digest(&bytes));
+
+        // Another range can't be exported to the same directory.
+        assert!(
+            export_file_store(&operator, &output_path, 0, Some(4_000), 2, None)
+                .await
+                .is_err()
+        );
+        // Neither can versions past the file store version.
+        assert!(export_file_store(
+            &operator,
+            &dir.path().join("other"),
+            0,
+            Some(6_000),
+            2,
+            None
+        )
+        .await
+        .is_err());
+    }
+
+    #[tokio::test]
+    async fn interrupted_export_is_resumed() {
+        let mut operator = file_store(3).await;
+        let dir = tempfile::tempdir().unwrap();
+        let output_path = dir.path().join("export");
+        export_file_store(&operator, &output_path, 0, Some(3_000), 1, None)
+            .await
+            .unwrap();
+        // As if the export stopped after the first blob.
+        std::fs::remove_file(output_path.join(blob_path(1_000))).unwrap();
+        std::fs::remove_file(output_path.join(blob_path(2_000))).unwrap();
+        // The blob already exported isn't read from the file store again.
+        operator.delete_blob(0).await.unwrap();
+
+        let manifest = export_file_store(&operator, &output_path, 0, Some(3_000), 1, None)
+            .await
+            .unwrap();
+        assert!(manifest.complete);
+        assert_eq!(manifest.blobs.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn corrupt_exported_blob_fails_the_verification() {
+        let operator = file_store(2).await;
+        let dir = tempfile::tempdir().unwrap();
+        let output_path = dir.path().join("export");
+        std::fs::create_dir_all(output_path.join(BLOBS_DIRECTORY_NAME)).unwrap();
+        // A blob holding other versions, e.g., left over from a bug.
+        std::fs::write(
+            output_path.join(blob_path(1_000)),
+            operator.get_raw_file(0).await.unwrap(),
+        )
+        .unwrap();
+
+        assert!(
+            export_file_store(&operator, &output_path, 0, Some(2_000), 2, None)
+                .await
+                .is_err()
+        );
+        assert!(
+            !read_manifest(&output_path.join(MANIFEST_FILE_NAME))
+                .unwrap()
+                .unwrap()
+                .complete
+        );
+    }
+
+    #[tokio::test]
+    async fn downloads_are_kept_under_the_bandwidth_limit() {
+        let bandwidth_limiter = BandwidthLimiter::new(10_000);
+        let started_at = Instant::now();
+        bandwidth_limiter.consume(1_000).await;
+        bandwidth_limiter.consume(1_000).await;
+        // 2,000 bytes at 10,000 bytes per second.
+        assert!(started_at.elapsed() >= Duration::from_millis(200));
+    }
+
+    #[tokio::test]
+    async fn export_is_packed_into_a_tarball() {
+        let operator = file_store(1).await;
+        let dir = tempfile::tempdir().unwrap();
+        let output_path = dir.path().join("export");
+        export_file_store(&operator, &output_path, 0, None, 1, None)
+            .await
+            .unwrap();
+
+        let tarball_path = write_tarball(&output_path).await.unwrap();
+        assert_eq!(tarball_path, dir.path().join("export.tar"));
+        let mut archive = tar::Archive::new(std::fs::File::open(&tarball_path).unwrap());
+        let mut paths: Vec<String> = archive
+            .entries()
+            .unwrap()
+            .map(|entry| entry.unwrap().path().unwrap().display().to_string())
+            .map(|path| path.trim_start_matches("./").to_string())
+            .filter(|path| path == MANIFEST_FILE_NAME || path.starts_with("blobs/"))
+            .collect();
+        paths.sort();
+        assert_eq!(paths, vec!["blobs/0", "manifest.json"]);
+    }
+}
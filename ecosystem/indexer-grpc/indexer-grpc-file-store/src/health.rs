@@ -0,0 +1,252 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::HealthServerConfig;
+use serde::{Deserialize, Serialize};
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+use warp::{http::StatusCode, Filter, Reply};
+
+// Marks a timestamp that was never recorded.
+const NEVER: u64 = u64::MAX;
+
+/// Progress of the processor; updated by the run loop and read by the health server.
+/// Timestamps are in milliseconds since the processor started.
+pub struct ProcessorHealth {
+    start_time: Instant,
+    last_iteration_timestamp_in_millis: AtomicU64,
+    last_progress_timestamp_in_millis: AtomicU64,
+    last_upload_timestamp_in_millis: AtomicU64,
+    file_store_version: AtomicU64,
+    cache_latest_version: AtomicU64,
+    // False while the circuit breaker around Redis is open.
+    cache_available: AtomicBool,
+    // Holder and expiry of the writer lease, if the processor holds one.
+    writer_lease: Mutex<Option<(String, Instant)>>,
+}
+
+/// Body of the `/status` endpoint.
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct ProcessorStatus {
+    pub file_store_version: u64,
+    pub cache_latest_version: u64,
+    pub lag: u64,
+    pub uptime_in_secs: u64,
+    pub secs_since_last_progress: Option<u64>,
+    pub secs_since_last_upload: Option<u64>,
+    pub cache_available: bool,
+    pub lease_holder: Option<String>,
+    pub secs_until_lease_expiry: Option<u64>,
+}
+
+impl Default for ProcessorHealth {
+    fn default() -> Self {
+        Self {
+            start_time: Instant::now(),
+            last_iteration_timestamp_in_millis: AtomicU64::new(0),
+            last_progress_timestamp_in_millis: AtomicU64::new(NEVER),
+            last_upload_timestamp_in_millis: AtomicU64::new(NEVER),
+            file_store_version: AtomicU64::new(0),
+            cache_latest_version: AtomicU64::new(0),
+            cache_available: AtomicBool::new(true),
+            writer_lease: Mutex::new(None),
+        }
+    }
+}
+
+impl ProcessorHealth {
+    /// Records a loop iteration, which starts with reading the cache head from Redis.
+    pub fn record_iteration(&self, cache_latest_version: u64, file_store_version: u64) {
+        self.cache_latest_version
+            .store(cache_latest_version, Ordering::Relaxed);
+        self.file_store_version
+            .store(file_store_version, Ordering::Relaxed);
+        self.last_iteration_timestamp_in_millis
+            .store(self.now_in_millis(), Ordering::Relaxed);
+    }
+
+    /// Records that the file store advanced to `file_store_version`, or is caught up with the cache.
+    pub fn record_progress(&self, file_store_version: u64) {
+        self.file_store_version
+            .store(file_store_version, Ordering::Relaxed);
+        self.last_progress_timestamp_in_millis
+            .store(self.now_in_millis(), Ordering::Relaxed);
+    }
+
+    /// Records that uploaded blobs advanced the file store to `file_store_version`.
+    pub fn record_upload(&self, file_store_version: u64) {
+        self.record_progress(file_store_version);
+        self.last_upload_timestamp_in_millis
+            .store(self.now_in_millis(), Ordering::Relaxed);
+    }
+
+    pub fn set_cache_available(&self, cache_available: bool) {
+        self.cache_available
+            .store(cache_available, Ordering::Relaxed);
+    }
+
+    /// Records that the writer lease is held by `holder` until `expires_at`.
+    pub fn record_writer_lease(&self, holder: &str, expires_at: Instant) {
+        *self.writer_lease.lock().unwrap() = Some((holder.to_string(), expires_at));
+    }
+
+    /// The run loop iterated within `timeout`.
+    pub fn is_live(&self, timeout: Duration) -> bool {
+        self.is_recent(&self.last_iteration_timestamp_in_millis, timeout)
+    }
+
+    /// The cache is available and the file store advanced, or was caught up, within `timeout`.
+    pub fn is_ready(&self, timeout: Duration) -> bool {
+        self.cache_available.load(Ordering::Relaxed)
+            && self.is_recent(&self.last_progress_timestamp_in_millis, timeout)
+    }
+
+    pub fn status(&self) -> ProcessorStatus {
+        let file_store_version = self.file_store_version.load(Ordering::Relaxed);
+        let cache_latest_version = self.cache_latest_version.load(Ordering::Relaxed);
+        let writer_lease = self.writer_lease.lock().unwrap().clone();
+        ProcessorStatus {
+            file_store_version,
+            cache_latest_version,
+            lag: cache_latest_version.saturating_sub(file_store_version),
+            uptime_in_secs: self.start_time.elapsed().as_secs(),
+            secs_since_last_progress: self.secs_since(&self.last_progress_timestamp_in_millis),
+            secs_since_last_upload: self.secs_since(&self.last_upload_timestamp_in_millis),
+            cache_available: self.cache_available.load(Ordering::Relaxed),
+            secs_until_lease_expiry: writer_lease.as_ref().map(|(_, expires_at)| {
+                expires_at
+                    .saturating_duration_since(Instant::now())
+                    .as_secs()
+            }),
+            lease_holder: writer_lease.map(|(holder, _)| holder),
+        }
+    }
+
+    fn now_in_millis(&self) -> u64 {
+        self.start_time.elapsed().as_millis() as u64
+    }
+
+    fn secs_since(&self, timestamp_in_millis: &AtomicU64) -> Option<u64> {
+        let timestamp_in_millis = timestamp_in_millis.load(Ordering::Relaxed);
+        (timestamp_in_millis != NEVER)
+            .then(|| self.now_in_millis().saturating_sub(timestamp_in_millis) / 1000)
+    }
+
+    fn is_recent(&self, timestamp_in_millis: &AtomicU64, timeout: Duration) -> bool {
+        let timestamp_in_millis = timestamp_in_millis.load(Ordering::Relaxed);
+        timestamp_in_millis != NEVER
+            && self.now_in_millis().saturating_sub(timestamp_in_millis)
+                <= timeout.as_millis() as u64
+    }
+}
+
+/// Serves `/healthz`, `/readyz` and `/status` on the configured port.
+pub async fn run_health_server(config: HealthServerConfig, health: Arc<ProcessorHealth>) {
+    let port = config.port;
+    warp::serve(health_routes(config, health))
+        .run(([0, 0, 0, 0], port))
+        .await;
+}
+
+fn health_routes(
+    config: HealthServerConfig,
+    health: Arc<ProcessorHealth>,
+) -> impl Filter<Extract = (impl Reply,), Error = warp::Rejection> + Clone {
+    let liveness_timeout = Duration::from_secs(config.liveness_timeout_in_secs);
+    let readiness_timeout = Duration::from_secs(config.readiness_timeout_in_secs);
+
+    let liveness_health = health.clone();
+    let healthz = warp::path("healthz").map(move || {
+        probe_reply(
+            liveness_health.is_live(liveness_timeout),
+            "alive",
+            "stalled",
+        )
+    });
+    let readiness_health = health.clone();
+    let readyz = warp::path("readyz").map(move || {
+        probe_reply(
+            readiness_health.is_ready(readiness_timeout),
+            "ready",
+            "not ready",
+        )
+    });
+    let status = warp::path("status").map(move || warp::reply::json(&health.status()));
+    healthz.or(readyz).or(status)
+}
+
+fn probe_reply(ok: bool, ok_message: &'static str, error_message: &'static str) -> impl Reply {
+    if ok {
+        warp::reply::with_status(ok_message, StatusCode::OK)
+    } else {
+        warp::reply::with_status(error_message, StatusCode::SERVICE_UNAVAILABLE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn health_server_config() -> HealthServerConfig {
+        HealthServerConfig {
+            port: 0,
+            liveness_timeout_in_secs: 60,
+            readiness_timeout_in_secs: 60,
+        }
+    }
+
+    #[test]
+    fn stalled_loop_fails_probes() {
+        let health = ProcessorHealth::default();
+        assert!(health.is_live(Duration::from_secs(60)));
+        // Not ready until the file store made progress once.
+        assert!(!health.is_ready(Duration::from_secs(60)));
+
+        health.record_progress(1_000);
+        assert!(health.is_ready(Duration::from_secs(60)));
+        health.set_cache_available(false);
+        assert!(!health.is_ready(Duration::from_secs(60)));
+        health.set_cache_available(true);
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(!health.is_live(Duration::from_millis(10)));
+        assert!(!health.is_ready(Duration::from_millis(10)));
+    }
+
+    #[tokio::test]
+    async fn routes_report_probes_and_status() {
+        let health = Arc::new(ProcessorHealth::default());
+        let routes = health_routes(health_server_config(), health.clone());
+
+        let response = warp::test::request().path("/readyz").reply(&routes).await;
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        health.record_iteration(5_000, 2_000);
+        health.record_progress(3_000);
+        let response = warp::test::request().path("/healthz").reply(&routes).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = warp::test::request().path("/readyz").reply(&routes).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = warp::test::request().path("/status").reply(&routes).await;
+        let status: ProcessorStatus = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(status.file_store_version, 3_000);
+        assert_eq!(status.cache_latest_version, 5_000);
+        assert_eq!(status.lag, 2_000);
+        assert_eq!(status.secs_since_last_progress, Some(0));
+        // Being caught up is progress, but not an upload.
+        assert_eq!(status.secs_since_last_upload, None);
+        assert!(status.cache_available);
+        assert_eq!(status.lease_holder, None);
+
+        health.record_writer_lease("pod-1:7", Instant::now() + Duration::from_secs(30));
+        let response = warp::test::request().path("/status").reply(&routes).await;
+        let status: ProcessorStatus = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(status.lease_holder.as_deref(), Some("pod-1:7"));
+        assert!(matches!(status.secs_until_lease_expiry, Some(29..=30)));
+    }
+}
@@ -0,0 +1,104 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::metrics::{AHEAD_OF_CACHE_IDLE_RATIO, AHEAD_OF_CACHE_SLEEP_DURATION_IN_SECS};
+use std::time::{Duration, Instant};
+
+const SERVICE_TYPE: &str = "file_worker";
+
+/// IdleTracker accounts for the time the processor sleeps because it's ahead of the cache, i.e.,
+/// caught up. Besides the total, it keeps the fraction of recent time spent idle, where time
+/// counts half as much every `half_life`, so that dashboards tell "caught up and idle" from
+/// "stalled" within a few half-lives.
+pub struct IdleTracker {
+    half_life: Duration,
+    // Decayed seconds spent idle, and in total, up to `last_update`.
+    idle_in_secs: f64,
+    total_in_secs: f64,
+    last_update: Instant,
+    // Start of the ongoing stretch of sleeps, if any.
+    idle_since: Option<Instant>,
+}
+
+impl IdleTracker {
+    pub fn new(half_life: Duration) -> Self {
+        Self {
+            half_life,
+            idle_in_secs: 0.0,
+            total_in_secs: 0.0,
+            last_update: Instant::now(),
+            idle_since: None,
+        }
+    }
+
+    /// Records a sleep ahead of the cache that just ended.
+    pub fn record_sleep(&mut self, duration: Duration) {
+        AHEAD_OF_CACHE_SLEEP_DURATION_IN_SECS.inc_by(duration.as_secs_f64());
+        let now = Instant::now();
+        self.update(now, duration);
+        self.idle_since
+            .get_or_insert(now.checked_sub(duration).unwrap_or(now));
+    }
+
+    /// Records time spent working, e.g., a round of uploads; ends the ongoing stretch of sleeps.
+    pub fn record_work(&mut self) {
+        let now = Instant::now();
+        self.update(now, Duration::ZERO);
+        if let Some(idle_since) = self.idle_since.take() {
+            tracing::info!(
+                idle_duration_in_secs = now.duration_since(idle_since).as_secs_f64(),
+                idle_ratio = self.idle_ratio(),
+                service_type = SERVICE_TYPE,
+                "[Filestore] Cache moved ahead; resuming uploads after idling."
+            );
+        }
+    }
+
+    /// Fraction of recent time spent sleeping ahead of the cache, 0 before anything is recorded.
+    pub fn idle_ratio(&self) -> f64 {
+        if self.total_in_secs > 0.0 {
+            self.idle_in_secs / self.total_in_secs
+        } else {
+            0.0
+        }
+    }
+
+    /// Decays the time recorded so far, then adds the time elapsed since, of which `idle` slept.
+    fn update(&mut self, now: Instant, idle: Duration) {
+        let elapsed = now.saturating_duration_since(self.last_update);
+        let decay = 0.5f64.powf(elapsed.as_secs_f64() / self.half_life.as_secs_f64());
+        self.idle_in_secs = self.idle_in_secs * decay + idle.min(elapsed).as_secs_f64();
+        self.total_in_secs = self.total_in_secs * decay + elapsed.as_secs_f64();
+        self.last_update = now;
+        AHEAD_OF_CACHE_IDLE_RATIO.set(self.idle_ratio());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn idle_ratio_decays_towards_recent_time() {
+        let mut tracker = IdleTracker::new(Duration::from_secs(60));
+        let mut now = tracker.last_update;
+        assert_eq!(tracker.idle_ratio(), 0.0);
+
+        // Caught up: a second of work per nine seconds of sleep.
+        for _ in 0..100 {
+            now += Duration::from_secs(1);
+            tracker.update(now, Duration::ZERO);
+            now += Duration::from_secs(9);
+            tracker.update(now, Duration::from_secs(9));
+        }
+        assert!((tracker.idle_ratio() - 0.9).abs() < 0.02);
+
+        // Stalled: without sleeps, the ratio falls below half within a half-life, then to 0.
+        now += Duration::from_secs(60);
+        tracker.update(now, Duration::ZERO);
+        assert!(tracker.idle_ratio() < 0.45);
+        now += Duration::from_secs(300);
+        tracker.update(now, Duration::ZERO);
+        assert!(tracker.idle_ratio() < 0.01);
+    }
+}
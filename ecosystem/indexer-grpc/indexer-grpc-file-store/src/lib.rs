@@ -1,67 +1,839 @@
 // Copyright © Aptos Foundation
 // SPDX-License-Identifier: Apache-2.0
 
+pub mod batch_fetcher;
+pub mod cache_import;
+pub mod cache_reader;
+pub mod circuit_breaker;
+pub mod compaction;
+pub mod error;
+pub mod export;
+pub mod health;
+pub mod idle_tracker;
 pub mod metrics;
+pub mod migration;
 pub mod processor;
+pub mod status_service;
+pub mod transaction_buffer;
+pub mod transaction_filter;
+pub mod verifier;
+pub mod writer_lease;
 
 use anyhow::Result;
 use aptos_indexer_grpc_server_framework::RunnableConfig;
-use aptos_indexer_grpc_utils::{config::IndexerGrpcFileStoreConfig, types::RedisUrl};
+use aptos_indexer_grpc_utils::{
+    cache_operator::{CacheRetentionPolicy, CACHE_SIZE_ESTIMATION},
+    compression_util::FILE_ENTRY_TRANSACTION_COUNT,
+    config::{check_zstd_compression_level, ensure_no_problems, IndexerGrpcFileStoreConfig},
+    rate_limiter::RateLimitConfig,
+    redis_tls::RedisTlsConfig,
+    types::{ChainId, RedisUrl},
+};
+use backoff::{backoff::Backoff, ExponentialBackoff};
+use error::{FailurePolicy, ProcessorError, PERMANENT_FAILURE_EXIT_CODE};
+use health::run_health_server;
+use migration::run_dual_write;
 use processor::Processor;
 use serde::{Deserialize, Serialize};
+use status_service::run_status_server;
+use std::{
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+use transaction_filter::{TransactionFilter, TransactionFilterConfig};
+use url::Url;
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(deny_unknown_fields)]
 pub struct IndexerGrpcFileStoreWorkerConfig {
     pub file_store_config: IndexerGrpcFileStoreConfig,
     pub redis_main_instance_address: RedisUrl,
+    // Transactions are read round-robin from these replicas; the primary is used if none can serve a read.
+    #[serde(default)]
+    pub redis_read_replica_addresses: Vec<RedisUrl>,
+    // If not empty, the cache is a Redis Cluster reached through these nodes and the main instance.
+    #[serde(default)]
+    pub redis_cluster_seed_addresses: Vec<RedisUrl>,
+    // TLS options of the connections to rediss:// addresses, e.g., the CA of a managed Redis.
+    #[serde(default)]
+    pub redis_tls_config: RedisTlsConfig,
     pub enable_expensive_logging: Option<bool>,
-    pub chain_id: u64,
+    pub chain_id: ChainId,
     #[serde(default = "default_enable_cache_compression")]
     pub enable_cache_compression: bool,
+    // If set, the cache is read as zstd compressed; takes precedence over `enable_cache_compression`.
+    #[serde(default)]
+    pub cache_zstd_compression_level: Option<i32>,
+    // If set, batches are read from the cache as concurrent MGETs of at most this many keys.
+    #[serde(default)]
+    pub cache_mget_chunk_size: Option<usize>,
+    // Retention of the cache; has to match the one of the cache worker.
+    #[serde(default)]
+    pub cache_retention_policy: CacheRetentionPolicy,
+    // If set, a warning is raised once the next batch to upload is this close to cache eviction.
+    #[serde(default)]
+    pub cache_eviction_warning_distance_in_versions: Option<u64>,
+    // If set, every uploaded blob is downloaded and checked before the metadata advances.
+    #[serde(default)]
+    pub verify_after_upload: bool,
+    // If set and the file store is empty, processing starts from this version instead of 0.
+    #[serde(default)]
+    pub starting_version: Option<u64>,
+    // If set, batches evicted from the cache are read back from the file store when they were already uploaded.
+    #[serde(default)]
+    pub recover_evicted_batches_from_file_store: bool,
+    // If set, batches evicted from the cache are read from this already populated file store.
+    #[serde(default)]
+    pub upstream_file_store_config: Option<IndexerGrpcFileStoreConfig>,
+    // If set, versions evicted from the cache that no file store has are skipped, leaving a gap.
+    #[serde(default)]
+    pub allow_gap_on_cache_eviction: bool,
+    // Maximum number of blobs uploaded concurrently; metadata only advances once all of them succeed.
+    #[serde(default = "default_max_concurrent_uploads")]
+    pub max_concurrent_uploads: usize,
+    // If set, the number of blobs uploaded per round follows the observed TPS.
+    #[serde(default)]
+    pub adaptive_batching_config: Option<AdaptiveBatchingConfig>,
+    // If the file store is ahead of the cache head, retry after this duration.
+    #[serde(default = "default_ahead_of_cache_sleep_duration_in_millis")]
+    pub ahead_of_cache_sleep_duration_in_millis: u64,
+    // Up to this much is added to every ahead-of-cache sleep, so processors don't poll Redis in lockstep.
+    #[serde(default = "default_ahead_of_cache_sleep_jitter_in_millis")]
+    pub ahead_of_cache_sleep_jitter_in_millis: u64,
+    // Minimum number of versions available in the cache before a round of uploads starts.
+    #[serde(default = "default_upload_threshold_in_versions")]
+    pub upload_threshold_in_versions: u64,
+    // If set, cache entries are deleted once their versions are persisted in the file store.
+    #[serde(default)]
+    pub cache_eviction_config: Option<CacheEvictionConfig>,
+    // If set, the processor backs off Redis after consecutive failures instead of exiting.
+    #[serde(default)]
+    pub redis_circuit_breaker_config: Option<CircuitBreakerConfig>,
+    // If set, the filtered subset of every uploaded batch is written to this file store as well.
+    #[serde(default)]
+    pub sidecar_file_store_config: Option<SidecarFileStoreConfig>,
+    // If set, transactions that don't pass this filter are stored as sentinels keeping their version.
+    #[serde(default)]
+    pub transaction_filter_config: Option<TransactionFilterConfig>,
+    // If set, every blob is also written to this file store, e.g., with another storage provider.
+    #[serde(default)]
+    pub secondary_file_store_config: Option<SecondaryFileStoreConfig>,
+    // If set, versions already evicted from the cache are streamed from this fullnode instead.
+    #[serde(default)]
+    pub backfill_config: Option<BackfillConfig>,
+    // If set, the metadata and progress are updated at this cadence instead of after every round.
+    #[serde(default)]
+    pub metadata_update_config: Option<MetadataUpdateConfig>,
+    // If set, no batch is fetched from the cache while the fetched and not yet uploaded transactions take
+    // this many bytes, encoded; one batch is always let through.
+    #[serde(default)]
+    pub max_buffered_size_in_bytes: Option<u64>,
+    // If set, batches are fetched ahead of their upload by a separate task, and handed over through a
+    // channel holding up to this many batches, so that slow uploads don't stall fetching.
+    #[serde(default)]
+    pub fetch_channel_capacity_in_batches: Option<usize>,
+    // If set, the processor stops once it has uploaded this many versions, rounded down to a multiple
+    // of the blob size, e.g., to backfill a file store in chunks.
+    #[serde(default)]
+    pub max_versions: Option<u64>,
+    // If set, transactions are read from the cache as their protobuf encodings and written into the
+    // blobs as they are, without decoding and encoding them again; only the last one of every batch
+    // is decoded. Can't be combined with filtering.
+    #[serde(default)]
+    pub enable_raw_transaction_pass_through: bool,
+    // If set, blobs found past the metadata on startup, left by a crash between the uploads of a round
+    // and the metadata update, are adopted or deleted; otherwise, they're uploaded again over themselves.
+    #[serde(default)]
+    pub orphan_blob_policy: Option<OrphanBlobPolicy>,
+    // If set, uploads and metadata writes to the file store are throttled to these rates, e.g., so
+    // that catching up doesn't trigger the write throttling of a bucket shared with other writers.
+    #[serde(default)]
+    pub write_rate_limit_config: Option<RateLimitConfig>,
+    // If set, every blob of the file store is copied to a second file store in its own format.
+    #[serde(default)]
+    pub dual_write_config: Option<DualWriteConfig>,
+    // If set, the processor only writes to the file store while it holds a lease in Redis, so that
+    // a second processor started on the same file store waits instead of writing along.
+    #[serde(default)]
+    pub writer_lease_config: Option<WriterLeaseConfig>,
+    // If set, liveness, readiness and status of the processor are served on this port.
+    #[serde(default)]
+    pub health_server_config: Option<HealthServerConfig>,
+    // If set, the `FileStoreStatus` gRPC service is served on this address.
+    #[serde(default)]
+    pub status_service_listen_address: Option<SocketAddr>,
+}
+
+/// Bounds of the adaptive batching mode, as multipliers of `max_concurrent_uploads`.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct AdaptiveBatchingConfig {
+    #[serde(default = "AdaptiveBatchingConfig::default_min_multiplier")]
+    pub min_multiplier: f64,
+    #[serde(default = "AdaptiveBatchingConfig::default_max_multiplier")]
+    pub max_multiplier: f64,
+}
+
+impl AdaptiveBatchingConfig {
+    pub const fn default_max_multiplier() -> f64 {
+        4.0
+    }
+
+    pub const fn default_min_multiplier() -> f64 {
+        0.1
+    }
+}
+
+/// Eviction of the cache entries that are persisted in the file store.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct CacheEvictionConfig {
+    // Number of versions below the file store version that are kept in the cache for live-streaming consumers.
+    #[serde(default = "CacheEvictionConfig::default_safety_margin_in_versions")]
+    pub safety_margin_in_versions: u64,
+    // Maximum number of keys deleted per round of uploads, so a large backlog doesn't stall Redis.
+    #[serde(default = "CacheEvictionConfig::default_max_evicted_versions_per_round")]
+    pub max_evicted_versions_per_round: u64,
+    // If set, keys are deleted with at most this many DELs, of 1000 versions each, per second.
+    #[serde(default)]
+    pub max_deletes_per_sec: Option<f64>,
+}
+
+impl CacheEvictionConfig {
+    pub const fn default_safety_margin_in_versions() -> u64 {
+        CACHE_SIZE_ESTIMATION
+    }
+
+    pub const fn default_max_evicted_versions_per_round() -> u64 {
+        10_000
+    }
+}
+
+/// Secondary file store receiving the transactions of every uploaded batch that pass the filter.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct SidecarFileStoreConfig {
+    pub file_store_config: IndexerGrpcFileStoreConfig,
+    pub filter: TransactionFilterConfig,
+}
+
+/// Mirror of the file store receiving every uploaded blob, for disaster recovery.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct SecondaryFileStoreConfig {
+    pub file_store_config: IndexerGrpcFileStoreConfig,
+    // If set, uploads to this file store are retried and gate the metadata like the ones to the file
+    // store; otherwise, failures are logged and the missed blobs are copied over later.
+    #[serde(default)]
+    pub strict: bool,
+    // Maximum number of missed blobs copied from the file store per round of uploads.
+    #[serde(default = "SecondaryFileStoreConfig::default_max_catch_up_blobs_per_round")]
+    pub max_catch_up_blobs_per_round: u64,
+}
+
+impl SecondaryFileStoreConfig {
+    pub const fn default_max_catch_up_blobs_per_round() -> u64 {
+        10
+    }
+}
+
+/// Fullnode the versions evicted from the cache are streamed from, e.g., when a new file store
+/// starts far behind the cache.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct BackfillConfig {
+    pub fullnode_grpc_address: Url,
+    // Number of versions requested per stream; the progress is checkpointed after every blob.
+    #[serde(default = "BackfillConfig::default_versions_per_stream")]
+    pub versions_per_stream: u64,
+}
+
+impl BackfillConfig {
+    pub const fn default_versions_per_stream() -> u64 {
+        100_000
+    }
+}
+
+/// Cadence of the metadata and progress updates, to save requests on object stores with
+/// per-operation pricing. Pending updates are written on shutdown.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct MetadataUpdateConfig {
+    // Maximum number of uploaded blobs the metadata lags behind.
+    #[serde(default = "MetadataUpdateConfig::default_max_blobs_between_updates")]
+    pub max_blobs_between_updates: u64,
+    // Maximum time the metadata lags behind the uploaded blobs.
+    #[serde(default = "MetadataUpdateConfig::default_max_interval_in_millis")]
+    pub max_interval_in_millis: u64,
+}
+
+impl MetadataUpdateConfig {
+    pub const fn default_max_blobs_between_updates() -> u64 {
+        10
+    }
+
+    pub const fn default_max_interval_in_millis() -> u64 {
+        10_000
+    }
+}
+
+/// What the processor does on startup with orphan blobs, i.e., blobs past the file store metadata,
+/// uploaded before a crash between the uploads of a round and the metadata update.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum OrphanBlobPolicy {
+    /// Valid orphans are kept and the metadata is advanced past them; invalid ones are deleted.
+    Adopt,
+    /// Orphans are deleted and uploaded again from the cache.
+    Reupload,
+}
+
+/// Second file store kept in sync with the file store, e.g., to move to another storage format
+/// without rebuilding the file store; see `migration::run_dual_write`.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct DualWriteConfig {
+    // The blobs are written in the storage format of this file store.
+    pub destination_file_store_config: IndexerGrpcFileStoreConfig,
+    // How long to wait for new blobs once the destination caught up.
+    #[serde(default = "DualWriteConfig::default_poll_interval_in_millis")]
+    pub poll_interval_in_millis: u64,
+    // Number of blobs migrated concurrently.
+    #[serde(default = "DualWriteConfig::default_parallelism")]
+    pub parallelism: usize,
+}
+
+impl DualWriteConfig {
+    pub const fn default_poll_interval_in_millis() -> u64 {
+        1_000
+    }
+
+    pub const fn default_parallelism() -> usize {
+        10
+    }
+}
+
+/// Lease in Redis on writing to the file store; see `writer_lease::WriterLease`.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct WriterLeaseConfig {
+    // The lease expires unless renewed within this duration; it's renewed every third of it. A
+    // round of uploads still running once it expires is abandoned.
+    #[serde(default = "WriterLeaseConfig::default_lease_duration_in_secs")]
+    pub lease_duration_in_secs: u64,
+    // If set, a processor finding the lease held waits for it; otherwise, it exits.
+    #[serde(default = "WriterLeaseConfig::default_wait_for_lease")]
+    pub wait_for_lease: bool,
+    // Identity of the processor in the lease; defaults to the host name and the process id.
+    #[serde(default)]
+    pub holder_id: Option<String>,
+}
+
+impl WriterLeaseConfig {
+    pub const fn default_lease_duration_in_secs() -> u64 {
+        30
+    }
+
+    pub const fn default_wait_for_lease() -> bool {
+        true
+    }
+}
+
+/// Circuit breaker around Redis operations of the processor.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct CircuitBreakerConfig {
+    // Number of consecutive Redis failures that open the breaker.
+    #[serde(default = "CircuitBreakerConfig::default_failure_threshold")]
+    pub failure_threshold: u32,
+    // How long the breaker stays open before a Redis operation is probed again.
+    #[serde(default = "CircuitBreakerConfig::default_cooldown_in_millis")]
+    pub cooldown_in_millis: u64,
+}
+
+impl CircuitBreakerConfig {
+    pub const fn default_failure_threshold() -> u32 {
+        5
+    }
+
+    pub const fn default_cooldown_in_millis() -> u64 {
+        10_000
+    }
+}
+
+/// HTTP server exposing `/healthz`, `/readyz` and `/status` of the processor.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct HealthServerConfig {
+    pub port: u16,
+    // `/healthz` fails if the run loop didn't iterate for this long.
+    #[serde(default = "HealthServerConfig::default_timeout_in_secs")]
+    pub liveness_timeout_in_secs: u64,
+    // `/readyz` fails if the file store didn't advance, or wasn't caught up, for this long.
+    #[serde(default = "HealthServerConfig::default_timeout_in_secs")]
+    pub readiness_timeout_in_secs: u64,
+}
+
+impl HealthServerConfig {
+    pub const fn default_timeout_in_secs() -> u64 {
+        60
+    }
+}
+
+// Cap of the backoff between runs of the processor after storage failures.
+const MAX_RESTART_BACKOFF_IN_SECS: u64 = 60;
+// Runs lasting at least this long reset the backoff between runs.
+const RESTART_BACKOFF_RESET_IN_SECS: u64 = 600;
+
+fn new_restart_backoff() -> ExponentialBackoff {
+    ExponentialBackoff {
+        max_interval: Duration::from_secs(MAX_RESTART_BACKOFF_IN_SECS),
+        max_elapsed_time: None,
+        ..Default::default()
+    }
+}
+
+/// Acts on the failure policy of `err`: returns after the backoff if the processor is to run
+/// again, otherwise exits the process.
+async fn handle_processor_error(err: ProcessorError, restart_backoff: &mut ExponentialBackoff) {
+    let kind = err.kind();
+    let failure_policy = err.failure_policy();
+    // Logged with the whole chain of causes.
+    let err = anyhow::Error::from(err);
+    match failure_policy {
+        FailurePolicy::RetryWithBackoff => {
+            let delay = restart_backoff
+                .next_backoff()
+                .unwrap_or(Duration::from_secs(MAX_RESTART_BACKOFF_IN_SECS));
+            tracing::warn!(
+                error_kind = kind,
+                backoff_in_millis = delay.as_millis() as u64,
+                error = ?err,
+                "[File worker] File store processor failed; running it again after the backoff."
+            );
+            tokio::time::sleep(delay).await;
+        },
+        FailurePolicy::CrashLoop => {
+            panic!("File store processor exited unexpectedly: {:?}", err);
+        },
+        FailurePolicy::ExitPermanently => {
+            tracing::error!(
+                error_kind = kind,
+                error = ?err,
+                "[File worker] File store processor failed permanently; exiting."
+            );
+            std::process::exit(PERMANENT_FAILURE_EXIT_CODE);
+        },
+    }
 }
 
 const fn default_enable_cache_compression() -> bool {
     false
 }
 
+const fn default_max_concurrent_uploads() -> usize {
+    10
+}
+
+const fn default_ahead_of_cache_sleep_duration_in_millis() -> u64 {
+    100
+}
+
+const fn default_ahead_of_cache_sleep_jitter_in_millis() -> u64 {
+    20
+}
+
+const fn default_upload_threshold_in_versions() -> u64 {
+    FILE_ENTRY_TRANSACTION_COUNT
+}
+
 impl IndexerGrpcFileStoreWorkerConfig {
+    /// Config of a processor uploading the cache at `redis_main_instance_address` to the file store,
+    /// with every option at its default. Other options are set with struct update syntax.
     pub fn new(
         file_store_config: IndexerGrpcFileStoreConfig,
         redis_main_instance_address: RedisUrl,
-        enable_expensive_logging: Option<bool>,
-        chain_id: u64,
-        enable_cache_compression: bool,
+        chain_id: ChainId,
     ) -> Self {
         Self {
             file_store_config,
             redis_main_instance_address,
-            enable_expensive_logging,
+            redis_read_replica_addresses: vec![],
+            redis_cluster_seed_addresses: vec![],
+            redis_tls_config: RedisTlsConfig::default(),
+            enable_expensive_logging: None,
             chain_id,
-            enable_cache_compression,
+            enable_cache_compression: default_enable_cache_compression(),
+            cache_zstd_compression_level: None,
+            cache_mget_chunk_size: None,
+            cache_retention_policy: CacheRetentionPolicy::default(),
+            cache_eviction_warning_distance_in_versions: None,
+            verify_after_upload: false,
+            starting_version: None,
+            recover_evicted_batches_from_file_store: false,
+            upstream_file_store_config: None,
+            allow_gap_on_cache_eviction: false,
+            max_concurrent_uploads: default_max_concurrent_uploads(),
+            adaptive_batching_config: None,
+            ahead_of_cache_sleep_duration_in_millis: default_ahead_of_cache_sleep_duration_in_millis(
+            ),
+            ahead_of_cache_sleep_jitter_in_millis: default_ahead_of_cache_sleep_jitter_in_millis(),
+            upload_threshold_in_versions: default_upload_threshold_in_versions(),
+            cache_eviction_config: None,
+            redis_circuit_breaker_config: None,
+            sidecar_file_store_config: None,
+            transaction_filter_config: None,
+            secondary_file_store_config: None,
+            backfill_config: None,
+            metadata_update_config: None,
+            max_buffered_size_in_bytes: None,
+            fetch_channel_capacity_in_batches: None,
+            max_versions: None,
+            enable_raw_transaction_pass_through: false,
+            orphan_blob_policy: None,
+            write_rate_limit_config: None,
+            dual_write_config: None,
+            writer_lease_config: None,
+            health_server_config: None,
+            status_service_listen_address: None,
         }
     }
 }
 
 #[async_trait::async_trait]
 impl RunnableConfig for IndexerGrpcFileStoreWorkerConfig {
+    /// Reports every problem found at once, before the processor reaches Redis or the file store.
+    fn validate(&self) -> Result<()> {
+        let mut problems = vec![];
+        if self.max_concurrent_uploads == 0 {
+            problems.push("max_concurrent_uploads must be at least 1".to_string());
+        }
+        if self.upload_threshold_in_versions < FILE_ENTRY_TRANSACTION_COUNT {
+            problems.push(format!(
+                "upload_threshold_in_versions must be at least one blob ({} versions)",
+                FILE_ENTRY_TRANSACTION_COUNT
+            ));
+        }
+        if let Some(config) = &self.adaptive_batching_config {
+            if config.min_multiplier <= 0.0 || config.max_multiplier < config.min_multiplier {
+                problems.push(
+                    "adaptive_batching_config requires 0 < min_multiplier <= max_multiplier"
+                        .to_string(),
+                );
+            }
+        }
+        let redis_addresses = std::iter::once(&self.redis_main_instance_address)
+            .chain(&self.redis_read_replica_addresses)
+            .chain(&self.redis_cluster_seed_addresses);
+        for address in redis_addresses {
+            if let Err(err) = address.check() {
+                problems.push(format!("Redis address {} is invalid: {}", address, err));
+            }
+        }
+        if !self.redis_cluster_seed_addresses.is_empty()
+            && !self.redis_read_replica_addresses.is_empty()
+        {
+            problems.push(
+                "redis_read_replica_addresses can't be used with redis_cluster_seed_addresses"
+                    .to_string(),
+            );
+        }
+        if self.redis_tls_config.is_set() && !self.redis_main_instance_address.is_tls() {
+            problems.push(
+                "redis_tls_config requires a rediss:// redis_main_instance_address".to_string(),
+            );
+        }
+        if self.redis_tls_config.ca_cert_path.is_some()
+            && !self.redis_cluster_seed_addresses.is_empty()
+        {
+            problems.push(
+                "redis_tls_config.ca_cert_path can't be used with redis_cluster_seed_addresses"
+                    .to_string(),
+            );
+        }
+        check_zstd_compression_level(
+            &mut problems,
+            "cache_zstd_compression_level",
+            self.cache_zstd_compression_level,
+        );
+        if self.cache_mget_chunk_size == Some(0) {
+            problems.push("cache_mget_chunk_size must be at least 1".to_string());
+        }
+        if let Err(err) = self.cache_retention_policy.validate() {
+            problems.push(format!("Invalid cache_retention_policy: {:#}", err));
+        }
+        if let Some(distance) = self.cache_eviction_warning_distance_in_versions {
+            if distance >= self.cache_retention_policy.retention_in_versions {
+                problems.push("cache_eviction_warning_distance_in_versions must be less than cache_retention_policy.retention_in_versions".to_string());
+            }
+        }
+        if let Some(config) = &self.cache_eviction_config {
+            if config.max_evicted_versions_per_round == 0 {
+                problems.push(
+                    "cache_eviction_config.max_evicted_versions_per_round must be at least 1"
+                        .to_string(),
+                );
+            }
+            if config
+                .max_deletes_per_sec
+                .map_or(false, |rate| rate.is_nan() || rate <= 0.0)
+            {
+                problems.push(
+                    "cache_eviction_config.max_deletes_per_sec must be positive".to_string(),
+                );
+            }
+        }
+        if let Some(config) = &self.redis_circuit_breaker_config {
+            if config.failure_threshold == 0 {
+                problems.push(
+                    "redis_circuit_breaker_config.failure_threshold must be at least 1".to_string(),
+                );
+            }
+        }
+        if let Some(config) = &self.sidecar_file_store_config {
+            if let Err(err) = TransactionFilter::new(&config.filter) {
+                problems.push(format!(
+                    "Invalid sidecar_file_store_config.filter: {:#}",
+                    err
+                ));
+            }
+        }
+        if let Some(config) = &self.transaction_filter_config {
+            if let Err(err) = TransactionFilter::new(config) {
+                problems.push(format!("Invalid transaction_filter_config: {:#}", err));
+            }
+        }
+        if let Some(config) = &self.secondary_file_store_config {
+            if config.max_catch_up_blobs_per_round == 0 {
+                problems.push(
+                    "secondary_file_store_config.max_catch_up_blobs_per_round must be at least 1"
+                        .to_string(),
+                );
+            }
+        }
+        if let Some(config) = &self.backfill_config {
+            // Whether it's a multiple of the blob size is checked at startup, once the file store
+            // metadata is read.
+            if config.versions_per_stream == 0 {
+                problems.push("backfill_config.versions_per_stream must be positive".to_string());
+            }
+        }
+        if let Some(config) = &self.metadata_update_config {
+            if config.max_blobs_between_updates == 0 {
+                problems.push(
+                    "metadata_update_config.max_blobs_between_updates must be at least 1"
+                        .to_string(),
+                );
+            }
+        }
+        if self.max_buffered_size_in_bytes == Some(0) {
+            problems.push("max_buffered_size_in_bytes must be at least 1".to_string());
+        }
+        if self.fetch_channel_capacity_in_batches == Some(0) {
+            problems.push("fetch_channel_capacity_in_batches must be at least 1".to_string());
+        }
+        if let Some(max_versions) = self.max_versions {
+            if max_versions < FILE_ENTRY_TRANSACTION_COUNT {
+                problems.push(format!(
+                    "max_versions must be at least {}, as only whole blobs are uploaded",
+                    FILE_ENTRY_TRANSACTION_COUNT
+                ));
+            }
+        }
+        if self.enable_raw_transaction_pass_through {
+            if self.transaction_filter_config.is_some() {
+                problems.push(
+                    "enable_raw_transaction_pass_through can't be combined with transaction_filter_config, which needs decoded transactions"
+                        .to_string(),
+                );
+            }
+            if self.sidecar_file_store_config.is_some() {
+                problems.push(
+                    "enable_raw_transaction_pass_through can't be combined with sidecar_file_store_config, which needs decoded transactions"
+                        .to_string(),
+                );
+            }
+        }
+        if self.orphan_blob_policy == Some(OrphanBlobPolicy::Adopt)
+            && self.sidecar_file_store_config.is_some()
+        {
+            problems.push(
+                "orphan_blob_policy Adopt can't be combined with sidecar_file_store_config, which may miss the adopted blobs"
+                    .to_string(),
+            );
+        }
+        if let Some(config) = &self.write_rate_limit_config {
+            problems.extend(
+                config
+                    .problems()
+                    .into_iter()
+                    .map(|problem| format!("write_rate_limit_config: {}", problem)),
+            );
+        }
+        if let Some(config) = &self.dual_write_config {
+            if config.parallelism == 0 {
+                problems.push("dual_write_config.parallelism must be at least 1".to_string());
+            }
+        }
+        if let Some(config) = &self.writer_lease_config {
+            if config.lease_duration_in_secs == 0 {
+                problems.push(
+                    "writer_lease_config.lease_duration_in_secs must be at least 1".to_string(),
+                );
+            }
+            if config.holder_id.as_ref().map_or(false, |id| id.is_empty()) {
+                problems.push("writer_lease_config.holder_id is empty".to_string());
+            }
+        }
+        // Problems of the file stores are prefixed with the field they're configured in.
+        let file_store_configs = [
+            Some(("file_store_config", &self.file_store_config)),
+            self.upstream_file_store_config
+                .as_ref()
+                .map(|config| ("upstream_file_store_config", config)),
+            self.sidecar_file_store_config
+                .as_ref()
+                .map(|config| ("sidecar_file_store_config", &config.file_store_config)),
+            self.secondary_file_store_config
+                .as_ref()
+                .map(|config| ("secondary_file_store_config", &config.file_store_config)),
+            self.dual_write_config.as_ref().map(|config| {
+                (
+                    "dual_write_config.destination_file_store_config",
+                    &config.destination_file_store_config,
+                )
+            }),
+        ];
+        for (field, config) in file_store_configs.into_iter().flatten() {
+            problems.extend(
+                config
+                    .problems()
+                    .into_iter()
+                    .map(|problem| format!("{}: {}", field, problem)),
+            );
+            // Only the upstream file store is read from.
+            if config.is_read_only() && field != "upstream_file_store_config" {
+                problems.push(format!("{} is read-only, but it's written to", field));
+            }
+        }
+        ensure_no_problems(&problems)
+    }
+
     async fn run(&self) -> Result<()> {
-        let mut processor = Processor::new(
-            self.redis_main_instance_address.clone(),
-            self.file_store_config.clone(),
-            self.chain_id,
-            self.enable_cache_compression,
-        )
-        .await
-        .expect("Failed to create file store processor");
-        processor
-            .run()
-            .await
-            .expect("File store processor exited unexpectedly");
-        Ok(())
+        let mut restart_backoff = new_restart_backoff();
+        let mut processor = loop {
+            match Processor::new(self).await {
+                Ok(processor) => break processor,
+                Err(err) => handle_processor_error(err.into(), &mut restart_backoff).await,
+            }
+        };
+        if let Some(config) = &self.health_server_config {
+            tokio::spawn(run_health_server(config.clone(), processor.health()));
+        }
+        if let Some(config) = self.dual_write_config.clone() {
+            let source = self.file_store_config.create();
+            let destination = config.destination_file_store_config.create();
+            tokio::spawn(async move {
+                if let Err(err) = run_dual_write(source, destination, config).await {
+                    tracing::error!(error = ?err, "[File worker] Dual write stopped.");
+                }
+            });
+        }
+        if let Some(listen_address) = self.status_service_listen_address {
+            let status_service = processor.status_service();
+            tokio::spawn(async move {
+                if let Err(err) = run_status_server(listen_address, status_service).await {
+                    tracing::error!(error = ?err, "[File worker] Status service stopped.");
+                }
+            });
+        }
+        loop {
+            let started_at = Instant::now();
+            let err = match processor.run().await {
+                Ok(version) => {
+                    tracing::info!(
+                        version = version,
+                        "[File worker] Processed the configured max versions; stopping."
+                    );
+                    return Ok(());
+                },
+                Err(err) => err,
+            };
+            // A processor that ran for a while starts over from the shortest backoff.
+            if started_at.elapsed() >= Duration::from_secs(RESTART_BACKOFF_RESET_IN_SECS) {
+                restart_backoff.reset();
+            }
+            handle_processor_error(err, &mut restart_backoff).await;
+        }
     }
 
     fn get_server_name(&self) -> String {
         "idxfilestore".to_string()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_problem_is_reported_at_once() {
+        let mut config: IndexerGrpcFileStoreWorkerConfig =
+            serde_json::from_value(serde_json::json!({
+                "file_store_config": {
+                    "file_store_type": "GcsFileStore",
+                    "gcs_file_store_bucket_name": "",
+                    "gcs_anonymous_credentials": true,
+                },
+                "redis_main_instance_address": "redis://localhost:6379",
+                "chain_id": 1,
+                "max_concurrent_uploads": 0,
+                "cache_zstd_compression_level": 1000,
+                "upstream_file_store_config": {
+                    "file_store_type": "LocalFileStore",
+                    "local_file_store_path": "relative/file/store",
+                },
+            }))
+            .unwrap();
+        config.redis_read_replica_addresses =
+            vec![RedisUrl(Url::parse("http://localhost:6379").unwrap())];
+
+        let report = config.validate().unwrap_err().to_string();
+        let problems: Vec<_> = report.lines().skip(1).collect();
+        assert_eq!(
+            report.lines().next().unwrap(),
+            "Found 5 configuration problem(s):"
+        );
+        assert_eq!(problems[0], "  - max_concurrent_uploads must be at least 1");
+        assert!(problems[1].starts_with(
+            "  - Redis address http://localhost:6379/ is invalid: Invalid scheme: http"
+        ));
+        assert!(problems[2].starts_with("  - cache_zstd_compression_level is 1000"));
+        assert_eq!(
+            problems[3],
+            "  - file_store_config: gcs_file_store_bucket_name is empty"
+        );
+        assert_eq!(
+            problems[4],
+            "  - upstream_file_store_config: local_file_store_path relative/file/store is relative and doesn't exist; use an absolute path"
+        );
+    }
+
+    #[test]
+    fn read_only_file_stores_are_only_read_from() {
+        let http_file_store = serde_json::json!({
+            "file_store_type": "HttpFileStore",
+            "http_file_store_base_url": "https://archive.example.com/mainnet/",
+        });
+        let config: IndexerGrpcFileStoreWorkerConfig = serde_json::from_value(serde_json::json!({
+            "file_store_config": http_file_store,
+            "redis_main_instance_address": "redis://localhost:6379",
+            "chain_id": 1,
+            "upstream_file_store_config": http_file_store,
+        }))
+        .unwrap();
+        let report = config.validate().unwrap_err().to_string();
+        assert_eq!(report.lines().skip(1).collect::<Vec<_>>(), vec![
+            "  - file_store_config is read-only, but it's written to"
+        ]);
+    }
+}
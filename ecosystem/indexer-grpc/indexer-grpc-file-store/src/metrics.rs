@@ -1,7 +1,12 @@
 // Copyright © Aptos Foundation
 // SPDX-License-Identifier: Apache-2.0
 
-use aptos_metrics_core::{register_int_counter, register_int_gauge, IntCounter, IntGauge};
+use aptos_metrics_core::{
+    exponential_buckets, register_counter, register_gauge, register_gauge_vec,
+    register_histogram_vec, register_int_counter, register_int_counter_vec, register_int_gauge,
+    register_int_gauge_vec, Counter, Gauge, GaugeVec, HistogramVec, IntCounter, IntCounterVec,
+    IntGauge, IntGaugeVec,
+};
 use once_cell::sync::Lazy;
 
 /// Latest version of transactions that have been stored.
@@ -13,6 +18,33 @@ pub static LATEST_PROCESSED_VERSION: Lazy<IntGauge> = Lazy::new(|| {
     .unwrap()
 });
 
+/// Latest version of the cache, i.e., the exclusive upper bound of transactions ready to be stored.
+pub static CACHE_LATEST_VERSION: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "indexer_grpc_file_store_cache_latest_version",
+        "Latest version of the cache as seen by file store",
+    )
+    .unwrap()
+});
+
+/// Number of versions that file store is behind the cache.
+pub static FILE_STORE_LAG_VERSIONS: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "indexer_grpc_file_store_lag_versions",
+        "Number of versions that file store is behind the cache",
+    )
+    .unwrap()
+});
+
+/// Seconds between now and the timestamp of the latest stored transaction, set after every round.
+pub static FILE_STORE_LAG_IN_SECS: Lazy<Gauge> = Lazy::new(|| {
+    register_gauge!(
+        "indexer_grpc_file_store_lag_in_secs",
+        "Seconds between now and the timestamp of the latest stored transaction",
+    )
+    .unwrap()
+});
+
 /// Number of transactions that have been stored.
 pub static PROCESSED_VERSIONS_COUNT: Lazy<IntCounter> = Lazy::new(|| {
     register_int_counter!(
@@ -22,6 +54,46 @@ pub static PROCESSED_VERSIONS_COUNT: Lazy<IntCounter> = Lazy::new(|| {
     .unwrap()
 });
 
+/// Encoded bytes of the blobs uploaded, by storage format.
+pub static UPLOADED_BYTES_COUNT: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "indexer_grpc_file_store_uploaded_bytes",
+        "Encoded bytes of the blobs uploaded",
+        &["storage_format"],
+    )
+    .unwrap()
+});
+
+/// Protobuf encoded bytes of the transactions uploaded, before compression, by storage format.
+pub static UPLOADED_RAW_BYTES_COUNT: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "indexer_grpc_file_store_uploaded_raw_bytes",
+        "Protobuf encoded bytes of the transactions uploaded, before compression",
+        &["storage_format"],
+    )
+    .unwrap()
+});
+
+/// Raw over encoded size of the last blob uploaded, by compressed storage format.
+pub static BLOB_COMPRESSION_RATIO: Lazy<GaugeVec> = Lazy::new(|| {
+    register_gauge_vec!(
+        "indexer_grpc_file_store_blob_compression_ratio",
+        "Raw over encoded size of the last blob uploaded",
+        &["storage_format"],
+    )
+    .unwrap()
+});
+
+/// Encoded size of the last blob uploaded, by storage format.
+pub static LAST_UPLOADED_BLOB_SIZE_IN_BYTES: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "indexer_grpc_file_store_last_uploaded_blob_size_in_bytes",
+        "Encoded size of the last blob uploaded",
+        &["storage_format"],
+    )
+    .unwrap()
+});
+
 /// Number of errors that file store has encountered.
 pub static ERROR_COUNT: Lazy<IntCounter> = Lazy::new(|| {
     register_int_counter!(
@@ -39,3 +111,348 @@ pub static METADATA_UPLOAD_FAILURE_COUNT: Lazy<IntCounter> = Lazy::new(|| {
     )
     .unwrap()
 });
+
+/// Number of failed writes of the processing progress.
+pub static PROGRESS_UPDATE_FAILURE_COUNT: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "indexer_grpc_file_store_progress_update_failures",
+        "Number of failed writes of the processing progress"
+    )
+    .unwrap()
+});
+
+/// Number of orphan blobs found past the metadata on startup, by what was done with them.
+pub static ORPHAN_BLOBS_COUNT: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "indexer_grpc_file_store_orphan_blobs",
+        "Number of blobs found past the file store metadata on startup",
+        &["decision"]
+    )
+    .unwrap()
+});
+
+/// Number of batch upload failures that file store has encountered.
+pub static UPLOAD_FAILURE_COUNT: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "indexer_grpc_file_store_upload_failures",
+        "Number of batch upload failures that file store has encountered"
+    )
+    .unwrap()
+});
+
+/// Number of uploaded batches that failed the read-back verification.
+pub static UPLOAD_VERIFICATION_FAILURE_COUNT: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "indexer_grpc_file_store_upload_verification_failures",
+        "Number of uploaded batches that failed the read-back verification"
+    )
+    .unwrap()
+});
+
+/// Number of batches evicted from the cache that were recovered from a file store, by source.
+pub static RECOVERED_EVICTED_BATCHES_COUNT: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "indexer_grpc_file_store_recovered_evicted_batches",
+        "Number of batches evicted from the cache that were recovered from a file store",
+        &["source"]
+    )
+    .unwrap()
+});
+
+/// Number of versions skipped because they were evicted from the cache and no file store had them.
+pub static SKIPPED_VERSIONS_COUNT: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "indexer_grpc_file_store_skipped_versions",
+        "Number of versions skipped because they were evicted from the cache and no file store had them"
+    )
+    .unwrap()
+});
+
+/// Number of cache keys deleted after their versions were persisted in the file store.
+pub static CACHE_EVICTED_KEYS_COUNT: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "indexer_grpc_file_store_cache_evicted_keys",
+        "Number of cache keys deleted after their versions were persisted in the file store"
+    )
+    .unwrap()
+});
+
+/// Versions below this are evicted from the cache by the file store processor.
+pub static CACHE_EVICTION_WATERMARK: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "indexer_grpc_file_store_cache_eviction_watermark",
+        "Versions below this are evicted from the cache by the file store processor"
+    )
+    .unwrap()
+});
+
+/// Versions between the next batch to upload and the lowest version still in the cache; negative
+/// once the batch is evicted.
+pub static CACHE_EVICTION_DISTANCE_VERSIONS: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "indexer_grpc_file_store_cache_eviction_distance_versions",
+        "Versions between the next batch to upload and the lowest version still in the cache"
+    )
+    .unwrap()
+});
+
+/// 1 if the next batch to upload is within the configured warning distance of cache eviction.
+pub static CACHE_EVICTION_DANGER: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "indexer_grpc_file_store_cache_eviction_danger",
+        "1 if the next batch to upload is within the configured warning distance of cache eviction"
+    )
+    .unwrap()
+});
+
+/// Number of cache batches whose transactions weren't the contiguous versions requested.
+pub static NON_CONTIGUOUS_CACHE_BATCH_COUNT: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "indexer_grpc_file_store_non_contiguous_cache_batches",
+        "Number of cache batches whose transactions weren't the contiguous versions requested"
+    )
+    .unwrap()
+});
+
+/// Number of failed batch reads from the cache, by kind: connection, timeout, decode or response.
+pub static CACHE_BATCH_GET_ERROR_COUNT: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "indexer_grpc_file_store_cache_batch_get_errors",
+        "Number of failed batch reads from the cache",
+        &["kind"]
+    )
+    .unwrap()
+});
+
+/// Number of reads that skipped a cache read replica, by reason.
+pub static CACHE_REPLICA_READ_SKIP_COUNT: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "indexer_grpc_file_store_cache_replica_read_skips",
+        "Number of reads that skipped a cache read replica",
+        &["reason"]
+    )
+    .unwrap()
+});
+
+/// 1 while the circuit breaker around Redis is open, 0 otherwise.
+pub static REDIS_CIRCUIT_BREAKER_OPEN: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "indexer_grpc_file_store_redis_circuit_breaker_open",
+        "1 while the circuit breaker around Redis is open, 0 otherwise"
+    )
+    .unwrap()
+});
+
+/// Number of Redis operation failures recorded by the circuit breaker.
+pub static REDIS_FAILURE_COUNT: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "indexer_grpc_file_store_redis_failures",
+        "Number of Redis operation failures recorded by the circuit breaker"
+    )
+    .unwrap()
+});
+
+/// Number of connections to Redis reopened after the previous one dropped.
+pub static REDIS_RECONNECT_COUNT: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "indexer_grpc_file_store_redis_reconnects",
+        "Number of connections to Redis reopened after the previous one dropped"
+    )
+    .unwrap()
+});
+
+/// Number of transactions written to the sidecar file store after filtering.
+pub static SIDECAR_UPLOADED_TRANSACTIONS_COUNT: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "indexer_grpc_file_store_sidecar_uploaded_transactions",
+        "Number of transactions written to the sidecar file store after filtering"
+    )
+    .unwrap()
+});
+
+/// Number of failed uploads of blobs and metadata to the secondary file store.
+pub static SECONDARY_UPLOAD_FAILURE_COUNT: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "indexer_grpc_file_store_secondary_upload_failures",
+        "Number of failed uploads of blobs and metadata to the secondary file store"
+    )
+    .unwrap()
+});
+
+/// Number of blobs the secondary file store missed that were copied from the file store.
+pub static SECONDARY_CAUGHT_UP_BLOBS_COUNT: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "indexer_grpc_file_store_secondary_caught_up_blobs",
+        "Number of blobs the secondary file store missed that were copied from the file store"
+    )
+    .unwrap()
+});
+
+/// Version of the secondary file store metadata, i.e., every blob before it is in the secondary file store.
+pub static SECONDARY_FILE_STORE_VERSION: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "indexer_grpc_file_store_secondary_version",
+        "Version of the secondary file store metadata"
+    )
+    .unwrap()
+});
+
+/// Number of transactions replaced with sentinels by the file store transaction filter.
+pub static FILTERED_TRANSACTIONS_COUNT: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "indexer_grpc_file_store_filtered_transactions",
+        "Number of transactions replaced with sentinels by the file store transaction filter"
+    )
+    .unwrap()
+});
+
+/// Number of transactions uploaded, by type: user, block_metadata, state_checkpoint, genesis,
+/// validator or unknown.
+pub static UPLOADED_TRANSACTIONS_BY_TYPE_COUNT: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "indexer_grpc_file_store_uploaded_transactions_by_type",
+        "Number of transactions uploaded, by type",
+        &["transaction_type"],
+    )
+    .unwrap()
+});
+
+/// Number of transactions uploaded without a known type, which points at an encoding problem
+/// upstream.
+pub static UNKNOWN_TYPE_TRANSACTIONS_COUNT: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "indexer_grpc_file_store_unknown_type_transactions",
+        "Number of transactions uploaded without a known type"
+    )
+    .unwrap()
+});
+
+/// Latency of uploading a batch of transactions to file store, by store type.
+pub static UPLOAD_LATENCY_IN_SECS: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "indexer_grpc_file_store_upload_latency_in_secs",
+        "Latency of uploading a batch of transactions to file store",
+        &["store_name"],
+        // 50ms to ~50s.
+        exponential_buckets(/*start=*/ 0.05, /*factor=*/ 2.0, /*count=*/ 11).unwrap(),
+    )
+    .unwrap()
+});
+
+/// p99 of the batch upload latency over the last minute, set after every round.
+pub static UPLOAD_LATENCY_P99_IN_SECS: Lazy<GaugeVec> = Lazy::new(|| {
+    register_gauge_vec!(
+        "indexer_grpc_file_store_upload_latency_p99_in_secs",
+        "p99 of the batch upload latency over the last minute",
+        &["store_name"],
+    )
+    .unwrap()
+});
+
+/// Size of the encoded blobs uploaded to file store, by store type.
+pub static UPLOADED_BLOB_SIZE_IN_BYTES: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "indexer_grpc_file_store_uploaded_blob_size_in_bytes",
+        "Size of the encoded blobs uploaded to file store",
+        &["store_name"],
+        // 16KiB to 128MiB.
+        exponential_buckets(/*start=*/ 16_384.0, /*factor=*/ 2.0, /*count=*/ 14).unwrap(),
+    )
+    .unwrap()
+});
+
+/// Duration of an iteration of the processing loop that uploaded batches, by store type.
+pub static LOOP_ITERATION_DURATION_IN_SECS: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "indexer_grpc_file_store_loop_iteration_duration_in_secs",
+        "Duration of an iteration of the processing loop that uploaded batches",
+        &["store_name"],
+        // 50ms to ~50s.
+        exponential_buckets(/*start=*/ 0.05, /*factor=*/ 2.0, /*count=*/ 11).unwrap(),
+    )
+    .unwrap()
+});
+
+/// Number of retries of file store operations, by operation.
+pub static RETRY_COUNT: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "indexer_grpc_file_store_retries",
+        "Number of retries of file store operations",
+        &["operation"],
+    )
+    .unwrap()
+});
+
+/// Number of versions streamed from the backfill fullnode and uploaded.
+pub static BACKFILLED_VERSIONS_COUNT: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "indexer_grpc_file_store_backfilled_versions",
+        "Number of versions streamed from the backfill fullnode and uploaded"
+    )
+    .unwrap()
+});
+
+/// Number of backfill streams that failed before reaching their last version.
+pub static BACKFILL_STREAM_FAILURE_COUNT: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "indexer_grpc_file_store_backfill_stream_failures",
+        "Number of backfill streams that failed before reaching their last version"
+    )
+    .unwrap()
+});
+
+/// Encoded size of the transactions fetched from the cache and not uploaded yet.
+pub static BUFFERED_TRANSACTIONS_SIZE_IN_BYTES: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "indexer_grpc_file_store_buffered_transactions_size_in_bytes",
+        "Encoded size of the transactions fetched from the cache and not uploaded yet",
+    )
+    .unwrap()
+});
+
+/// Number of transactions fetched from the cache and not uploaded yet.
+pub static BUFFERED_TRANSACTIONS_COUNT: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "indexer_grpc_file_store_buffered_transactions",
+        "Number of transactions fetched from the cache and not uploaded yet",
+    )
+    .unwrap()
+});
+
+/// Seconds spent sleeping because the file store is caught up with the cache.
+pub static AHEAD_OF_CACHE_SLEEP_DURATION_IN_SECS: Lazy<Counter> = Lazy::new(|| {
+    register_counter!(
+        "indexer_grpc_file_store_ahead_of_cache_sleep_duration_in_secs",
+        "Seconds spent sleeping because the file store is caught up with the cache",
+    )
+    .unwrap()
+});
+
+/// Seconds writes to the file store waited for the write rate limit.
+pub static WRITE_RATE_LIMIT_WAIT_DURATION_IN_SECS: Lazy<Counter> = Lazy::new(|| {
+    register_counter!(
+        "indexer_grpc_file_store_write_rate_limit_wait_duration_in_secs",
+        "Seconds writes to the file store waited for the write rate limit",
+    )
+    .unwrap()
+});
+
+/// Number of writes to the file store delayed by the write rate limit, by kind of write.
+pub static WRITE_RATE_LIMITED_COUNT: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "indexer_grpc_file_store_write_rate_limited",
+        "Number of writes to the file store delayed by the write rate limit",
+        &["operation"]
+    )
+    .unwrap()
+});
+
+/// Fraction of recent time spent sleeping ahead of the cache, decayed over a few minutes. Close
+/// to 1 when caught up and idle; dropping while the lag grows means the processor is stalled.
+pub static AHEAD_OF_CACHE_IDLE_RATIO: Lazy<Gauge> = Lazy::new(|| {
+    register_gauge!(
+        "indexer_grpc_file_store_ahead_of_cache_idle_ratio",
+        "Fraction of recent time spent sleeping ahead of the cache",
+    )
+    .unwrap()
+});
@@ -0,0 +1,698 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::DualWriteConfig;
+use anyhow::{bail, ensure, Context, Result};
+use aptos_indexer_grpc_server_framework::load;
+use aptos_indexer_grpc_utils::{
+    config::IndexerGrpcFileStoreConfig, file_store_operator::FileStoreOperator, types::ChainId,
+};
+use aptos_protos::transaction::v1::Transaction;
+use clap::Parser;
+use serde::{Deserialize, Serialize};
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+// Number of retries when reading a blob from the source or destination file store.
+const MIGRATION_DOWNLOAD_RETRIES: u8 = 3;
+
+/// Copies the blobs of a file store into another one, re-encoded in the storage format and keyed
+/// with the key layout of the destination, e.g., to move an uncompressed file store to a
+/// compressed one, or a flat one to the sharded layout.
+#[derive(Clone, Debug, Parser)]
+pub struct MigrateArgs {
+    /// Path to the migration config, with the source and destination file stores.
+    #[clap(short, long, value_parser)]
+    pub config_path: PathBuf,
+    /// First version to migrate; a multiple of the blob size, 1000 by default.
+    #[clap(long, default_value_t = 0)]
+    pub start_version: u64,
+    /// Version to stop at, exclusive; a multiple of the blob size. Defaults to the source file store
+    /// version.
+    #[clap(long)]
+    pub end_version: Option<u64>,
+    /// Number of blobs migrated concurrently.
+    #[clap(long, default_value_t = 10)]
+    pub parallelism: usize,
+    /// Overwrite destination blobs that hold different transactions than the source.
+    #[clap(long)]
+    pub overwrite: bool,
+    /// Write the destination metadata once the migration is done; requires a start version of 0.
+    #[clap(long)]
+    pub write_destination_metadata: bool,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct FileStoreMigrationConfig {
+    pub source_file_store_config: IndexerGrpcFileStoreConfig,
+    // The storage format and key layout of the migrated blobs are the ones of this file store.
+    pub destination_file_store_config: IndexerGrpcFileStoreConfig,
+    // Local file recording the progress, so an interrupted migration resumes where it stopped.
+    pub checkpoint_path: PathBuf,
+}
+
+/// Progress of a migration; versions before `next_version` are migrated.
+#[derive(Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub(crate) struct MigrationCheckpoint {
+    pub(crate) start_version: u64,
+    pub(crate) end_version: u64,
+    pub(crate) next_version: u64,
+}
+
+pub async fn run_migration(args: MigrateArgs) -> Result<()> {
+    let config: FileStoreMigrationConfig = load(&args.config_path)?;
+    let source = config.source_file_store_config.create();
+    source.verify_storage_bucket_existence().await;
+    let mut destination = config.destination_file_store_config.create();
+    destination.verify_storage_bucket_existence().await;
+    let migrated_version = migrate_file_store(
+        source.as_ref(),
+        destination.as_mut(),
+        &args,
+        &config.checkpoint_path,
+    )
+    .await?;
+    tracing::info!(
+        migrated_version = migrated_version,
+        "[File store migration] Migration is done."
+    );
+    Ok(())
+}
+
+/// Migrates the blobs in `[start_version, end_version)` and returns the end version.
+async fn migrate_file_store(
+    source: &dyn FileStoreOperator,
+    destination: &mut dyn FileStoreOperator,
+    args: &MigrateArgs,
+    checkpoint_path: &Path,
+) -> Result<u64> {
+    let source_metadata = match source.get_file_store_metadata().await {
+        Some(metadata) => metadata,
+        None => bail!("The source file store has no metadata."),
+    };
+    let blob_size = source_metadata.blob_size();
+    ensure_destination_blob_size(destination, blob_size).await?;
+    let start_version = args.start_version;
+    let end_version = args.end_version.unwrap_or(source_metadata.version);
+    ensure!(args.parallelism > 0, "Parallelism has to be positive.");
+    ensure!(
+        start_version % blob_size == 0 && end_version % blob_size == 0,
+        "Start and end versions have to be multiples of {}.",
+        blob_size
+    );
+    ensure!(
+        start_version <= end_version && end_version <= source_metadata.version,
+        "Versions {}-{} are not in the source file store, which ends at {}.",
+        start_version,
+        end_version,
+        source_metadata.version
+    );
+    ensure!(
+        !args.write_destination_metadata || start_version == 0,
+        "The destination metadata can only be written when migrating from version 0."
+    );
+
+    let mut next_version = match read_checkpoint(checkpoint_path)? {
+        Some(checkpoint) => {
+            ensure!(
+                checkpoint.start_version == start_version && checkpoint.end_version == end_version,
+                "The checkpoint at {:?} is for versions {}-{}; remove it to migrate {}-{}.",
+                checkpoint_path,
+                checkpoint.start_version,
+                checkpoint.end_version,
+                start_version,
+                end_version
+            );
+            checkpoint.next_version
+        },
+        None => start_version,
+    };
+    if next_version > start_version {
+        tracing::info!(
+            next_version = next_version,
+            "[File store migration] Resuming from the checkpoint."
+        );
+    }
+
+    let round_size = args.parallelism as u64 * blob_size;
+    while next_version < end_version {
+        let round_end_version = (next_version + round_size).min(end_version);
+        let tasks = (next_version..round_end_version)
+            .step_by(blob_size as usize)
+            .map(|version| {
+                let mut destination = destination.clone_box();
+                async move {
+                    migrate_blob(
+                        source,
+                        destination.as_mut(),
+                        source_metadata.chain_id,
+                        version,
+                        blob_size,
+                        args.overwrite,
+                    )
+                    .await
+                }
+            });
+        futures::future::try_join_all(tasks).await?;
+        next_version = round_end_version;
+        write_checkpoint(checkpoint_path, &MigrationCheckpoint {
+            start_version,
+            end_version,
+            next_version,
+        })?;
+        tracing::info!(
+            next_version = next_version,
+            end_version = end_version,
+            "[File store migration] Migrated blobs."
+        );
+    }
+
+    if args.write_destination_metadata {
+        if let Some(metadata) = destination.get_file_store_metadata().await {
+            // E.g., the destination is the source bucket under the prefix of another format.
+            ensure!(
+                metadata.storage_format == destination.storage_format(),
+                "The destination metadata is in the {:?} format; not overwriting it.",
+                metadata.storage_format
+            );
+            if metadata.key_layout != destination.key_layout().await? {
+                // The destination is converted in place; its readers switch to the migrated
+                // blobs once the metadata records their layout.
+                metadata.chain_id.ensure_matches(
+                    "the destination file store",
+                    source_metadata.chain_id,
+                    "the source file store",
+                )?;
+                destination
+                    .update_file_store_metadata_internal(source_metadata.chain_id, end_version)
+                    .await?;
+                return Ok(end_version);
+            }
+        }
+        destination
+            .update_file_store_metadata_with_timeout(source_metadata.chain_id, end_version)
+            .await?;
+    }
+    Ok(end_version)
+}
+
+/// Keeps `destination` a copy of `source`, in the storage format of the destination: migrates
+/// the blobs the destination misses, then polls the source for new ones. It only reads `source`,
+/// so it runs next to the processor writing it.
+pub async fn run_dual_write(
+    source: Box<dyn FileStoreOperator>,
+    mut destination: Box<dyn FileStoreOperator>,
+    config: DualWriteConfig,
+) -> Result<()> {
+    destination.verify_storage_bucket_existence().await;
+    loop {
+        let version =
+            sync_file_store(source.as_ref(), destination.as_mut(), config.parallelism).await?;
+        tracing::debug!(
+            version = version,
+            "[File store dual write] Destination is caught up."
+        );
+        tokio::time::sleep(Duration::from_millis(config.poll_interval_in_millis)).await;
+    }
+}
+
+/// Migrates the blobs between the destination and source versions, `parallelism` at a time,
+/// advancing the destination metadata after each round. Returns the destination version.
+async fn sync_file_store(
+    source: &dyn FileStoreOperator,
+    destination: &mut dyn FileStoreOperator,
+    parallelism: usize,
+) -> Result<u64> {
+    ensure!(parallelism > 0, "Parallelism has to be positive.");
+    let source_metadata = match source.get_file_store_metadata().await {
+        Some(metadata) => metadata,
+        // Nothing is uploaded yet.
+        None => return Ok(0),
+    };
+    let blob_size = source_metadata.blob_size();
+    ensure_destination_blob_size(destination, blob_size).await?;
+    let mut next_version = match destination.get_file_store_metadata().await {
+        Some(metadata) => {
+            metadata.chain_id.ensure_matches(
+                "the destination file store",
+                source_metadata.chain_id,
+                "the source file store",
+            )?;
+            ensure!(
+                metadata.storage_format == destination.storage_format(),
+                "The destination metadata is in the {:?} format, not {:?}.",
+                metadata.storage_format,
+                destination.storage_format()
+            );
+            metadata.version
+        },
+        None => 0,
+    };
+
+    let round_size = parallelism as u64 * blob_size;
+    while next_version < source_metadata.version {
+        let round_end_version = (next_version + round_size).min(source_metadata.version);
+        let tasks = (next_version..round_end_version)
+            .step_by(blob_size as usize)
+            .map(|version| {
+                let mut destination = destination.clone_box();
+                async move {
+                    migrate_blob(
+                        source,
+                        destination.as_mut(),
+                        source_metadata.chain_id,
+                        version,
+                        blob_size,
+                        false,
+                    )
+                    .await
+                }
+            });
+        futures::future::try_join_all(tasks).await?;
+        destination
+            .update_file_store_metadata_internal(source_metadata.chain_id, round_end_version)
+            .await?;
+        next_version = round_end_version;
+        tracing::info!(
+            next_version = next_version,
+            source_version = source_metadata.version,
+            "[File store dual write] Migrated blobs."
+        );
+    }
+    Ok(next_version)
+}
+
+/// Fails unless the blobs of `destination` are of `blob_size` versions, like the ones of the
+/// source; a new destination gets its configured blob size.
+async fn ensure_destination_blob_size(
+    destination: &dyn FileStoreOperator,
+    blob_size: u64,
+) -> Result<()> {
+    let destination_blob_size = destination.blob_size().await?;
+    ensure!(
+        destination_blob_size == blob_size,
+        "The source file store has blobs of {} versions, but the destination has blobs of {}; set its blob_size_in_versions to {}.",
+        blob_size,
+        destination_blob_size,
+        blob_size
+    );
+    Ok(())
+}
+
+/// Copies the blob of `blob_size` versions at `version`; a destination blob with the same
+/// transactions is left as is.
+async fn migrate_blob(
+    source: &dyn FileStoreOperator,
+    destination: &mut dyn FileStoreOperator,
+    chain_id: ChainId,
+    version: u64,
+    blob_size: u64,
+    overwrite: bool,
+) -> Result<()> {
+    let transactions = source
+        .get_transactions(version, MIGRATION_DOWNLOAD_RETRIES)
+        .await
+        .with_context(|| format!("Failed to read the source blob at {}", version))?;
+    verify_blob(&transactions, version, blob_size).context("The source blob is invalid")?;
+
+    // A missing destination blob is read as an error.
+    if let Ok(existing_transactions) = destination.get_transactions(version, 0).await {
+        if existing_transactions == transactions {
+            return Ok(());
+        }
+        ensure!(
+            overwrite,
+            "The destination blob at {} holds different transactions; pass --overwrite to replace it.",
+            version
+        );
+        // Uploads never replace a blob holding different transactions.
+        destination.delete_blob(version).await?;
+    }
+
+    destination
+        .upload_transaction_batch(chain_id, transactions.clone())
+        .await?;
+    let migrated_transactions = destination
+        .get_transactions(version, MIGRATION_DOWNLOAD_RETRIES)
+        .await
+        .with_context(|| format!("Failed to read back the migrated blob at {}", version))?;
+    ensure!(
+        migrated_transactions == transactions,
+        "The migrated blob at {} holds different transactions than the source",
+        version
+    );
+    Ok(())
+}
+
+/// Checks the blob holds the versions `[start_version, start_version + blob_size)`.
+pub(crate) fn verify_blob(
+    transactions: &[Transaction],
+    start_version: u64,
+    blob_size: u64,
+) -> Result<()> {
+    ensure!(
+        transactions.len() as u64 == blob_size,
+        "Expected {} transactions at {}, found {}",
+        blob_size,
+        start_version,
+        transactions.len()
+    );
+    let first_version = transactions.first().map(|t| t.version);
+    let last_version = transactions.last().map(|t| t.version);
+    ensure!(
+        first_version == Some(start_version) && last_version == Some(start_version + blob_size - 1),
+        "Expected versions {}-{}, found {:?}-{:?}",
+        start_version,
+        start_version + blob_size - 1,
+        first_version,
+        last_version
+    );
+    Ok(())
+}
+
+pub(crate) fn read_checkpoint(path: &Path) -> Result<Option<MigrationCheckpoint>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let bytes = std::fs::read(path)
+        .with_context(|| format!("Failed to read the checkpoint at {:?}", path))?;
+    Ok(Some(serde_json::from_slice(&bytes).with_context(|| {
+        format!("Failed to parse the checkpoint at {:?}", path)
+    })?))
+}
+
+/// Writes the checkpoint to a temporary file first, so a crash never leaves a partial checkpoint.
+pub(crate) fn write_checkpoint(path: &Path, checkpoint: &MigrationCheckpoint) -> Result<()> {
+    let temp_path = path.with_extension("tmp");
+    std::fs::write(&temp_path, serde_json::to_vec(checkpoint)?)
+        .with_context(|| format!("Failed to write the checkpoint at {:?}", temp_path))?;
+    std::fs::rename(&temp_path, path)
+        .with_context(|| format!("Failed to write the checkpoint at {:?}", path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aptos_indexer_grpc_utils::{
+        compression_util::{FileEntry, KeyLayout, StorageFormat, FILE_ENTRY_TRANSACTION_COUNT},
+        file_store_operator::{
+            blob_transactions, InMemoryFileStoreOperator, LocalFileStoreOperator,
+        },
+    };
+
+    async fn source_file_store(blob_count: u64) -> InMemoryFileStoreOperator {
+        let mut source = InMemoryFileStoreOperator::new(false, None);
+        for i in 0..blob_count {
+            source
+                .upload_transaction_batch(
+                    ChainId(1),
+                    blob_transactions(i * FILE_ENTRY_TRANSACTION_COUNT),
+                )
+                .await
+                .unwrap();
+        }
+        source
+            .update_file_store_metadata_with_timeout(
+                ChainId(1),
+                blob_count * FILE_ENTRY_TRANSACTION_COUNT,
+            )
+            .await
+            .unwrap();
+        source
+    }
+
+    fn migrate_args(end_version: Option<u64>) -> MigrateArgs {
+        MigrateArgs {
+            config_path: PathBuf::new(),
+            start_version: 0,
+            end_version,
+            parallelism: 2,
+            overwrite: false,
+            write_destination_metadata: true,
+        }
+    }
+
+    #[tokio::test]
+    async fn migration_re_encodes_blobs_and_resumes_from_checkpoint() {
+        let source = source_file_store(5).await;
+        let mut destination = InMemoryFileStoreOperator::new(true, None);
+        let checkpoint_dir = tempfile::tempdir().unwrap();
+        let checkpoint_path = checkpoint_dir.path().join("checkpoint.json");
+
+        // A bounded run stops at the end version.
+        let migrated_version = migrate_file_store(
+            &source,
+            &mut destination,
+            &migrate_args(Some(3_000)),
+            &checkpoint_path,
+        )
+        .await
+        .unwrap();
+        assert_eq!(migrated_version, 3_000);
+        assert_eq!(destination.blob_versions(), vec![0, 1_000, 2_000]);
+        assert_eq!(
+            destination.storage_format(),
+            StorageFormat::GzipCompressedProto
+        );
+        assert_eq!(
+            destination.get_transactions(2_000, 0).await.unwrap(),
+            blob_transactions(2_000)
+        );
+
+        // A checkpoint for another range is rejected rather than silently ignored.
+        assert!(migrate_file_store(
+            &source,
+            &mut destination,
+            &migrate_args(None),
+            &checkpoint_path
+        )
+        .await
+        .is_err());
+
+        // Resuming an interrupted migration skips the checkpointed blobs.
+        write_checkpoint(&checkpoint_path, &MigrationCheckpoint {
+            start_version: 0,
+            end_version: 5_000,
+            next_version: 2_000,
+        })
+        .unwrap();
+        assert_eq!(
+            migrate_file_store(
+                &source,
+                &mut destination,
+                &migrate_args(None),
+                &checkpoint_path
+            )
+            .await
+            .unwrap(),
+            5_000
+        );
+        assert_eq!(destination.blob_versions(), vec![
+            0, 1_000, 2_000, 3_000, 4_000
+        ]);
+        assert_eq!(destination.get_latest_version().await, Some(5_000));
+        assert_eq!(
+            read_checkpoint(&checkpoint_path)
+                .unwrap()
+                .unwrap()
+                .next_version,
+            5_000
+        );
+    }
+
+    #[tokio::test]
+    async fn migration_keeps_the_blob_size_of_the_source() {
+        let mut source = InMemoryFileStoreOperator::new(false, None).with_blob_size(Some(100));
+        for start_version in [0, 100, 200] {
+            let transactions = (start_version..start_version + 100)
+                .map(|version| Transaction {
+                    version,
+                    ..Default::default()
+                })
+                .collect();
+            source
+                .upload_transaction_batch(ChainId(1), transactions)
+                .await
+                .unwrap();
+        }
+        source
+            .update_file_store_metadata_with_timeout(ChainId(1), 300)
+            .await
+            .unwrap();
+        let checkpoint_dir = tempfile::tempdir().unwrap();
+        let checkpoint_path = checkpoint_dir.path().join("checkpoint.json");
+
+        let mut destination = InMemoryFileStoreOperator::new(true, None);
+        let err = migrate_file_store(
+            &source,
+            &mut destination,
+            &migrate_args(None),
+            &checkpoint_path,
+        )
+        .await
+        .unwrap_err();
+        assert!(
+            err.to_string().contains("blobs of 100 versions"),
+            "{:?}",
+            err
+        );
+
+        let mut destination = InMemoryFileStoreOperator::new(true, None).with_blob_size(Some(100));
+        assert_eq!(
+            migrate_file_store(
+                &source,
+                &mut destination,
+                &migrate_args(None),
+                &checkpoint_path
+            )
+            .await
+            .unwrap(),
+            300
+        );
+        assert_eq!(destination.blob_versions(), vec![0, 100, 200]);
+        assert_eq!(
+            destination
+                .get_file_store_metadata()
+                .await
+                .unwrap()
+                .blob_size(),
+            100
+        );
+    }
+
+    #[tokio::test]
+    async fn migration_refuses_to_overwrite_different_blobs() {
+        let source = source_file_store(1).await;
+        let mut destination = InMemoryFileStoreOperator::new(true, None);
+        let mut different_transactions = blob_transactions(0);
+        different_transactions[10].epoch = 1;
+        destination
+            .upload_transaction_batch(ChainId(1), different_transactions)
+            .await
+            .unwrap();
+        let checkpoint_dir = tempfile::tempdir().unwrap();
+        let checkpoint_path = checkpoint_dir.path().join("checkpoint.json");
+
+        assert!(migrate_file_store(
+            &source,
+            &mut destination,
+            &migrate_args(None),
+            &checkpoint_path
+        )
+        .await
+        .is_err());
+
+        let mut args = migrate_args(None);
+        args.overwrite = true;
+        migrate_file_store(&source, &mut destination, &args, &checkpoint_path)
+            .await
+            .unwrap();
+        assert_eq!(
+            destination.get_transactions(0, 0).await.unwrap(),
+            blob_transactions(0)
+        );
+    }
+
+    #[tokio::test]
+    async fn migration_converts_a_flat_file_store_in_place() {
+        let store_dir = tempfile::tempdir().unwrap();
+        let mut source = LocalFileStoreOperator::new(store_dir.path().to_path_buf(), true, None)
+            .with_key_layout(Some(KeyLayout::Flat));
+        source
+            .upload_transaction_batch(ChainId(1), blob_transactions(0))
+            .await
+            .unwrap();
+        source
+            .upload_transaction_batch(ChainId(1), blob_transactions(1_000))
+            .await
+            .unwrap();
+        source
+            .update_file_store_metadata_internal(ChainId(1), 2_000)
+            .await
+            .unwrap();
+        let source = LocalFileStoreOperator::new(store_dir.path().to_path_buf(), true, None);
+        let mut destination =
+            LocalFileStoreOperator::new(store_dir.path().to_path_buf(), true, None)
+                .with_key_layout(Some(KeyLayout::Sharded));
+        let checkpoint_dir = tempfile::tempdir().unwrap();
+        let checkpoint_path = checkpoint_dir.path().join("checkpoint.json");
+
+        migrate_file_store(
+            &source,
+            &mut destination,
+            &migrate_args(None),
+            &checkpoint_path,
+        )
+        .await
+        .unwrap();
+
+        // Readers following the metadata switch to the sharded blobs; the flat ones are kept for
+        // readers that started before.
+        let reader = LocalFileStoreOperator::new(store_dir.path().to_path_buf(), true, None);
+        let metadata = reader.get_file_store_metadata().await.unwrap();
+        assert_eq!(metadata.key_layout, KeyLayout::Sharded);
+        assert_eq!(metadata.version, 2_000);
+        for key_layout in [KeyLayout::Flat, KeyLayout::Sharded] {
+            assert!(store_dir
+                .path()
+                .join(FileEntry::build_key_with_layout(
+                    1_000,
+                    StorageFormat::GzipCompressedProto,
+                    &key_layout
+                ))
+                .exists());
+        }
+        assert_eq!(
+            reader.get_transactions(1_000, 0).await.unwrap(),
+            blob_transactions(1_000)
+        );
+    }
+
+    #[tokio::test]
+    async fn dual_write_follows_the_source_file_store() {
+        let mut source = source_file_store(3).await;
+        let mut destination = InMemoryFileStoreOperator::new(true, None);
+
+        assert_eq!(
+            sync_file_store(&source, &mut destination, 2).await.unwrap(),
+            3_000
+        );
+        assert_eq!(destination.get_latest_version().await, Some(3_000));
+
+        // The live writer uploads more blobs; only those are migrated.
+        for version in [3_000, 4_000] {
+            source
+                .upload_transaction_batch(ChainId(1), blob_transactions(version))
+                .await
+                .unwrap();
+        }
+        source
+            .update_file_store_metadata_internal(ChainId(1), 5_000)
+            .await
+            .unwrap();
+        assert_eq!(
+            sync_file_store(&source, &mut destination, 2).await.unwrap(),
+            5_000
+        );
+        let metadata = destination.get_file_store_metadata().await.unwrap();
+        assert_eq!(metadata.version, 5_000);
+        assert_eq!(metadata.storage_format, StorageFormat::GzipCompressedProto);
+        for version in (0..5_000).step_by(FILE_ENTRY_TRANSACTION_COUNT as usize) {
+            assert_eq!(
+                destination.get_transactions(version, 0).await.unwrap(),
+                source.get_transactions(version, 0).await.unwrap()
+            );
+        }
+
+        // A destination for another chain is rejected.
+        let mut other_chain = InMemoryFileStoreOperator::new(true, None);
+        other_chain
+            .update_file_store_metadata_internal(ChainId(2), 0)
+            .await
+            .unwrap();
+        assert!(sync_file_store(&source, &mut other_chain, 2).await.is_err());
+    }
+}
@@ -0,0 +1,119 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::health::ProcessorHealth;
+use anyhow::{Context, Result};
+use aptos_indexer_grpc_utils::{compression_util::StorageFormat, types::ChainId};
+use aptos_protos::indexer::v1::{
+    file_store_status_server::{FileStoreStatus, FileStoreStatusServer},
+    GetStatusRequest, GetStatusResponse,
+};
+use std::{net::SocketAddr, sync::Arc};
+use tonic::{Request, Response, Status};
+
+/// FileStoreStatusService answers `GetStatus` from the progress recorded by the run loop, so
+/// requests never reach Redis or the file store.
+pub struct FileStoreStatusService {
+    chain_id: ChainId,
+    cache_storage_format: StorageFormat,
+    file_store_storage_format: StorageFormat,
+    health: Arc<ProcessorHealth>,
+}
+
+impl FileStoreStatusService {
+    pub fn new(
+        chain_id: ChainId,
+        cache_storage_format: StorageFormat,
+        file_store_storage_format: StorageFormat,
+        health: Arc<ProcessorHealth>,
+    ) -> Self {
+        Self {
+            chain_id,
+            cache_storage_format,
+            file_store_storage_format,
+            health,
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl FileStoreStatus for FileStoreStatusService {
+    async fn get_status(
+        &self,
+        _request: Request<GetStatusRequest>,
+    ) -> Result<Response<GetStatusResponse>, Status> {
+        let status = self.health.status();
+        Ok(Response::new(GetStatusResponse {
+            chain_id: Some(self.chain_id.0),
+            file_store_version: Some(status.file_store_version),
+            cache_latest_version: Some(status.cache_latest_version),
+            cache_storage_format: Some(format!("{:?}", self.cache_storage_format)),
+            file_store_storage_format: Some(format!("{:?}", self.file_store_storage_format)),
+            secs_since_last_upload: status.secs_since_last_upload,
+        }))
+    }
+}
+
+/// Serves the `FileStoreStatus` gRPC service on `listen_address`.
+pub async fn run_status_server(
+    listen_address: SocketAddr,
+    service: FileStoreStatusService,
+) -> Result<()> {
+    tonic::transport::Server::builder()
+        .add_service(FileStoreStatusServer::new(service))
+        .serve(listen_address)
+        .await
+        .with_context(|| format!("Failed to serve the status service on {}", listen_address))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aptos_protos::indexer::v1::file_store_status_client::FileStoreStatusClient;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn client_reads_status_recorded_by_the_processor() {
+        let listen_address = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let health = Arc::new(ProcessorHealth::default());
+        health.record_iteration(5_000, 2_000);
+        health.record_upload(3_000);
+        tokio::spawn(run_status_server(
+            listen_address,
+            FileStoreStatusService::new(
+                ChainId(1),
+                StorageFormat::Base64UncompressedProto,
+                StorageFormat::JsonBase64UncompressedProto,
+                health,
+            ),
+        ));
+
+        let mut client = None;
+        for _ in 0..50 {
+            match FileStoreStatusClient::connect(format!("http://{}", listen_address)).await {
+                Ok(connected) => {
+                    client = Some(connected);
+                    break;
+                },
+                Err(_) => tokio::time::sleep(Duration::from_millis(20)).await,
+            }
+        }
+        let status = client
+            .expect("status server should accept connections")
+            .get_status(GetStatusRequest {})
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(status, GetStatusResponse {
+            chain_id: Some(1),
+            file_store_version: Some(3_000),
+            cache_latest_version: Some(5_000),
+            cache_storage_format: Some("Base64UncompressedProto".to_string()),
+            file_store_storage_format: Some("JsonBase64UncompressedProto".to_string()),
+            secs_since_last_upload: Some(0),
+        });
+    }
+}
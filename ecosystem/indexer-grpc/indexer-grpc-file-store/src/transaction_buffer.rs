@@ -0,0 +1,207 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::{
+    batch_fetcher::BatchTransactions,
+    metrics::{BUFFERED_TRANSACTIONS_COUNT, BUFFERED_TRANSACTIONS_SIZE_IN_BYTES},
+};
+use std::sync::{Arc, Mutex};
+use tokio::sync::Notify;
+
+/// TransactionBuffer accounts for the transactions fetched from the cache and not uploaded yet.
+/// With a byte budget, batches wait for room in the budget before they are fetched, so rounds of
+/// large transactions don't exhaust memory even though rounds are bounded by batch count.
+pub struct TransactionBuffer {
+    max_size_in_bytes: Option<u64>,
+    state: Mutex<BufferState>,
+    // Notified whenever a batch leaves the buffer.
+    drained: Notify,
+}
+
+#[derive(Default)]
+struct BufferState {
+    size_in_bytes: u64,
+    transaction_count: u64,
+    // Size of the last fetched batch; batches are assumed to be that large until fetched.
+    batch_size_estimate_in_bytes: Option<u64>,
+}
+
+impl TransactionBuffer {
+    pub fn new(max_size_in_bytes: Option<u64>) -> Self {
+        Self {
+            max_size_in_bytes,
+            state: Mutex::new(BufferState::default()),
+            drained: Notify::new(),
+        }
+    }
+
+    /// Waits until the budget has room for another batch, then reserves it, sized after the last
+    /// fetched batch. Until a batch is fetched, batches are fetched one at a time. An empty buffer
+    /// always admits a batch, however large.
+    pub async fn reserve(self: &Arc<Self>) -> BufferedBatch {
+        let mut waited = false;
+        loop {
+            // Created before checking the budget, so a batch leaving in between isn't missed.
+            let drained = self.drained.notified();
+            {
+                let mut state = self.state.lock().unwrap();
+                let estimate = match (self.max_size_in_bytes, state.batch_size_estimate_in_bytes) {
+                    (None, _) => 0,
+                    (Some(max_size_in_bytes), estimate) => estimate.unwrap_or(max_size_in_bytes),
+                };
+                let has_room = self.max_size_in_bytes.map_or(true, |max_size_in_bytes| {
+                    state.size_in_bytes + estimate <= max_size_in_bytes
+                });
+                if state.size_in_bytes == 0 || has_room {
+                    state.size_in_bytes += estimate;
+                    state.record_gauges();
+                    return BufferedBatch {
+                        buffer: self.clone(),
+                        size_in_bytes: estimate,
+                        transaction_count: 0,
+                    };
+                }
+            }
+            if !waited {
+                tracing::debug!(
+                    size_in_bytes = self.size_in_bytes(),
+                    max_size_in_bytes = self.max_size_in_bytes,
+                    "[Filestore] Transaction buffer is full; waiting for uploads before fetching."
+                );
+                waited = true;
+            }
+            drained.await;
+        }
+    }
+
+    pub fn size_in_bytes(&self) -> u64 {
+        self.state.lock().unwrap().size_in_bytes
+    }
+
+    pub fn transaction_count(&self) -> u64 {
+        self.state.lock().unwrap().transaction_count
+    }
+}
+
+impl BufferState {
+    fn record_gauges(&self) {
+        BUFFERED_TRANSACTIONS_SIZE_IN_BYTES.set(self.size_in_bytes as i64);
+        BUFFERED_TRANSACTIONS_COUNT.set(self.transaction_count as i64);
+    }
+}
+
+/// Room of a batch in the `TransactionBuffer`, released once dropped, i.e., once the batch is
+/// uploaded or abandoned.
+pub struct BufferedBatch {
+    buffer: Arc<TransactionBuffer>,
+    size_in_bytes: u64,
+    transaction_count: u64,
+}
+
+impl BufferedBatch {
+    /// Replaces the reserved size with the encoded size of the fetched `transactions`.
+    pub fn fill(&mut self, transactions: &BatchTransactions) {
+        let size_in_bytes = transactions.encoded_size_in_bytes() as u64;
+        let transaction_count = transactions.transaction_count() as u64;
+        let mut state = self.buffer.state.lock().unwrap();
+        state.size_in_bytes = state.size_in_bytes - self.size_in_bytes + size_in_bytes;
+        state.transaction_count =
+            state.transaction_count - self.transaction_count + transaction_count;
+        state.batch_size_estimate_in_bytes = Some(size_in_bytes);
+        state.record_gauges();
+        self.size_in_bytes = size_in_bytes;
+        self.transaction_count = transaction_count;
+    }
+}
+
+impl Drop for BufferedBatch {
+    fn drop(&mut self) {
+        {
+            let mut state = self.buffer.state.lock().unwrap();
+            state.size_in_bytes -= self.size_in_bytes;
+            state.transaction_count -= self.transaction_count;
+            state.record_gauges();
+        }
+        self.buffer.drained.notify_waiters();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aptos_protos::transaction::v1::Transaction;
+    use std::time::Duration;
+
+    fn transactions(count: u64, payload_size: usize) -> BatchTransactions {
+        BatchTransactions::Decoded(
+            (0..count)
+                .map(|version| Transaction {
+                    version,
+                    info: Some(aptos_protos::transaction::v1::TransactionInfo {
+                        hash: vec![0; payload_size],
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                })
+                .collect(),
+        )
+    }
+
+    async fn is_admitted(buffer: &Arc<TransactionBuffer>) -> Option<BufferedBatch> {
+        tokio::time::timeout(Duration::from_millis(50), buffer.reserve())
+            .await
+            .ok()
+    }
+
+    #[tokio::test]
+    async fn batches_wait_for_room_in_the_budget() {
+        let buffer = Arc::new(TransactionBuffer::new(Some(250_000)));
+        // The first batch is admitted alone, since its size is unknown.
+        let mut first = buffer.reserve().await;
+        assert!(is_admitted(&buffer).await.is_none());
+        let batch = transactions(1_000, 100);
+        first.fill(&batch);
+        let batch_size_in_bytes = buffer.size_in_bytes();
+        assert!(batch_size_in_bytes > 100_000 && batch_size_in_bytes < 125_000);
+        assert_eq!(buffer.transaction_count(), 1_000);
+
+        // Once sized, batches are admitted while the estimate fits.
+        let mut second = is_admitted(&buffer).await.unwrap();
+        assert!(is_admitted(&buffer).await.is_none());
+        second.fill(&batch);
+        assert_eq!(buffer.transaction_count(), 2_000);
+
+        // An upload drains the buffer and lets a waiting batch in.
+        let waiting = tokio::spawn({
+            let buffer = buffer.clone();
+            async move { buffer.reserve().await }
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!waiting.is_finished());
+        drop(first);
+        let third = tokio::time::timeout(Duration::from_secs(1), waiting)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(buffer.transaction_count(), 1_000);
+        drop((second, third));
+        assert_eq!(buffer.size_in_bytes(), 0);
+        assert_eq!(buffer.transaction_count(), 0);
+    }
+
+    #[tokio::test]
+    async fn oversized_batches_are_admitted_into_an_empty_buffer() {
+        let buffer = Arc::new(TransactionBuffer::new(Some(1_000)));
+        let mut batch = buffer.reserve().await;
+        batch.fill(&transactions(10, 1_000));
+        assert!(is_admitted(&buffer).await.is_none());
+        drop(batch);
+        assert!(is_admitted(&buffer).await.is_some());
+
+        // Without a budget, batches are only accounted for.
+        let buffer = Arc::new(TransactionBuffer::new(None));
+        let mut batch = buffer.reserve().await;
+        batch.fill(&transactions(10, 1_000));
+        assert!(is_admitted(&buffer).await.is_some());
+    }
+}
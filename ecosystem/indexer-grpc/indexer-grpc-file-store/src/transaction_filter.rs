@@ -0,0 +1,146 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+use anyhow::{bail, Result};
+use aptos_protos::transaction::v1::{transaction::TransactionType, Transaction};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+/// Selects the transactions written to the sidecar file store, or kept in the file store.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct TransactionFilterConfig {
+    // Types of the transactions to keep, e.g., `user` or `TRANSACTION_TYPE_USER`.
+    pub transaction_types: Vec<String>,
+}
+
+pub struct TransactionFilter {
+    transaction_types: HashSet<i32>,
+}
+
+impl TransactionFilter {
+    pub fn new(config: &TransactionFilterConfig) -> Result<Self> {
+        if config.transaction_types.is_empty() {
+            bail!("The transaction filter has to allow at least one transaction type");
+        }
+        let mut transaction_types = HashSet::new();
+        for name in &config.transaction_types {
+            transaction_types.insert(parse_transaction_type(name)? as i32);
+        }
+        Ok(Self { transaction_types })
+    }
+
+    pub fn matches(&self, transaction: &Transaction) -> bool {
+        self.transaction_types.contains(&transaction.r#type)
+    }
+
+    /// Keeps the matching transactions, in order.
+    pub fn filter(&self, transactions: &[Transaction]) -> Vec<Transaction> {
+        transactions
+            .iter()
+            .filter(|transaction| self.matches(transaction))
+            .cloned()
+            .collect()
+    }
+
+    /// Replaces the transactions that don't match with sentinels, so that the batch keeps a
+    /// transaction for every version. Returns the number of replaced transactions.
+    pub fn replace_with_sentinels(&self, transactions: &mut [Transaction]) -> u64 {
+        let mut replaced = 0;
+        for transaction in transactions.iter_mut() {
+            if !self.matches(transaction) {
+                *transaction = sentinel(transaction);
+                replaced += 1;
+            }
+        }
+        replaced
+    }
+}
+
+/// Placeholder of a filtered out transaction: it only keeps the version, timestamp, epoch and block
+/// height, and has the unspecified type.
+pub fn sentinel(transaction: &Transaction) -> Transaction {
+    Transaction {
+        version: transaction.version,
+        timestamp: transaction.timestamp.clone(),
+        epoch: transaction.epoch,
+        block_height: transaction.block_height,
+        ..Default::default()
+    }
+}
+
+/// Whether `transaction` is the placeholder of a filtered out transaction.
+pub fn is_sentinel(transaction: &Transaction) -> bool {
+    transaction.r#type == TransactionType::Unspecified as i32 && transaction.info.is_none()
+}
+
+fn parse_transaction_type(name: &str) -> Result<TransactionType> {
+    let proto_name = if name.starts_with("TRANSACTION_TYPE_") {
+        name.to_string()
+    } else {
+        format!("TRANSACTION_TYPE_{}", name.to_uppercase())
+    };
+    match TransactionType::from_str_name(&proto_name) {
+        Some(transaction_type) => Ok(transaction_type),
+        None => bail!("Unknown transaction type: {}", name),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transaction(version: u64, transaction_type: TransactionType) -> Transaction {
+        Transaction {
+            version,
+            r#type: transaction_type as i32,
+            ..Default::default()
+        }
+    }
+
+    fn filter(transaction_types: &[&str]) -> Result<TransactionFilter> {
+        TransactionFilter::new(&TransactionFilterConfig {
+            transaction_types: transaction_types.iter().map(|t| t.to_string()).collect(),
+        })
+    }
+
+    #[test]
+    fn filter_keeps_allowed_transaction_types() {
+        let filter = filter(&["user", "TRANSACTION_TYPE_GENESIS"]).unwrap();
+        let transactions = vec![
+            transaction(0, TransactionType::Genesis),
+            transaction(1, TransactionType::BlockMetadata),
+            transaction(2, TransactionType::User),
+            transaction(3, TransactionType::StateCheckpoint),
+        ];
+        let versions: Vec<u64> = filter
+            .filter(&transactions)
+            .iter()
+            .map(|t| t.version)
+            .collect();
+        assert_eq!(versions, vec![0, 2]);
+        assert!(filter.filter(&transactions[1..2]).is_empty());
+    }
+
+    #[test]
+    fn filtered_out_transactions_become_sentinels() {
+        let filter = filter(&["user"]).unwrap();
+        let mut transactions = vec![
+            transaction(0, TransactionType::Genesis),
+            transaction(1, TransactionType::User),
+            transaction(2, TransactionType::BlockMetadata),
+        ];
+        assert_eq!(filter.replace_with_sentinels(&mut transactions), 2);
+        let versions: Vec<u64> = transactions.iter().map(|t| t.version).collect();
+        assert_eq!(versions, vec![0, 1, 2]);
+        assert!(is_sentinel(&transactions[0]));
+        assert!(!is_sentinel(&transactions[1]));
+        assert!(is_sentinel(&transactions[2]));
+    }
+
+    #[test]
+    fn unknown_or_missing_transaction_types_are_rejected() {
+        assert!(filter(&["user", "coffee"]).is_err());
+        assert!(filter(&[]).is_err());
+    }
+}
@@ -0,0 +1,537 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::processor::resolve_chain_id;
+use anyhow::{bail, ensure, Context, Result};
+use aptos_indexer_grpc_server_framework::load;
+use aptos_indexer_grpc_utils::{
+    cache_operator::CacheOperator,
+    compression_util::StorageFormat,
+    config::IndexerGrpcFileStoreConfig,
+    file_store_operator::{decode_transaction_stream, FileStoreOperator, StreamingBlobDigest},
+    redis_cluster::CacheConnection,
+    redis_tls::RedisTlsConfig,
+    types::{ChainId, RedisUrl},
+};
+use aptos_protos::transaction::v1::Transaction;
+use clap::Parser;
+use futures::StreamExt;
+use std::{fmt, path::PathBuf};
+
+// Number of retries when reading a blob.
+const VERIFIER_DOWNLOAD_RETRIES: u8 = 3;
+
+/// Checks every blob of a version range exists, decodes, holds the expected versions, and matches
+/// its recorded digest.
+#[derive(Clone, Debug, Parser)]
+pub struct VerifyArgs {
+    /// Path to the config of the file store to verify.
+    #[clap(short, long, value_parser)]
+    pub config_path: PathBuf,
+    /// First version to verify; a multiple of 1000.
+    #[clap(long, default_value_t = 0)]
+    pub start_version: u64,
+    /// Version to stop at, exclusive; a multiple of 1000. Defaults to the file store version.
+    #[clap(long)]
+    pub end_version: Option<u64>,
+    /// Number of blobs verified concurrently.
+    #[clap(long, default_value_t = 10)]
+    pub parallelism: usize,
+    /// Path to the config of a secondary file store; damaged blobs are re-fetched from it.
+    #[clap(long, value_parser)]
+    pub fix_from: Option<PathBuf>,
+    /// Skips comparing blobs with their recorded digests.
+    #[clap(long)]
+    pub skip_digests: bool,
+    /// Cache to read the chain id of re-fetched blobs from. The metadata of the file stores is
+    /// used if it's unreachable or not given.
+    #[clap(long)]
+    pub redis_main_instance_address: Option<RedisUrl>,
+}
+
+/// What is wrong with a blob.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum BlobDamage {
+    // The blob can't be read, e.g., it doesn't exist.
+    Missing(String),
+    // The blob is readable but can't be decoded, holds other versions, or mismatches its digest.
+    Corrupt(String),
+}
+
+impl fmt::Display for BlobDamage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BlobDamage::Missing(reason) => write!(f, "missing: {}", reason),
+            BlobDamage::Corrupt(reason) => write!(f, "corrupt: {}", reason),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct VerificationReport {
+    pub start_version: u64,
+    pub end_version: u64,
+    pub verified_blob_count: usize,
+    // Starting versions of the damaged blobs, in order, with what is wrong with them.
+    pub damaged_blobs: Vec<(u64, BlobDamage)>,
+    // Starting versions of the damaged blobs re-fetched from the secondary file store.
+    pub fixed_blobs: Vec<u64>,
+}
+
+impl VerificationReport {
+    /// Whether every blob of the range is intact, possibly after being fixed.
+    pub fn is_intact(&self) -> bool {
+        self.damaged_blobs.len() == self.fixed_blobs.len()
+    }
+
+    /// The damaged blob with the lowest version, i.e., up to which the file store can be trusted.
+    pub fn first_anomaly(&self) -> Option<&(u64, BlobDamage)> {
+        self.damaged_blobs.first()
+    }
+}
+
+impl fmt::Display for VerificationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (version, damage) in &self.damaged_blobs {
+            let fixed = if self.fixed_blobs.contains(version) {
+                " (fixed)"
+            } else {
+                ""
+            };
+            writeln!(f, "Blob {}: {}{}", version, damage, fixed)?;
+        }
+        if let Some((version, damage)) = self.first_anomaly() {
+            writeln!(
+                f,
+                "First anomaly at version {}: {}; versions {}-{} are intact.",
+                version, damage, self.start_version, version
+            )?;
+        }
+        write!(
+            f,
+            "Verified {} blobs in versions {}-{}: {} damaged, {} fixed.",
+            self.verified_blob_count,
+            self.start_version,
+            self.end_version,
+            self.damaged_blobs.len(),
+            self.fixed_blobs.len()
+        )
+    }
+}
+
+pub async fn run_verifier(args: VerifyArgs) -> Result<()> {
+    let config: IndexerGrpcFileStoreConfig = load(&args.config_path)?;
+    let mut operator = config.create();
+    operator.verify_storage_bucket_existence().await;
+    let secondary = match &args.fix_from {
+        Some(path) => {
+            let config: IndexerGrpcFileStoreConfig = load(path)?;
+            let secondary = config.create();
+            secondary.verify_storage_bucket_existence().await;
+            Some(secondary)
+        },
+        None => None,
+    };
+    let mut cache_operator = match &args.redis_main_instance_address {
+        Some(address) => connect_to_cache(address).await,
+        None => None,
+    };
+    let report = verify_file_store(
+        operator.as_mut(),
+        secondary.as_deref(),
+        cache_operator.as_mut(),
+        args.start_version,
+        args.end_version,
+        args.parallelism,
+        !args.skip_digests,
+    )
+    .await?;
+    println!("{}", report);
+    ensure!(report.is_intact(), "The file store is damaged.");
+    Ok(())
+}
+
+/// Connects to the cache at `address`, if it's reachable.
+async fn connect_to_cache(address: &RedisUrl) -> Option<CacheOperator<CacheConnection>> {
+    match CacheConnection::connect(address, &[], &RedisTlsConfig::default()).await {
+        Ok(conn) => Some(CacheOperator::new(
+            conn,
+            StorageFormat::for_cache(false, None),
+        )),
+        Err(err) => {
+            tracing::warn!(
+                error = format!("{:#}", err),
+                "[File store verifier] Cache is unreachable; reading the chain id from the file store."
+            );
+            None
+        },
+    }
+}
+
+/// Verifies the blobs in `[start_version, end_version)`, then re-fetches the damaged ones from
+/// `secondary`, if any, for the chain id of the cache, or else of the file stores. Blobs are only
+/// compared with their recorded digests if `check_digests`.
+pub async fn verify_file_store(
+    operator: &mut dyn FileStoreOperator,
+    secondary: Option<&dyn FileStoreOperator>,
+    cache_operator: Option<&mut CacheOperator<CacheConnection>>,
+    start_version: u64,
+    end_version: Option<u64>,
+    parallelism: usize,
+    check_digests: bool,
+) -> Result<VerificationReport> {
+    ensure!(parallelism > 0, "Parallelism has to be positive.");
+    let metadata = operator.get_file_store_metadata().await;
+    let end_version = match (end_version, &metadata) {
+        (Some(end_version), _) => end_version,
+        (None, Some(metadata)) => metadata.version,
+        (None, None) => bail!("The file store has no metadata; pass an end version."),
+    };
+    let blob_size = operator.blob_size().await?;
+    ensure!(
+        start_version % blob_size == 0
+            && end_version % blob_size == 0
+            && start_version <= end_version,
+        "Start and end versions have to be ordered multiples of the blob size {}.",
+        blob_size
+    );
+
+    let versions: Vec<u64> = (start_version..end_version)
+        .step_by(blob_size as usize)
+        .collect();
+    let operator_ref = &*operator;
+    let results: Vec<(u64, Option<BlobDamage>)> = futures::stream::iter(versions)
+        .map(|version| async move {
+            (
+                version,
+                verify_blob(operator_ref, version, blob_size, check_digests).await,
+            )
+        })
+        .buffered(parallelism)
+        .collect()
+        .await;
+    let mut report = VerificationReport {
+        start_version,
+        end_version,
+        verified_blob_count: results.len(),
+        ..Default::default()
+    };
+    report.damaged_blobs = results
+        .into_iter()
+        .filter_map(|(version, damage)| damage.map(|damage| (version, damage)))
+        .collect();
+
+    if let Some(secondary) = secondary {
+        let chain_id = resolve_chain_id(cache_operator, &[&*operator, secondary]).await?;
+        for (version, damage) in &report.damaged_blobs {
+            match fix_blob(
+                operator,
+                secondary,
+                chain_id,
+                *version,
+                blob_size,
+                check_digests,
+            )
+            .await
+            {
+                Ok(()) => report.fixed_blobs.push(*version),
+                Err(err) => tracing::error!(
+                    version = version,
+                    damage = damage.to_string(),
+                    error = format!("{:#}", err),
+                    "[File store verifier] Failed to fix the blob."
+                ),
+            }
+        }
+    }
+    Ok(report)
+}
+
+/// Returns what is wrong with the blob at `version`, of `blob_size` versions, if anything. The blob
+/// is streamed, so that only one of its transactions is held in memory at a time.
+async fn verify_blob(
+    operator: &dyn FileStoreOperator,
+    version: u64,
+    blob_size: u64,
+    check_digests: bool,
+) -> Option<BlobDamage> {
+    let bytes = match operator
+        .get_raw_file_stream_with_retries(version, VERIFIER_DOWNLOAD_RETRIES)
+        .await
+    {
+        Ok(bytes) => bytes,
+        Err(err) => return Some(BlobDamage::Missing(format!("{:#}", err))),
+    };
+    let digest = StreamingBlobDigest::default();
+    let mut transactions =
+        decode_transaction_stream(digest.hash(bytes), operator.storage_format(), 0);
+    let mut transaction_count = 0;
+    while let Some(transaction) = transactions.next().await {
+        let expected_version = version + transaction_count;
+        match transaction {
+            Ok(transaction) if transaction.version == expected_version => {},
+            Ok(transaction) => {
+                return Some(BlobDamage::Corrupt(format!(
+                    "expected version {}, found {}",
+                    expected_version, transaction.version
+                )))
+            },
+            Err(err) => return Some(BlobDamage::Corrupt(format!("failed to decode: {:#}", err))),
+        }
+        transaction_count += 1;
+    }
+    if transaction_count != blob_size {
+        return Some(BlobDamage::Corrupt(format!(
+            "expected {} transactions, found {}",
+            blob_size, transaction_count
+        )));
+    }
+    if check_digests {
+        let digest = digest.digest();
+        match operator.get_blob_digest(version).await {
+            Ok(Some(recorded_digest)) if recorded_digest != digest => {
+                return Some(BlobDamage::Corrupt(format!(
+                    "digest {} does not match the recorded digest {}",
+                    digest, recorded_digest
+                )));
+            },
+            Ok(_) => {},
+            Err(err) => return Some(BlobDamage::Missing(format!("digest: {:#}", err))),
+        }
+    }
+    None
+}
+
+/// Checks the blob holds the contiguous versions `[start_version, start_version + blob_size)`.
+fn check_versions(transactions: &[Transaction], start_version: u64, blob_size: u64) -> Result<()> {
+    ensure!(
+        transactions.len() as u64 == blob_size,
+        "expected {} transactions, found {}",
+        blob_size,
+        transactions.len()
+    );
+    for (expected_version, transaction) in (start_version..).zip(transactions) {
+        ensure!(
+            transaction.version == expected_version,
+            "expected version {}, found {}",
+            expected_version,
+            transaction.version
+        );
+    }
+    Ok(())
+}
+
+/// Replaces the blob at `version` with the one of `secondary`, then verifies it again.
+async fn fix_blob(
+    operator: &mut dyn FileStoreOperator,
+    secondary: &dyn FileStoreOperator,
+    chain_id: ChainId,
+    version: u64,
+    blob_size: u64,
+    check_digests: bool,
+) -> Result<()> {
+    let transactions = secondary
+        .get_transactions(version, VERIFIER_DOWNLOAD_RETRIES)
+        .await
+        .with_context(|| format!("Failed to read the secondary blob at {}", version))?;
+    check_versions(&transactions, version, blob_size)
+        .context("The secondary blob is damaged too")?;
+    // Uploads never replace a blob holding different transactions.
+    operator.delete_blob(version).await?;
+    operator
+        .upload_transaction_batch(chain_id, transactions)
+        .await?;
+    if let Some(damage) = verify_blob(operator, version, blob_size, check_digests).await {
+        bail!("The re-fetched blob is {}", damage);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aptos_indexer_grpc_utils::{
+        compression_util::{FileEntry, FILE_ENTRY_TRANSACTION_COUNT},
+        file_store_operator::{
+            blob_transactions, InMemoryFileStoreOperator, LocalFileStoreOperator,
+        },
+    };
+
+    async fn file_store(blob_count: u64) -> InMemoryFileStoreOperator {
+        let mut operator = InMemoryFileStoreOperator::new(true, None);
+        for i in 0..blob_count {
+            operator
+                .upload_transaction_batch(
+                    ChainId(1),
+                    blob_transactions(i * FILE_ENTRY_TRANSACTION_COUNT),
+                )
+                .await
+                .unwrap();
+        }
+        operator
+            .update_file_store_metadata_with_timeout(
+                ChainId(1),
+                blob_count * FILE_ENTRY_TRANSACTION_COUNT,
+            )
+            .await
+            .unwrap();
+        operator
+    }
+
+    /// Damages the blobs at 1000 (undecodable), 2000 (valid but not the recorded digest), and
+    /// 3000 (missing) of a file store of 5 blobs.
+    async fn damaged_file_store() -> InMemoryFileStoreOperator {
+        let mut operator = file_store(5).await;
+        operator.replace_blob(1_000, b"corrupted".to_vec());
+        let mut shuffled_transactions = blob_transactions(2_000);
+        shuffled_transactions.swap(998, 999);
+        operator.replace_blob(
+            2_000,
+            FileEntry::from_transactions(shuffled_transactions, operator.storage_format())
+                .into_inner(),
+        );
+        operator.delete_blob(3_000).await.unwrap();
+        operator
+    }
+
+    #[tokio::test]
+    async fn intact_file_store_is_verified() {
+        let mut operator = file_store(3).await;
+        let report = verify_file_store(&mut operator, None, None, 0, None, 2, true)
+            .await
+            .unwrap();
+        assert!(report.is_intact());
+        assert_eq!(report.verified_blob_count, 3);
+        assert_eq!(report.end_version, 3_000);
+        assert!(report.damaged_blobs.is_empty());
+    }
+
+    #[tokio::test]
+    async fn damaged_blobs_are_reported() {
+        let mut operator = damaged_file_store().await;
+        let report = verify_file_store(&mut operator, None, None, 0, None, 2, true)
+            .await
+            .unwrap();
+        assert!(!report.is_intact());
+        assert_eq!(report.verified_blob_count, 5);
+        let damaged: Vec<(u64, bool)> = report
+            .damaged_blobs
+            .iter()
+            .map(|(version, damage)| (*version, matches!(damage, BlobDamage::Missing(_))))
+            .collect();
+        assert_eq!(damaged, vec![(1_000, false), (2_000, false), (3_000, true)]);
+        assert!(report.to_string().contains("3 damaged, 0 fixed"));
+        assert!(report
+            .to_string()
+            .contains("First anomaly at version 1000: corrupt"));
+
+        // Only the requested range is verified.
+        let report = verify_file_store(&mut operator, None, None, 4_000, Some(5_000), 2, true)
+            .await
+            .unwrap();
+        assert!(report.is_intact());
+        assert_eq!(report.verified_blob_count, 1);
+    }
+
+    #[tokio::test]
+    async fn injected_gap_is_the_first_anomaly() {
+        let mut operator = file_store(4).await;
+        operator.delete_blob(2_000).await.unwrap();
+        let report = verify_file_store(&mut operator, None, None, 0, None, 2, false)
+            .await
+            .unwrap();
+        assert!(!report.is_intact());
+        let (version, damage) = report.first_anomaly().unwrap();
+        assert_eq!(*version, 2_000);
+        assert!(matches!(damage, BlobDamage::Missing(_)));
+        assert!(report.to_string().contains("versions 0-2000 are intact"));
+
+        // A blob holding the versions of its neighbour, i.e., an overlap, is reported too.
+        let mut operator = file_store(4).await;
+        operator.replace_blob(
+            1_000,
+            FileEntry::from_transactions(blob_transactions(2_000), operator.storage_format())
+                .into_inner(),
+        );
+        let report = verify_file_store(&mut operator, None, None, 0, None, 2, false)
+            .await
+            .unwrap();
+        assert_eq!(report.first_anomaly().unwrap().0, 1_000);
+        assert_eq!(report.damaged_blobs.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn damaged_blobs_are_fixed_from_the_secondary() {
+        let mut operator = damaged_file_store().await;
+        let secondary = file_store(5).await;
+        let report = verify_file_store(&mut operator, Some(&secondary), None, 0, None, 2, true)
+            .await
+            .unwrap();
+        assert!(report.is_intact());
+        assert_eq!(report.fixed_blobs, vec![1_000, 2_000, 3_000]);
+        for version in [1_000, 2_000, 3_000] {
+            assert_eq!(
+                operator.get_transactions(version, 0).await.unwrap(),
+                blob_transactions(version)
+            );
+        }
+
+        // A secondary missing the blob leaves it damaged.
+        let mut operator = damaged_file_store().await;
+        let secondary = file_store(2).await;
+        let report = verify_file_store(&mut operator, Some(&secondary), None, 0, None, 2, true)
+            .await
+            .unwrap();
+        assert!(!report.is_intact());
+        assert_eq!(report.fixed_blobs, vec![1_000]);
+    }
+
+    #[tokio::test]
+    async fn verifier_starts_without_the_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config_paths = vec![];
+        for (name, blob_versions) in [("primary", vec![0]), ("secondary", vec![0, 1_000])] {
+            let path = dir.path().join(name);
+            std::fs::create_dir(&path).unwrap();
+            let mut operator = LocalFileStoreOperator::new(path.clone(), false, None);
+            for version in blob_versions {
+                operator
+                    .upload_transaction_batch(ChainId(1), blob_transactions(version))
+                    .await
+                    .unwrap();
+            }
+            operator
+                .update_file_store_metadata_with_timeout(ChainId(1), 2_000)
+                .await
+                .unwrap();
+            let config_path = dir.path().join(format!("{}.yaml", name));
+            std::fs::write(
+                &config_path,
+                format!(
+                    "file_store_type: LocalFileStore\nlocal_file_store_path: {}\n",
+                    path.display()
+                ),
+            )
+            .unwrap();
+            config_paths.push(config_path);
+        }
+
+        // Nothing listens on the port, so the chain id of the fixed blob comes from the metadata.
+        run_verifier(VerifyArgs {
+            config_path: config_paths[0].clone(),
+            start_version: 0,
+            end_version: None,
+            parallelism: 2,
+            fix_from: Some(config_paths[1].clone()),
+            skip_digests: false,
+            redis_main_instance_address: Some("redis://127.0.0.1:1".parse().unwrap()),
+        })
+        .await
+        .unwrap();
+        let operator = LocalFileStoreOperator::new(dir.path().join("primary"), false, None);
+        assert_eq!(
+            operator.get_transactions(1_000, 0).await.unwrap(),
+            blob_transactions(1_000)
+        );
+    }
+}
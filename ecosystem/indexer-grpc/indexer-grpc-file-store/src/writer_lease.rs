@@ -0,0 +1,266 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::{error::ProcessorError, WriterLeaseConfig};
+use anyhow::{anyhow, Result};
+use aptos_indexer_grpc_utils::cache_operator::CacheOperator;
+use std::{future::Future, time::Duration};
+use tokio::{sync::watch, time::Instant};
+
+const SERVICE_TYPE: &str = "file_worker";
+// Prefix of the Redis key of the lease; the location of the file store follows it.
+const WRITER_LEASE_KEY_PREFIX: &str = "file_store_writer_lease";
+
+/// WriterLease is a lease in Redis, keyed by the location of the file store, that only one
+/// processor holds at a time, so that two processors never write to the same file store. The holder
+/// renews it while writing, and stops writing once it expires without being renewed.
+pub struct WriterLease {
+    key: String,
+    holder: String,
+    duration: Duration,
+    wait_for_lease: bool,
+    // When the lease expires, unless renewed; watched by `expired`.
+    expires_at: watch::Sender<Instant>,
+}
+
+impl WriterLease {
+    pub fn new(config: &WriterLeaseConfig, file_store_location: &str) -> Self {
+        Self {
+            key: format!("{}:{}", WRITER_LEASE_KEY_PREFIX, file_store_location),
+            holder: config.holder_id.clone().unwrap_or_else(default_holder_id),
+            duration: Duration::from_secs(config.lease_duration_in_secs),
+            wait_for_lease: config.wait_for_lease,
+            expires_at: watch::channel(Instant::now()).0,
+        }
+    }
+
+    pub fn holder(&self) -> &str {
+        &self.holder
+    }
+
+    pub fn expires_at(&self) -> std::time::Instant {
+        self.expires_at.borrow().into_std()
+    }
+
+    /// Takes the lease. If another processor holds it, waits until it's released or expires, or
+    /// fails if configured not to wait.
+    pub async fn acquire<T: redis::aio::ConnectionLike + Send + Clone>(
+        &mut self,
+        cache_operator: &mut CacheOperator<T>,
+    ) -> Result<()> {
+        loop {
+            let requested_at = Instant::now();
+            let lease = cache_operator
+                .acquire_lease(&self.key, &self.holder, self.duration)
+                .await?;
+            if lease.is_held_by(&self.holder) {
+                self.expires_at.send_replace(requested_at + self.duration);
+                tracing::info!(
+                    key = self.key,
+                    holder = self.holder,
+                    service_type = SERVICE_TYPE,
+                    "[File worker] Acquired the file store writer lease."
+                );
+                return Ok(());
+            }
+            if !self.wait_for_lease {
+                return Err(ProcessorError::Lease(anyhow!(
+                    "The file store writer lease {} is held by {}.",
+                    self.key,
+                    lease.holder
+                ))
+                .into());
+            }
+            // Polled every lease duration if the lease doesn't expire.
+            let delay = match lease.expires_in {
+                Duration::ZERO => self.duration,
+                expires_in => expires_in.clamp(Duration::from_millis(100), self.duration),
+            };
+            tracing::info!(
+                key = self.key,
+                holder = lease.holder,
+                expires_in_millis = lease.expires_in.as_millis() as u64,
+                service_type = SERVICE_TYPE,
+                "[File worker] File store writer lease is held by another processor; waiting."
+            );
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    /// Renews the lease once a third of it has elapsed. Fails if another processor took it over. A
+    /// failed renewal is only logged: the lease still holds until it expires, and `expired`
+    /// completes then.
+    pub async fn renew_if_due<T: redis::aio::ConnectionLike + Send + Clone>(
+        &mut self,
+        cache_operator: &mut CacheOperator<T>,
+    ) -> Result<()> {
+        let expires_at = *self.expires_at.borrow();
+        if expires_at.saturating_duration_since(Instant::now()) > self.duration * 2 / 3 {
+            return Ok(());
+        }
+        let requested_at = Instant::now();
+        match cache_operator
+            .acquire_lease(&self.key, &self.holder, self.duration)
+            .await
+        {
+            Ok(lease) if lease.is_held_by(&self.holder) && requested_at < expires_at => {
+                self.expires_at.send_replace(requested_at + self.duration);
+                Ok(())
+            },
+            // Taken over, or expired before the renewal; another processor may have written since.
+            Ok(lease) => Err(ProcessorError::Lease(anyhow!(
+                "The file store writer lease {} was lost; it's held by {}.",
+                self.key,
+                lease.holder
+            ))
+            .into()),
+            Err(err) => {
+                tracing::warn!(
+                    key = self.key,
+                    expires_in_millis =
+                        expires_at.saturating_duration_since(Instant::now()).as_millis() as u64,
+                    service_type = SERVICE_TYPE,
+                    error = ?err,
+                    "[File worker] Failed to renew the file store writer lease."
+                );
+                Ok(())
+            },
+        }
+    }
+
+    /// Releases the lease, so that another processor can take it over without waiting for it to
+    /// expire. Best effort.
+    pub async fn release<T: redis::aio::ConnectionLike + Send + Clone>(
+        &mut self,
+        cache_operator: &mut CacheOperator<T>,
+    ) {
+        match cache_operator.release_lease(&self.key, &self.holder).await {
+            Ok(_) => {
+                self.expires_at.send_replace(Instant::now());
+            },
+            Err(err) => tracing::warn!(
+                key = self.key,
+                service_type = SERVICE_TYPE,
+                error = ?err,
+                "[File worker] Failed to release the file store writer lease."
+            ),
+        }
+    }
+
+    /// Completes once the lease expires without being renewed.
+    pub fn expired(&self) -> impl Future<Output = ()> + Send + 'static {
+        let mut expires_at = self.expires_at.subscribe();
+        async move {
+            loop {
+                let deadline = *expires_at.borrow_and_update();
+                tokio::select! {
+                    _ = tokio::time::sleep_until(deadline) => return,
+                    changed = expires_at.changed() => {
+                        // The lease is gone with its processor.
+                        if changed.is_err() {
+                            std::future::pending::<()>().await;
+                        }
+                    },
+                }
+            }
+        }
+    }
+}
+
+/// Name of the host, e.g., the pod on Kubernetes, and id of the process.
+fn default_holder_id() -> String {
+    let host = std::env::var("HOSTNAME").unwrap_or_else(|_| "unknown".to_string());
+    format!("{}:{}", host, std::process::id())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aptos_indexer_grpc_utils::{
+        cache_operator::acquire_lease_cmd, compression_util::StorageFormat,
+    };
+    use redis_test::{MockCmd, MockRedisConnection};
+
+    fn writer_lease(holder: &str, lease_duration_in_secs: u64, wait: bool) -> WriterLease {
+        WriterLease::new(
+            &WriterLeaseConfig {
+                lease_duration_in_secs,
+                wait_for_lease: wait,
+                holder_id: Some(holder.to_string()),
+            },
+            "gs://bucket",
+        )
+    }
+
+    fn acquire_cmd(holder: &str, duration_in_millis: u64, reply: (&str, i64)) -> MockCmd {
+        MockCmd::new(
+            acquire_lease_cmd(
+                "file_store_writer_lease:gs://bucket",
+                holder,
+                Duration::from_millis(duration_in_millis),
+            ),
+            Ok(redis::Value::Bulk(vec![
+                redis::Value::Data(reply.0.as_bytes().to_vec()),
+                redis::Value::Int(reply.1),
+            ])),
+        )
+    }
+
+    fn cache_operator(cmds: Vec<MockCmd>) -> CacheOperator<MockRedisConnection> {
+        CacheOperator::new(
+            MockRedisConnection::new(cmds),
+            StorageFormat::Base64UncompressedProto,
+        )
+    }
+
+    #[tokio::test]
+    async fn held_leases_are_waited_for_or_fail() {
+        let mut cache_operator = cache_operator(vec![
+            acquire_cmd("a", 1_000, ("b", 50)),
+            acquire_cmd("a", 1_000, ("a", 1_000)),
+            acquire_cmd("c", 1_000, ("a", 900)),
+        ]);
+        let mut lease = writer_lease("a", 1, true);
+        lease.acquire(&mut cache_operator).await.unwrap();
+        assert_eq!(lease.holder(), "a");
+        assert!(lease.expires_at() > std::time::Instant::now());
+
+        let err = writer_lease("c", 1, false)
+            .acquire(&mut cache_operator)
+            .await
+            .unwrap_err();
+        let err = ProcessorError::from(err);
+        assert_eq!(err.kind(), "lease");
+        assert_eq!(
+            err.to_string(),
+            "The file store writer lease file_store_writer_lease:gs://bucket is held by a."
+        );
+    }
+
+    #[tokio::test]
+    async fn leases_expire_unless_renewed() {
+        let mut cache_operator = cache_operator(vec![
+            acquire_cmd("a", 300_000, ("a", 300_000)),
+            acquire_cmd("a", 300_000, ("b", 300_000)),
+        ]);
+        let mut lease = writer_lease("a", 300, true);
+        lease.acquire(&mut cache_operator).await.unwrap();
+        // Not due yet, so Redis isn't asked.
+        lease.renew_if_due(&mut cache_operator).await.unwrap();
+
+        // Taken over once due.
+        lease
+            .expires_at
+            .send_replace(Instant::now() + Duration::from_secs(60));
+        let err = lease.renew_if_due(&mut cache_operator).await.unwrap_err();
+        assert_eq!(ProcessorError::from(err).kind(), "lease");
+
+        let expired = lease.expired();
+        lease
+            .expires_at
+            .send_replace(Instant::now() + Duration::from_millis(20));
+        tokio::time::timeout(Duration::from_secs(5), expired)
+            .await
+            .unwrap();
+    }
+}
@@ -1,9 +1,11 @@
 // Copyright © Aptos Foundation
 
 use crate::{counters::CHANNEL_SIZE, stream_coordinator::IndexerStreamCoordinator, ServiceContext};
-use aptos_indexer_grpc_utils::counters::{log_grpc_step_fullnode, IndexerGrpcStep};
+use aptos_indexer_grpc_utils::{
+    counters::{log_grpc_step_fullnode, IndexerGrpcStep},
+    moving_average::MovingAverage,
+};
 use aptos_logger::{error, info};
-use aptos_moving_average::MovingAverage;
 use aptos_protos::internal::fullnode::v1::{
     fullnode_data_server::FullnodeData, stream_status::StatusType, transactions_from_node_response,
     GetTransactionsFromNodeRequest, StreamStatus, TransactionsFromNodeResponse,
@@ -3,6 +3,6 @@
 
 // We hide these tests behind a feature flag because these are not standard unit tests,
 // these are integration tests that rely on a variety of outside pieces such as a local
-// testnet and a running Redis instance.
+// testnet, a running Redis instance, and a fake-gcs-server.
 #[cfg(feature = "integration-tests")]
 mod tests;
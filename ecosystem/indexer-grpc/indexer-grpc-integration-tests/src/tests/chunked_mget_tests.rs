@@ -0,0 +1,88 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+//! Times chunked cache reads against a Redis on port 6379, e.g.:
+//! `docker run -d --net host redis:7.0`
+
+use anyhow::Result;
+use aptos_indexer_grpc_utils::{
+    cache_operator::CacheOperator,
+    compression_util::{StorageFormat, FILE_ENTRY_TRANSACTION_COUNT},
+};
+use aptos_protos::{
+    transaction::v1::{Transaction, TransactionInfo},
+    util::timestamp::Timestamp,
+};
+use redis::aio::ConnectionManager;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+const REDIS_URL: &str = "redis://127.0.0.1:6379";
+// Size of every cached transaction, so that a batch is a multi-megabyte MGET reply.
+const TRANSACTION_SIZE_IN_BYTES: usize = 16 * 1024;
+
+/// Latency of a small request sent while a batch of `FILE_ENTRY_TRANSACTION_COUNT`
+/// transactions is read over the same connection.
+async fn small_request_latency_during_batch_read(
+    conn: ConnectionManager,
+    mget_chunk_size: Option<usize>,
+) -> Result<Duration> {
+    let mut batch_reader = CacheOperator::new(conn.clone(), StorageFormat::Base64UncompressedProto)
+        .with_mget_chunk_size(mget_chunk_size);
+    let batch_read = tokio::spawn(async move {
+        batch_reader
+            .get_transactions(0, FILE_ENTRY_TRANSACTION_COUNT)
+            .await
+    });
+    tokio::time::sleep(Duration::from_millis(1)).await;
+    let mut small_reader = CacheOperator::new(conn, StorageFormat::Base64UncompressedProto);
+    let start_time = Instant::now();
+    small_reader.get_latest_version().await?;
+    let latency = start_time.elapsed();
+    assert_eq!(
+        batch_read.await??.len() as u64,
+        FILE_ENTRY_TRANSACTION_COUNT
+    );
+    Ok(latency)
+}
+
+#[tokio::test]
+async fn test_chunked_mget_does_not_block_the_shared_connection() -> Result<()> {
+    let conn = redis::Client::open(REDIS_URL)?
+        .get_tokio_connection_manager()
+        .await?;
+    let mut cache_operator =
+        CacheOperator::new(conn.clone(), StorageFormat::Base64UncompressedProto);
+    cache_operator.cache_setup_if_needed().await?;
+
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
+    let transactions: Vec<Transaction> = (0..FILE_ENTRY_TRANSACTION_COUNT)
+        .map(|version| Transaction {
+            version,
+            timestamp: Some(Timestamp {
+                seconds: now,
+                nanos: 0,
+            }),
+            info: Some(TransactionInfo {
+                hash: vec![0; TRANSACTION_SIZE_IN_BYTES],
+                ..TransactionInfo::default()
+            }),
+            ..Transaction::default()
+        })
+        .collect();
+    cache_operator
+        .update_cache_transactions(transactions)
+        .await?;
+    cache_operator
+        .update_cache_latest_version(FILE_ENTRY_TRANSACTION_COUNT, FILE_ENTRY_TRANSACTION_COUNT)
+        .await?;
+
+    let unchunked_latency = small_request_latency_during_batch_read(conn.clone(), None).await?;
+    let chunked_latency = small_request_latency_during_batch_read(conn, Some(100)).await?;
+    assert!(
+        chunked_latency < unchunked_latency,
+        "chunked: {:?}, unchunked: {:?}",
+        chunked_latency,
+        unchunked_latency
+    );
+    Ok(())
+}
@@ -300,12 +300,13 @@ async fn test_cold_start_file_store_worker_progress() {
         redis_main_instance_address: (*REDIS_PRIMARY_URL).clone(),
     };
 
-    let file_store_worker_config = IndexerGrpcFileStoreWorkerConfig {
-        redis_main_instance_address: (*REDIS_PRIMARY_URL).clone(),
-        file_store_config: IndexerGrpcFileStoreConfig::LocalFileStore(LocalFileStore {
+    let file_store_worker_config = IndexerGrpcFileStoreWorkerConfig::new(
+        IndexerGrpcFileStoreConfig::LocalFileStore(LocalFileStore {
             local_file_store_path: tmp_dir.path().to_path_buf(),
         }),
-    };
+        (*REDIS_PRIMARY_URL).clone(),
+        aptos_indexer_grpc_utils::types::ChainId(ChainId::test().id().into()),
+    );
 
     let (_cache_worker_port, _cache_worker_handle) =
         start_server::<IndexerGrpcCacheWorkerConfig>(cache_worker_config.clone())
@@ -0,0 +1,96 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+//! Runs the GCS file store operator against a fake-gcs-server, e.g.:
+//! `docker run -d -p 4443:4443 fsouza/fake-gcs-server -scheme http`
+
+use anyhow::{ensure, Result};
+use aptos_indexer_grpc_utils::{
+    compression_util::FILE_ENTRY_TRANSACTION_COUNT,
+    config::{GcsCredentialSource, GcsFileStore, GcsRetryConfig, IndexerGrpcFileStoreConfig},
+    file_store_operator::ExistingBlobCheck,
+    types::ChainId,
+};
+use aptos_protos::transaction::v1::Transaction;
+use std::{
+    collections::BTreeMap,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+static FAKE_GCS_SERVER_URL: &str = "http://127.0.0.1:4443";
+
+async fn create_bucket(bucket_name: &str) -> Result<()> {
+    let response = reqwest::Client::new()
+        .post(format!("{}/storage/v1/b?project=test", FAKE_GCS_SERVER_URL))
+        .json(&serde_json::json!({ "name": bucket_name }))
+        .send()
+        .await?;
+    ensure!(
+        response.status().is_success(),
+        "Failed to create bucket {}: {}",
+        bucket_name,
+        response.text().await?
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_gcs_file_store_with_fake_gcs_server() -> Result<()> {
+    let bucket_name = format!(
+        "indexer-grpc-file-store-{}",
+        SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis()
+    );
+    create_bucket(&bucket_name).await?;
+    let mut operator = IndexerGrpcFileStoreConfig::GcsFileStore(GcsFileStore {
+        gcs_file_store_bucket_name: bucket_name,
+        gcs_file_store_service_account_key_path: None,
+        gcs_credential_source: GcsCredentialSource::KeyFile,
+        enable_compression: true,
+        zstd_compression_level: None,
+        enable_parquet: false,
+        encryption_key_path: None,
+        gcs_endpoint: Some(FAKE_GCS_SERVER_URL.to_string()),
+        gcs_anonymous_credentials: true,
+        gcs_retry_config: GcsRetryConfig::default(),
+        gcs_resumable_upload_threshold_in_bytes: None,
+        gcs_customer_supplied_encryption_key_path: None,
+        gcs_kms_key_name: None,
+        gcs_blob_metadata: BTreeMap::new(),
+        key_layout: None,
+        blob_size_in_versions: None,
+        existing_blob_check: ExistingBlobCheck::default(),
+    })
+    .create();
+    operator.verify_storage_bucket_existence().await;
+
+    // The metadata is created if absent.
+    assert!(operator.get_file_store_metadata().await.is_none());
+    // Metadata updates are throttled right after the operator is created.
+    tokio::time::sleep(Duration::from_millis(250)).await;
+    operator
+        .update_file_store_metadata_with_timeout(ChainId(1), 0)
+        .await?;
+    let metadata = operator.get_file_store_metadata().await.unwrap();
+    assert_eq!((metadata.chain_id, metadata.version), (ChainId(1), 0));
+
+    let transactions: Vec<Transaction> = (0..FILE_ENTRY_TRANSACTION_COUNT)
+        .map(|version| Transaction {
+            version,
+            epoch: 1,
+            ..Transaction::default()
+        })
+        .collect();
+    let (first_version, last_version, _) = operator
+        .upload_transaction_batch(ChainId(1), transactions.clone())
+        .await?;
+    assert_eq!((first_version, last_version), (0, 999));
+    assert_eq!(operator.get_transactions(0, 1).await?, transactions);
+    assert_eq!(operator.verify_blob_digest(0).await?, Some(true));
+
+    tokio::time::sleep(Duration::from_millis(250)).await;
+    operator
+        .update_file_store_metadata_with_timeout(ChainId(1), 1_000)
+        .await?;
+    assert_eq!(operator.get_latest_version().await, Some(1_000));
+    Ok(())
+}
@@ -1,5 +1,11 @@
 // Copyright © Aptos Foundation
 // SPDX-License-Identifier: Apache-2.0
 
+#[cfg(test)]
+mod chunked_mget_tests;
 #[cfg(test)]
 mod fullnode_tests;
+#[cfg(test)]
+mod gcs_file_store_tests;
+#[cfg(test)]
+mod redis_cluster_tests;
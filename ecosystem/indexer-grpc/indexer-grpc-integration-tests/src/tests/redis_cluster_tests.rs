@@ -0,0 +1,51 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+//! Runs the cache operator against a Redis Cluster with nodes on ports 7000-7005, e.g.:
+//! `docker run -d --net host -e IP=127.0.0.1 grokzen/redis-cluster:7.0.10`
+
+use anyhow::Result;
+use aptos_indexer_grpc_utils::{
+    cache_operator::CacheOperator,
+    compression_util::StorageFormat,
+    redis_cluster::CacheConnection,
+    redis_tls::RedisTlsConfig,
+    types::{ChainId, RedisUrl},
+};
+use aptos_protos::{transaction::v1::Transaction, util::timestamp::Timestamp};
+use std::{
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+#[tokio::test]
+async fn test_cache_operator_with_redis_cluster() -> Result<()> {
+    let seed = RedisUrl::from_str("redis://127.0.0.1:7000")?;
+    let other_seed = RedisUrl::from_str("redis://127.0.0.1:7001")?;
+    let conn = CacheConnection::connect(&seed, &[other_seed], &RedisTlsConfig::default()).await?;
+    let mut cache_operator = CacheOperator::new(conn, StorageFormat::Base64UncompressedProto);
+    cache_operator.cache_setup_if_needed().await?;
+    cache_operator.set_chain_id(ChainId(1)).await?;
+
+    // Consecutive versions hash to slots served by different nodes.
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
+    let transactions: Vec<Transaction> = (0..100)
+        .map(|version| Transaction {
+            version,
+            timestamp: Some(Timestamp {
+                seconds: now,
+                nanos: 0,
+            }),
+            ..Transaction::default()
+        })
+        .collect();
+    cache_operator
+        .update_cache_transactions(transactions.clone())
+        .await?;
+    cache_operator.update_cache_latest_version(100, 100).await?;
+
+    assert_eq!(cache_operator.get_latest_version().await?, Some(100));
+    assert_eq!(cache_operator.get_chain_id().await?, Some(ChainId(1)));
+    assert_eq!(cache_operator.get_transactions(0, 100).await?, transactions);
+    Ok(())
+}
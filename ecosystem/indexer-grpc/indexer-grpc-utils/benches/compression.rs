@@ -0,0 +1,86 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+use aptos_indexer_grpc_utils::compression_util::{
+    FileEntry, StorageFormat, FILE_ENTRY_TRANSACTION_COUNT,
+};
+use aptos_protos::{transaction::v1::Transaction, util::timestamp::Timestamp};
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use prost::Message;
+
+// Zstd compression levels to compare against gzip.
+const ZSTD_COMPRESSION_LEVELS: [i32; 3] = [1, 3, 9];
+
+fn sample_transactions() -> Vec<Transaction> {
+    (0..FILE_ENTRY_TRANSACTION_COUNT)
+        .map(|version| Transaction {
+            version,
+            epoch: version / 100,
+            block_height: version / 10,
+            timestamp: Some(Timestamp {
+                seconds: 1_700_000_000 + version as i64,
+                nanos: 0,
+            }),
+            ..Transaction::default()
+        })
+        .collect()
+}
+
+fn bench_file_entry(c: &mut Criterion) {
+    let transactions = sample_transactions();
+    let raw_size: usize = transactions.iter().map(|t| t.encoded_len()).sum();
+
+    let mut formats = vec![("gzip".to_string(), StorageFormat::GzipCompressedProto, 0)];
+    for level in ZSTD_COMPRESSION_LEVELS {
+        formats.push((
+            format!("zstd_level_{}", level),
+            StorageFormat::ZstdCompressedProto,
+            level,
+        ));
+    }
+
+    let mut group = c.benchmark_group("file_entry_encode");
+    group.throughput(Throughput::Bytes(raw_size as u64));
+    for (name, storage_format, level) in &formats {
+        group.bench_function(name.as_str(), |b| {
+            b.iter_batched(
+                || transactions.clone(),
+                |transactions| {
+                    FileEntry::from_transactions_with_compression_level(
+                        transactions,
+                        *storage_format,
+                        *level,
+                    )
+                },
+                BatchSize::SmallInput,
+            )
+        });
+    }
+    group.finish();
+
+    let mut group = c.benchmark_group("file_entry_decode");
+    group.throughput(Throughput::Bytes(raw_size as u64));
+    for (name, storage_format, level) in &formats {
+        let bytes = FileEntry::from_transactions_with_compression_level(
+            transactions.clone(),
+            *storage_format,
+            *level,
+        )
+        .into_inner();
+        group.bench_function(name.as_str(), |b| {
+            b.iter_batched(
+                || bytes.clone(),
+                |bytes| {
+                    FileEntry::new(bytes, *storage_format)
+                        .into_transactions_in_storage()
+                        .unwrap()
+                },
+                BatchSize::SmallInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_file_entry);
+criterion_main!(benches);
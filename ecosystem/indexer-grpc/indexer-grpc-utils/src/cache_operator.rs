@@ -2,12 +2,23 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use crate::{
-    compression_util::{CacheEntry, StorageFormat, FILE_ENTRY_TRANSACTION_COUNT},
-    counters::{log_grpc_step, IndexerGrpcStep},
+    compression_util::{
+        CacheEntry, StorageFormat, DEFAULT_ZSTD_COMPRESSION_LEVEL, FILE_ENTRY_TRANSACTION_COUNT,
+    },
+    counters::{
+        log_grpc_step, IndexerGrpcStep, CACHE_BATCH_GET_STATUS_COUNT, CACHE_MGET_CHUNK_RETRIES,
+        CACHE_OPERATION_ERROR_COUNT, CACHE_OPERATION_LATENCY_IN_SECS,
+    },
+    rate_limiter::RateLimiter,
+    types::ChainId,
 };
 use anyhow::{ensure, Context};
 use aptos_protos::transaction::v1::Transaction;
+use backoff::{backoff::Backoff, ExponentialBackoff};
+use futures::{Future, StreamExt, TryStreamExt};
 use redis::{AsyncCommands, RedisResult};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
 
 // Configurations for cache.
 // Cache entries that are present.
@@ -28,6 +39,19 @@ const CACHE_KEY_CHAIN_ID: &str = "chain_id";
 // 9999-12-31 23:59:59. UTC.
 const BASE_EXPIRATION_EPOCH_TIME_IN_SECONDS: u64 = 253_402_300_799;
 
+// Number of MGET chunks of a batch sent concurrently, each as its own request on the connection;
+// other requests sharing the connection wait for at most this many chunks.
+const MAX_CONCURRENT_MGET_CHUNKS: usize = 2;
+// Number of times a failed MGET chunk is retried before the batch read fails.
+const MGET_CHUNK_RETRIES: usize = 3;
+// Backoff between retries of a failed MGET chunk; it starts here and grows, with jitter, up to the
+// max, so that chunks failing at once don't hit a struggling Redis together again.
+const MGET_CHUNK_INITIAL_BACKOFF_IN_MILLIS: u64 = 50;
+const MGET_CHUNK_MAX_BACKOFF_IN_MILLIS: u64 = 1_000;
+
+// Number of versions whose keys are deleted by a single DEL when evicting transactions.
+const EVICTION_CHUNK_SIZE_IN_VERSIONS: u64 = 1_000;
+
 // Default values for cache.
 const CACHE_DEFAULT_LATEST_VERSION_NUMBER: &str = "0";
 const FILE_STORE_LATEST_VERSION: &str = "file_store_latest_version";
@@ -56,6 +80,58 @@ const CACHE_SCRIPT_UPDATE_LATEST_VERSION: &str = r#"
     end
 "#;
 
+/// This Lua script is used to acquire or renew a lease.
+///   Returns the holder and the time to live of the lease in milliseconds; the lease is only
+///   taken, or extended, if it's free or already held by ARGV[1].
+const CACHE_SCRIPT_ACQUIRE_LEASE: &str = r#"
+    local holder = redis.call("GET", KEYS[1])
+    if not holder then
+        redis.call("SET", KEYS[1], ARGV[1], "PX", ARGV[2])
+        return {ARGV[1], tonumber(ARGV[2])}
+    elseif holder == ARGV[1] then
+        redis.call("PEXPIRE", KEYS[1], ARGV[2])
+        return {holder, tonumber(ARGV[2])}
+    else
+        return {holder, redis.call("PTTL", KEYS[1])}
+    end
+"#;
+
+/// This Lua script is used to release a lease.
+///   Returns 1 if the lease was held by ARGV[1] and is released, 0 otherwise.
+const CACHE_SCRIPT_RELEASE_LEASE: &str = r#"
+    if redis.call("GET", KEYS[1]) == ARGV[1] then
+        return redis.call("DEL", KEYS[1])
+    else
+        return 0
+    end
+"#;
+
+/// Lease stored in the cache, as seen by the last acquisition attempt.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheLease {
+    pub holder: String,
+    // Zero if the lease doesn't expire.
+    pub expires_in: Duration,
+}
+
+impl CacheLease {
+    pub fn is_held_by(&self, holder: &str) -> bool {
+        self.holder == holder
+    }
+}
+
+/// Command `CacheOperator::acquire_lease` sends to Redis, for mocked connections.
+#[cfg(any(test, feature = "testing"))]
+pub fn acquire_lease_cmd(key: &str, holder: &str, duration: Duration) -> redis::Cmd {
+    let mut cmd = redis::cmd("EVALSHA");
+    cmd.arg(redis::Script::new(CACHE_SCRIPT_ACQUIRE_LEASE).get_hash())
+        .arg(1)
+        .arg(key)
+        .arg(holder)
+        .arg(duration.as_millis() as u64);
+    cmd
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum CacheBatchGetStatus {
     /// OK with batch of encoded transactions.
@@ -66,6 +142,57 @@ pub enum CacheBatchGetStatus {
     NotReady,
 }
 
+impl CacheBatchGetStatus {
+    /// Label of the outcome in `CACHE_BATCH_GET_STATUS_COUNT`.
+    fn label(&self) -> &'static str {
+        match self {
+            Self::Ok(_) => "ok",
+            Self::EvictedFromCache => "evicted_from_cache",
+            Self::NotReady => "not_ready",
+        }
+    }
+}
+
+/// Outcome of `batch_get_available_encoded_proto_data`, with the latest version of the cache.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CacheAvailableBatch {
+    /// Contiguous encoded transactions from the requested version on, up to the requested count;
+    /// empty if the requested version isn't in the cache yet.
+    Ok {
+        encoded_transactions: Vec<Vec<u8>>,
+        cache_head_version: u64,
+    },
+    /// Requested version is already evicted from cache. Visit file store instead.
+    EvictedFromCache { cache_head_version: u64 },
+}
+
+impl CacheAvailableBatch {
+    pub fn cache_head_version(&self) -> u64 {
+        match self {
+            Self::Ok {
+                cache_head_version, ..
+            }
+            | Self::EvictedFromCache { cache_head_version } => *cache_head_version,
+        }
+    }
+
+    /// Label of the outcome in `CACHE_BATCH_GET_STATUS_COUNT`; `not_ready` if nothing is available.
+    fn label(&self, transaction_count: u64) -> &'static str {
+        match self {
+            Self::Ok {
+                encoded_transactions,
+                ..
+            } if encoded_transactions.is_empty() => "not_ready",
+            Self::Ok {
+                encoded_transactions,
+                ..
+            } if (encoded_transactions.len() as u64) < transaction_count => "partial",
+            Self::Ok { .. } => "ok",
+            Self::EvictedFromCache { .. } => "evicted_from_cache",
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum CacheUpdateStatus {
     /// 0 - Cache is updated from version x to x + 1. New key `x+1` with corresponding encoded data is added.
@@ -87,6 +214,69 @@ pub enum CacheCoverageStatus {
     CacheEvicted,
 }
 
+/// Retention of the cache: how far behind its head transactions are served and kept. It has to be
+/// the same for the cache worker and all the services reading the cache.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct CacheRetentionPolicy {
+    // Number of versions behind the cache head that are served; older ones are reported as evicted.
+    #[serde(default = "CacheRetentionPolicy::default_retention_in_versions")]
+    pub retention_in_versions: u64,
+    // The cache worker deletes the entry this many versions behind every version it writes. It
+    // has to exceed `retention_in_versions`, since the head and the entries aren't read atomically.
+    #[serde(default = "CacheRetentionPolicy::default_eviction_distance_in_versions")]
+    pub eviction_distance_in_versions: u64,
+    // If set, entries also expire this many seconds after they're written; by default, they don't.
+    #[serde(default)]
+    pub ttl_in_seconds: Option<u64>,
+}
+
+impl CacheRetentionPolicy {
+    pub const fn default_retention_in_versions() -> u64 {
+        CACHE_SIZE_ESTIMATION
+    }
+
+    pub const fn default_eviction_distance_in_versions() -> u64 {
+        CACHE_SIZE_EVICTION_LOWER_BOUND
+    }
+
+    pub fn validate(&self) -> anyhow::Result<()> {
+        ensure!(
+            self.retention_in_versions > 0,
+            "retention_in_versions must be at least 1"
+        );
+        ensure!(
+            self.eviction_distance_in_versions > self.retention_in_versions,
+            "eviction_distance_in_versions must be greater than retention_in_versions"
+        );
+        ensure!(
+            self.ttl_in_seconds != Some(0),
+            "ttl_in_seconds must be at least 1"
+        );
+        Ok(())
+    }
+
+    /// Lowest version served by a cache whose latest version is `latest_version`.
+    pub fn low_watermark_version(&self, latest_version: u64) -> u64 {
+        latest_version.saturating_sub(self.retention_in_versions)
+    }
+
+    fn ttl_in_seconds(&self, timestamp_in_seconds: u64) -> u64 {
+        self.ttl_in_seconds
+            .unwrap_or_else(|| get_ttl_in_seconds(timestamp_in_seconds))
+    }
+}
+
+impl Default for CacheRetentionPolicy {
+    fn default() -> Self {
+        Self {
+            retention_in_versions: Self::default_retention_in_versions(),
+            eviction_distance_in_versions: Self::default_eviction_distance_in_versions(),
+            ttl_in_seconds: None,
+        }
+    }
+}
+
 /// Get the TTL in seconds for a given timestamp.
 pub fn get_ttl_in_seconds(timestamp_in_seconds: u64) -> u64 {
     let current_time = std::time::SystemTime::now()
@@ -96,11 +286,44 @@ pub fn get_ttl_in_seconds(timestamp_in_seconds: u64) -> u64 {
     BASE_EXPIRATION_EPOCH_TIME_IN_SECONDS - (current_time - timestamp_in_seconds)
 }
 
+fn new_mget_chunk_backoff() -> ExponentialBackoff {
+    ExponentialBackoff {
+        initial_interval: Duration::from_millis(MGET_CHUNK_INITIAL_BACKOFF_IN_MILLIS),
+        max_interval: Duration::from_millis(MGET_CHUNK_MAX_BACKOFF_IN_MILLIS),
+        // Retries are bounded by MGET_CHUNK_RETRIES instead.
+        max_elapsed_time: None,
+        ..Default::default()
+    }
+}
+
+/// Records the latency of the cache operation `operation`, and counts it as an error if it fails.
+async fn observe_cache_operation<R>(
+    operation: &str,
+    future: impl Future<Output = anyhow::Result<R>>,
+) -> anyhow::Result<R> {
+    let start_time = std::time::Instant::now();
+    let result = future.await;
+    CACHE_OPERATION_LATENCY_IN_SECS
+        .with_label_values(&[operation])
+        .observe(start_time.elapsed().as_secs_f64());
+    if result.is_err() {
+        CACHE_OPERATION_ERROR_COUNT
+            .with_label_values(&[operation])
+            .inc();
+    }
+    result
+}
+
 // Cache operator directly interacts with redis conn.
 #[derive(Clone)]
 pub struct CacheOperator<T: redis::aio::ConnectionLike + Send> {
     conn: T,
     storage_format: StorageFormat,
+    // Zstd compression level used when writing zstd compressed entries.
+    compression_level: i32,
+    // If set, batch reads are split into MGETs of at most this many keys.
+    mget_chunk_size: Option<usize>,
+    retention_policy: CacheRetentionPolicy,
 }
 
 impl<T: redis::aio::ConnectionLike + Send + Clone> CacheOperator<T> {
@@ -108,7 +331,158 @@ impl<T: redis::aio::ConnectionLike + Send + Clone> CacheOperator<T> {
         Self {
             conn,
             storage_format,
+            compression_level: DEFAULT_ZSTD_COMPRESSION_LEVEL,
+            mget_chunk_size: None,
+            retention_policy: CacheRetentionPolicy::default(),
+        }
+    }
+
+    pub fn storage_format(&self) -> StorageFormat {
+        self.storage_format
+    }
+
+    /// Sets the zstd compression level used when writing cache entries.
+    pub fn with_compression_level(mut self, compression_level: i32) -> Self {
+        self.compression_level = compression_level;
+        self
+    }
+
+    /// Splits batch reads into concurrent MGETs of at most `chunk_size` keys, so a batch of large
+    /// transactions doesn't hold the shared connection with a single multi-megabyte response.
+    pub fn with_mget_chunk_size(mut self, chunk_size: Option<usize>) -> Self {
+        self.mget_chunk_size = chunk_size;
+        self
+    }
+
+    /// Sets how far behind the head transactions are served, evicted and expired.
+    pub fn with_retention_policy(mut self, retention_policy: CacheRetentionPolicy) -> Self {
+        self.retention_policy = retention_policy;
+        self
+    }
+
+    pub fn retention_policy(&self) -> CacheRetentionPolicy {
+        self.retention_policy
+    }
+
+    /// Replaces the connection, e.g., once the previous one dropped; the other settings are kept.
+    pub fn set_connection(&mut self, conn: T) {
+        self.conn = conn;
+    }
+
+    /// MGETs `keys`, in order. In chunked mode, every chunk is a separate MGET, up to
+    /// `MAX_CONCURRENT_MGET_CHUNKS` of them sent concurrently, and a failed chunk is retried on its
+    /// own. They aren't sent as one `redis::pipe()`, whose replies come back together and would
+    /// hold the connection like the single MGET.
+    async fn mget_encoded_transactions(
+        &mut self,
+        keys: Vec<String>,
+    ) -> anyhow::Result<Vec<Vec<u8>>> {
+        let chunk_size = match self.mget_chunk_size {
+            Some(chunk_size) if chunk_size < keys.len() => chunk_size,
+            _ => {
+                return self
+                    .conn
+                    .mget(keys)
+                    .await
+                    .context("Failed to mget from Redis")
+            },
+        };
+        // Every chunk gets its own handle on the connection, so other requests can go in between.
+        let chunk_reads: Vec<_> = keys
+            .chunks(chunk_size)
+            .map(|chunk| Self::mget_chunk_with_retries(self.conn.clone(), chunk.to_vec()))
+            .collect();
+        let chunks: Vec<Vec<Vec<u8>>> = futures::stream::iter(chunk_reads)
+            .buffered(MAX_CONCURRENT_MGET_CHUNKS)
+            .try_collect()
+            .await?;
+        Ok(chunks.into_iter().flatten().collect())
+    }
+
+    async fn mget_chunk_with_retries(
+        mut conn: T,
+        chunk: Vec<String>,
+    ) -> anyhow::Result<Vec<Vec<u8>>> {
+        let mut backoff = new_mget_chunk_backoff();
+        let mut retries = 0;
+        loop {
+            match conn.mget::<_, Vec<Vec<u8>>>(&chunk).await {
+                Ok(encoded_transactions) => return Ok(encoded_transactions),
+                Err(err) if retries < MGET_CHUNK_RETRIES => {
+                    retries += 1;
+                    let max_backoff = Duration::from_millis(MGET_CHUNK_MAX_BACKOFF_IN_MILLIS);
+                    let delay = backoff
+                        .next_backoff()
+                        .map_or(max_backoff, |delay| delay.min(max_backoff));
+                    CACHE_MGET_CHUNK_RETRIES.inc();
+                    tracing::warn!(
+                        first_key = chunk[0].as_str(),
+                        retries = retries,
+                        backoff_in_millis = delay.as_millis() as u64,
+                        error = ?err,
+                        "Failed to mget a chunk from Redis; retrying it."
+                    );
+                    tokio::time::sleep(delay).await;
+                },
+                Err(err) => {
+                    return Err(anyhow::Error::from(err)).context(format!(
+                        "Failed to mget the chunk starting at {} from Redis",
+                        chunk[0]
+                    ))
+                },
+            }
+        }
+    }
+
+    /// Fills the missing entries of `encoded_transactions`, the entries of the versions from
+    /// `start_version` on, with the entries of the same versions under the keys of the legacy
+    /// storage formats, e.g., written before the cache switched from gzip to zstd. Entries still
+    /// missing are left empty.
+    async fn read_missing_entries_from_legacy_keys(
+        &mut self,
+        start_version: u64,
+        encoded_transactions: &mut [Vec<u8>],
+    ) -> anyhow::Result<()> {
+        let missing_versions: Vec<u64> = (start_version..)
+            .zip(encoded_transactions.iter())
+            .filter(|(_, encoded_transaction)| encoded_transaction.is_empty())
+            .map(|(version, _)| version)
+            .collect();
+        if missing_versions.is_empty() {
+            return Ok(());
         }
+        let legacy_formats = CacheEntry::legacy_storage_formats(self.storage_format);
+        let keys = missing_versions
+            .iter()
+            .flat_map(|version| {
+                legacy_formats
+                    .iter()
+                    .map(|legacy_format| CacheEntry::build_key(*version, *legacy_format))
+            })
+            .collect::<Vec<String>>();
+        let values: Vec<Vec<u8>> = redis::cmd("MGET")
+            .arg(keys)
+            .query_async(&mut self.conn)
+            .await
+            .context("Failed to mget legacy entries from Redis")?;
+        for (version, values) in missing_versions
+            .into_iter()
+            .zip(values.chunks(legacy_formats.len()))
+        {
+            if let Some((legacy_format, bytes)) = legacy_formats
+                .iter()
+                .zip(values)
+                .find(|(_, bytes)| !bytes.is_empty())
+            {
+                encoded_transactions[(version - start_version) as usize] =
+                    CacheEntry::from_legacy_entry(
+                        bytes.clone(),
+                        *legacy_format,
+                        self.storage_format,
+                    )?;
+            }
+        }
+        Ok(())
     }
 
     // Set up the cache if needed.
@@ -129,20 +503,27 @@ impl<T: redis::aio::ConnectionLike + Send + Clone> CacheOperator<T> {
         Ok(version_inserted)
     }
 
-    pub async fn set_chain_id(&mut self, chain_id: u64) -> anyhow::Result<()> {
+    pub async fn set_chain_id(&mut self, chain_id: ChainId) -> anyhow::Result<()> {
         self.conn
-            .set(CACHE_KEY_CHAIN_ID, chain_id)
+            .set(CACHE_KEY_CHAIN_ID, chain_id.0)
             .await
             .context("Redis chain id update failed.")?;
         Ok(())
     }
 
-    pub async fn get_chain_id(&mut self) -> anyhow::Result<Option<u64>> {
-        self.get_config_by_key(CACHE_KEY_CHAIN_ID).await
+    pub async fn get_chain_id(&mut self) -> anyhow::Result<Option<ChainId>> {
+        let chain_id =
+            observe_cache_operation("get_chain_id", self.get_config_by_key(CACHE_KEY_CHAIN_ID))
+                .await?;
+        Ok(chain_id.map(ChainId))
     }
 
     pub async fn get_latest_version(&mut self) -> anyhow::Result<Option<u64>> {
-        self.get_config_by_key(CACHE_KEY_LATEST_VERSION).await
+        observe_cache_operation(
+            "get_latest_version",
+            self.get_config_by_key(CACHE_KEY_LATEST_VERSION),
+        )
+        .await
     }
 
     /// Returns starting version and ending version.
@@ -152,7 +533,7 @@ impl<T: redis::aio::ConnectionLike + Send + Clone> CacheOperator<T> {
         let latest_version = self.get_latest_version().await?;
         match latest_version {
             Some(version) => Ok(Some((
-                version.saturating_sub(CACHE_SIZE_ESTIMATION),
+                self.retention_policy.low_watermark_version(version),
                 // Fix this: current latest version is exclusive.
                 version.saturating_sub(1),
             ))),
@@ -160,6 +541,15 @@ impl<T: redis::aio::ConnectionLike + Send + Clone> CacheOperator<T> {
         }
     }
 
+    /// Lowest version the cache serves, i.e., versions below it are evicted. `None` if the cache
+    /// has no latest version yet.
+    pub async fn get_low_watermark_version(&mut self) -> anyhow::Result<Option<u64>> {
+        Ok(self
+            .get_latest_version()
+            .await?
+            .map(|version| self.retention_policy.low_watermark_version(version)))
+    }
+
     pub async fn get_file_store_latest_version(&mut self) -> anyhow::Result<Option<u64>> {
         self.get_config_by_key(FILE_STORE_LATEST_VERSION).await
     }
@@ -187,31 +577,78 @@ impl<T: redis::aio::ConnectionLike + Send + Clone> CacheOperator<T> {
         Ok(())
     }
 
+    /// Deletes the cached transactions in [start_version, end_version), under the keys of the
+    /// storage format and of the legacy ones, which reads fall back to. Keys are deleted
+    /// `EVICTION_CHUNK_SIZE_IN_VERSIONS` versions at a time, every DEL waiting for `rate_limiter`
+    /// if set. Returns the number of keys that were deleted.
+    pub async fn evict_transactions(
+        &mut self,
+        start_version: u64,
+        end_version: u64,
+        rate_limiter: Option<&RateLimiter>,
+    ) -> anyhow::Result<u64> {
+        let storage_formats: Vec<StorageFormat> = std::iter::once(self.storage_format)
+            .chain(CacheEntry::legacy_storage_formats(self.storage_format))
+            .collect();
+        let mut evicted_keys = 0;
+        let mut chunk_start_version = start_version;
+        while chunk_start_version < end_version {
+            let chunk_end_version =
+                (chunk_start_version + EVICTION_CHUNK_SIZE_IN_VERSIONS).min(end_version);
+            let keys = (chunk_start_version..chunk_end_version)
+                .flat_map(|version| {
+                    storage_formats
+                        .iter()
+                        .map(move |storage_format| CacheEntry::build_key(version, *storage_format))
+                })
+                .collect::<Vec<String>>();
+            if let Some(rate_limiter) = rate_limiter {
+                rate_limiter.acquire_request().await;
+            }
+            evicted_keys += self
+                .conn
+                .del::<_, u64>(keys)
+                .await
+                .context("Failed to evict transactions from Redis.")?;
+            chunk_start_version = chunk_end_version;
+        }
+        Ok(evicted_keys)
+    }
+
     // Internal function to get the latest version from cache.
     pub async fn check_cache_coverage_status(
         &mut self,
         requested_version: u64,
+    ) -> anyhow::Result<CacheCoverageStatus> {
+        self.check_cache_coverage_status_n(requested_version, FILE_ENTRY_TRANSACTION_COUNT)
+            .await
+    }
+
+    /// Like `check_cache_coverage_status`, with a hit of up to `transaction_count` versions.
+    async fn check_cache_coverage_status_n(
+        &mut self,
+        requested_version: u64,
+        transaction_count: u64,
     ) -> anyhow::Result<CacheCoverageStatus> {
         let latest_version: u64 = match self
             .conn
             .get::<&str, String>(CACHE_KEY_LATEST_VERSION)
             .await
         {
-            Ok(v) => v
-                .parse::<u64>()
-                .expect("Redis latest_version is not a number."),
+            Ok(v) => v.parse::<u64>().with_context(|| {
+                format!("Redis key {} is not a number.", CACHE_KEY_LATEST_VERSION)
+            })?,
             Err(err) => return Err(err.into()),
         };
 
         if requested_version >= latest_version {
             Ok(CacheCoverageStatus::DataNotReady)
-        } else if requested_version + CACHE_SIZE_ESTIMATION < latest_version {
+        } else if requested_version < self.retention_policy.low_watermark_version(latest_version) {
             Ok(CacheCoverageStatus::CacheEvicted)
         } else {
-            // TODO: rewrite this logic to surface this max fetch size better
             Ok(CacheCoverageStatus::CacheHit(std::cmp::min(
                 latest_version - requested_version,
-                FILE_ENTRY_TRANSACTION_COUNT,
+                transaction_count,
             )))
         }
     }
@@ -221,22 +658,32 @@ impl<T: redis::aio::ConnectionLike + Send + Clone> CacheOperator<T> {
         &mut self,
         start_version: u64,
         transaction_count: u64,
+    ) -> anyhow::Result<(Vec<Transaction>, f64, f64)> {
+        observe_cache_operation(
+            "batch_get_transactions",
+            self.fetch_transactions_with_durations(start_version, transaction_count),
+        )
+        .await
+    }
+
+    async fn fetch_transactions_with_durations(
+        &mut self,
+        start_version: u64,
+        transaction_count: u64,
     ) -> anyhow::Result<(Vec<Transaction>, f64, f64)> {
         let start_time = std::time::Instant::now();
         let versions = (start_version..start_version + transaction_count)
             .map(|e| CacheEntry::build_key(e, self.storage_format).to_string())
             .collect::<Vec<String>>();
-        let encoded_transactions: Vec<Vec<u8>> = self
-            .conn
-            .mget(versions)
-            .await
-            .context("Failed to mget from Redis")?;
+        let mut encoded_transactions = self.mget_encoded_transactions(versions).await?;
+        self.read_missing_entries_from_legacy_keys(start_version, &mut encoded_transactions)
+            .await?;
         let io_duration = start_time.elapsed().as_secs_f64();
         let start_time = std::time::Instant::now();
         let mut transactions = vec![];
         for encoded_transaction in encoded_transactions {
             let cache_entry: CacheEntry = CacheEntry::new(encoded_transaction, self.storage_format);
-            let transaction = cache_entry.into_transaction();
+            let transaction = cache_entry.into_transaction()?;
             transactions.push(transaction);
         }
         ensure!(
@@ -266,8 +713,11 @@ impl<T: redis::aio::ConnectionLike + Send + Clone> CacheOperator<T> {
                 .timestamp
                 .clone()
                 .map_or(0, |t| t.seconds as u64);
-            let cache_entry: CacheEntry =
-                CacheEntry::from_transaction(transaction, self.storage_format);
+            let cache_entry: CacheEntry = CacheEntry::from_transaction_with_compression_level(
+                transaction,
+                self.storage_format,
+                self.compression_level,
+            );
             let bytes = cache_entry.into_inner();
             size_in_bytes += bytes.len();
             redis_pipeline
@@ -275,17 +725,15 @@ impl<T: redis::aio::ConnectionLike + Send + Clone> CacheOperator<T> {
                 .arg(cache_key)
                 .arg(bytes)
                 .arg("EX")
-                .arg(get_ttl_in_seconds(timestamp_in_seconds))
+                .arg(self.retention_policy.ttl_in_seconds(timestamp_in_seconds))
                 .ignore();
             // Actively evict the expired cache. This is to avoid using Redis
             // eviction policy, which is probabilistic-based and may evict the
             // cache that is still needed.
-            if version >= CACHE_SIZE_EVICTION_LOWER_BOUND {
-                let key = CacheEntry::build_key(
-                    version - CACHE_SIZE_EVICTION_LOWER_BOUND,
-                    self.storage_format,
-                )
-                .to_string();
+            let eviction_distance = self.retention_policy.eviction_distance_in_versions;
+            if version >= eviction_distance {
+                let key = CacheEntry::build_key(version - eviction_distance, self.storage_format)
+                    .to_string();
                 redis_pipeline.cmd("DEL").arg(key).ignore();
             }
         }
@@ -317,18 +765,56 @@ impl<T: redis::aio::ConnectionLike + Send + Clone> CacheOperator<T> {
     // Requested version x
     // Cache hit x +
 
-    // TODO: Remove this
+    // TODO: Remove this; `batch_get_available_encoded_proto_data` also returns the cache head.
     pub async fn batch_get_encoded_proto_data(
         &mut self,
         start_version: u64,
     ) -> anyhow::Result<CacheBatchGetStatus> {
-        let cache_coverage_status = self.check_cache_coverage_status(start_version).await;
+        self.batch_get_encoded_proto_data_n(start_version, FILE_ENTRY_TRANSACTION_COUNT)
+            .await
+    }
+
+    /// Like `batch_get_encoded_proto_data`, with up to `transaction_count` transactions instead of
+    /// a blob's worth: fewer if the cache head is closer.
+    pub async fn batch_get_encoded_proto_data_n(
+        &mut self,
+        start_version: u64,
+        transaction_count: u64,
+    ) -> anyhow::Result<CacheBatchGetStatus> {
+        ensure!(
+            transaction_count > 0,
+            "The transaction count has to be positive."
+        );
+        let status = observe_cache_operation(
+            "batch_get_transactions",
+            self.fetch_encoded_proto_data(start_version, transaction_count),
+        )
+        .await?;
+        CACHE_BATCH_GET_STATUS_COUNT
+            .with_label_values(&[status.label()])
+            .inc();
+        Ok(status)
+    }
+
+    async fn fetch_encoded_proto_data(
+        &mut self,
+        start_version: u64,
+        transaction_count: u64,
+    ) -> anyhow::Result<CacheBatchGetStatus> {
+        let cache_coverage_status = self
+            .check_cache_coverage_status_n(start_version, transaction_count)
+            .await;
         match cache_coverage_status {
             Ok(CacheCoverageStatus::CacheHit(v)) => {
                 let versions = (start_version..start_version + v)
                     .map(|e| CacheEntry::build_key(e, self.storage_format))
                     .collect::<Vec<String>>();
-                let encoded_transactions: Vec<Vec<u8>> = self.conn.mget(versions).await?;
+                let mut encoded_transactions: Vec<Vec<u8>> = self.conn.mget(versions).await?;
+                self.read_missing_entries_from_legacy_keys(
+                    start_version,
+                    &mut encoded_transactions,
+                )
+                .await?;
                 Ok(CacheBatchGetStatus::Ok(encoded_transactions))
             },
             Ok(CacheCoverageStatus::CacheEvicted) => Ok(CacheBatchGetStatus::EvictedFromCache),
@@ -337,6 +823,70 @@ impl<T: redis::aio::ConnectionLike + Send + Clone> CacheOperator<T> {
         }
     }
 
+    /// Gets up to `transaction_count` transactions from `start_version` on, however many are
+    /// contiguously available: fewer at the head of the cache, or if an entry is missing. Unlike
+    /// `batch_get_encoded_proto_data`, nothing available isn't a distinct status.
+    pub async fn batch_get_available_encoded_proto_data(
+        &mut self,
+        start_version: u64,
+        transaction_count: u64,
+    ) -> anyhow::Result<CacheAvailableBatch> {
+        let batch = observe_cache_operation(
+            "batch_get_transactions",
+            self.fetch_available_encoded_proto_data(start_version, transaction_count),
+        )
+        .await?;
+        CACHE_BATCH_GET_STATUS_COUNT
+            .with_label_values(&[batch.label(transaction_count)])
+            .inc();
+        Ok(batch)
+    }
+
+    async fn fetch_available_encoded_proto_data(
+        &mut self,
+        start_version: u64,
+        transaction_count: u64,
+    ) -> anyhow::Result<CacheAvailableBatch> {
+        let cache_head_version = self.get_latest_version().await?.unwrap_or_default();
+        if start_version
+            < self
+                .retention_policy
+                .low_watermark_version(cache_head_version)
+        {
+            return Ok(CacheAvailableBatch::EvictedFromCache { cache_head_version });
+        }
+        let available_count = cache_head_version
+            .saturating_sub(start_version)
+            .min(transaction_count);
+        if available_count == 0 {
+            return Ok(CacheAvailableBatch::Ok {
+                encoded_transactions: vec![],
+                cache_head_version,
+            });
+        }
+        let keys = (start_version..start_version + available_count)
+            .map(|version| CacheEntry::build_key(version, self.storage_format))
+            .collect::<Vec<String>>();
+        let values: Vec<Option<Vec<u8>>> = redis::cmd("MGET")
+            .arg(keys)
+            .query_async(&mut self.conn)
+            .await
+            .context("Failed to mget from Redis")?;
+        let mut encoded_transactions: Vec<Vec<u8>> =
+            values.into_iter().map(Option::unwrap_or_default).collect();
+        self.read_missing_entries_from_legacy_keys(start_version, &mut encoded_transactions)
+            .await?;
+        // A missing entry ends the batch, so that the transactions stay contiguous.
+        let encoded_transactions = encoded_transactions
+            .into_iter()
+            .take_while(|encoded_transaction| !encoded_transaction.is_empty())
+            .collect();
+        Ok(CacheAvailableBatch::Ok {
+            encoded_transactions,
+            cache_head_version,
+        })
+    }
+
     // Update the latest version in cache.
     pub async fn update_cache_latest_version(
         &mut self,
@@ -365,34 +915,50 @@ impl<T: redis::aio::ConnectionLike + Send + Clone> CacheOperator<T> {
         }
     }
 
+    /// Takes the lease at `key` for `holder`, or extends it if `holder` already has it, for
+    /// `duration`. Returns the lease as it is afterwards, i.e., held by someone else if it's taken.
+    pub async fn acquire_lease(
+        &mut self,
+        key: &str,
+        holder: &str,
+        duration: Duration,
+    ) -> anyhow::Result<CacheLease> {
+        let (holder, expires_in_millis): (String, i64) =
+            redis::Script::new(CACHE_SCRIPT_ACQUIRE_LEASE)
+                .key(key)
+                .arg(holder)
+                .arg(duration.as_millis() as u64)
+                .invoke_async(&mut self.conn)
+                .await
+                .with_context(|| format!("Redis lease {} acquisition failed.", key))?;
+        Ok(CacheLease {
+            holder,
+            // PTTL is negative for a lease without expiration.
+            expires_in: Duration::from_millis(expires_in_millis.max(0) as u64),
+        })
+    }
+
+    /// Releases the lease at `key` if `holder` has it. Returns whether it was released.
+    pub async fn release_lease(&mut self, key: &str, holder: &str) -> anyhow::Result<bool> {
+        let released: u64 = redis::Script::new(CACHE_SCRIPT_RELEASE_LEASE)
+            .key(key)
+            .arg(holder)
+            .invoke_async(&mut self.conn)
+            .await
+            .with_context(|| format!("Redis lease {} release failed.", key))?;
+        Ok(released == 1)
+    }
+
     pub async fn get_transactions_with_durations(
         &mut self,
         start_version: u64,
         transaction_count: u64,
     ) -> anyhow::Result<(Vec<Transaction>, f64, f64)> {
-        let start_time = std::time::Instant::now();
-        let versions = (start_version..start_version + transaction_count)
-            .map(|e| CacheEntry::build_key(e, self.storage_format))
-            .collect::<Vec<String>>();
-        let encoded_transactions: Vec<Vec<u8>> = self
-            .conn
-            .mget(versions)
-            .await
-            .context("Failed to mget from Redis")?;
-        let io_duration = start_time.elapsed().as_secs_f64();
-        let start_time = std::time::Instant::now();
-        let mut transactions = vec![];
-        for encoded_transaction in encoded_transactions {
-            let cache_entry: CacheEntry = CacheEntry::new(encoded_transaction, self.storage_format);
-            let transaction = cache_entry.into_transaction();
-            transactions.push(transaction);
-        }
-        ensure!(
-            transactions.len() == transaction_count as usize,
-            "Failed to get all transactions from cache."
-        );
-        let decoding_duration = start_time.elapsed().as_secs_f64();
-        Ok((transactions, io_duration, decoding_duration))
+        observe_cache_operation(
+            "batch_get_transactions",
+            self.fetch_transactions_with_durations(start_version, transaction_count),
+        )
+        .await
     }
 
     /// Fail if not all transactions requested are returned
@@ -406,14 +972,53 @@ impl<T: redis::aio::ConnectionLike + Send + Clone> CacheOperator<T> {
             .await?;
         Ok(transactions)
     }
+
+    /// Same as `get_transactions`, returning the protobuf encoding of each transaction instead, to
+    /// be passed through to the file store without decoding it. Fails if not all transactions
+    /// requested are returned.
+    pub async fn get_encoded_transactions(
+        &mut self,
+        start_version: u64,
+        transaction_count: u64,
+    ) -> anyhow::Result<Vec<Vec<u8>>> {
+        observe_cache_operation(
+            "batch_get_transactions",
+            self.fetch_encoded_transactions(start_version, transaction_count),
+        )
+        .await
+    }
+
+    async fn fetch_encoded_transactions(
+        &mut self,
+        start_version: u64,
+        transaction_count: u64,
+    ) -> anyhow::Result<Vec<Vec<u8>>> {
+        let versions = (start_version..start_version + transaction_count)
+            .map(|e| CacheEntry::build_key(e, self.storage_format).to_string())
+            .collect::<Vec<String>>();
+        let mut encoded_transactions = self.mget_encoded_transactions(versions).await?;
+        self.read_missing_entries_from_legacy_keys(start_version, &mut encoded_transactions)
+            .await?;
+        let encoded_transactions = encoded_transactions
+            .into_iter()
+            .map(|bytes| CacheEntry::new(bytes, self.storage_format).into_encoded_transaction())
+            .collect::<anyhow::Result<Vec<Vec<u8>>>>()?;
+        ensure!(
+            encoded_transactions.len() == transaction_count as usize,
+            "Failed to get all transactions from cache."
+        );
+        Ok(encoded_transactions)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::rate_limiter::RateLimitConfig;
     use aptos_protos::util::timestamp::Timestamp;
     use prost::Message;
     use redis_test::{MockCmd, MockRedisConnection};
+    use std::sync::Arc;
 
     #[tokio::test]
     async fn cache_is_setup_if_empty() {
@@ -461,6 +1066,23 @@ mod tests {
         assert_eq!(cache_operator.get_latest_version().await.unwrap(), Some(12));
     }
 
+    #[tokio::test]
+    async fn cache_coverage_status_fails_on_a_malformed_latest_version() {
+        let cmds = vec![MockCmd::new(
+            redis::cmd("GET").arg(CACHE_KEY_LATEST_VERSION),
+            Ok("not a version"),
+        )];
+        let mock_connection = MockRedisConnection::new(cmds);
+        let mut cache_operator: CacheOperator<MockRedisConnection> =
+            CacheOperator::new(mock_connection, StorageFormat::Base64UncompressedProto);
+
+        let err = cache_operator
+            .check_cache_coverage_status(10)
+            .await
+            .unwrap_err();
+        assert!(err.is::<std::num::ParseIntError>());
+    }
+
     // Cache chain id tests.
     #[tokio::test]
     async fn cache_chain_id_ok() {
@@ -472,7 +1094,45 @@ mod tests {
         let mut cache_operator: CacheOperator<MockRedisConnection> =
             CacheOperator::new(mock_connection, StorageFormat::Base64UncompressedProto);
 
-        assert_eq!(cache_operator.get_chain_id().await.unwrap(), Some(123));
+        assert_eq!(
+            cache_operator.get_chain_id().await.unwrap(),
+            Some(ChainId(123))
+        );
+    }
+
+    #[tokio::test]
+    async fn cache_operations_are_measured() {
+        let cmds = vec![
+            MockCmd::new(
+                redis::cmd("GET").arg(CACHE_KEY_CHAIN_ID),
+                Err::<String, _>(redis::RedisError::from((
+                    redis::ErrorKind::IoError,
+                    "Connection reset",
+                ))),
+            ),
+            MockCmd::new(redis::cmd("GET").arg(CACHE_KEY_LATEST_VERSION), Ok("12")),
+        ];
+        let mut cache_operator = CacheOperator::new(
+            MockRedisConnection::new(cmds),
+            StorageFormat::Base64UncompressedProto,
+        );
+        let latency = CACHE_OPERATION_LATENCY_IN_SECS.with_label_values(&["get_chain_id"]);
+        let errors = CACHE_OPERATION_ERROR_COUNT.with_label_values(&["get_chain_id"]);
+        let not_ready = CACHE_BATCH_GET_STATUS_COUNT.with_label_values(&["not_ready"]);
+        let (latency_samples, error_count, not_ready_count) =
+            (latency.get_sample_count(), errors.get(), not_ready.get());
+
+        assert!(cache_operator.get_chain_id().await.is_err());
+        assert_eq!(
+            cache_operator
+                .batch_get_encoded_proto_data(12)
+                .await
+                .unwrap(),
+            CacheBatchGetStatus::NotReady
+        );
+        assert_eq!(latency.get_sample_count(), latency_samples + 1);
+        assert_eq!(errors.get(), error_count + 1);
+        assert_eq!(not_ready.get(), not_ready_count + 1);
     }
 
     // Cache latest version tests.
@@ -557,4 +1217,573 @@ mod tests {
         println!("{:?}", res);
         assert!(res.is_ok());
     }
+
+    #[tokio::test]
+    async fn cache_retention_policy_is_applied() {
+        let policy = CacheRetentionPolicy {
+            retention_in_versions: 10,
+            eviction_distance_in_versions: 20,
+            ttl_in_seconds: Some(60),
+        };
+        assert!(policy.validate().is_ok());
+        let version = 25;
+        let transactions = vec![Transaction {
+            version,
+            timestamp: Some(Timestamp {
+                seconds: 1,
+                nanos: 0,
+            }),
+            ..Default::default()
+        }];
+        let mut buf = vec![];
+        transactions[0].encode(&mut buf).unwrap();
+        let mut redis_pipeline = redis::pipe();
+        redis_pipeline
+            .cmd("SET")
+            .arg(version.to_string())
+            .arg(base64::encode(&buf))
+            .arg("EX")
+            .arg(60);
+        redis_pipeline.cmd("DEL").arg(version - 20);
+        let cmds = vec![
+            MockCmd::new(redis_pipeline, Ok("ok")),
+            MockCmd::new(redis::cmd("GET").arg(CACHE_KEY_LATEST_VERSION), Ok("100")),
+            MockCmd::new(redis::cmd("GET").arg(CACHE_KEY_LATEST_VERSION), Ok("100")),
+        ];
+        let mut cache_operator = CacheOperator::new(
+            MockRedisConnection::new(cmds),
+            StorageFormat::Base64UncompressedProto,
+        )
+        .with_retention_policy(policy);
+
+        assert!(cache_operator
+            .update_cache_transactions(transactions)
+            .await
+            .is_ok());
+        assert_eq!(
+            cache_operator.get_low_watermark_version().await.unwrap(),
+            Some(90)
+        );
+        assert_eq!(
+            cache_operator
+                .check_cache_coverage_status(89)
+                .await
+                .unwrap(),
+            CacheCoverageStatus::CacheEvicted
+        );
+    }
+
+    #[test]
+    fn cache_retention_policy_is_validated() {
+        assert!(CacheRetentionPolicy::default().validate().is_ok());
+        let policy = CacheRetentionPolicy {
+            retention_in_versions: 10,
+            eviction_distance_in_versions: 10,
+            ttl_in_seconds: None,
+        };
+        assert!(policy.validate().is_err());
+        let policy = CacheRetentionPolicy {
+            eviction_distance_in_versions: 11,
+            ttl_in_seconds: Some(0),
+            ..policy
+        };
+        assert!(policy.validate().is_err());
+        let policy: CacheRetentionPolicy = serde_yaml::from_str(
+            "retention_in_versions: 1000\neviction_distance_in_versions: 2000",
+        )
+        .unwrap();
+        assert_eq!(policy.ttl_in_seconds, None);
+    }
+
+    #[tokio::test]
+    async fn cache_evict_transactions_ok() {
+        // Under the keys of the storage format and of the legacy ones.
+        let keys = vec![
+            "10", "zstd:10", "gz:10", "11", "zstd:11", "gz:11", "12", "zstd:12", "gz:12",
+        ];
+        let cmds = vec![MockCmd::new(redis::cmd("DEL").arg(keys), Ok(2))];
+        let mock_connection = MockRedisConnection::new(cmds);
+        let mut cache_operator: CacheOperator<MockRedisConnection> =
+            CacheOperator::new(mock_connection, StorageFormat::Base64UncompressedProto);
+
+        assert_eq!(
+            cache_operator
+                .evict_transactions(10, 13, None)
+                .await
+                .unwrap(),
+            2
+        );
+        // Empty ranges don't reach Redis.
+        assert_eq!(
+            cache_operator
+                .evict_transactions(13, 13, None)
+                .await
+                .unwrap(),
+            0
+        );
+    }
+
+    #[tokio::test]
+    async fn cache_evict_transactions_deletes_in_chunks() {
+        let chunk_keys = |versions: std::ops::Range<u64>| {
+            versions
+                .flat_map(|version| {
+                    vec![
+                        format!("zstd:{}", version),
+                        format!("gz:{}", version),
+                        version.to_string(),
+                    ]
+                })
+                .collect::<Vec<String>>()
+        };
+        let cmds = vec![
+            MockCmd::new(redis::cmd("DEL").arg(chunk_keys(0..1_000)), Ok(0)),
+            MockCmd::new(redis::cmd("DEL").arg(chunk_keys(1_000..1_500)), Ok(0)),
+        ];
+        let mut cache_operator = CacheOperator::new(
+            MockRedisConnection::new(cmds),
+            StorageFormat::ZstdCompressedProto,
+        );
+        let rate_limiter = RateLimiter::new(&RateLimitConfig {
+            max_requests_per_sec: Some(1_000.0),
+            max_bytes_per_sec: None,
+        });
+        assert_eq!(
+            cache_operator
+                .evict_transactions(0, 1_500, Some(&rate_limiter))
+                .await
+                .unwrap(),
+            0
+        );
+    }
+
+    fn encoded_transaction(version: u64) -> Vec<u8> {
+        CacheEntry::from_transaction(
+            Transaction {
+                version,
+                ..Default::default()
+            },
+            StorageFormat::Base64UncompressedProto,
+        )
+        .into_inner()
+    }
+
+    fn mget_cmd(versions: std::ops::Range<u64>) -> MockCmd {
+        let keys = versions
+            .clone()
+            .map(|version| CacheEntry::build_key(version, StorageFormat::Base64UncompressedProto))
+            .collect::<Vec<String>>();
+        let values = versions
+            .map(|version| redis::Value::Data(encoded_transaction(version)))
+            .collect();
+        MockCmd::new(redis::cmd("MGET").arg(keys), Ok(redis::Value::Bulk(values)))
+    }
+
+    #[tokio::test]
+    async fn chunked_mget_reassembles_chunks_in_order() {
+        let failed_chunk = MockCmd::new::<_, &str>(
+            redis::cmd("MGET").arg(
+                (4..8)
+                    .map(|version| {
+                        CacheEntry::build_key(version, StorageFormat::Base64UncompressedProto)
+                    })
+                    .collect::<Vec<String>>(),
+            ),
+            Err(redis::RedisError::from((
+                redis::ErrorKind::IoError,
+                "connection reset",
+            ))),
+        );
+        // Only the failed chunk is fetched again.
+        let cmds = vec![
+            mget_cmd(0..4),
+            failed_chunk,
+            mget_cmd(4..8),
+            mget_cmd(8..10),
+        ];
+        let mut cache_operator = CacheOperator::new(
+            MockRedisConnection::new(cmds),
+            StorageFormat::Base64UncompressedProto,
+        )
+        .with_mget_chunk_size(Some(4));
+
+        let transactions = cache_operator.get_transactions(0, 10).await.unwrap();
+        let versions: Vec<u64> = transactions.iter().map(|t| t.version).collect();
+        assert_eq!(versions, (0..10).collect::<Vec<u64>>());
+    }
+
+    #[tokio::test]
+    async fn chunked_mget_fails_once_a_chunk_runs_out_of_retries() {
+        let mut cmds = vec![mget_cmd(0..4)];
+        cmds.extend((0..=MGET_CHUNK_RETRIES).map(|_| {
+            MockCmd::new::<_, &str>(
+                redis::cmd("MGET").arg(
+                    (4..8)
+                        .map(|version| {
+                            CacheEntry::build_key(version, StorageFormat::Base64UncompressedProto)
+                        })
+                        .collect::<Vec<String>>(),
+                ),
+                Err(redis::RedisError::from((
+                    redis::ErrorKind::IoError,
+                    "connection reset",
+                ))),
+            )
+        }));
+        let mut cache_operator = CacheOperator::new(
+            MockRedisConnection::new(cmds),
+            StorageFormat::Base64UncompressedProto,
+        )
+        .with_mget_chunk_size(Some(4));
+
+        assert!(cache_operator.get_transactions(0, 8).await.is_err());
+    }
+
+    /// Connection whose responses go through a single wire, one at a time and in request order,
+    /// taking longer for more keys, like a multiplexed connection to Redis.
+    #[derive(Clone)]
+    struct SharedWireConnection {
+        wire: Arc<tokio::sync::Mutex<()>>,
+        transfer_time_per_key: std::time::Duration,
+    }
+
+    impl SharedWireConnection {
+        fn mget_reply(key_count: usize) -> redis::Value {
+            redis::Value::Bulk(
+                (0..key_count)
+                    .map(|_| redis::Value::Data(encoded_transaction(0)))
+                    .collect(),
+            )
+        }
+    }
+
+    impl redis::aio::ConnectionLike for SharedWireConnection {
+        fn req_packed_command<'a>(
+            &'a mut self,
+            cmd: &'a redis::Cmd,
+        ) -> redis::RedisFuture<'a, redis::Value> {
+            let key_count = cmd.args_iter().count() - 1;
+            Box::pin(async move {
+                let _wire = self.wire.lock().await;
+                tokio::time::sleep(self.transfer_time_per_key * key_count as u32).await;
+                Ok(Self::mget_reply(key_count))
+            })
+        }
+
+        // The replies of a pipeline come back together, holding the wire for all of its keys.
+        fn req_packed_commands<'a>(
+            &'a mut self,
+            cmd: &'a redis::Pipeline,
+            offset: usize,
+            count: usize,
+        ) -> redis::RedisFuture<'a, Vec<redis::Value>> {
+            let key_counts: Vec<usize> = cmd
+                .cmd_iter()
+                .map(|cmd| cmd.args_iter().count() - 1)
+                .collect();
+            Box::pin(async move {
+                let _wire = self.wire.lock().await;
+                let key_count: usize = key_counts.iter().sum();
+                tokio::time::sleep(self.transfer_time_per_key * key_count as u32).await;
+                Ok(key_counts
+                    .into_iter()
+                    .map(Self::mget_reply)
+                    .skip(offset)
+                    .take(count)
+                    .collect())
+            })
+        }
+
+        fn get_db(&self) -> i64 {
+            0
+        }
+    }
+
+    /// Latency of a small request sent while a batch of `FILE_ENTRY_TRANSACTION_COUNT`
+    /// transactions is read over the same connection.
+    async fn small_request_latency_during_batch_read(
+        mget_chunk_size: Option<usize>,
+    ) -> std::time::Duration {
+        let conn = SharedWireConnection {
+            wire: Arc::new(tokio::sync::Mutex::new(())),
+            transfer_time_per_key: std::time::Duration::from_micros(50),
+        };
+        let mut batch_reader =
+            CacheOperator::new(conn.clone(), StorageFormat::Base64UncompressedProto)
+                .with_mget_chunk_size(mget_chunk_size);
+        let batch_read = tokio::spawn(async move {
+            batch_reader
+                .get_transactions(0, FILE_ENTRY_TRANSACTION_COUNT)
+                .await
+                .unwrap()
+        });
+        tokio::time::sleep(std::time::Duration::from_millis(2)).await;
+        let mut small_reader = CacheOperator::new(conn, StorageFormat::Base64UncompressedProto);
+        let start_time = std::time::Instant::now();
+        small_reader.get_transactions(0, 1).await.unwrap();
+        let latency = start_time.elapsed();
+        assert_eq!(
+            batch_read.await.unwrap().len() as u64,
+            FILE_ENTRY_TRANSACTION_COUNT
+        );
+        latency
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn chunked_mget_does_not_block_the_shared_connection() {
+        // The whole batch takes 50ms on the wire; a chunk of 100 keys takes 5ms.
+        let unchunked_latency = small_request_latency_during_batch_read(None).await;
+        let chunked_latency = small_request_latency_during_batch_read(Some(100)).await;
+        assert!(
+            chunked_latency * 2 < unchunked_latency,
+            "chunked: {:?}, unchunked: {:?}",
+            chunked_latency,
+            unchunked_latency
+        );
+    }
+
+    fn latest_version_cmd(latest_version: u64) -> MockCmd {
+        MockCmd::new(
+            redis::cmd("GET").arg(CACHE_KEY_LATEST_VERSION),
+            Ok(latest_version.to_string()),
+        )
+    }
+
+    #[tokio::test]
+    async fn batches_hold_the_requested_count() {
+        let cmds = vec![latest_version_cmd(800), mget_cmd(100..300)];
+        let mut cache_operator = CacheOperator::new(
+            MockRedisConnection::new(cmds),
+            StorageFormat::Base64UncompressedProto,
+        );
+        match cache_operator
+            .batch_get_encoded_proto_data_n(100, 200)
+            .await
+            .unwrap()
+        {
+            CacheBatchGetStatus::Ok(encoded_transactions) => {
+                assert_eq!(encoded_transactions.len(), 200)
+            },
+            status => panic!("Unexpected status: {:?}", status),
+        }
+    }
+
+    #[tokio::test]
+    async fn batches_stop_at_the_cache_head_past_the_requested_count() {
+        let cmds = vec![latest_version_cmd(800), mget_cmd(500..800)];
+        let mut cache_operator = CacheOperator::new(
+            MockRedisConnection::new(cmds),
+            StorageFormat::Base64UncompressedProto,
+        );
+        match cache_operator
+            .batch_get_encoded_proto_data_n(500, 5_000)
+            .await
+            .unwrap()
+        {
+            CacheBatchGetStatus::Ok(encoded_transactions) => {
+                assert_eq!(encoded_transactions.len(), 300)
+            },
+            status => panic!("Unexpected status: {:?}", status),
+        }
+        assert!(cache_operator
+            .batch_get_encoded_proto_data_n(500, 0)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn available_batches_stop_at_the_cache_head() {
+        let cmds = vec![latest_version_cmd(800), mget_cmd(0..800)];
+        let mut cache_operator = CacheOperator::new(
+            MockRedisConnection::new(cmds),
+            StorageFormat::Base64UncompressedProto,
+        );
+        let batch = cache_operator
+            .batch_get_available_encoded_proto_data(0, FILE_ENTRY_TRANSACTION_COUNT)
+            .await
+            .unwrap();
+        assert_eq!(batch.cache_head_version(), 800);
+        match batch {
+            CacheAvailableBatch::Ok {
+                encoded_transactions,
+                ..
+            } => assert_eq!(encoded_transactions.len(), 800),
+            batch => panic!("Unexpected batch: {:?}", batch),
+        }
+    }
+
+    #[tokio::test]
+    async fn available_batches_are_empty_at_the_cache_head() {
+        // Nothing is read beyond the head, and a cache without latest version has nothing.
+        let cmds = vec![
+            latest_version_cmd(800),
+            MockCmd::new(
+                redis::cmd("GET").arg(CACHE_KEY_LATEST_VERSION),
+                Ok(redis::Value::Nil),
+            ),
+        ];
+        let mut cache_operator = CacheOperator::new(
+            MockRedisConnection::new(cmds),
+            StorageFormat::Base64UncompressedProto,
+        );
+        for cache_head_version in [800, 0] {
+            assert_eq!(
+                cache_operator
+                    .batch_get_available_encoded_proto_data(800, FILE_ENTRY_TRANSACTION_COUNT)
+                    .await
+                    .unwrap(),
+                CacheAvailableBatch::Ok {
+                    encoded_transactions: vec![],
+                    cache_head_version,
+                }
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn available_batches_stop_at_missing_entries() {
+        let keys = (10..13)
+            .map(|version| CacheEntry::build_key(version, StorageFormat::Base64UncompressedProto))
+            .collect::<Vec<String>>();
+        let values = redis::Value::Bulk(vec![
+            redis::Value::Data(encoded_transaction(10)),
+            redis::Value::Nil,
+            redis::Value::Data(encoded_transaction(12)),
+        ]);
+        // Nor is the missing entry under the keys of the legacy storage formats.
+        let legacy_keys = vec!["zstd:11", "gz:11"];
+        let legacy_values = redis::Value::Bulk(vec![redis::Value::Nil, redis::Value::Nil]);
+        let cmds = vec![
+            latest_version_cmd(13),
+            MockCmd::new(redis::cmd("MGET").arg(keys), Ok(values)),
+            MockCmd::new(redis::cmd("MGET").arg(legacy_keys), Ok(legacy_values)),
+            latest_version_cmd(CACHE_SIZE_ESTIMATION + 100),
+        ];
+        let mut cache_operator = CacheOperator::new(
+            MockRedisConnection::new(cmds),
+            StorageFormat::Base64UncompressedProto,
+        );
+        assert_eq!(
+            cache_operator
+                .batch_get_available_encoded_proto_data(10, 5)
+                .await
+                .unwrap(),
+            CacheAvailableBatch::Ok {
+                encoded_transactions: vec![encoded_transaction(10)],
+                cache_head_version: 13,
+            }
+        );
+        assert_eq!(
+            cache_operator
+                .batch_get_available_encoded_proto_data(10, 5)
+                .await
+                .unwrap(),
+            CacheAvailableBatch::EvictedFromCache {
+                cache_head_version: CACHE_SIZE_ESTIMATION + 100,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn entries_missing_from_the_storage_format_are_read_from_legacy_keys() {
+        let transaction = |version| Transaction {
+            version,
+            ..Default::default()
+        };
+        // Version 1 was written in gzip, and version 2 in base64, before the cache switched to
+        // zstd.
+        let values = redis::Value::Bulk(vec![
+            redis::Value::Data(
+                CacheEntry::from_transaction(transaction(0), StorageFormat::ZstdCompressedProto)
+                    .into_inner(),
+            ),
+            redis::Value::Nil,
+            redis::Value::Nil,
+        ]);
+        let legacy_values = redis::Value::Bulk(vec![
+            redis::Value::Data(
+                CacheEntry::from_transaction(transaction(1), StorageFormat::GzipCompressedProto)
+                    .into_inner(),
+            ),
+            redis::Value::Nil,
+            redis::Value::Nil,
+            redis::Value::Data(
+                CacheEntry::from_transaction(
+                    transaction(2),
+                    StorageFormat::Base64UncompressedProto,
+                )
+                .into_inner(),
+            ),
+        ]);
+        let cmds = vec![
+            MockCmd::new(
+                redis::cmd("MGET").arg(vec!["zstd:0", "zstd:1", "zstd:2"]),
+                Ok(values),
+            ),
+            MockCmd::new(
+                redis::cmd("MGET").arg(vec!["gz:1", "1", "gz:2", "2"]),
+                Ok(legacy_values),
+            ),
+        ];
+        let mut cache_operator = CacheOperator::new(
+            MockRedisConnection::new(cmds),
+            StorageFormat::ZstdCompressedProto,
+        );
+        assert_eq!(
+            cache_operator.get_transactions(0, 3).await.unwrap(),
+            (0..3).map(transaction).collect::<Vec<_>>()
+        );
+    }
+
+    fn lease_cmd(holder: &str, reply: (&str, i64)) -> MockCmd {
+        MockCmd::new(
+            acquire_lease_cmd("lease", holder, Duration::from_secs(30)),
+            Ok(redis::Value::Bulk(vec![
+                redis::Value::Data(reply.0.as_bytes().to_vec()),
+                redis::Value::Int(reply.1),
+            ])),
+        )
+    }
+
+    #[tokio::test]
+    async fn leases_are_only_acquired_if_free_or_held() {
+        let cmds = vec![
+            lease_cmd("a", ("a", 30_000)),
+            lease_cmd("b", ("a", 12_000)),
+            lease_cmd("b", ("a", -1)),
+            MockCmd::new(
+                redis::cmd("EVALSHA")
+                    .arg(redis::Script::new(CACHE_SCRIPT_RELEASE_LEASE).get_hash())
+                    .arg(1)
+                    .arg("lease")
+                    .arg("b"),
+                Ok(0),
+            ),
+        ];
+        let mut cache_operator = CacheOperator::new(
+            MockRedisConnection::new(cmds),
+            StorageFormat::Base64UncompressedProto,
+        );
+        let lease = cache_operator
+            .acquire_lease("lease", "a", Duration::from_secs(30))
+            .await
+            .unwrap();
+        assert!(lease.is_held_by("a"));
+        assert_eq!(lease.expires_in, Duration::from_secs(30));
+        // Another holder gets the current lease instead.
+        let lease = cache_operator
+            .acquire_lease("lease", "b", Duration::from_secs(30))
+            .await
+            .unwrap();
+        assert_eq!(lease, CacheLease {
+            holder: "a".to_string(),
+            expires_in: Duration::from_secs(12),
+        });
+        let lease = cache_operator
+            .acquire_lease("lease", "b", Duration::from_secs(30))
+            .await
+            .unwrap();
+        assert_eq!(lease.expires_in, Duration::ZERO);
+        assert!(!cache_operator.release_lease("lease", "b").await.unwrap());
+    }
 }
@@ -1,14 +1,42 @@
 // Copyright © Aptos Foundation
 
-use crate::default_file_storage_format;
+use crate::{
+    default_file_storage_format,
+    encryption_util::EncryptionScheme,
+    parquet_util::{decode_parquet, encode_parquet},
+    types::ChainId,
+};
+use anyhow::Context;
 use aptos_protos::{indexer::v1::TransactionsInStorage, transaction::v1::Transaction};
 use flate2::read::{GzDecoder, GzEncoder};
+use once_cell::sync::Lazy;
 use prost::Message;
 use ripemd::{Digest, Ripemd128};
 use serde::{Deserialize, Serialize};
-use std::io::Read;
+use std::io::{BufRead, BufReader, Read, Write};
 
+// Number of versions of each blob of a new file store; existing file stores keep the blob size
+// recorded in their metadata.
 pub const FILE_ENTRY_TRANSACTION_COUNT: u64 = 1000;
+// Default zstd compression level used when none is configured.
+pub const DEFAULT_ZSTD_COMPRESSION_LEVEL: i32 = zstd::DEFAULT_COMPRESSION_LEVEL;
+
+// Magic bytes at the start of gzip and zstd streams; used to detect the
+// compression of an entry regardless of the configured compressed format.
+const GZIP_MAGIC_BYTES: [u8; 2] = [0x1F, 0x8B];
+const ZSTD_MAGIC_BYTES: [u8; 4] = [0x28, 0xB5, 0x2F, 0xFD];
+// Magic bytes at the start of the header of a blob; distinct from the first bytes of every
+// storage format, so blobs written before headers were can still be told apart.
+const BLOB_HEADER_MAGIC_BYTES: [u8; 4] = *b"APTB";
+// Magic bytes, storage format and format version.
+pub const BLOB_HEADER_SIZE: usize = BLOB_HEADER_MAGIC_BYTES.len() + 2;
+// Latest format version of the blobs, written to new file stores. Bump it when the encoding of a
+// storage format changes, and keep decoding the older versions in `FileEntry` and
+// `FileEntryReader`.
+pub const BLOB_FORMAT_VERSION: u8 = 2;
+// Format version of the blobs without a header, i.e., written before headers were; file stores
+// created before then keep it, so that older readers can still read them.
+pub const LEGACY_BLOB_FORMAT_VERSION: u8 = 1;
 
 #[derive(Serialize, Deserialize, Debug, Copy, Clone, Eq, PartialEq)]
 pub enum StorageFormat {
@@ -19,6 +47,209 @@ pub enum StorageFormat {
     // Only used for legacy file format.
     // Use by file store only.
     JsonBase64UncompressedProto,
+    // Zstd compressed protobuf; used by both cache and file store.
+    ZstdCompressedProto,
+    // Columnar Parquet files for query engines; see `parquet_util`.
+    // Use by file store only.
+    Parquet,
+}
+
+impl StorageFormat {
+    /// Storage format of the cache for the given compression settings.
+    /// A configured zstd compression level takes precedence over gzip.
+    pub fn for_cache(enable_compression: bool, zstd_compression_level: Option<i32>) -> Self {
+        if zstd_compression_level.is_some() {
+            StorageFormat::ZstdCompressedProto
+        } else if enable_compression {
+            StorageFormat::GzipCompressedProto
+        } else {
+            StorageFormat::Base64UncompressedProto
+        }
+    }
+
+    /// Whether the encoded transactions are compressed as a whole.
+    pub fn is_compressed(&self) -> bool {
+        matches!(
+            self,
+            StorageFormat::GzipCompressedProto | StorageFormat::ZstdCompressedProto
+        )
+    }
+
+    /// Whether blobs in this format can be written one transaction at a time with
+    /// `FileEntryWriter`, and read one transaction at a time with `FileEntryReader`.
+    pub fn supports_incremental_encoding(&self) -> bool {
+        matches!(
+            self,
+            StorageFormat::GzipCompressedProto | StorageFormat::ZstdCompressedProto
+        )
+    }
+
+    /// Storage format of the file store for the given compression settings.
+    /// A configured zstd compression level takes precedence over gzip.
+    pub fn for_file_store(enable_compression: bool, zstd_compression_level: Option<i32>) -> Self {
+        if zstd_compression_level.is_some() {
+            StorageFormat::ZstdCompressedProto
+        } else if enable_compression {
+            StorageFormat::GzipCompressedProto
+        } else {
+            StorageFormat::JsonBase64UncompressedProto
+        }
+    }
+
+    /// Whether blobs in this format are written with a `BlobHeader` in blob format version
+    /// `blob_format_version`. Parquet files are left as is for query engines to read, and the
+    /// legacy JSON files for older readers.
+    pub fn has_blob_header(&self, blob_format_version: u8) -> bool {
+        blob_format_version > LEGACY_BLOB_FORMAT_VERSION && self.is_compressed()
+    }
+
+    // Identifies the storage format in blob headers; never reuse a tag.
+    fn blob_header_tag(&self) -> u8 {
+        match self {
+            StorageFormat::GzipCompressedProto => 1,
+            StorageFormat::Base64UncompressedProto => 2,
+            StorageFormat::JsonBase64UncompressedProto => 3,
+            StorageFormat::ZstdCompressedProto => 4,
+            StorageFormat::Parquet => 5,
+        }
+    }
+
+    fn from_blob_header_tag(tag: u8) -> Option<Self> {
+        match tag {
+            1 => Some(StorageFormat::GzipCompressedProto),
+            2 => Some(StorageFormat::Base64UncompressedProto),
+            3 => Some(StorageFormat::JsonBase64UncompressedProto),
+            4 => Some(StorageFormat::ZstdCompressedProto),
+            5 => Some(StorageFormat::Parquet),
+            _ => None,
+        }
+    }
+}
+
+/// Header at the start of a blob, with the storage format and the format version its payload is
+/// encoded with, so the blob is decoded as written regardless of the configured storage format.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct BlobHeader {
+    pub storage_format: StorageFormat,
+    pub format_version: u8,
+}
+
+impl BlobHeader {
+    /// Header of the blobs written by this code.
+    pub fn new(storage_format: StorageFormat) -> Self {
+        Self {
+            storage_format,
+            format_version: BLOB_FORMAT_VERSION,
+        }
+    }
+
+    pub fn to_bytes(&self) -> [u8; BLOB_HEADER_SIZE] {
+        let mut bytes = [0; BLOB_HEADER_SIZE];
+        bytes[..BLOB_HEADER_MAGIC_BYTES.len()].copy_from_slice(&BLOB_HEADER_MAGIC_BYTES);
+        bytes[BLOB_HEADER_SIZE - 2] = self.storage_format.blob_header_tag();
+        bytes[BLOB_HEADER_SIZE - 1] = self.format_version;
+        bytes
+    }
+
+    /// Parses the header at the start of `bytes`; `None` if the blob has none, i.e., is of format
+    /// version `LEGACY_BLOB_FORMAT_VERSION`. Fails for unknown storage formats, and for format
+    /// versions newer than `BLOB_FORMAT_VERSION`, which this code might misread.
+    pub fn parse(bytes: &[u8]) -> std::io::Result<Option<Self>> {
+        if !bytes.starts_with(&BLOB_HEADER_MAGIC_BYTES) {
+            return Ok(None);
+        }
+        if bytes.len() < BLOB_HEADER_SIZE {
+            return Err(invalid_data("Truncated blob header."));
+        }
+        let tag = bytes[BLOB_HEADER_SIZE - 2];
+        let storage_format = StorageFormat::from_blob_header_tag(tag).ok_or_else(|| {
+            invalid_data(format!(
+                "Unknown storage format {} in the blob header.",
+                tag
+            ))
+        })?;
+        let format_version = bytes[BLOB_HEADER_SIZE - 1];
+        if format_version <= LEGACY_BLOB_FORMAT_VERSION || format_version > BLOB_FORMAT_VERSION {
+            return Err(invalid_data(format!(
+                "The blob has format version {}, but only {} to {} are supported; upgrade the indexer.",
+                format_version, LEGACY_BLOB_FORMAT_VERSION, BLOB_FORMAT_VERSION
+            )));
+        }
+        Ok(Some(Self {
+            storage_format,
+            format_version,
+        }))
+    }
+}
+
+/// Payload of a blob, after its header if it has one. Fails on invalid headers.
+fn blob_payload(bytes: &[u8]) -> std::io::Result<&[u8]> {
+    match BlobHeader::parse(bytes)? {
+        Some(_) => Ok(&bytes[BLOB_HEADER_SIZE..]),
+        None => Ok(bytes),
+    }
+}
+
+/// Prepends the header of `storage_format` to the payload of a blob, if blobs of
+/// `blob_format_version` have one.
+fn with_blob_header(
+    storage_format: StorageFormat,
+    blob_format_version: u8,
+    payload: Vec<u8>,
+) -> Vec<u8> {
+    if !storage_format.has_blob_header(blob_format_version) {
+        return payload;
+    }
+    let mut blob = Vec::with_capacity(BLOB_HEADER_SIZE + payload.len());
+    blob.extend_from_slice(&BlobHeader::new(storage_format).to_bytes());
+    blob.extend(payload);
+    blob
+}
+
+/// Starting version of the blob holding `version`, in a file store with blobs of `blob_size`
+/// versions.
+pub fn blob_start_version(version: u64, blob_size: u64) -> u64 {
+    version / blob_size * blob_size
+}
+
+fn compress_gzip(bytes: &[u8]) -> Vec<u8> {
+    let mut compressed = GzEncoder::new(bytes, flate2::Compression::fast());
+    let mut result = Vec::new();
+    compressed
+        .read_to_end(&mut result)
+        .expect("Gzip compression failed.");
+    result
+}
+
+fn compress_zstd(bytes: &[u8], compression_level: i32) -> Vec<u8> {
+    zstd::stream::encode_all(bytes, compression_level).expect("Zstd compression failed.")
+}
+
+/// Compressed storage format of `bytes`, detected from their magic bytes; `None` if they aren't
+/// compressed.
+fn detect_compression(bytes: &[u8]) -> Option<StorageFormat> {
+    if bytes.starts_with(&ZSTD_MAGIC_BYTES) {
+        Some(StorageFormat::ZstdCompressedProto)
+    } else if bytes.starts_with(&GZIP_MAGIC_BYTES) {
+        Some(StorageFormat::GzipCompressedProto)
+    } else {
+        None
+    }
+}
+
+/// Decompresses a compressed entry. The compression is detected from the magic bytes,
+/// so entries written with either compressed format can be read back by both. Bytes without
+/// magic bytes are raw protobuf, and returned as is.
+fn decompress(bytes: &[u8]) -> std::io::Result<Vec<u8>> {
+    match detect_compression(bytes) {
+        Some(StorageFormat::ZstdCompressedProto) => zstd::stream::decode_all(bytes),
+        Some(_) => {
+            let mut decompressed = Vec::new();
+            GzDecoder::new(bytes).read_to_end(&mut decompressed)?;
+            Ok(decompressed)
+        },
+        None => Ok(bytes.to_vec()),
+    }
 }
 
 #[derive(Serialize, Deserialize)]
@@ -30,30 +261,299 @@ pub struct TransactionsLegacyFile {
     pub transactions_in_base64: Vec<String>,
 }
 
+// Schema version of the file store metadata written by this code. Bump it when a change needs
+// more than serde defaults to read older metadata, and upgrade older metadata in
+// `FileStoreOperator::migrate_file_store_metadata`.
+pub const FILE_STORE_METADATA_SCHEMA_VERSION: u64 = 1;
+
+// Number of versions grouped in a folder of a file store with the sharded key layout.
+pub const KEY_LAYOUT_SHARD_SIZE_IN_VERSIONS: u64 = 1_000_000;
+
+/// Layout of the blob keys of a file store; recorded in the file store metadata.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, Eq, PartialEq)]
+pub enum KeyLayout {
+    /// Every blob of a storage format under the same folder, e.g., `files/3000000.json`.
+    #[default]
+    Flat,
+    /// Blobs grouped in folders of `KEY_LAYOUT_SHARD_SIZE_IN_VERSIONS` versions, e.g.,
+    /// `files/3/3000000.json`, so that no folder grows without bound.
+    Sharded,
+    /// Blobs keyed by a custom template, e.g., to keep the layout of another indexer or to share a
+    /// bucket between file stores under distinct prefixes.
+    Template(KeyTemplate),
+}
+
+impl KeyLayout {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        match self {
+            KeyLayout::Flat | KeyLayout::Sharded => Ok(()),
+            KeyLayout::Template(template) => template.validate(),
+        }
+    }
+}
+
+// Widest zero-padded version; u64 versions have at most 20 digits.
+const MAX_KEY_TEMPLATE_PADDING_WIDTH: usize = 20;
+
+/// Template of the blob keys of the `KeyLayout::Template` layout, e.g., `{prefix}/{version_padded}.blob`.
+/// Placeholders are replaced with, for the blob holding a version:
+/// * `{prefix}`: the `prefix`.
+/// * `{version}`: the starting version of the blob.
+/// * `{version_padded}`: the starting version, zero-padded to `padding_width` digits.
+/// * `{shard}`: the starting version divided by `KEY_LAYOUT_SHARD_SIZE_IN_VERSIONS`.
+/// * `{format}`: `gzip`, `zstd`, `json` or `parquet`, after the storage format.
+///
+/// A template has to contain `{version}` or `{version_padded}`, so that blobs get distinct keys.
+#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct KeyTemplate {
+    pub template: String,
+    #[serde(default)]
+    pub prefix: String,
+    #[serde(default = "KeyTemplate::default_padding_width")]
+    pub padding_width: usize,
+}
+
+impl KeyTemplate {
+    pub const fn default_padding_width() -> usize {
+        MAX_KEY_TEMPLATE_PADDING_WIDTH
+    }
+
+    pub fn validate(&self) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            self.padding_width <= MAX_KEY_TEMPLATE_PADDING_WIDTH,
+            "The key template padding width is {}, but at most {} is supported.",
+            self.padding_width,
+            MAX_KEY_TEMPLATE_PADDING_WIDTH
+        );
+        let mut has_version = false;
+        Self::render_with(&self.template, |placeholder| {
+            has_version |= matches!(placeholder, "version" | "version_padded");
+            matches!(
+                placeholder,
+                "prefix" | "version" | "version_padded" | "shard" | "format"
+            )
+            .then(String::new)
+        })
+        .map_err(|err| anyhow::anyhow!("Invalid key template '{}': {}", self.template, err))?;
+        anyhow::ensure!(
+            has_version,
+            "Invalid key template '{}': it has to contain {{version}} or {{version_padded}}.",
+            self.template
+        );
+        let key = self.render(0, StorageFormat::ZstdCompressedProto);
+        anyhow::ensure!(
+            !key.starts_with('/') && !key.ends_with('/') && !key.contains("//"),
+            "Invalid key template '{}': keys can't have empty folder or file names, e.g., '{}'.",
+            self.template,
+            key
+        );
+        Ok(())
+    }
+
+    /// Key of the blob starting at `starting_version`. The template is assumed valid.
+    fn render(&self, starting_version: u64, storage_format: StorageFormat) -> String {
+        Self::render_with(&self.template, |placeholder| match placeholder {
+            "prefix" => Some(self.prefix.clone()),
+            "version" => Some(starting_version.to_string()),
+            "version_padded" => Some(format!(
+                "{:0width$}",
+                starting_version,
+                width = self.padding_width
+            )),
+            "shard" => Some((starting_version / KEY_LAYOUT_SHARD_SIZE_IN_VERSIONS).to_string()),
+            "format" => Some(
+                match storage_format {
+                    StorageFormat::GzipCompressedProto => "gzip",
+                    StorageFormat::ZstdCompressedProto => "zstd",
+                    StorageFormat::JsonBase64UncompressedProto => "json",
+                    StorageFormat::Parquet => "parquet",
+                    StorageFormat::Base64UncompressedProto => {
+                        panic!("Base64UncompressedProto is not supported.")
+                    },
+                }
+                .to_string(),
+            ),
+            _ => None,
+        })
+        .expect("Key templates are validated.")
+    }
+
+    /// Replaces every `{placeholder}` of `template` with `value(placeholder)`; fails on unknown
+    /// placeholders, i.e., if `value` returns `None`, and on unbalanced braces.
+    fn render_with(
+        template: &str,
+        mut value: impl FnMut(&str) -> Option<String>,
+    ) -> Result<String, String> {
+        let mut key = String::with_capacity(template.len());
+        let mut rest = template;
+        while let Some(start) = rest.find(|c| c == '{' || c == '}') {
+            if rest[start..].starts_with('}') {
+                return Err("unbalanced '}'".to_string());
+            }
+            key.push_str(&rest[..start]);
+            let end = rest[start..]
+                .find('}')
+                .ok_or_else(|| "unbalanced '{'".to_string())?
+                + start;
+            let placeholder = &rest[start + 1..end];
+            key.push_str(
+                &value(placeholder)
+                    .ok_or_else(|| format!("unknown placeholder {{{}}}", placeholder))?,
+            );
+            rest = &rest[end + 1..];
+        }
+        key.push_str(rest);
+        Ok(key)
+    }
+}
+
+// Identity recorded as the writer of the file store metadata: the Kubernetes pod name if set,
+// otherwise the hostname.
+static METADATA_WRITER: Lazy<String> = Lazy::new(|| {
+    std::env::var("POD_NAME")
+        .ok()
+        .or_else(|| {
+            hostname::get()
+                .ok()
+                .and_then(|name| name.into_string().ok())
+        })
+        .unwrap_or_default()
+});
+
 /// FileStoreMetadata is the metadata for the file store.
 /// It's a JSON file with name: metadata.json.
-#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
 pub struct FileStoreMetadata {
-    pub chain_id: u64,
-    // The size of each file folder, BLOB_STORAGE_SIZE, i.e., 1_000.
+    pub chain_id: ChainId,
+    // The number of versions of each blob, set when the file store is created; see `blob_size`.
     pub file_folder_size: usize,
     // The current version of the file store.
     pub version: u64,
     // Storage format; backward compatible.
     #[serde(default = "default_file_storage_format")]
     pub storage_format: StorageFormat,
+    // Client-side encryption of the blobs; backward compatible.
+    #[serde(default)]
+    pub encryption_scheme: EncryptionScheme,
+    // Id of the encryption key, if the blobs are encrypted and it was recorded.
+    #[serde(default)]
+    pub encryption_key_id: Option<String>,
+    // Schema version of the metadata; 0 for metadata written before it was recorded.
+    #[serde(default)]
+    pub schema_version: u64,
+    // Unix timestamp of the update, in seconds; 0 for metadata written before it was recorded.
+    #[serde(default)]
+    pub last_updated_at_in_secs: u64,
+    // Pod name, or hostname, of the process that wrote the metadata; empty if unknown.
+    #[serde(default)]
+    pub writer: String,
+    // Incremented on every update; 0 for metadata written before it was recorded.
+    #[serde(default)]
+    pub revision: u64,
+    // Layout of the blob keys; flat for metadata written before it was recorded.
+    #[serde(default)]
+    pub key_layout: KeyLayout,
+    // First version of the blobs uploaded with a digest; `None` for metadata written before
+    // digests were recorded, whose blobs have none.
+    #[serde(default)]
+    pub blob_digests_since_version: Option<u64>,
+    // Format version of the blobs written to the file store; legacy, i.e., without headers, for
+    // metadata written before it was recorded.
+    #[serde(default = "default_blob_format_version")]
+    pub blob_format_version: u8,
+}
+
+const fn default_blob_format_version() -> u8 {
+    LEGACY_BLOB_FORMAT_VERSION
 }
 
 impl FileStoreMetadata {
-    pub fn new(chain_id: u64, version: u64, storage_format: StorageFormat) -> Self {
+    pub fn new(
+        chain_id: ChainId,
+        version: u64,
+        storage_format: StorageFormat,
+        encryption_scheme: EncryptionScheme,
+    ) -> Self {
         Self {
             chain_id,
             file_folder_size: FILE_ENTRY_TRANSACTION_COUNT as usize,
             version,
             storage_format,
+            encryption_scheme,
+            encryption_key_id: None,
+            schema_version: FILE_STORE_METADATA_SCHEMA_VERSION,
+            last_updated_at_in_secs: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|duration| duration.as_secs())
+                .unwrap_or_default(),
+            writer: METADATA_WRITER.clone(),
+            revision: 0,
+            key_layout: KeyLayout::default(),
+            blob_digests_since_version: None,
+            blob_format_version: LEGACY_BLOB_FORMAT_VERSION,
         }
     }
 
+    pub fn with_revision(mut self, revision: u64) -> Self {
+        self.revision = revision;
+        self
+    }
+
+    pub fn with_key_layout(mut self, key_layout: KeyLayout) -> Self {
+        self.key_layout = key_layout;
+        self
+    }
+
+    pub fn with_encryption_key_id(mut self, encryption_key_id: Option<String>) -> Self {
+        self.encryption_key_id = encryption_key_id;
+        self
+    }
+
+    pub fn with_blob_size(mut self, blob_size: u64) -> Self {
+        self.file_folder_size = blob_size as usize;
+        self
+    }
+
+    pub fn with_blob_digests_since_version(mut self, blob_digests_since_version: u64) -> Self {
+        self.blob_digests_since_version = Some(blob_digests_since_version);
+        self
+    }
+
+    pub fn with_blob_format_version(mut self, blob_format_version: u8) -> Self {
+        self.blob_format_version = blob_format_version;
+        self
+    }
+
+    /// Number of versions of each blob of the file store.
+    pub fn blob_size(&self) -> u64 {
+        self.file_folder_size as u64
+    }
+
+    /// Fails if the file store has blobs of another size than `blob_size`, which can't be changed
+    /// once blobs were written.
+    pub fn check_blob_size(&self, blob_size: u64) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            self.blob_size() == blob_size,
+            "The file store has blobs of {} versions, but {} are configured; the blob size can't change once a file store is created.",
+            self.blob_size(),
+            blob_size
+        );
+        Ok(())
+    }
+
+    /// Fails if the metadata was written by a newer version of the code, whose fields might be
+    /// misinterpreted.
+    pub fn check_schema_version(&self) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            self.schema_version <= FILE_STORE_METADATA_SCHEMA_VERSION,
+            "The file store metadata has schema version {}, but at most {} is supported; upgrade the indexer.",
+            self.schema_version,
+            FILE_STORE_METADATA_SCHEMA_VERSION
+        );
+        Ok(())
+    }
+
     pub fn from_bytes(bytes: Vec<u8>) -> Self {
         serde_json::from_slice(bytes.as_slice())
             .expect("FileStoreMetadata json deserialization failed.")
@@ -68,10 +568,21 @@ pub enum CacheEntry {
     GzipCompressionProto(Vec<u8>),
     // Only used for legacy cache entry.
     Base64UncompressedProto(Vec<u8>),
+    ZstdCompressionProto(Vec<u8>),
 }
 
+// Storage formats of the cache, in the order their keys are tried when an entry is missing.
+const CACHE_STORAGE_FORMATS: [StorageFormat; 3] = [
+    StorageFormat::ZstdCompressedProto,
+    StorageFormat::GzipCompressedProto,
+    StorageFormat::Base64UncompressedProto,
+];
+
 impl CacheEntry {
+    /// Compressed entries are detected from their magic bytes, which base64 never starts with, so
+    /// they're read back whatever the configured `storage_format`.
     pub fn new(bytes: Vec<u8>, storage_format: StorageFormat) -> Self {
+        let storage_format = detect_compression(&bytes).unwrap_or(storage_format);
         match storage_format {
             StorageFormat::GzipCompressedProto => Self::GzipCompressionProto(bytes),
             // Legacy format.
@@ -79,6 +590,10 @@ impl CacheEntry {
             StorageFormat::JsonBase64UncompressedProto => {
                 panic!("JsonBase64UncompressedProto is not supported.")
             },
+            StorageFormat::ZstdCompressedProto => Self::ZstdCompressionProto(bytes),
+            StorageFormat::Parquet => {
+                panic!("Parquet is not supported.")
+            },
         }
     }
 
@@ -86,6 +601,7 @@ impl CacheEntry {
         match self {
             CacheEntry::GzipCompressionProto(bytes) => bytes,
             CacheEntry::Base64UncompressedProto(bytes) => bytes,
+            CacheEntry::ZstdCompressionProto(bytes) => bytes,
         }
     }
 
@@ -93,22 +609,35 @@ impl CacheEntry {
         match self {
             CacheEntry::GzipCompressionProto(bytes) => bytes.len(),
             CacheEntry::Base64UncompressedProto(bytes) => bytes.len(),
+            CacheEntry::ZstdCompressionProto(bytes) => bytes.len(),
         }
     }
 
     pub fn from_transaction(transaction: Transaction, storage_format: StorageFormat) -> Self {
+        Self::from_transaction_with_compression_level(
+            transaction,
+            storage_format,
+            DEFAULT_ZSTD_COMPRESSION_LEVEL,
+        )
+    }
+
+    /// Same as `from_transaction`, with an explicit zstd compression level.
+    /// The level is ignored by the other storage formats.
+    pub fn from_transaction_with_compression_level(
+        transaction: Transaction,
+        storage_format: StorageFormat,
+        compression_level: i32,
+    ) -> Self {
         let mut bytes = Vec::new();
         transaction
             .encode(&mut bytes)
             .expect("proto serialization failed.");
         match storage_format {
             StorageFormat::GzipCompressedProto => {
-                let mut compressed = GzEncoder::new(bytes.as_slice(), flate2::Compression::fast());
-                let mut result = Vec::new();
-                compressed
-                    .read_to_end(&mut result)
-                    .expect("Gzip compression failed.");
-                CacheEntry::GzipCompressionProto(result)
+                CacheEntry::GzipCompressionProto(compress_gzip(&bytes))
+            },
+            StorageFormat::ZstdCompressedProto => {
+                CacheEntry::ZstdCompressionProto(compress_zstd(&bytes, compression_level))
             },
             StorageFormat::Base64UncompressedProto => {
                 let base64 = base64::encode(bytes).into_bytes();
@@ -118,6 +647,9 @@ impl CacheEntry {
                 // This is fatal to see that we are using legacy file format in cache side.
                 panic!("JsonBase64UncompressedProto is not supported in cache.")
             },
+            StorageFormat::Parquet => {
+                panic!("Parquet is not supported in cache.")
+            },
         }
     }
 
@@ -129,45 +661,97 @@ impl CacheEntry {
             StorageFormat::Base64UncompressedProto => {
                 format!("{}", version)
             },
+            StorageFormat::ZstdCompressedProto => {
+                format!("zstd:{}", version)
+            },
             StorageFormat::JsonBase64UncompressedProto => {
                 // This is fatal to see that we are using legacy file format in cache side.
                 panic!("JsonBase64UncompressedProto is not supported in cache.")
             },
+            StorageFormat::Parquet => {
+                panic!("Parquet is not supported in cache.")
+            },
         }
     }
 
-    pub fn into_transaction(self) -> Transaction {
+    /// Storage formats whose keys a cache in `storage_format` falls back to for missing entries,
+    /// e.g., the gzip entries written before the cache switched to zstd, in the order they're
+    /// tried.
+    pub fn legacy_storage_formats(storage_format: StorageFormat) -> Vec<StorageFormat> {
+        CACHE_STORAGE_FORMATS
+            .into_iter()
+            .filter(|legacy_format| *legacy_format != storage_format)
+            .collect()
+    }
+
+    /// Converts an entry read under the key of `legacy_format` into one that `CacheEntry::new`
+    /// reads back in `storage_format`. Compressed entries are kept, since they're detected from
+    /// their magic bytes; base64 entries are decoded into raw protobuf for compressed formats,
+    /// which read it as is.
+    pub fn from_legacy_entry(
+        bytes: Vec<u8>,
+        legacy_format: StorageFormat,
+        storage_format: StorageFormat,
+    ) -> anyhow::Result<Vec<u8>> {
+        if legacy_format == StorageFormat::Base64UncompressedProto && storage_format.is_compressed()
+        {
+            CacheEntry::new(bytes, legacy_format).into_encoded_transaction()
+        } else {
+            Ok(bytes)
+        }
+    }
+
+    pub fn into_transaction(self) -> anyhow::Result<Transaction> {
+        let bytes = self.into_encoded_transaction()?;
+        Transaction::decode(bytes.as_slice()).context("proto deserialization failed.")
+    }
+
+    /// Returns the protobuf encoding of the transaction, decompressed but not decoded.
+    pub fn into_encoded_transaction(self) -> anyhow::Result<Vec<u8>> {
         match self {
-            CacheEntry::GzipCompressionProto(bytes) => {
-                let mut decompressor = GzDecoder::new(&bytes[..]);
-                let mut decompressed = Vec::new();
-                decompressor
-                    .read_to_end(&mut decompressed)
-                    .expect("Gzip decompression failed.");
-                Transaction::decode(decompressed.as_slice()).expect("proto deserialization failed.")
+            CacheEntry::GzipCompressionProto(bytes) | CacheEntry::ZstdCompressionProto(bytes) => {
+                decompress(&bytes).context("Decompression failed.")
             },
             CacheEntry::Base64UncompressedProto(bytes) => {
-                let bytes: Vec<u8> = base64::decode(bytes).expect("base64 decoding failed.");
-                Transaction::decode(bytes.as_slice()).expect("proto deserialization failed.")
+                base64::decode(bytes).context("base64 decoding failed.")
             },
         }
     }
 }
 
+// Storage formats of the file store other than Parquet, in the order their keys are tried when a
+// blob is missing.
+const FILE_STORE_STORAGE_FORMATS: [StorageFormat; 3] = [
+    StorageFormat::ZstdCompressedProto,
+    StorageFormat::GzipCompressedProto,
+    StorageFormat::JsonBase64UncompressedProto,
+];
+
 pub enum FileEntry {
     GzipCompressionProto(Vec<u8>),
     // Only used for legacy file format.
     JsonBase64UncompressedProto(Vec<u8>),
+    ZstdCompressionProto(Vec<u8>),
+    Parquet(Vec<u8>),
 }
 
 impl FileEntry {
+    /// The storage format in the header of the blob, if it has a valid one, takes precedence over
+    /// `storage_format`.
     pub fn new(bytes: Vec<u8>, storage_format: StorageFormat) -> Self {
+        let storage_format = match BlobHeader::parse(&bytes) {
+            Ok(Some(header)) => header.storage_format,
+            // Blobs with invalid headers fail to decode.
+            Ok(None) | Err(_) => storage_format,
+        };
         match storage_format {
             StorageFormat::GzipCompressedProto => Self::GzipCompressionProto(bytes),
             StorageFormat::Base64UncompressedProto => {
                 panic!("Base64UncompressedProto is not supported.")
             },
             StorageFormat::JsonBase64UncompressedProto => Self::JsonBase64UncompressedProto(bytes),
+            StorageFormat::ZstdCompressedProto => Self::ZstdCompressionProto(bytes),
+            StorageFormat::Parquet => Self::Parquet(bytes),
         }
     }
 
@@ -175,6 +759,8 @@ impl FileEntry {
         match self {
             FileEntry::GzipCompressionProto(bytes) => bytes,
             FileEntry::JsonBase64UncompressedProto(bytes) => bytes,
+            FileEntry::ZstdCompressionProto(bytes) => bytes,
+            FileEntry::Parquet(bytes) => bytes,
         }
     }
 
@@ -182,6 +768,8 @@ impl FileEntry {
         match self {
             FileEntry::GzipCompressionProto(bytes) => bytes.len(),
             FileEntry::JsonBase64UncompressedProto(bytes) => bytes.len(),
+            FileEntry::ZstdCompressionProto(bytes) => bytes.len(),
+            FileEntry::Parquet(bytes) => bytes.len(),
         }
     }
 
@@ -189,7 +777,20 @@ impl FileEntry {
         transactions: Vec<Transaction>,
         storage_format: StorageFormat,
     ) -> Self {
-        let mut bytes = Vec::new();
+        Self::from_transactions_with_compression_level(
+            transactions,
+            storage_format,
+            DEFAULT_ZSTD_COMPRESSION_LEVEL,
+        )
+    }
+
+    /// Same as `from_transactions`, with an explicit zstd compression level.
+    /// Parquet uses it for its pages; the other storage formats ignore it.
+    pub fn from_transactions_with_compression_level(
+        transactions: Vec<Transaction>,
+        storage_format: StorageFormat,
+        compression_level: i32,
+    ) -> Self {
         let starting_version = transactions
             .first()
             .expect("Cannot build empty file")
@@ -201,19 +802,50 @@ impl FileEntry {
         if starting_version % FILE_ENTRY_TRANSACTION_COUNT != 0 {
             panic!("Starting version has to be a multiple of FILE_ENTRY_TRANSACTION_COUNT.")
         }
+        Self::from_filtered_transactions(
+            starting_version,
+            transactions,
+            storage_format,
+            compression_level,
+            BLOB_FORMAT_VERSION,
+        )
+    }
+
+    /// Builds the file of the batch starting at `starting_version` from a subset of its
+    /// transactions, e.g., after filtering, in blob format version `blob_format_version`. The
+    /// subset may be empty.
+    pub fn from_filtered_transactions(
+        starting_version: u64,
+        transactions: Vec<Transaction>,
+        storage_format: StorageFormat,
+        compression_level: i32,
+        blob_format_version: u8,
+    ) -> Self {
+        let mut bytes = Vec::new();
         match storage_format {
             StorageFormat::GzipCompressedProto => {
                 let t = TransactionsInStorage {
-                    starting_version: Some(transactions.first().unwrap().version),
+                    starting_version: Some(starting_version),
                     transactions,
                 };
                 t.encode(&mut bytes).expect("proto serialization failed.");
-                let mut compressed = GzEncoder::new(bytes.as_slice(), flate2::Compression::fast());
-                let mut result = Vec::new();
-                compressed
-                    .read_to_end(&mut result)
-                    .expect("Gzip compression failed.");
-                FileEntry::GzipCompressionProto(result)
+                FileEntry::GzipCompressionProto(with_blob_header(
+                    storage_format,
+                    blob_format_version,
+                    compress_gzip(&bytes),
+                ))
+            },
+            StorageFormat::ZstdCompressedProto => {
+                let t = TransactionsInStorage {
+                    starting_version: Some(starting_version),
+                    transactions,
+                };
+                t.encode(&mut bytes).expect("proto serialization failed.");
+                FileEntry::ZstdCompressionProto(with_blob_header(
+                    storage_format,
+                    blob_format_version,
+                    compress_zstd(&bytes, compression_level),
+                ))
             },
             StorageFormat::Base64UncompressedProto => {
                 panic!("Base64UncompressedProto is not supported.")
@@ -236,12 +868,24 @@ impl FileEntry {
                 let json = serde_json::to_vec(&file).expect("json serialization failed.");
                 FileEntry::JsonBase64UncompressedProto(json)
             },
+            StorageFormat::Parquet => FileEntry::Parquet(encode_parquet(
+                starting_version,
+                &transactions,
+                compression_level,
+            )),
         }
     }
 
+    /// Key of the blob holding `version` in a flat file store with blobs of
+    /// `FILE_ENTRY_TRANSACTION_COUNT` versions.
     pub fn build_key(version: u64, storage_format: StorageFormat) -> String {
-        let starting_version =
-            version / FILE_ENTRY_TRANSACTION_COUNT * FILE_ENTRY_TRANSACTION_COUNT;
+        Self::build_flat_key(
+            blob_start_version(version, FILE_ENTRY_TRANSACTION_COUNT),
+            storage_format,
+        )
+    }
+
+    fn build_flat_key(starting_version: u64, storage_format: StorageFormat) -> String {
         let mut hasher = Ripemd128::new();
         hasher.update(starting_version.to_string());
         let file_prefix = format!("{:x}", hasher.finalize());
@@ -255,45 +899,363 @@ impl FileEntry {
             StorageFormat::JsonBase64UncompressedProto => {
                 format!("files/{}.json", starting_version)
             },
+            StorageFormat::ZstdCompressedProto => {
+                format!(
+                    "compressed_files/zstd/{}_{}.bin",
+                    file_prefix, starting_version
+                )
+            },
+            StorageFormat::Parquet => {
+                format!("parquet_files/{}_{}.parquet", file_prefix, starting_version)
+            },
             StorageFormat::Base64UncompressedProto => {
                 panic!("Base64UncompressedProto is not supported.")
             },
         }
     }
 
-    pub fn into_transactions_in_storage(self) -> TransactionsInStorage {
+    /// Keys the blob starting at `blob_version` has if it was written in another storage format
+    /// than `storage_format`, e.g., gzip blobs of a file store since switched to zstd, with their
+    /// storage formats, in the order they're tried. Parquet file stores are never converted.
+    pub fn build_legacy_blob_keys(
+        blob_version: u64,
+        storage_format: StorageFormat,
+        key_layout: &KeyLayout,
+    ) -> Vec<(StorageFormat, String)> {
+        if storage_format == StorageFormat::Parquet {
+            return vec![];
+        }
+        FILE_STORE_STORAGE_FORMATS
+            .into_iter()
+            .filter(|legacy_format| *legacy_format != storage_format)
+            .map(|legacy_format| {
+                (
+                    legacy_format,
+                    Self::build_blob_key(blob_version, legacy_format, key_layout),
+                )
+            })
+            .collect()
+    }
+
+    /// Key of the blob holding `version` in a file store with the given key layout and blobs of
+    /// `FILE_ENTRY_TRANSACTION_COUNT` versions.
+    pub fn build_key_with_layout(
+        version: u64,
+        storage_format: StorageFormat,
+        key_layout: &KeyLayout,
+    ) -> String {
+        Self::build_blob_key(
+            blob_start_version(version, FILE_ENTRY_TRANSACTION_COUNT),
+            storage_format,
+            key_layout,
+        )
+    }
+
+    /// Key of the blob starting at `blob_version` in a file store with the given key layout, for
+    /// any blob size.
+    pub fn build_blob_key(
+        blob_version: u64,
+        storage_format: StorageFormat,
+        key_layout: &KeyLayout,
+    ) -> String {
+        match key_layout {
+            KeyLayout::Flat => Self::build_flat_key(blob_version, storage_format),
+            KeyLayout::Sharded => {
+                let key = Self::build_flat_key(blob_version, storage_format);
+                let (folder, file_name) = key.rsplit_once('/').expect("Keys have a folder.");
+                format!(
+                    "{}/{}/{}",
+                    folder,
+                    blob_version / KEY_LAYOUT_SHARD_SIZE_IN_VERSIONS,
+                    file_name
+                )
+            },
+            KeyLayout::Template(template) => template.render(blob_version, storage_format),
+        }
+    }
+
+    pub fn into_transactions_in_storage(self) -> anyhow::Result<TransactionsInStorage> {
         match self {
-            FileEntry::GzipCompressionProto(bytes) => {
-                let mut decompressor = GzDecoder::new(&bytes[..]);
-                let mut decompressed = Vec::new();
-                decompressor
-                    .read_to_end(&mut decompressed)
-                    .expect("Gzip decompression failed.");
+            // Format versions 1 and 2 only differ by the header.
+            FileEntry::GzipCompressionProto(bytes) | FileEntry::ZstdCompressionProto(bytes) => {
+                let decompressed =
+                    decompress(blob_payload(&bytes)?).context("Decompression failed.")?;
                 TransactionsInStorage::decode(decompressed.as_slice())
-                    .expect("proto deserialization failed.")
+                    .context("proto deserialization failed.")
             },
             FileEntry::JsonBase64UncompressedProto(bytes) => {
-                let file: TransactionsLegacyFile =
-                    serde_json::from_slice(bytes.as_slice()).expect("json deserialization failed.");
+                let file: TransactionsLegacyFile = serde_json::from_slice(blob_payload(&bytes)?)
+                    .context("json deserialization failed.")?;
                 let transactions = file
                     .transactions_in_base64
                     .into_iter()
                     .map(|base64| {
                         let bytes: Vec<u8> =
-                            base64::decode(base64).expect("base64 decoding failed.");
+                            base64::decode(base64).context("base64 decoding failed.")?;
                         Transaction::decode(bytes.as_slice())
-                            .expect("proto deserialization failed.")
+                            .context("proto deserialization failed.")
                     })
-                    .collect::<Vec<Transaction>>();
-                TransactionsInStorage {
+                    .collect::<anyhow::Result<Vec<Transaction>>>()?;
+                Ok(TransactionsInStorage {
                     starting_version: Some(file.starting_version),
                     transactions,
-                }
+                })
             },
+            FileEntry::Parquet(bytes) => {
+                decode_parquet(blob_payload(&bytes)?).context("parquet deserialization failed.")
+            },
+        }
+    }
+}
+
+// Field numbers of `TransactionsInStorage`.
+const TRANSACTIONS_FIELD_NUMBER: u32 = 1;
+const STARTING_VERSION_FIELD_NUMBER: u32 = 2;
+// Protobuf wire types.
+const VARINT_WIRE_TYPE: u64 = 0;
+const FIXED64_WIRE_TYPE: u64 = 1;
+const LENGTH_DELIMITED_WIRE_TYPE: u64 = 2;
+const FIXED32_WIRE_TYPE: u64 = 5;
+
+enum Compressor<W: Write> {
+    Gzip(flate2::write::GzEncoder<W>),
+    Zstd(zstd::stream::write::Encoder<'static, W>),
+}
+
+impl<W: Write> Compressor<W> {
+    fn writer(&mut self) -> &mut dyn Write {
+        match self {
+            Compressor::Gzip(encoder) => encoder,
+            Compressor::Zstd(encoder) => encoder,
+        }
+    }
+
+    fn finish(self) -> std::io::Result<W> {
+        match self {
+            Compressor::Gzip(encoder) => encoder.finish(),
+            Compressor::Zstd(encoder) => encoder.finish(),
+        }
+    }
+}
+
+/// Writes the blob of a batch to a sink one transaction at a time, each encoded straight into the
+/// compressor, so that neither a copy of the batch nor its uncompressed encoding is held in
+/// memory. The blob decodes like one built with `FileEntry::from_transactions`, though its bytes
+/// may differ; writing the same transactions again gives the same bytes.
+pub struct FileEntryWriter<W: Write> {
+    compressor: Compressor<W>,
+    starting_version: u64,
+    transaction_count: u64,
+    // Encoding of the transaction being written; reused across transactions.
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> FileEntryWriter<W> {
+    /// Writes the blob header right away, if blobs of `blob_format_version` have one. Panics for
+    /// storage formats that don't support incremental encoding.
+    pub fn new(
+        mut sink: W,
+        starting_version: u64,
+        storage_format: StorageFormat,
+        compression_level: i32,
+        blob_format_version: u8,
+    ) -> std::io::Result<Self> {
+        if storage_format.has_blob_header(blob_format_version) {
+            sink.write_all(&BlobHeader::new(storage_format).to_bytes())?;
         }
+        let compressor = match storage_format {
+            StorageFormat::GzipCompressedProto => Compressor::Gzip(flate2::write::GzEncoder::new(
+                sink,
+                flate2::Compression::fast(),
+            )),
+            StorageFormat::ZstdCompressedProto => {
+                Compressor::Zstd(zstd::stream::write::Encoder::new(sink, compression_level)?)
+            },
+            _ => panic!("{:?} doesn't support incremental encoding.", storage_format),
+        };
+        Ok(Self {
+            compressor,
+            starting_version,
+            transaction_count: 0,
+            buffer: Vec::new(),
+        })
+    }
+
+    pub fn write_transaction(&mut self, transaction: &Transaction) -> std::io::Result<()> {
+        self.buffer.clear();
+        prost::encoding::message::encode(TRANSACTIONS_FIELD_NUMBER, transaction, &mut self.buffer);
+        self.compressor.writer().write_all(&self.buffer)?;
+        self.transaction_count += 1;
+        Ok(())
+    }
+
+    /// Same as `write_transaction`, for the protobuf encoding of a transaction, e.g., as read from
+    /// the cache; it is written as is, without being decoded, which gives the same bytes.
+    pub fn write_encoded_transaction(&mut self, encoded_transaction: &[u8]) -> std::io::Result<()> {
+        self.buffer.clear();
+        prost::encoding::encode_key(
+            TRANSACTIONS_FIELD_NUMBER,
+            prost::encoding::WireType::LengthDelimited,
+            &mut self.buffer,
+        );
+        prost::encoding::encode_varint(encoded_transaction.len() as u64, &mut self.buffer);
+        let writer = self.compressor.writer();
+        writer.write_all(&self.buffer)?;
+        writer.write_all(encoded_transaction)?;
+        self.transaction_count += 1;
+        Ok(())
+    }
+
+    pub fn transaction_count(&self) -> u64 {
+        self.transaction_count
+    }
+
+    /// Writes the end of the blob and returns the sink.
+    pub fn finish(mut self) -> std::io::Result<W> {
+        self.buffer.clear();
+        prost::encoding::uint64::encode(
+            STARTING_VERSION_FIELD_NUMBER,
+            &self.starting_version,
+            &mut self.buffer,
+        );
+        self.compressor.writer().write_all(&self.buffer)?;
+        self.compressor.finish()
     }
 }
 
+/// Reads the transactions of a compressed blob one at a time, decompressing and decoding it as it
+/// is read, so that neither the blob nor its uncompressed encoding is held in memory; only the
+/// transaction being decoded and the buffers of the decompressor are. Like `decompress`, it reads
+/// blobs of either compressed format, with or without a header.
+pub struct FileEntryReader {
+    source: BufReader<Box<dyn Read + Send>>,
+    starting_version: Option<u64>,
+    // Encoding of the transaction being read; reused across transactions.
+    buffer: Vec<u8>,
+    done: bool,
+}
+
+impl FileEntryReader {
+    pub fn new<R: Read + Send + 'static>(mut source: R) -> std::io::Result<Self> {
+        // Skips the header, if any, then reads the magic bytes and puts them back in front of the
+        // rest of the blob.
+        let mut magic_bytes = Vec::with_capacity(BLOB_HEADER_SIZE);
+        (&mut source)
+            .take(BLOB_HEADER_SIZE as u64)
+            .read_to_end(&mut magic_bytes)?;
+        if BlobHeader::parse(&magic_bytes)?.is_some() {
+            magic_bytes.clear();
+        }
+        (&mut source)
+            .take(ZSTD_MAGIC_BYTES.len().saturating_sub(magic_bytes.len()) as u64)
+            .read_to_end(&mut magic_bytes)?;
+        let source = std::io::Cursor::new(magic_bytes.clone()).chain(source);
+        let decompressor: Box<dyn Read + Send> = if magic_bytes.starts_with(&ZSTD_MAGIC_BYTES) {
+            Box::new(zstd::stream::read::Decoder::new(source)?)
+        } else if magic_bytes.starts_with(&GZIP_MAGIC_BYTES) {
+            Box::new(GzDecoder::new(source))
+        } else {
+            return Err(invalid_data("Unknown compression format."));
+        };
+        Ok(Self {
+            source: BufReader::new(decompressor),
+            starting_version: None,
+            buffer: Vec::new(),
+            done: false,
+        })
+    }
+
+    /// Starting version recorded in the blob; known once every transaction is read, since it's
+    /// encoded after them.
+    pub fn starting_version(&self) -> Option<u64> {
+        self.starting_version
+    }
+
+    fn read_transaction(&mut self) -> std::io::Result<Option<Transaction>> {
+        loop {
+            let key = match read_varint(&mut self.source)? {
+                Some(key) => key,
+                None => return Ok(None),
+            };
+            let (field_number, wire_type) = (key >> 3, key & 0x7);
+            match wire_type {
+                LENGTH_DELIMITED_WIRE_TYPE => {
+                    let length = read_varint(&mut self.source)?
+                        .ok_or_else(|| invalid_data("Truncated field length."))?;
+                    self.buffer.clear();
+                    (&mut self.source)
+                        .take(length)
+                        .read_to_end(&mut self.buffer)?;
+                    if self.buffer.len() as u64 != length {
+                        return Err(invalid_data("Truncated field."));
+                    }
+                    if field_number == TRANSACTIONS_FIELD_NUMBER as u64 {
+                        return Transaction::decode(self.buffer.as_slice())
+                            .map(Some)
+                            .map_err(invalid_data);
+                    }
+                },
+                VARINT_WIRE_TYPE => {
+                    let value = read_varint(&mut self.source)?
+                        .ok_or_else(|| invalid_data("Truncated varint."))?;
+                    if field_number == STARTING_VERSION_FIELD_NUMBER as u64 {
+                        self.starting_version = Some(value);
+                    }
+                },
+                FIXED64_WIRE_TYPE | FIXED32_WIRE_TYPE => {
+                    let length = if wire_type == FIXED64_WIRE_TYPE { 8 } else { 4 };
+                    let mut value = [0; 8];
+                    self.source.read_exact(&mut value[..length])?;
+                },
+                _ => {
+                    return Err(invalid_data(format!(
+                        "Unsupported wire type {}.",
+                        wire_type
+                    )))
+                },
+            }
+        }
+    }
+}
+
+impl Iterator for FileEntryReader {
+    type Item = std::io::Result<Transaction>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let transaction = self.read_transaction();
+        // Stops at the end of the blob and at the first error.
+        self.done = !matches!(transaction, Ok(Some(_)));
+        transaction.transpose()
+    }
+}
+
+fn invalid_data<E: Into<Box<dyn std::error::Error + Send + Sync>>>(error: E) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, error)
+}
+
+/// Reads a varint, or returns `None` at the end of `source`.
+fn read_varint(source: &mut impl BufRead) -> std::io::Result<Option<u64>> {
+    let mut value = 0;
+    for i in 0..10 {
+        let mut byte = [0];
+        if source.read(&mut byte)? == 0 {
+            return if i == 0 {
+                Ok(None)
+            } else {
+                Err(invalid_data("Truncated varint."))
+            };
+        }
+        value |= ((byte[0] & 0x7F) as u64) << (7 * i);
+        if byte[0] & 0x80 == 0 {
+            return Ok(Some(value));
+        }
+    }
+    Err(invalid_data("Invalid varint."))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -311,7 +1273,7 @@ mod tests {
             CacheEntry::from_transaction(transaction, StorageFormat::Base64UncompressedProto);
         // Make sure data is compressed.
         assert_ne!(cache_entry.size(), transaction_size);
-        let deserialized_transaction = cache_entry.into_transaction();
+        let deserialized_transaction = cache_entry.into_transaction().unwrap();
         assert_eq!(transaction_clone, deserialized_transaction);
     }
 
@@ -328,7 +1290,7 @@ mod tests {
             CacheEntry::from_transaction(transaction, StorageFormat::GzipCompressedProto);
         let compressed_size = cache_entry.size();
         assert!(compressed_size != proto_size);
-        let deserialized_transaction = cache_entry.into_transaction();
+        let deserialized_transaction = cache_entry.into_transaction().unwrap();
         assert_eq!(transaction_clone, deserialized_transaction);
     }
 
@@ -371,7 +1333,7 @@ mod tests {
             transactions.clone(),
             StorageFormat::JsonBase64UncompressedProto,
         );
-        let deserialized_transactions = file_entry.into_transactions_in_storage();
+        let deserialized_transactions = file_entry.into_transactions_in_storage().unwrap();
         for (i, transaction) in transactions.iter().enumerate() {
             assert_eq!(transaction, &deserialized_transactions.transactions[i]);
         }
@@ -394,12 +1356,327 @@ mod tests {
         let file_entry =
             FileEntry::from_transactions(transactions.clone(), StorageFormat::GzipCompressedProto);
         assert_ne!(file_entry.size(), transactions_in_storage_size);
-        let deserialized_transactions = file_entry.into_transactions_in_storage();
+        let deserialized_transactions = file_entry.into_transactions_in_storage().unwrap();
         for (i, transaction) in transactions.iter().enumerate() {
             assert_eq!(transaction, &deserialized_transactions.transactions[i]);
         }
     }
 
+    #[test]
+    fn test_cache_entry_builder_zstd_compressed_proto() {
+        let transaction = Transaction {
+            version: 42,
+            epoch: 333,
+            ..Transaction::default()
+        };
+        let transaction_clone = transaction.clone();
+        let cache_entry = CacheEntry::from_transaction_with_compression_level(
+            transaction,
+            StorageFormat::ZstdCompressedProto,
+            19,
+        );
+        assert!(cache_entry.size() > 0);
+        let deserialized_transaction = cache_entry.into_transaction().unwrap();
+        assert_eq!(transaction_clone, deserialized_transaction);
+    }
+
+    #[test]
+    fn test_file_entry_builder_zstd_compressed_proto() {
+        let transactions = (1000..2000)
+            .map(|version| Transaction {
+                version,
+                epoch: 333,
+                ..Transaction::default()
+            })
+            .collect::<Vec<Transaction>>();
+        let transactions_in_storage = TransactionsInStorage {
+            starting_version: Some(1000),
+            transactions: transactions.clone(),
+        };
+        let transactions_in_storage_size = transactions_in_storage.encoded_len();
+        let file_entry =
+            FileEntry::from_transactions(transactions.clone(), StorageFormat::ZstdCompressedProto);
+        assert!(file_entry.size() < transactions_in_storage_size);
+        let deserialized_transactions = file_entry.into_transactions_in_storage().unwrap();
+        for (i, transaction) in transactions.iter().enumerate() {
+            assert_eq!(transaction, &deserialized_transactions.transactions[i]);
+        }
+    }
+
+    #[test]
+    fn test_compressed_entries_are_readable_by_either_compressed_format() {
+        let transactions = (0..1000)
+            .map(|version| Transaction {
+                version,
+                epoch: 333,
+                ..Transaction::default()
+            })
+            .collect::<Vec<Transaction>>();
+        let gzip_bytes =
+            FileEntry::from_transactions(transactions.clone(), StorageFormat::GzipCompressedProto)
+                .into_inner();
+        let zstd_bytes =
+            FileEntry::from_transactions(transactions.clone(), StorageFormat::ZstdCompressedProto)
+                .into_inner();
+        let from_gzip = FileEntry::new(gzip_bytes, StorageFormat::ZstdCompressedProto)
+            .into_transactions_in_storage()
+            .unwrap();
+        let from_zstd = FileEntry::new(zstd_bytes, StorageFormat::GzipCompressedProto)
+            .into_transactions_in_storage()
+            .unwrap();
+        assert_eq!(from_gzip.transactions, transactions);
+        assert_eq!(from_zstd.transactions, transactions);
+    }
+
+    #[test]
+    fn legacy_cache_entries_are_readable_in_every_storage_format() {
+        let transaction = Transaction {
+            version: 42,
+            epoch: 333,
+            ..Transaction::default()
+        };
+        for storage_format in CACHE_STORAGE_FORMATS {
+            for legacy_format in CacheEntry::legacy_storage_formats(storage_format) {
+                let legacy_entry =
+                    CacheEntry::from_transaction(transaction.clone(), legacy_format).into_inner();
+                let entry =
+                    CacheEntry::from_legacy_entry(legacy_entry, legacy_format, storage_format)
+                        .unwrap();
+                assert_eq!(
+                    CacheEntry::new(entry, storage_format)
+                        .into_transaction()
+                        .unwrap(),
+                    transaction
+                );
+            }
+        }
+        // Raw protobuf isn't compressed, and is read as is.
+        assert_eq!(
+            CacheEntry::new(
+                transaction.encode_to_vec(),
+                StorageFormat::ZstdCompressedProto
+            )
+            .into_transaction()
+            .unwrap(),
+            transaction
+        );
+        // Corrupt entries fail to decode instead of panicking.
+        let mut corrupt_entry =
+            CacheEntry::from_transaction(transaction, StorageFormat::ZstdCompressedProto)
+                .into_inner();
+        corrupt_entry.truncate(ZSTD_MAGIC_BYTES.len() + 1);
+        assert!(
+            CacheEntry::new(corrupt_entry, StorageFormat::ZstdCompressedProto)
+                .into_transaction()
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn legacy_blob_keys_are_those_of_the_other_storage_formats() {
+        assert_eq!(
+            FileEntry::build_legacy_blob_keys(
+                0,
+                StorageFormat::ZstdCompressedProto,
+                &KeyLayout::Flat
+            ),
+            vec![
+                (
+                    StorageFormat::GzipCompressedProto,
+                    "compressed_files/gzip/3d1bff1ba654ca5fdb6ac1370533d876_0.bin".to_string()
+                ),
+                (
+                    StorageFormat::JsonBase64UncompressedProto,
+                    "files/0.json".to_string()
+                ),
+            ]
+        );
+        assert!(
+            FileEntry::build_legacy_blob_keys(0, StorageFormat::Parquet, &KeyLayout::Flat)
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn legacy_blobs_without_a_header_are_readable() {
+        let transactions_in_storage = TransactionsInStorage {
+            starting_version: Some(1000),
+            transactions: (1000..2000)
+                .map(|version| Transaction {
+                    version,
+                    ..Transaction::default()
+                })
+                .collect(),
+        };
+        let encoded = transactions_in_storage.encode_to_vec();
+        for (storage_format, legacy_blob) in [
+            (StorageFormat::GzipCompressedProto, compress_gzip(&encoded)),
+            (
+                StorageFormat::ZstdCompressedProto,
+                compress_zstd(&encoded, DEFAULT_ZSTD_COMPRESSION_LEVEL),
+            ),
+        ] {
+            assert_eq!(BlobHeader::parse(&legacy_blob).unwrap(), None);
+            assert_eq!(
+                FileEntry::new(legacy_blob.clone(), storage_format)
+                    .into_transactions_in_storage()
+                    .unwrap(),
+                transactions_in_storage
+            );
+            let read: Vec<Transaction> = FileEntryReader::new(std::io::Cursor::new(legacy_blob))
+                .unwrap()
+                .map(|transaction| transaction.unwrap())
+                .collect();
+            assert_eq!(read, transactions_in_storage.transactions);
+
+            // Blobs written now have a header, and read the same.
+            let blob = FileEntry::from_transactions(
+                transactions_in_storage.transactions.clone(),
+                storage_format,
+            )
+            .into_inner();
+            assert_eq!(
+                BlobHeader::parse(&blob).unwrap(),
+                Some(BlobHeader {
+                    storage_format,
+                    format_version: BLOB_FORMAT_VERSION,
+                })
+            );
+            assert_eq!(
+                FileEntry::new(blob, storage_format)
+                    .into_transactions_in_storage()
+                    .unwrap(),
+                transactions_in_storage
+            );
+
+            // Unless the file store keeps the legacy format version, for older readers.
+            let blob = FileEntry::from_filtered_transactions(
+                1000,
+                transactions_in_storage.transactions.clone(),
+                storage_format,
+                DEFAULT_ZSTD_COMPRESSION_LEVEL,
+                LEGACY_BLOB_FORMAT_VERSION,
+            )
+            .into_inner();
+            assert_eq!(BlobHeader::parse(&blob).unwrap(), None);
+            assert_eq!(decompress(&blob).unwrap(), encoded);
+            let mut writer = FileEntryWriter::new(
+                Vec::new(),
+                1000,
+                storage_format,
+                DEFAULT_ZSTD_COMPRESSION_LEVEL,
+                LEGACY_BLOB_FORMAT_VERSION,
+            )
+            .unwrap();
+            for transaction in &transactions_in_storage.transactions {
+                writer.write_transaction(transaction).unwrap();
+            }
+            let blob = writer.finish().unwrap();
+            assert_eq!(BlobHeader::parse(&blob).unwrap(), None);
+            assert_eq!(
+                FileEntry::new(blob, storage_format)
+                    .into_transactions_in_storage()
+                    .unwrap(),
+                transactions_in_storage
+            );
+        }
+        // Neither Parquet files nor the legacy JSON files have a header.
+        let json_blob = FileEntry::from_transactions(
+            transactions_in_storage.transactions.clone(),
+            StorageFormat::JsonBase64UncompressedProto,
+        )
+        .into_inner();
+        assert_eq!(BlobHeader::parse(&json_blob).unwrap(), None);
+    }
+
+    #[test]
+    fn blobs_of_future_format_versions_are_rejected() {
+        let transactions = (0..1000)
+            .map(|version| Transaction {
+                version,
+                ..Transaction::default()
+            })
+            .collect::<Vec<Transaction>>();
+        let mut blob =
+            FileEntry::from_transactions(transactions, StorageFormat::ZstdCompressedProto)
+                .into_inner();
+        blob[BLOB_HEADER_SIZE - 1] = BLOB_FORMAT_VERSION + 1;
+
+        let err = BlobHeader::parse(&blob).unwrap_err();
+        assert!(err.to_string().contains("upgrade the indexer"), "{}", err);
+        assert!(FileEntryReader::new(std::io::Cursor::new(blob.clone())).is_err());
+        assert!(FileEntry::new(blob, StorageFormat::ZstdCompressedProto)
+            .into_transactions_in_storage()
+            .is_err());
+    }
+
+    #[test]
+    fn test_storage_format_from_compression_settings() {
+        assert_eq!(
+            StorageFormat::for_cache(false, None),
+            StorageFormat::Base64UncompressedProto
+        );
+        assert_eq!(
+            StorageFormat::for_cache(true, None),
+            StorageFormat::GzipCompressedProto
+        );
+        assert_eq!(
+            StorageFormat::for_cache(true, Some(3)),
+            StorageFormat::ZstdCompressedProto
+        );
+        assert_eq!(
+            StorageFormat::for_file_store(false, None),
+            StorageFormat::JsonBase64UncompressedProto
+        );
+        assert_eq!(
+            StorageFormat::for_file_store(false, Some(3)),
+            StorageFormat::ZstdCompressedProto
+        );
+    }
+
+    #[test]
+    fn test_cache_entry_key_to_string_zstd_compressed_proto() {
+        assert_eq!(
+            CacheEntry::build_key(42, StorageFormat::ZstdCompressedProto),
+            "zstd:42"
+        );
+    }
+
+    #[test]
+    fn test_file_entry_builder_parquet() {
+        let transactions = (1000..2000)
+            .map(|version| Transaction {
+                version,
+                epoch: 333,
+                ..Transaction::default()
+            })
+            .collect::<Vec<Transaction>>();
+        let file_entry = FileEntry::from_transactions(transactions.clone(), StorageFormat::Parquet);
+        let bytes = file_entry.into_inner();
+        assert!(bytes.starts_with(b"PAR1"));
+        let deserialized_transactions = FileEntry::new(bytes, StorageFormat::Parquet)
+            .into_transactions_in_storage()
+            .unwrap();
+        assert_eq!(deserialized_transactions.starting_version, Some(1000));
+        assert_eq!(deserialized_transactions.transactions, transactions);
+    }
+
+    #[test]
+    fn test_file_entry_key_to_string_parquet() {
+        assert_eq!(
+            FileEntry::build_key(42, StorageFormat::Parquet),
+            "parquet_files/3d1bff1ba654ca5fdb6ac1370533d876_0.parquet"
+        );
+    }
+
+    #[test]
+    fn test_file_entry_key_to_string_zstd_compressed_proto() {
+        assert_eq!(
+            FileEntry::build_key(42, StorageFormat::ZstdCompressedProto),
+            "compressed_files/zstd/3d1bff1ba654ca5fdb6ac1370533d876_0.bin"
+        );
+    }
+
     #[test]
     fn test_cache_entry_key_to_string_gzip_compressed_proto() {
         assert_eq!(
@@ -459,10 +1736,250 @@ mod tests {
             file_metadata.storage_format,
             StorageFormat::JsonBase64UncompressedProto
         );
-        assert_eq!(file_metadata.chain_id, 1);
+        assert_eq!(file_metadata.encryption_scheme, EncryptionScheme::None);
+        assert_eq!(file_metadata.key_layout, KeyLayout::Flat);
+        assert_eq!(file_metadata.chain_id, ChainId(1));
         assert_eq!(file_metadata.file_folder_size, 1000);
     }
 
+    #[test]
+    fn test_sharded_file_entry_keys() {
+        assert_eq!(
+            FileEntry::build_key_with_layout(
+                3_000_042,
+                StorageFormat::JsonBase64UncompressedProto,
+                &KeyLayout::Sharded
+            ),
+            "files/3/3000000.json"
+        );
+        assert_eq!(
+            FileEntry::build_key_with_layout(
+                42,
+                StorageFormat::ZstdCompressedProto,
+                &KeyLayout::Sharded
+            ),
+            "compressed_files/zstd/0/3d1bff1ba654ca5fdb6ac1370533d876_0.bin"
+        );
+        assert_eq!(
+            FileEntry::build_key_with_layout(42, StorageFormat::Parquet, &KeyLayout::Flat),
+            FileEntry::build_key(42, StorageFormat::Parquet)
+        );
+    }
+
+    #[test]
+    fn test_templated_file_entry_keys() {
+        let template = KeyTemplate {
+            template: "{prefix}/{format}/{shard}/{version_padded}.blob".to_string(),
+            prefix: "mainnet".to_string(),
+            padding_width: 12,
+        };
+        assert!(template.validate().is_ok());
+        assert_eq!(
+            FileEntry::build_key_with_layout(
+                3_000_042,
+                StorageFormat::ZstdCompressedProto,
+                &KeyLayout::Template(template)
+            ),
+            "mainnet/zstd/3/000003000000.blob"
+        );
+
+        let template = KeyTemplate {
+            template: "blobs/{version}".to_string(),
+            prefix: String::new(),
+            padding_width: KeyTemplate::default_padding_width(),
+        };
+        assert_eq!(
+            FileEntry::build_key_with_layout(
+                1_000,
+                StorageFormat::JsonBase64UncompressedProto,
+                &KeyLayout::Template(template)
+            ),
+            "blobs/1000"
+        );
+
+        let key_layout: KeyLayout = serde_yaml::from_str(
+            "Template:\n  template: \"{prefix}/{version_padded}.blob\"\n  prefix: testnet\n",
+        )
+        .unwrap();
+        assert!(key_layout.validate().is_ok());
+        assert_eq!(
+            FileEntry::build_key_with_layout(0, StorageFormat::Parquet, &key_layout),
+            "testnet/00000000000000000000.blob"
+        );
+    }
+
+    #[test]
+    fn test_invalid_key_templates() {
+        for invalid_template in [
+            // Blobs would share a key.
+            "{prefix}/blob",
+            "{prefix}/{versions}.blob",
+            "{prefix}/{version.blob",
+            "{prefix}}/{version}.blob",
+            // An empty prefix leaves an empty folder name.
+            "{prefix}/{version}.blob",
+            "/{version}.blob",
+        ] {
+            let template = KeyTemplate {
+                template: invalid_template.to_string(),
+                prefix: String::new(),
+                padding_width: 10,
+            };
+            assert!(
+                template.validate().is_err(),
+                "{} should be invalid",
+                invalid_template
+            );
+        }
+        assert!(KeyTemplate {
+            template: "{version_padded}".to_string(),
+            prefix: String::new(),
+            padding_width: 21,
+        }
+        .validate()
+        .is_err());
+    }
+
+    #[test]
+    fn file_entry_writer_streams_into_the_sink() {
+        // Transactions with distinct payloads, so the compressed blob stays large.
+        let transactions: Vec<Transaction> = (0..FILE_ENTRY_TRANSACTION_COUNT)
+            .map(|version| Transaction {
+                version,
+                info: Some(aptos_protos::transaction::v1::TransactionInfo {
+                    hash: (0..4096u64)
+                        .map(|i| (i.wrapping_mul(2_654_435_761) ^ version) as u8)
+                        .collect(),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            })
+            .collect();
+        for storage_format in [
+            StorageFormat::GzipCompressedProto,
+            StorageFormat::ZstdCompressedProto,
+        ] {
+            let mut writer =
+                FileEntryWriter::new(Vec::new(), 0, storage_format, 3, BLOB_FORMAT_VERSION)
+                    .unwrap();
+            let mut flushed_before_the_end = false;
+            for transaction in &transactions {
+                writer.write_transaction(transaction).unwrap();
+                flushed_before_the_end |= match &writer.compressor {
+                    Compressor::Gzip(encoder) => encoder.get_ref().len() > BLOB_HEADER_SIZE,
+                    Compressor::Zstd(encoder) => encoder.get_ref().len() > BLOB_HEADER_SIZE,
+                };
+            }
+            assert!(flushed_before_the_end);
+            assert_eq!(writer.transaction_count(), FILE_ENTRY_TRANSACTION_COUNT);
+            let bytes = writer.finish().unwrap();
+            assert_eq!(
+                FileEntry::new(bytes, storage_format)
+                    .into_transactions_in_storage()
+                    .unwrap(),
+                TransactionsInStorage {
+                    starting_version: Some(0),
+                    transactions: transactions.clone(),
+                }
+            );
+        }
+        assert!(!StorageFormat::Parquet.supports_incremental_encoding());
+    }
+
+    /// Transactions of `payload_size` pseudo-random bytes, i.e., that don't compress.
+    fn incompressible_transactions(
+        versions: std::ops::Range<u64>,
+        payload_size: usize,
+    ) -> Vec<Transaction> {
+        let mut state = 0x2545_F491_4F6C_DD1Du64;
+        versions
+            .map(|version| {
+                let mut hash = Vec::with_capacity(payload_size);
+                while hash.len() < payload_size {
+                    state ^= state << 13;
+                    state ^= state >> 7;
+                    state ^= state << 17;
+                    hash.extend_from_slice(&state.to_le_bytes());
+                }
+                Transaction {
+                    version,
+                    info: Some(aptos_protos::transaction::v1::TransactionInfo {
+                        hash,
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                }
+            })
+            .collect()
+    }
+
+    /// Reads from a blob, counting the bytes read.
+    struct CountingReader {
+        blob: std::io::Cursor<Vec<u8>>,
+        bytes_read: std::sync::Arc<std::sync::atomic::AtomicUsize>,
+    }
+
+    impl Read for CountingReader {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let length = self.blob.read(buf)?;
+            self.bytes_read
+                .fetch_add(length, std::sync::atomic::Ordering::SeqCst);
+            Ok(length)
+        }
+    }
+
+    #[test]
+    fn file_entry_reader_holds_one_transaction_at_a_time() {
+        const PAYLOAD_SIZE: usize = 128 * 1024;
+        // Bytes the decompressors read ahead.
+        const READ_AHEAD_SIZE: usize = 512 * 1024;
+        let transactions = incompressible_transactions(1_000..1_024, PAYLOAD_SIZE);
+        for storage_format in [
+            StorageFormat::GzipCompressedProto,
+            StorageFormat::ZstdCompressedProto,
+        ] {
+            let mut writer =
+                FileEntryWriter::new(Vec::new(), 1_000, storage_format, 3, BLOB_FORMAT_VERSION)
+                    .unwrap();
+            for transaction in &transactions {
+                writer.write_transaction(transaction).unwrap();
+            }
+            let blob = writer.finish().unwrap();
+            let blob_size = blob.len();
+            let bytes_read = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+            let mut reader = FileEntryReader::new(CountingReader {
+                blob: std::io::Cursor::new(blob.clone()),
+                bytes_read: bytes_read.clone(),
+            })
+            .unwrap();
+            for (i, transaction) in transactions.iter().enumerate() {
+                assert_eq!(&reader.next().unwrap().unwrap(), transaction);
+                // Only the transactions read so far were read from the blob.
+                assert!(
+                    bytes_read.load(std::sync::atomic::Ordering::SeqCst)
+                        <= (i + 1) * PAYLOAD_SIZE + READ_AHEAD_SIZE
+                );
+            }
+            assert!(reader.next().is_none());
+            assert_eq!(reader.starting_version(), Some(1_000));
+            assert_eq!(
+                bytes_read.load(std::sync::atomic::Ordering::SeqCst),
+                blob_size
+            );
+
+            // A truncated blob fails after its complete transactions.
+            let truncated_blob = blob[..blob_size / 2].to_vec();
+            let results: Vec<_> = FileEntryReader::new(std::io::Cursor::new(truncated_blob))
+                .unwrap()
+                .collect();
+            assert!(results.last().unwrap().is_err());
+            assert!(results[..results.len() - 1]
+                .iter()
+                .all(|result| result.is_ok()));
+        }
+        assert!(FileEntryReader::new(std::io::Cursor::new(b"corrupt".to_vec())).is_err());
+    }
+
     #[test]
     fn test_new_format_can_be_parse() {
         let file_metadata_serialized_json = r#"{
@@ -479,7 +1996,73 @@ mod tests {
             file_metadata.storage_format,
             StorageFormat::GzipCompressedProto
         );
-        assert_eq!(file_metadata.chain_id, 1);
+        assert_eq!(file_metadata.chain_id, ChainId(1));
         assert_eq!(file_metadata.file_folder_size, 1000);
     }
+
+    #[test]
+    fn test_file_store_metadata_schema_version() {
+        // Metadata written before the schema version was recorded.
+        let old_metadata = FileStoreMetadata::from_bytes(
+            br#"{"chain_id":1,"file_folder_size":1000,"version":5000}"#.to_vec(),
+        );
+        assert_eq!(old_metadata.schema_version, 0);
+        assert_eq!(old_metadata.revision, 0);
+        assert_eq!(old_metadata.last_updated_at_in_secs, 0);
+        assert!(old_metadata.writer.is_empty());
+        assert_eq!(old_metadata.blob_digests_since_version, None);
+        assert_eq!(
+            old_metadata.storage_format,
+            StorageFormat::JsonBase64UncompressedProto
+        );
+        assert!(old_metadata.check_schema_version().is_ok());
+
+        let mut metadata = FileStoreMetadata::new(
+            ChainId(1),
+            5000,
+            StorageFormat::ZstdCompressedProto,
+            EncryptionScheme::None,
+        );
+        assert_eq!(metadata.schema_version, FILE_STORE_METADATA_SCHEMA_VERSION);
+        assert!(metadata.check_schema_version().is_ok());
+        metadata.schema_version = FILE_STORE_METADATA_SCHEMA_VERSION + 1;
+        assert!(metadata.check_schema_version().is_err());
+    }
+
+    #[test]
+    fn test_filtered_file_entry_round_trip() {
+        for storage_format in [
+            StorageFormat::GzipCompressedProto,
+            StorageFormat::ZstdCompressedProto,
+            StorageFormat::JsonBase64UncompressedProto,
+            StorageFormat::Parquet,
+        ] {
+            let transactions = vec![Transaction {
+                version: 1_042,
+                ..Transaction::default()
+            }];
+            let file_entry = FileEntry::from_filtered_transactions(
+                1_000,
+                transactions.clone(),
+                storage_format,
+                DEFAULT_ZSTD_COMPRESSION_LEVEL,
+                BLOB_FORMAT_VERSION,
+            );
+            let transactions_in_storage = file_entry.into_transactions_in_storage().unwrap();
+            assert_eq!(transactions_in_storage.starting_version, Some(1_000));
+            assert_eq!(transactions_in_storage.transactions, transactions);
+
+            // Every transaction of the batch may be filtered out.
+            let file_entry = FileEntry::from_filtered_transactions(
+                1_000,
+                vec![],
+                storage_format,
+                DEFAULT_ZSTD_COMPRESSION_LEVEL,
+                BLOB_FORMAT_VERSION,
+            );
+            let transactions_in_storage = file_entry.into_transactions_in_storage().unwrap();
+            assert_eq!(transactions_in_storage.starting_version, Some(1_000));
+            assert!(transactions_in_storage.transactions.is_empty());
+        }
+    }
 }
@@ -1,17 +1,131 @@
 // Copyright © Aptos Foundation
 // SPDX-License-Identifier: Apache-2.0
 
+use crate::{
+    compression_util::KeyLayout,
+    encryption_util::BlobCipher,
+    file_store_operator::{gcs::GcsServerSideEncryption, ExistingBlobCheck},
+};
 use serde::{Deserialize, Serialize};
 /// Common configuration for Indexer GRPC Store.
-use std::path::PathBuf;
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct GcsFileStore {
     pub gcs_file_store_bucket_name: String,
-    // Required to operate on GCS.
-    pub gcs_file_store_service_account_key_path: String,
+    // Required to operate on GCS with the `KeyFile` credential source, unless
+    // `gcs_anonymous_credentials` is set.
+    #[serde(default)]
+    pub gcs_file_store_service_account_key_path: Option<String>,
+    // Where the credentials of the requests to GCS come from; a service account key file by
+    // default.
+    #[serde(default)]
+    pub gcs_credential_source: GcsCredentialSource,
     #[serde(default = "default_enable_compression")]
     pub enable_compression: bool,
+    // If set, blobs are compressed with zstd at this level instead of gzip.
+    #[serde(default)]
+    pub zstd_compression_level: Option<i32>,
+    // If set, blobs are written as Parquet files, with pages compressed at
+    // `zstd_compression_level`; takes precedence over `enable_compression`.
+    #[serde(default)]
+    pub enable_parquet: bool,
+    // If set, blobs are encrypted with AES-256-GCM using the hex encoded key in this file.
+    #[serde(default)]
+    pub encryption_key_path: Option<PathBuf>,
+    // If set, requests are sent to this GCS compatible endpoint, e.g., `http://localhost:4443` for
+    // fake-gcs-server, instead of GCS.
+    #[serde(default)]
+    pub gcs_endpoint: Option<String>,
+    // If set, requests carry no credentials, e.g., for fake-gcs-server or public buckets.
+    #[serde(default)]
+    pub gcs_anonymous_credentials: bool,
+    // How requests to GCS are retried and timed out.
+    #[serde(default)]
+    pub gcs_retry_config: GcsRetryConfig,
+    // If set, blobs larger than this are sent as resumable uploads, so a transfer interrupted by a
+    // transient error resumes where it stopped instead of starting over.
+    #[serde(default)]
+    pub gcs_resumable_upload_threshold_in_bytes: Option<usize>,
+    // If set, GCS encrypts the objects with the hex encoded AES-256 key in this file (CSEK), which
+    // is then required to read them. Unlike `encryption_key_path`, GCS does the encryption.
+    #[serde(default)]
+    pub gcs_customer_supplied_encryption_key_path: Option<PathBuf>,
+    // If set, GCS encrypts new objects with this Cloud KMS key (CMEK), e.g.,
+    // `projects/<project>/locations/<location>/keyRings/<ring>/cryptoKeys/<key>`.
+    #[serde(default)]
+    pub gcs_kms_key_name: Option<String>,
+    // Custom metadata set on every blob and blob digest, e.g., `retention-class: short-lived`, so
+    // that the lifecycle rules of the bucket can expire them; the metadata and progress objects
+    // aren't tagged. Reads ignore it.
+    #[serde(default)]
+    pub gcs_blob_metadata: BTreeMap<String, String>,
+    // If set, blobs are keyed with this layout rather than the one recorded in the metadata, e.g.,
+    // to convert a file store with the migrate tool, or with a custom `Template`. A new file store
+    // is sharded by default.
+    #[serde(default)]
+    pub key_layout: Option<KeyLayout>,
+    // Number of versions of each blob if the file store is new; an existing file store keeps the
+    // blob size recorded in its metadata, and the worker fails at startup if it differs. Defaults
+    // to 1000.
+    #[serde(default)]
+    pub blob_size_in_versions: Option<u64>,
+    // How an upload checks whether its blob is already there, e.g., after a restart, before
+    // writing it: `Contents` (default) downloads and compares the blob, `Digest` only compares its
+    // recorded digest, and `Disabled` always writes it.
+    #[serde(default)]
+    pub existing_blob_check: ExistingBlobCheck,
+}
+
+/// Source of the credentials the GCS file store operator authenticates with.
+#[derive(Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+pub enum GcsCredentialSource {
+    /// The service account key JSON at `gcs_file_store_service_account_key_path`.
+    #[default]
+    KeyFile,
+    /// Application default credentials: the key file `GOOGLE_APPLICATION_CREDENTIALS` points to
+    /// if set, else the metadata server, e.g., with GKE workload identity.
+    ApplicationDefault,
+    /// A fixed OAuth2 access token, e.g., for tests; it isn't refreshed.
+    AccessToken(String),
+}
+
+// The token is left out, since configs and errors end up in logs.
+impl std::fmt::Debug for GcsCredentialSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::KeyFile => write!(f, "KeyFile"),
+            Self::ApplicationDefault => write!(f, "ApplicationDefault"),
+            Self::AccessToken(_) => write!(f, "AccessToken(<redacted>)"),
+        }
+    }
+}
+
+/// Retry policy applied to every request the GCS and HTTP file store operators send.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct GcsRetryConfig {
+    // Total number of attempts per request, including the first one.
+    pub max_attempts: u32,
+    // The backoff starts here and doubles after each failed attempt, with random jitter.
+    pub initial_backoff_in_millis: u64,
+    pub max_backoff_in_millis: u64,
+    // Each attempt is abandoned, and retried, after this long.
+    pub request_timeout_in_secs: u64,
+}
+
+impl Default for GcsRetryConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            initial_backoff_in_millis: 100,
+            max_backoff_in_millis: 10_000,
+            request_timeout_in_secs: 30,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -19,6 +133,61 @@ pub struct LocalFileStore {
     pub local_file_store_path: PathBuf,
     #[serde(default = "default_enable_compression")]
     pub enable_compression: bool,
+    // If set, blobs are compressed with zstd at this level instead of gzip.
+    #[serde(default)]
+    pub zstd_compression_level: Option<i32>,
+    // If set, blobs are written as Parquet files, with pages compressed at
+    // `zstd_compression_level`; takes precedence over `enable_compression`.
+    #[serde(default)]
+    pub enable_parquet: bool,
+    // If set, blobs are encrypted with AES-256-GCM using the hex encoded key in this file.
+    #[serde(default)]
+    pub encryption_key_path: Option<PathBuf>,
+    // Same as `encryption_key_path`, with the hex encoded key in this environment variable instead.
+    #[serde(default)]
+    pub encryption_key_env_var: Option<String>,
+    // If set, blobs and metadata are fsynced, along with their directory, before a write returns.
+    #[serde(default)]
+    pub enable_fsync: bool,
+    // If set, blobs are keyed with this layout rather than the one recorded in the metadata, e.g.,
+    // to convert a file store with the migrate tool, or with a custom `Template`. A new file store
+    // is sharded by default.
+    #[serde(default)]
+    pub key_layout: Option<KeyLayout>,
+    // Number of versions of each blob if the file store is new; an existing file store keeps the
+    // blob size recorded in its metadata, and the worker fails at startup if it differs. Defaults
+    // to 1000.
+    #[serde(default)]
+    pub blob_size_in_versions: Option<u64>,
+    // How an upload checks whether its blob is already there, e.g., after a restart, before
+    // writing it: `Contents` (default) downloads and compares the blob, `Digest` only compares its
+    // recorded digest, and `Disabled` always writes it.
+    #[serde(default)]
+    pub existing_blob_check: ExistingBlobCheck,
+}
+
+/// A file store served over HTTP(S), e.g., a public archive behind a CDN; read-only.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct HttpFileStore {
+    // URL the objects of the file store are served under, e.g.,
+    // `https://archive.example.com/mainnet/` serves `https://archive.example.com/mainnet/metadata.json`.
+    pub http_file_store_base_url: String,
+    // The storage format the blobs were written in.
+    #[serde(default = "default_enable_compression")]
+    pub enable_compression: bool,
+    #[serde(default)]
+    pub zstd_compression_level: Option<i32>,
+    #[serde(default)]
+    pub enable_parquet: bool,
+    // If set, encrypted blobs are decrypted with the hex encoded key in this file.
+    #[serde(default)]
+    pub encryption_key_path: Option<PathBuf>,
+    // How requests are retried and timed out.
+    #[serde(default)]
+    pub http_retry_config: GcsRetryConfig,
+    // If set, blobs are keyed with this layout rather than the one recorded in the metadata.
+    #[serde(default)]
+    pub key_layout: Option<KeyLayout>,
 }
 
 const fn default_enable_compression() -> bool {
@@ -30,6 +199,7 @@ const fn default_enable_compression() -> bool {
 pub enum IndexerGrpcFileStoreConfig {
     GcsFileStore(GcsFileStore),
     LocalFileStore(LocalFileStore),
+    HttpFileStore(HttpFileStore),
 }
 
 impl Default for IndexerGrpcFileStoreConfig {
@@ -37,6 +207,14 @@ impl Default for IndexerGrpcFileStoreConfig {
         IndexerGrpcFileStoreConfig::LocalFileStore(LocalFileStore {
             local_file_store_path: std::env::current_dir().unwrap(),
             enable_compression: false,
+            zstd_compression_level: None,
+            enable_parquet: false,
+            encryption_key_path: None,
+            encryption_key_env_var: None,
+            enable_fsync: false,
+            key_layout: None,
+            blob_size_in_versions: None,
+            existing_blob_check: ExistingBlobCheck::default(),
         })
     }
 }
@@ -45,20 +223,597 @@ impl IndexerGrpcFileStoreConfig {
     pub fn create(&self) -> Box<dyn crate::file_store_operator::FileStoreOperator> {
         match self {
             IndexerGrpcFileStoreConfig::GcsFileStore(gcs_file_store) => {
-                Box::new(crate::file_store_operator::gcs::GcsFileStoreOperator::new(
+                let operator = crate::file_store_operator::gcs::GcsFileStoreOperator::new(
                     gcs_file_store.gcs_file_store_bucket_name.clone(),
                     gcs_file_store
                         .gcs_file_store_service_account_key_path
                         .clone(),
                     gcs_file_store.enable_compression,
-                ))
+                    gcs_file_store.zstd_compression_level,
+                )
+                .with_parquet(gcs_file_store.enable_parquet)
+                .with_endpoint(
+                    gcs_file_store.gcs_endpoint.clone(),
+                    gcs_file_store.gcs_anonymous_credentials,
+                )
+                .with_credential_source(gcs_file_store.gcs_credential_source.clone())
+                .with_retry_config(gcs_file_store.gcs_retry_config.clone())
+                .with_resumable_upload_threshold(
+                    gcs_file_store.gcs_resumable_upload_threshold_in_bytes,
+                )
+                .with_server_side_encryption(load_server_side_encryption(gcs_file_store))
+                .with_blob_metadata(gcs_file_store.gcs_blob_metadata.clone())
+                .with_key_layout(load_key_layout(&gcs_file_store.key_layout))
+                .with_blob_size(gcs_file_store.blob_size_in_versions)
+                .with_existing_blob_check(gcs_file_store.existing_blob_check);
+                match &gcs_file_store.encryption_key_path {
+                    Some(path) => Box::new(operator.with_cipher(load_cipher(path))),
+                    None => Box::new(operator),
+                }
             },
-            IndexerGrpcFileStoreConfig::LocalFileStore(local_file_store) => Box::new(
-                crate::file_store_operator::local::LocalFileStoreOperator::new(
+            IndexerGrpcFileStoreConfig::LocalFileStore(local_file_store) => {
+                let operator = crate::file_store_operator::local::LocalFileStoreOperator::new(
                     local_file_store.local_file_store_path.clone(),
                     local_file_store.enable_compression,
-                ),
-            ),
+                    local_file_store.zstd_compression_level,
+                )
+                .with_parquet(local_file_store.enable_parquet)
+                .with_fsync(local_file_store.enable_fsync)
+                .with_key_layout(load_key_layout(&local_file_store.key_layout))
+                .with_blob_size(local_file_store.blob_size_in_versions)
+                .with_existing_blob_check(local_file_store.existing_blob_check);
+                match (
+                    &local_file_store.encryption_key_path,
+                    &local_file_store.encryption_key_env_var,
+                ) {
+                    (Some(path), _) => Box::new(operator.with_cipher(load_cipher(path))),
+                    (None, Some(name)) => Box::new(operator.with_cipher(
+                        BlobCipher::from_env_var(name)
+                            .expect("Failed to load the file store encryption key."),
+                    )),
+                    (None, None) => Box::new(operator),
+                }
+            },
+            IndexerGrpcFileStoreConfig::HttpFileStore(http_file_store) => {
+                let operator = crate::file_store_operator::http::HttpFileStoreOperator::new(
+                    &http_file_store.http_file_store_base_url,
+                    http_file_store.enable_compression,
+                    http_file_store.zstd_compression_level,
+                )
+                .with_parquet(http_file_store.enable_parquet)
+                .with_retry_config(http_file_store.http_retry_config.clone())
+                .with_key_layout(load_key_layout(&http_file_store.key_layout));
+                match &http_file_store.encryption_key_path {
+                    Some(path) => Box::new(operator.with_cipher(load_cipher(path))),
+                    None => Box::new(operator),
+                }
+            },
+        }
+    }
+
+    /// Whether the operator can only read, so the file store can't be written to.
+    pub fn is_read_only(&self) -> bool {
+        matches!(self, IndexerGrpcFileStoreConfig::HttpFileStore(_))
+    }
+
+    /// Where the file store is, for errors and logs, e.g., `gs://<bucket>` or a local path.
+    pub fn location(&self) -> String {
+        match self {
+            IndexerGrpcFileStoreConfig::GcsFileStore(gcs_file_store) => {
+                format!("gs://{}", gcs_file_store.gcs_file_store_bucket_name)
+            },
+            IndexerGrpcFileStoreConfig::LocalFileStore(local_file_store) => {
+                local_file_store.local_file_store_path.display().to_string()
+            },
+            IndexerGrpcFileStoreConfig::HttpFileStore(http_file_store) => {
+                http_file_store.http_file_store_base_url.clone()
+            },
+        }
+    }
+
+    /// Checks the config without reaching the file store, so that a misconfigured worker fails at
+    /// startup, with every problem at once, rather than deep in its run loop. Returns a description
+    /// of each problem found.
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = vec![];
+        match self {
+            IndexerGrpcFileStoreConfig::GcsFileStore(gcs_file_store) => {
+                if gcs_file_store.gcs_file_store_bucket_name.trim().is_empty() {
+                    problems.push("gcs_file_store_bucket_name is empty".to_string());
+                }
+                // Anonymous requests carry no credentials, whatever the key path.
+                let key_path = &gcs_file_store.gcs_file_store_service_account_key_path;
+                match (
+                    &gcs_file_store.gcs_credential_source,
+                    gcs_file_store.gcs_anonymous_credentials,
+                ) {
+                    (GcsCredentialSource::KeyFile, true) => {},
+                    (GcsCredentialSource::KeyFile, false) => match key_path {
+                        Some(key_path) if !key_path.is_empty() => check_file_exists(
+                            &mut problems,
+                            "gcs_file_store_service_account_key_path",
+                            Path::new(key_path),
+                        ),
+                        _ => problems.push(
+                            "gcs_file_store_service_account_key_path is required with the KeyFile gcs_credential_source unless gcs_anonymous_credentials is set"
+                                .to_string(),
+                        ),
+                    },
+                    (source, anonymous_credentials) => {
+                        if anonymous_credentials {
+                            problems.push(format!(
+                                "gcs_anonymous_credentials conflicts with gcs_credential_source {:?}",
+                                source
+                            ));
+                        }
+                        if key_path.is_some() {
+                            problems.push(format!(
+                                "gcs_file_store_service_account_key_path is only used with the KeyFile gcs_credential_source, not {:?}",
+                                source
+                            ));
+                        }
+                        if *source == GcsCredentialSource::AccessToken(String::new()) {
+                            problems.push(
+                                "gcs_credential_source AccessToken is empty".to_string(),
+                            );
+                        }
+                    },
+                }
+                if let Some(endpoint) = &gcs_file_store.gcs_endpoint {
+                    match url::Url::parse(endpoint) {
+                        Ok(url) if url.scheme() == "http" || url.scheme() == "https" => {},
+                        Ok(url) => problems.push(format!(
+                            "gcs_endpoint {} has scheme {}; expected http:// or https://",
+                            endpoint,
+                            url.scheme()
+                        )),
+                        Err(err) => {
+                            problems.push(format!("gcs_endpoint {} is invalid: {}", endpoint, err))
+                        },
+                    }
+                }
+                if gcs_file_store.gcs_retry_config.max_attempts == 0 {
+                    problems.push("gcs_retry_config.max_attempts must be at least 1".to_string());
+                }
+                match (
+                    &gcs_file_store.gcs_customer_supplied_encryption_key_path,
+                    &gcs_file_store.gcs_kms_key_name,
+                ) {
+                    (Some(_), Some(_)) => problems.push(
+                        "Only one of gcs_customer_supplied_encryption_key_path and gcs_kms_key_name can be set"
+                            .to_string(),
+                    ),
+                    (Some(path), None) => check_file_exists(
+                        &mut problems,
+                        "gcs_customer_supplied_encryption_key_path",
+                        path,
+                    ),
+                    (None, _) => {},
+                }
+                if gcs_file_store
+                    .gcs_blob_metadata
+                    .keys()
+                    .any(|key| key.trim().is_empty())
+                {
+                    problems.push("gcs_blob_metadata has an empty key".to_string());
+                }
+                check_storage_format(
+                    &mut problems,
+                    gcs_file_store.zstd_compression_level,
+                    &gcs_file_store.encryption_key_path,
+                    &gcs_file_store.key_layout,
+                );
+                check_blob_size(&mut problems, gcs_file_store.blob_size_in_versions);
+            },
+            IndexerGrpcFileStoreConfig::LocalFileStore(local_file_store) => {
+                check_local_file_store_path(&mut problems, &local_file_store.local_file_store_path);
+                if let Some(name) = &local_file_store.encryption_key_env_var {
+                    if local_file_store.encryption_key_path.is_some() {
+                        problems.push(
+                            "Only one of encryption_key_path and encryption_key_env_var can be set"
+                                .to_string(),
+                        );
+                    } else if std::env::var_os(name).is_none() {
+                        problems.push(format!("encryption_key_env_var {} is not set", name));
+                    }
+                }
+                check_storage_format(
+                    &mut problems,
+                    local_file_store.zstd_compression_level,
+                    &local_file_store.encryption_key_path,
+                    &local_file_store.key_layout,
+                );
+                check_blob_size(&mut problems, local_file_store.blob_size_in_versions);
+            },
+            IndexerGrpcFileStoreConfig::HttpFileStore(http_file_store) => {
+                let base_url = &http_file_store.http_file_store_base_url;
+                match url::Url::parse(base_url) {
+                    Ok(url) if url.scheme() == "http" || url.scheme() == "https" => {},
+                    Ok(url) => problems.push(format!(
+                        "http_file_store_base_url {} has scheme {}; expected http:// or https://",
+                        base_url,
+                        url.scheme()
+                    )),
+                    Err(err) => problems.push(format!(
+                        "http_file_store_base_url {} is invalid: {}",
+                        base_url, err
+                    )),
+                }
+                if http_file_store.http_retry_config.max_attempts == 0 {
+                    problems.push("http_retry_config.max_attempts must be at least 1".to_string());
+                }
+                check_storage_format(
+                    &mut problems,
+                    http_file_store.zstd_compression_level,
+                    &http_file_store.encryption_key_path,
+                    &http_file_store.key_layout,
+                );
+            },
+        }
+        problems
+    }
+
+    /// Fails with a report of every problem of the config, if any; see `problems`.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        ensure_no_problems(&self.problems())
+    }
+}
+
+/// Fails with a single report listing every problem, if any.
+pub fn ensure_no_problems(problems: &[String]) -> anyhow::Result<()> {
+    if problems.is_empty() {
+        return Ok(());
+    }
+    let report: Vec<_> = problems
+        .iter()
+        .map(|problem| format!("  - {}", problem))
+        .collect();
+    anyhow::bail!(
+        "Found {} configuration problem(s):\n{}",
+        problems.len(),
+        report.join("\n")
+    )
+}
+
+/// Checks that a zstd compression level is one zstd supports.
+pub fn check_zstd_compression_level(
+    problems: &mut Vec<String>,
+    field: &str,
+    zstd_compression_level: Option<i32>,
+) {
+    if let Some(level) = zstd_compression_level {
+        let range = zstd::compression_level_range();
+        if !range.contains(&level) {
+            problems.push(format!(
+                "{} is {}, but zstd only supports levels {} to {}",
+                field,
+                level,
+                range.start(),
+                range.end()
+            ));
         }
     }
 }
+
+fn check_storage_format(
+    problems: &mut Vec<String>,
+    zstd_compression_level: Option<i32>,
+    encryption_key_path: &Option<PathBuf>,
+    key_layout: &Option<KeyLayout>,
+) {
+    check_zstd_compression_level(problems, "zstd_compression_level", zstd_compression_level);
+    if let Some(path) = encryption_key_path {
+        check_file_exists(problems, "encryption_key_path", path);
+    }
+    if let Some(Err(err)) = key_layout.as_ref().map(KeyLayout::validate) {
+        problems.push(format!("key_layout is invalid: {}", err));
+    }
+}
+
+fn check_blob_size(problems: &mut Vec<String>, blob_size_in_versions: Option<u64>) {
+    if blob_size_in_versions == Some(0) {
+        problems.push("blob_size_in_versions must be positive".to_string());
+    }
+}
+
+fn check_file_exists(problems: &mut Vec<String>, field: &str, path: &Path) {
+    if !path.is_file() {
+        problems.push(format!("{} {} is not a file", field, path.display()));
+    }
+}
+
+/// The file store directory has to exist, or be creatable, i.e., its closest existing ancestor is
+/// a directory. Relative paths depend on the working directory, so they have to exist.
+fn check_local_file_store_path(problems: &mut Vec<String>, path: &Path) {
+    if path.as_os_str().is_empty() {
+        problems.push("local_file_store_path is empty".to_string());
+    } else if path.exists() {
+        if !path.is_dir() {
+            problems.push(format!(
+                "local_file_store_path {} is not a directory",
+                path.display()
+            ));
+        }
+    } else if path.is_relative() {
+        problems.push(format!(
+            "local_file_store_path {} is relative and doesn't exist; use an absolute path",
+            path.display()
+        ));
+    } else {
+        match path.ancestors().skip(1).find(|ancestor| ancestor.exists()) {
+            Some(ancestor) if ancestor.is_dir() => {},
+            _ => problems.push(format!(
+                "local_file_store_path {} doesn't exist and can't be created",
+                path.display()
+            )),
+        }
+    }
+}
+
+fn load_cipher(path: &Path) -> BlobCipher {
+    BlobCipher::from_key_file(path).expect("Failed to load the file store encryption key.")
+}
+
+fn load_key_layout(key_layout: &Option<KeyLayout>) -> Option<KeyLayout> {
+    if let Some(key_layout) = key_layout {
+        key_layout
+            .validate()
+            .expect("Invalid file store key layout.");
+    }
+    key_layout.clone()
+}
+
+fn load_server_side_encryption(gcs_file_store: &GcsFileStore) -> Option<GcsServerSideEncryption> {
+    match (
+        &gcs_file_store.gcs_customer_supplied_encryption_key_path,
+        &gcs_file_store.gcs_kms_key_name,
+    ) {
+        (Some(_), Some(_)) => panic!(
+            "Only one of gcs_customer_supplied_encryption_key_path and gcs_kms_key_name can be set."
+        ),
+        (Some(path), None) => Some(
+            GcsServerSideEncryption::customer_supplied_from_key_file(path)
+                .expect("Failed to load the GCS customer-supplied encryption key."),
+        ),
+        (None, Some(key_name)) => Some(GcsServerSideEncryption::Kms {
+            key_name: key_name.clone(),
+        }),
+        (None, None) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compression_util::KeyTemplate;
+
+    fn gcs_file_store() -> GcsFileStore {
+        serde_yaml::from_str(
+            r#"
+            gcs_file_store_bucket_name: bucket
+            gcs_anonymous_credentials: true
+            "#,
+        )
+        .unwrap()
+    }
+
+    fn local_file_store(path: PathBuf) -> LocalFileStore {
+        LocalFileStore {
+            local_file_store_path: path,
+            enable_compression: false,
+            zstd_compression_level: None,
+            enable_parquet: false,
+            encryption_key_path: None,
+            encryption_key_env_var: None,
+            enable_fsync: false,
+            key_layout: None,
+            blob_size_in_versions: None,
+            existing_blob_check: ExistingBlobCheck::default(),
+        }
+    }
+
+    fn gcs_problems(gcs_file_store: GcsFileStore) -> Vec<String> {
+        IndexerGrpcFileStoreConfig::GcsFileStore(gcs_file_store).problems()
+    }
+
+    fn local_problems(local_file_store: LocalFileStore) -> Vec<String> {
+        IndexerGrpcFileStoreConfig::LocalFileStore(local_file_store).problems()
+    }
+
+    #[test]
+    fn http_file_store_needs_an_http_base_url() {
+        let config: IndexerGrpcFileStoreConfig = serde_yaml::from_str(
+            r#"
+            file_store_type: HttpFileStore
+            http_file_store_base_url: https://archive.example.com/mainnet
+            enable_compression: true
+            "#,
+        )
+        .unwrap();
+        assert!(config.problems().is_empty());
+        assert!(config.is_read_only());
+
+        let mut config = match config {
+            IndexerGrpcFileStoreConfig::HttpFileStore(config) => config,
+            _ => unreachable!(),
+        };
+        config.http_file_store_base_url = "gs://bucket".to_string();
+        assert_eq!(IndexerGrpcFileStoreConfig::HttpFileStore(config).problems(), vec![
+            "http_file_store_base_url gs://bucket has scheme gs; expected http:// or https://"
+        ]);
+    }
+
+    #[test]
+    fn valid_configs_have_no_problems() {
+        assert!(gcs_problems(gcs_file_store()).is_empty());
+        let dir = tempfile::tempdir().unwrap();
+        assert!(local_problems(local_file_store(dir.path().to_path_buf())).is_empty());
+        // A missing directory is created, as long as its parent is a directory.
+        assert!(local_problems(local_file_store(dir.path().join("a/b"))).is_empty());
+    }
+
+    #[test]
+    fn gcs_bucket_and_credentials_are_required() {
+        let mut config = gcs_file_store();
+        config.gcs_file_store_bucket_name = " ".to_string();
+        assert_eq!(gcs_problems(config), vec![
+            "gcs_file_store_bucket_name is empty"
+        ]);
+
+        let mut config = gcs_file_store();
+        config.gcs_anonymous_credentials = false;
+        assert_eq!(gcs_problems(config.clone()), vec![
+            "gcs_file_store_service_account_key_path is required with the KeyFile gcs_credential_source unless gcs_anonymous_credentials is set"
+        ]);
+        config.gcs_file_store_service_account_key_path = Some("/does/not/exist.json".to_string());
+        assert_eq!(gcs_problems(config), vec![
+            "gcs_file_store_service_account_key_path /does/not/exist.json is not a file"
+        ]);
+    }
+
+    #[test]
+    fn gcs_credential_sources_other_than_a_key_file_need_no_key_path() {
+        let config: GcsFileStore = serde_yaml::from_str(
+            r#"
+            gcs_file_store_bucket_name: bucket
+            gcs_credential_source: ApplicationDefault
+            "#,
+        )
+        .unwrap();
+        assert!(gcs_problems(config.clone()).is_empty());
+
+        let mut conflicting = config.clone();
+        conflicting.gcs_anonymous_credentials = true;
+        conflicting.gcs_file_store_service_account_key_path = Some("key.json".to_string());
+        assert_eq!(gcs_problems(conflicting), vec![
+            "gcs_anonymous_credentials conflicts with gcs_credential_source ApplicationDefault",
+            "gcs_file_store_service_account_key_path is only used with the KeyFile gcs_credential_source, not ApplicationDefault",
+        ]);
+
+        let mut config = config;
+        config.gcs_credential_source = GcsCredentialSource::AccessToken("secret".to_string());
+        assert!(gcs_problems(config.clone()).is_empty());
+        // The token is kept out of the logs.
+        assert!(!format!("{:?}", config).contains("secret"));
+        config.gcs_credential_source = GcsCredentialSource::AccessToken(String::new());
+        assert_eq!(gcs_problems(config), vec![
+            "gcs_credential_source AccessToken is empty"
+        ]);
+    }
+
+    #[test]
+    fn gcs_key_path_stays_compatible_with_existing_configs() {
+        let config: GcsFileStore = serde_yaml::from_str(
+            r#"
+            gcs_file_store_bucket_name: bucket
+            gcs_file_store_service_account_key_path: /secrets/indexer-sa-key
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.gcs_file_store_service_account_key_path.as_deref(),
+            Some("/secrets/indexer-sa-key")
+        );
+        assert_eq!(config.gcs_credential_source, GcsCredentialSource::KeyFile);
+    }
+
+    #[test]
+    fn gcs_endpoint_needs_an_http_scheme() {
+        let mut config = gcs_file_store();
+        config.gcs_endpoint = Some("http://localhost:4443".to_string());
+        assert!(gcs_problems(config.clone()).is_empty());
+        config.gcs_endpoint = Some("redis://localhost:4443".to_string());
+        assert_eq!(gcs_problems(config.clone()), vec![
+            "gcs_endpoint redis://localhost:4443 has scheme redis; expected http:// or https://"
+        ]);
+        config.gcs_endpoint = Some("localhost".to_string());
+        assert_eq!(gcs_problems(config).len(), 1);
+    }
+
+    #[test]
+    fn gcs_server_side_encryption_options_are_exclusive() {
+        let mut config = gcs_file_store();
+        config.gcs_retry_config.max_attempts = 0;
+        config.gcs_kms_key_name =
+            Some("projects/p/locations/l/keyRings/r/cryptoKeys/k".to_string());
+        config.gcs_customer_supplied_encryption_key_path = Some(PathBuf::from("/key"));
+        assert_eq!(gcs_problems(config), vec![
+            "gcs_retry_config.max_attempts must be at least 1",
+            "Only one of gcs_customer_supplied_encryption_key_path and gcs_kms_key_name can be set",
+        ]);
+    }
+
+    #[test]
+    fn local_file_store_path_has_to_exist_or_be_creatable() {
+        assert_eq!(local_problems(local_file_store(PathBuf::new())), vec![
+            "local_file_store_path is empty"
+        ]);
+        assert_eq!(
+            local_problems(local_file_store(PathBuf::from("does/not/exist"))),
+            vec!["local_file_store_path does/not/exist is relative and doesn't exist; use an absolute path"]
+        );
+
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("file");
+        std::fs::write(&file, b"").unwrap();
+        assert_eq!(local_problems(local_file_store(file.clone())), vec![format!(
+            "local_file_store_path {} is not a directory",
+            file.display()
+        )]);
+        let under_file = file.join("store");
+        assert_eq!(local_problems(local_file_store(under_file.clone())), vec![format!(
+            "local_file_store_path {} doesn't exist and can't be created",
+            under_file.display()
+        )]);
+    }
+
+    #[test]
+    fn storage_format_options_are_checked() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = local_file_store(dir.path().to_path_buf());
+        config.zstd_compression_level = Some(100);
+        config.encryption_key_path = Some(dir.path().join("missing.key"));
+        config.key_layout = Some(KeyLayout::Template(KeyTemplate {
+            template: "{prefix}/blob".to_string(),
+            prefix: "mainnet".to_string(),
+            padding_width: 12,
+        }));
+        config.blob_size_in_versions = Some(0);
+        let problems = local_problems(config);
+        assert_eq!(problems.len(), 4);
+        assert!(
+            problems[0].starts_with("zstd_compression_level is 100, but zstd only supports levels")
+        );
+        assert!(problems[1].starts_with("encryption_key_path "));
+        assert!(problems[2].starts_with("key_layout is invalid: "));
+        assert_eq!(problems[3], "blob_size_in_versions must be positive");
+    }
+
+    #[test]
+    fn local_encryption_key_is_read_from_one_source() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = local_file_store(dir.path().to_path_buf());
+        config.encryption_key_env_var = Some("INDEXER_GRPC_TEST_MISSING_KEY".to_string());
+        assert_eq!(local_problems(config.clone()), vec![
+            "encryption_key_env_var INDEXER_GRPC_TEST_MISSING_KEY is not set"
+        ]);
+
+        let key_path = dir.path().join("key");
+        std::fs::write(&key_path, hex::encode([7u8; 32])).unwrap();
+        config.encryption_key_path = Some(key_path);
+        assert_eq!(local_problems(config), vec![
+            "Only one of encryption_key_path and encryption_key_env_var can be set"
+        ]);
+    }
+
+    #[test]
+    fn problems_are_reported_together() {
+        assert!(ensure_no_problems(&[]).is_ok());
+        let err =
+            ensure_no_problems(&["a is empty".to_string(), "b is 0".to_string()]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Found 2 configuration problem(s):\n  - a is empty\n  - b is 0"
+        );
+    }
+}
@@ -2,10 +2,13 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use crate::{constants::IndexerGrpcRequestMetadata, timestamp_to_iso, timestamp_to_unixtime};
-use aptos_metrics_core::{register_gauge_vec, register_int_gauge_vec, GaugeVec, IntGaugeVec};
+use aptos_metrics_core::{
+    exponential_buckets, register_gauge_vec, register_histogram_vec, register_int_gauge_vec,
+    GaugeVec, HistogramVec, IntGaugeVec,
+};
 use aptos_protos::util::timestamp::Timestamp;
 use once_cell::sync::Lazy;
-use prometheus::{register_int_counter_vec, IntCounterVec};
+use prometheus::{register_int_counter, register_int_counter_vec, IntCounter, IntCounterVec};
 
 pub enum IndexerGrpcStep {
     // [Data Service] New request received.
@@ -184,6 +187,76 @@ pub static TRANSACTION_STORE_FETCH_RETRIES: Lazy<IntCounterVec> = Lazy::new(|| {
     .unwrap()
 });
 
+/// Number of GCS requests retried by the GCS file store operator, by operation
+pub static GCS_REQUEST_RETRIES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "indexer_grpc_gcs_request_retries",
+        "Number of times a GCS request is retried by the file store operator",
+        &["operation"],
+    )
+    .unwrap()
+});
+
+/// Number of requests retried by the HTTP file store operator, by operation
+pub static HTTP_FILE_STORE_REQUEST_RETRIES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "indexer_grpc_http_file_store_request_retries",
+        "Number of times a request is retried by the HTTP file store operator",
+        &["operation"],
+    )
+    .unwrap()
+});
+
+/// Number of fsyncs issued by the local file store operator, by target (file or directory)
+pub static LOCAL_FILE_STORE_FSYNC_COUNT: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "indexer_grpc_local_file_store_fsync_count",
+        "Number of fsyncs issued by the local file store operator",
+        &["target"],
+    )
+    .unwrap()
+});
+
+/// Latency of cache operations, by operation
+pub static CACHE_OPERATION_LATENCY_IN_SECS: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "indexer_grpc_cache_operation_latency_in_secs",
+        "Latency of cache operations",
+        &["operation"],
+        exponential_buckets(/*start=*/ 0.0005, /*factor=*/ 2.0, /*count=*/ 16).unwrap(),
+    )
+    .unwrap()
+});
+
+/// Number of failed cache operations, by operation
+pub static CACHE_OPERATION_ERROR_COUNT: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "indexer_grpc_cache_operation_errors",
+        "Number of failed cache operations",
+        &["operation"],
+    )
+    .unwrap()
+});
+
+/// Number of MGET chunks retried after a failure
+pub static CACHE_MGET_CHUNK_RETRIES: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "indexer_grpc_cache_mget_chunk_retries",
+        "Number of times a failed MGET chunk of a batch read is retried"
+    )
+    .unwrap()
+});
+
+/// Number of cache batch reads, by `CacheBatchGetStatus` outcome
+pub static CACHE_BATCH_GET_STATUS_COUNT: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "indexer_grpc_cache_batch_get_status_count",
+        "Number of cache batch reads by outcome",
+        &["status"],
+    )
+    .unwrap()
+});
+
 /// Generic duration metric
 pub static DURATION_IN_SECS: Lazy<GaugeVec> = Lazy::new(|| {
     register_gauge_vec!("indexer_grpc_duration_in_secs", "Duration in seconds", &[
@@ -0,0 +1,252 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+use aes_gcm::{
+    aead::{Aead, AeadCore, KeyInit, OsRng},
+    Aes256Gcm, Nonce,
+};
+use anyhow::{bail, ensure, Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::Path;
+
+// Prefix of every encrypted blob; used to tell encrypted blobs from plaintext ones.
+const ENCRYPTED_BLOB_MAGIC_BYTES: &[u8; 4] = b"AGCM";
+const AES_256_GCM_KEY_SIZE: usize = 32;
+const AES_256_GCM_NONCE_SIZE: usize = 12;
+// Number of bytes of the key digest kept as the key id.
+const KEY_ID_SIZE: usize = 8;
+
+/// Client-side encryption scheme of the file store blobs; recorded in the file store metadata.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub enum EncryptionScheme {
+    #[default]
+    None,
+    Aes256Gcm,
+}
+
+/// BlobCipher encrypts and decrypts file store blobs with AES-256-GCM.
+/// Encrypted blobs are laid out as `magic bytes | nonce | ciphertext`.
+#[derive(Clone)]
+pub struct BlobCipher {
+    cipher: Aes256Gcm,
+    // Hex encoded prefix of the SHA-256 digest of the key; identifies the key without revealing it.
+    key_id: String,
+}
+
+impl BlobCipher {
+    pub fn new(key: &[u8]) -> Result<Self> {
+        ensure!(
+            key.len() == AES_256_GCM_KEY_SIZE,
+            "Encryption key has to be {} bytes, got {}.",
+            AES_256_GCM_KEY_SIZE,
+            key.len()
+        );
+        Ok(Self {
+            cipher: Aes256Gcm::new_from_slice(key)?,
+            key_id: hex::encode(&Sha256::digest(key)[..KEY_ID_SIZE]),
+        })
+    }
+
+    /// Loads the key from a file containing the hex encoded key.
+    pub fn from_key_file(path: &Path) -> Result<Self> {
+        let hex_key = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read encryption key file {}", path.display()))?;
+        Self::from_hex_key(&hex_key)
+    }
+
+    /// Loads the key from an environment variable holding the hex encoded key.
+    pub fn from_env_var(name: &str) -> Result<Self> {
+        let hex_key = std::env::var(name)
+            .with_context(|| format!("Failed to read encryption key variable {}", name))?;
+        Self::from_hex_key(&hex_key)
+    }
+
+    fn from_hex_key(hex_key: &str) -> Result<Self> {
+        let key = hex::decode(hex_key.trim()).context("Encryption key is not valid hex.")?;
+        Self::new(&key)
+    }
+
+    /// Id of the key, recorded in the file store metadata so that readers with another key fail
+    /// before decrypting anything.
+    pub fn key_id(&self) -> &str {
+        &self.key_id
+    }
+
+    pub fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>> {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = match self.cipher.encrypt(&nonce, plaintext) {
+            Ok(ciphertext) => ciphertext,
+            Err(err) => bail!("Failed to encrypt blob: {}", err),
+        };
+        let mut bytes = Vec::with_capacity(
+            ENCRYPTED_BLOB_MAGIC_BYTES.len() + AES_256_GCM_NONCE_SIZE + ciphertext.len(),
+        );
+        bytes.extend_from_slice(ENCRYPTED_BLOB_MAGIC_BYTES);
+        bytes.extend_from_slice(nonce.as_slice());
+        bytes.extend_from_slice(&ciphertext);
+        Ok(bytes)
+    }
+
+    pub fn decrypt(&self, bytes: &[u8]) -> Result<Vec<u8>> {
+        ensure!(is_encrypted(bytes), "Blob is not encrypted.");
+        let (nonce, ciphertext) =
+            bytes[ENCRYPTED_BLOB_MAGIC_BYTES.len()..].split_at(AES_256_GCM_NONCE_SIZE);
+        match self.cipher.decrypt(Nonce::from_slice(nonce), ciphertext) {
+            Ok(plaintext) => Ok(plaintext),
+            Err(err) => bail!(
+                "Failed to decrypt blob; the encryption key might be wrong: {}",
+                err
+            ),
+        }
+    }
+}
+
+fn is_encrypted(bytes: &[u8]) -> bool {
+    bytes.len() >= ENCRYPTED_BLOB_MAGIC_BYTES.len() + AES_256_GCM_NONCE_SIZE
+        && bytes.starts_with(ENCRYPTED_BLOB_MAGIC_BYTES)
+}
+
+/// Fails unless `cipher` can decrypt a file store whose metadata records `encryption_scheme` and
+/// `key_id`, so that readers fail fast rather than on the first blob, or decode garbage.
+pub fn check_encryption_key(
+    encryption_scheme: EncryptionScheme,
+    key_id: Option<&str>,
+    cipher: Option<&BlobCipher>,
+) -> Result<()> {
+    match (encryption_scheme, cipher) {
+        (EncryptionScheme::None, _) => Ok(()),
+        (EncryptionScheme::Aes256Gcm, None) => {
+            bail!("The file store is encrypted, but no encryption key is provided.")
+        },
+        (EncryptionScheme::Aes256Gcm, Some(cipher)) => {
+            if let Some(key_id) = key_id {
+                ensure!(
+                    key_id == cipher.key_id(),
+                    "The file store is encrypted with key {}, but key {} is provided.",
+                    key_id,
+                    cipher.key_id()
+                );
+            }
+            Ok(())
+        },
+    }
+}
+
+/// Encrypts the blob if a cipher is configured.
+pub fn encrypt_blob(cipher: Option<&BlobCipher>, bytes: Vec<u8>) -> Result<Vec<u8>> {
+    match cipher {
+        Some(cipher) => cipher.encrypt(&bytes),
+        None => Ok(bytes),
+    }
+}
+
+/// Decrypts the blob if it's encrypted; blobs written before encryption was enabled are returned as is.
+pub fn decrypt_blob(cipher: Option<&BlobCipher>, bytes: Vec<u8>) -> Result<Vec<u8>> {
+    if !is_encrypted(&bytes) {
+        return Ok(bytes);
+    }
+    match cipher {
+        Some(cipher) => cipher.decrypt(&bytes),
+        None => bail!("Blob is encrypted but no encryption key is configured."),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blob_encryption_round_trip() {
+        let cipher = BlobCipher::new(&[7u8; AES_256_GCM_KEY_SIZE]).unwrap();
+        let plaintext = b"transactions".to_vec();
+        let encrypted = encrypt_blob(Some(&cipher), plaintext.clone()).unwrap();
+        assert_ne!(encrypted, plaintext);
+        assert_eq!(decrypt_blob(Some(&cipher), encrypted).unwrap(), plaintext);
+    }
+
+    #[test]
+    fn encrypted_blob_is_not_readable_without_key() {
+        let cipher = BlobCipher::new(&[7u8; AES_256_GCM_KEY_SIZE]).unwrap();
+        let encrypted = encrypt_blob(Some(&cipher), b"transactions".to_vec()).unwrap();
+        assert!(decrypt_blob(None, encrypted.clone()).is_err());
+
+        let wrong_cipher = BlobCipher::new(&[8u8; AES_256_GCM_KEY_SIZE]).unwrap();
+        assert!(decrypt_blob(Some(&wrong_cipher), encrypted).is_err());
+    }
+
+    #[test]
+    fn plaintext_blob_is_returned_as_is() {
+        let cipher = BlobCipher::new(&[7u8; AES_256_GCM_KEY_SIZE]).unwrap();
+        let plaintext = b"transactions".to_vec();
+        assert_eq!(
+            decrypt_blob(Some(&cipher), plaintext.clone()).unwrap(),
+            plaintext
+        );
+        assert_eq!(encrypt_blob(None, plaintext.clone()).unwrap(), plaintext);
+    }
+
+    #[test]
+    fn encryption_key_is_checked_against_the_metadata() {
+        let cipher = BlobCipher::new(&[7u8; AES_256_GCM_KEY_SIZE]).unwrap();
+        let key_id = cipher.key_id().to_string();
+        assert_eq!(key_id.len(), 2 * KEY_ID_SIZE);
+        assert!(check_encryption_key(EncryptionScheme::None, None, None).is_ok());
+        assert!(
+            check_encryption_key(EncryptionScheme::Aes256Gcm, Some(&key_id), Some(&cipher)).is_ok()
+        );
+        // Metadata written before key ids were recorded.
+        assert!(check_encryption_key(EncryptionScheme::Aes256Gcm, None, Some(&cipher)).is_ok());
+        assert_eq!(
+            check_encryption_key(EncryptionScheme::Aes256Gcm, Some(&key_id), None)
+                .unwrap_err()
+                .to_string(),
+            "The file store is encrypted, but no encryption key is provided."
+        );
+
+        let wrong_cipher = BlobCipher::new(&[8u8; AES_256_GCM_KEY_SIZE]).unwrap();
+        assert_eq!(
+            check_encryption_key(
+                EncryptionScheme::Aes256Gcm,
+                Some(&key_id),
+                Some(&wrong_cipher)
+            )
+            .unwrap_err()
+            .to_string(),
+            format!(
+                "The file store is encrypted with key {}, but key {} is provided.",
+                key_id,
+                wrong_cipher.key_id()
+            )
+        );
+    }
+
+    #[test]
+    fn invalid_key_size_is_rejected() {
+        assert!(BlobCipher::new(&[7u8; 16]).is_err());
+    }
+
+    #[test]
+    fn key_is_loaded_from_hex_file() {
+        let key_file = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(key_file.path(), format!("{}\n", hex::encode([7u8; 32]))).unwrap();
+        let cipher = BlobCipher::from_key_file(key_file.path()).unwrap();
+        let encrypted = cipher.encrypt(b"transactions").unwrap();
+        let expected_cipher = BlobCipher::new(&[7u8; AES_256_GCM_KEY_SIZE]).unwrap();
+        assert_eq!(
+            expected_cipher.decrypt(&encrypted).unwrap(),
+            b"transactions".to_vec()
+        );
+    }
+
+    #[test]
+    fn key_is_loaded_from_env_var() {
+        let name = "INDEXER_GRPC_TEST_ENCRYPTION_KEY";
+        assert!(BlobCipher::from_env_var(name).is_err());
+        std::env::set_var(name, hex::encode([7u8; 32]));
+        let cipher = BlobCipher::from_env_var(name).unwrap();
+        let expected_cipher = BlobCipher::new(&[7u8; AES_256_GCM_KEY_SIZE]).unwrap();
+        assert_eq!(cipher.key_id(), expected_cipher.key_id());
+        std::env::remove_var(name);
+    }
+}
@@ -2,44 +2,567 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use crate::{
-    compression_util::{FileEntry, FileStoreMetadata, StorageFormat, FILE_ENTRY_TRANSACTION_COUNT},
-    counters::{log_grpc_step, IndexerGrpcStep},
-    file_store_operator::{FileStoreOperator, METADATA_FILE_NAME},
+    compression_util::{
+        FileEntry, FileStoreMetadata, KeyLayout, StorageFormat, DEFAULT_ZSTD_COMPRESSION_LEVEL,
+    },
+    config::{GcsCredentialSource, GcsRetryConfig},
+    counters::{log_grpc_step, IndexerGrpcStep, GCS_REQUEST_RETRIES},
+    encryption_util::{decrypt_blob, encrypt_blob, BlobCipher, EncryptionScheme},
+    file_store_operator::{
+        blob_byte_stream_from_bytes, compute_blob_digest, decode_encoded_transactions,
+        encode_encoded_transactions, encode_transaction_stream, is_blob_already_uploaded,
+        is_encoded_blob_already_uploaded, peek_start_version, BlobByteStream, BlobDigestsTracker,
+        BlobFormatTracker, BlobSizeTracker, EncodedBatch, ExistingBlobCheck, FileStoreOperator,
+        FileStoreProgress, KeyLayoutTracker, MetadataRevisionTracker, METADATA_FILE_NAME,
+        PROGRESS_FILE_NAME,
+    },
+    types::ChainId,
 };
-use anyhow::bail;
+use anyhow::{bail, ensure, Context};
 use aptos_protos::transaction::v1::Transaction;
-use cloud_storage::{Bucket, Object};
-use std::env;
+use backoff::backoff::Backoff;
+use cloud_storage::{Bucket, Object, TokenCache};
+use futures::{StreamExt, TryStreamExt};
+use once_cell::sync::OnceCell;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::BTreeMap,
+    env,
+    future::Future,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use url::Url;
 
 const JSON_FILE_TYPE: &str = "application/json";
+const TEXT_FILE_TYPE: &str = "text/plain";
 // The environment variable to set the service account path.
 const SERVICE_ACCOUNT_ENV_VAR: &str = "SERVICE_ACCOUNT";
+// Path of the key file of the application default credentials, if not the metadata server.
+const APPLICATION_CREDENTIALS_ENV_VAR: &str = "GOOGLE_APPLICATION_CREDENTIALS";
+// Overrides the host of the metadata server, e.g., for an emulator.
+const METADATA_HOST_ENV_VAR: &str = "GCE_METADATA_HOST";
+const DEFAULT_METADATA_HOST: &str = "metadata.google.internal";
+// Tokens from the metadata server are refreshed this long before they expire.
+const METADATA_TOKEN_REFRESH_MARGIN: Duration = Duration::from_secs(60);
 const FILE_STORE_METADATA_TIMEOUT_MILLIS: u128 = 200;
+const DEFAULT_GCS_ENDPOINT: &str = "https://storage.googleapis.com";
+// Size of the chunks of resumable uploads; GCS requires a multiple of 256 KiB.
+const RESUMABLE_UPLOAD_CHUNK_SIZE: usize = 8 * 1024 * 1024;
+// Status GCS answers with while a resumable upload is incomplete.
+const RESUME_INCOMPLETE_STATUS: u16 = 308;
+// Separates the object resource from the media in multipart uploads.
+const MULTIPART_BOUNDARY: &str = "indexer_grpc_file_store_boundary";
+// Status GCS answers with when the precondition of a conditional write doesn't hold.
+const PRECONDITION_FAILED_STATUS: u16 = 412;
+// Parts of the GCS error messages that retrying cannot fix, e.g., a missing object or bucket, or
+// a caller without access to it.
+const NON_RETRYABLE_ERROR_MESSAGES: [&str; 4] = [
+    "No such object: ",
+    "The specified bucket does not exist",
+    "does not have storage.",
+    "Anonymous caller",
+];
+
+/// Server-side encryption of the objects with a key of the caller instead of a Google-managed key.
+#[derive(Clone)]
+pub enum GcsServerSideEncryption {
+    /// A customer-supplied AES-256 key (CSEK), base64 encoded with its SHA-256 hash. It is sent with
+    /// every write and read; GCS only keeps the hash.
+    CustomerSupplied { key: String, key_sha256: String },
+    /// A Cloud KMS key (CMEK), named on every write; reads need no key.
+    Kms { key_name: String },
+}
+
+impl GcsServerSideEncryption {
+    /// Loads the customer-supplied key from a file holding the hex encoded 32-byte key.
+    pub fn customer_supplied_from_key_file(path: &Path) -> anyhow::Result<Self> {
+        let hex_key = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read the encryption key {:?}.", path))?;
+        let key = hex::decode(hex_key.trim()).context("Encryption key is not valid hex.")?;
+        ensure!(
+            key.len() == 32,
+            "Customer-supplied encryption key must be 32 bytes, got {}.",
+            key.len()
+        );
+        Ok(Self::CustomerSupplied {
+            key: base64::encode(&key),
+            key_sha256: base64::encode(Sha256::digest(&key)),
+        })
+    }
+}
 
 #[derive(Clone)]
 pub struct GcsFileStoreOperator {
     bucket_name: String,
     file_store_metadata_last_updated: std::time::Instant,
     storage_format: StorageFormat,
+    compression_level: i32,
+    // If set, blobs are encrypted before upload and decrypted on read.
+    cipher: Option<BlobCipher>,
+    retry_config: GcsRetryConfig,
+    credential_source: GcsCredentialSource,
+    // If set, requests are sent here instead of through the `cloud_storage` client.
+    endpoint: Option<GcsEndpoint>,
+    // Client of the GCS JSON API for requests the `cloud_storage` client doesn't support, created
+    // on first use.
+    default_endpoint: OnceCell<GcsEndpoint>,
+    metadata_revision: MetadataRevisionTracker,
+    key_layout: KeyLayoutTracker,
+    blob_size: BlobSizeTracker,
+    blob_digests: BlobDigestsTracker,
+    blob_format: BlobFormatTracker,
+    existing_blob_check: ExistingBlobCheck,
+    // If set, objects larger than this are sent as resumable uploads.
+    resumable_upload_threshold_in_bytes: Option<usize>,
+    resumable_upload_chunk_size: usize,
+    // If set, objects are encrypted by GCS with this key; writes and reads then go through the
+    // JSON API, since the `cloud_storage` client can't send it.
+    server_side_encryption: Option<GcsServerSideEncryption>,
+    // Generation of the progress object as last read or written, 0 if it doesn't exist; unknown
+    // until then. Progress is only written over this generation.
+    progress_generation: Arc<Mutex<Option<i64>>>,
+    // Custom metadata set on every blob and blob digest, e.g., a retention class for the lifecycle
+    // rules of the bucket; writes then go through the JSON API. Reads ignore it.
+    blob_metadata: BTreeMap<String, String>,
 }
 
 impl GcsFileStoreOperator {
     pub fn new(
         bucket_name: String,
-        service_account_path: String,
+        service_account_path: Option<String>,
         enable_compression: bool,
+        zstd_compression_level: Option<i32>,
     ) -> Self {
-        env::set_var(SERVICE_ACCOUNT_ENV_VAR, service_account_path);
-        let storage_format = if enable_compression {
-            StorageFormat::GzipCompressedProto
-        } else {
-            StorageFormat::JsonBase64UncompressedProto
-        };
+        if let Some(service_account_path) = service_account_path {
+            env::set_var(SERVICE_ACCOUNT_ENV_VAR, service_account_path);
+        }
+        let storage_format =
+            StorageFormat::for_file_store(enable_compression, zstd_compression_level);
         Self {
             bucket_name,
             file_store_metadata_last_updated: std::time::Instant::now(),
             storage_format,
+            compression_level: zstd_compression_level.unwrap_or(DEFAULT_ZSTD_COMPRESSION_LEVEL),
+            cipher: None,
+            retry_config: GcsRetryConfig::default(),
+            credential_source: GcsCredentialSource::KeyFile,
+            endpoint: None,
+            default_endpoint: OnceCell::new(),
+            metadata_revision: MetadataRevisionTracker::default(),
+            key_layout: KeyLayoutTracker::default(),
+            blob_size: BlobSizeTracker::default(),
+            blob_digests: BlobDigestsTracker::default(),
+            blob_format: BlobFormatTracker::default(),
+            existing_blob_check: ExistingBlobCheck::default(),
+            resumable_upload_threshold_in_bytes: None,
+            resumable_upload_chunk_size: RESUMABLE_UPLOAD_CHUNK_SIZE,
+            server_side_encryption: None,
+            progress_generation: Arc::new(Mutex::new(None)),
+            blob_metadata: BTreeMap::new(),
+        }
+    }
+
+    /// Sends the requests to `endpoint`, e.g., a fake-gcs-server, instead of GCS. With
+    /// `anonymous_credentials`, requests carry no credentials; this also works against GCS itself.
+    pub fn with_endpoint(mut self, endpoint: Option<String>, anonymous_credentials: bool) -> Self {
+        if endpoint.is_some() || anonymous_credentials {
+            let url = endpoint.as_deref().unwrap_or(DEFAULT_GCS_ENDPOINT);
+            let credentials = if anonymous_credentials {
+                GcsCredentials::Anonymous
+            } else {
+                GcsCredentials::new(&self.credential_source)
+            };
+            self.endpoint = Some(GcsEndpoint::new(
+                url,
+                credentials,
+                self.server_side_encryption.clone(),
+            ));
         }
+        self
+    }
+
+    /// Authenticates the requests with credentials from `source` instead of the service account
+    /// key file. Requests with anonymous credentials stay anonymous.
+    pub fn with_credential_source(mut self, source: GcsCredentialSource) -> Self {
+        match &mut self.endpoint {
+            Some(endpoint) => {
+                if !matches!(endpoint.credentials, GcsCredentials::Anonymous) {
+                    endpoint.credentials = GcsCredentials::new(&source);
+                }
+            },
+            // The `cloud_storage` client only authenticates with a key file.
+            None if source != GcsCredentialSource::KeyFile => {
+                self.endpoint = Some(GcsEndpoint::new(
+                    DEFAULT_GCS_ENDPOINT,
+                    GcsCredentials::new(&source),
+                    self.server_side_encryption.clone(),
+                ));
+            },
+            None => {},
+        }
+        self.credential_source = source;
+        self
+    }
+
+    /// Keys the blobs with `key_layout` instead of the layout recorded in the metadata.
+    pub fn with_key_layout(mut self, key_layout: Option<KeyLayout>) -> Self {
+        self.key_layout = KeyLayoutTracker::new(key_layout);
+        self
+    }
+
+    /// Writes blobs of `blob_size` versions if the file store is new; an existing file store keeps
+    /// the blob size recorded in its metadata.
+    pub fn with_blob_size(mut self, blob_size: Option<u64>) -> Self {
+        self.blob_size = BlobSizeTracker::new(blob_size);
+        self
+    }
+
+    /// Checks whether a blob is already uploaded with `existing_blob_check` before writing it.
+    pub fn with_existing_blob_check(mut self, existing_blob_check: ExistingBlobCheck) -> Self {
+        self.existing_blob_check = existing_blob_check;
+        self
+    }
+
+    /// Sends objects larger than `threshold_in_bytes` as resumable uploads, in chunks; a chunk
+    /// that fails is resumed from the last byte GCS persisted.
+    pub fn with_resumable_upload_threshold(mut self, threshold_in_bytes: Option<usize>) -> Self {
+        self.resumable_upload_threshold_in_bytes = threshold_in_bytes;
+        self
+    }
+
+    /// Has GCS encrypt the objects with `encryption` instead of a Google-managed key. Objects
+    /// written with a customer-supplied key can only be read with it.
+    pub fn with_server_side_encryption(
+        mut self,
+        encryption: Option<GcsServerSideEncryption>,
+    ) -> Self {
+        if let Some(endpoint) = &mut self.endpoint {
+            endpoint.server_side_encryption = encryption.clone();
+        }
+        self.server_side_encryption = encryption;
+        self
+    }
+
+    /// Sets `metadata` as custom metadata of the blobs and their digests, so that the lifecycle
+    /// rules of the bucket can expire them. The metadata and progress objects aren't tagged.
+    pub fn with_blob_metadata(mut self, metadata: BTreeMap<String, String>) -> Self {
+        self.blob_metadata = metadata;
+        self
+    }
+
+    fn json_api_endpoint(&self) -> &GcsEndpoint {
+        match &self.endpoint {
+            Some(endpoint) => endpoint,
+            None => self.default_endpoint.get_or_init(|| {
+                GcsEndpoint::new(
+                    DEFAULT_GCS_ENDPOINT,
+                    GcsCredentials::new(&self.credential_source),
+                    self.server_side_encryption.clone(),
+                )
+            }),
+        }
+    }
+
+    /// Endpoint objects are written to and read from, if not through the `cloud_storage` client.
+    fn object_endpoint(&self) -> Option<&GcsEndpoint> {
+        match (&self.endpoint, &self.server_side_encryption) {
+            (Some(endpoint), _) => Some(endpoint),
+            (None, Some(_)) => Some(self.json_api_endpoint()),
+            (None, None) => None,
+        }
+    }
+
+    /// Writes the blobs as Parquet files instead of the format derived from the compression
+    /// settings.
+    pub fn with_parquet(mut self, enable_parquet: bool) -> Self {
+        if enable_parquet {
+            self.storage_format = StorageFormat::Parquet;
+        }
+        self
+    }
+
+    pub fn with_retry_config(mut self, retry_config: GcsRetryConfig) -> Self {
+        self.retry_config = retry_config;
+        self
+    }
+
+    fn object_path(&self, key: &str) -> String {
+        format!("gs://{}/{}", self.bucket_name, key)
+    }
+
+    /// Where the credentials of the requests come from, to explain authentication failures.
+    fn credentials_description(&self) -> String {
+        match &self.endpoint {
+            Some(endpoint) => endpoint.credentials.description(),
+            None => GcsCredentials::service_account_key_description(),
+        }
+    }
+
+    /// Sends the request built by `request` until it succeeds, fails with an error that retrying
+    /// cannot fix, or runs out of attempts. Each attempt is bounded by the request timeout.
+    async fn with_retries<T, F, Fut>(
+        &self,
+        operation: &'static str,
+        key: &str,
+        request: F,
+    ) -> Result<T, cloud_storage::Error>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<T, cloud_storage::Error>>,
+    {
+        let request_timeout = Duration::from_secs(self.retry_config.request_timeout_in_secs);
+        let mut backoff = build_backoff(&self.retry_config);
+        let mut attempt = 1;
+        loop {
+            let err = match tokio::time::timeout(request_timeout, request()).await {
+                Ok(Ok(value)) => return Ok(value),
+                Ok(Err(err)) => err,
+                Err(_) => cloud_storage::Error::Other(format!(
+                    "Request timed out after {:?}.",
+                    request_timeout
+                )),
+            };
+            if attempt >= self.retry_config.max_attempts || !is_retryable_gcs_error(&err) {
+                return Err(explain_credentials_error(
+                    explain_encryption_key_error(err),
+                    &self.credentials_description(),
+                ));
+            }
+            tracing::warn!(
+                object_path = self.object_path(key),
+                operation = operation,
+                attempt = attempt,
+                error = err.to_string(),
+                "[Indexer File] GCS request failed; retrying."
+            );
+            GCS_REQUEST_RETRIES.with_label_values(&[operation]).inc();
+            let delay = backoff.next_backoff().unwrap_or(Duration::from_millis(
+                self.retry_config.max_backoff_in_millis,
+            ));
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+
+    async fn create_object(
+        &self,
+        operation: &'static str,
+        bytes: Vec<u8>,
+        key: &str,
+        mime_type: &str,
+    ) -> anyhow::Result<()> {
+        self.create_object_with_metadata(operation, bytes, key, mime_type, &BTreeMap::new())
+            .await
+    }
+
+    /// Creates the object with `metadata` as its custom metadata, if any. The `cloud_storage`
+    /// client can't set it, so such writes go through the JSON API.
+    async fn create_object_with_metadata(
+        &self,
+        operation: &'static str,
+        bytes: Vec<u8>,
+        key: &str,
+        mime_type: &str,
+        metadata: &BTreeMap<String, String>,
+    ) -> anyhow::Result<()> {
+        if self
+            .resumable_upload_threshold_in_bytes
+            .map_or(false, |threshold| bytes.len() > threshold)
+        {
+            return self
+                .create_object_resumably(operation, &bytes, key, mime_type, metadata)
+                .await
+                .with_context(|| {
+                    format!("[Indexer File] Failed to upload {}.", self.object_path(key))
+                });
+        }
+        let endpoint = match self.object_endpoint() {
+            None if !metadata.is_empty() => Some(self.json_api_endpoint()),
+            endpoint => endpoint,
+        };
+        self.with_retries(operation, key, || async {
+            match endpoint {
+                Some(endpoint) => {
+                    endpoint
+                        .create(&self.bucket_name, bytes.clone(), key, mime_type, metadata)
+                        .await
+                },
+                None => Object::create(self.bucket_name.as_str(), bytes.clone(), key, mime_type)
+                    .await
+                    .map(|_| ()),
+            }
+        })
+        .await
+        .with_context(|| format!("[Indexer File] Failed to upload {}.", self.object_path(key)))?;
+        Ok(())
+    }
+
+    /// Uploads `bytes` in chunks of a resumable upload. Every chunk is retried like other requests;
+    /// a retry first asks GCS how much of the upload it persisted, and resumes from there.
+    async fn create_object_resumably(
+        &self,
+        operation: &'static str,
+        bytes: &[u8],
+        key: &str,
+        mime_type: &str,
+        metadata: &BTreeMap<String, String>,
+    ) -> Result<(), cloud_storage::Error> {
+        let endpoint = self.json_api_endpoint();
+        let session = self
+            .with_retries(operation, key, || {
+                endpoint.start_resumable_upload(
+                    &self.bucket_name,
+                    key,
+                    mime_type,
+                    bytes.len(),
+                    metadata,
+                )
+            })
+            .await?;
+        // Offset the next chunk starts at; unknown after a failed attempt, until GCS is asked.
+        let next_offset = Mutex::new(Some(0));
+        loop {
+            let persisted_size = self
+                .with_retries(operation, key, || async {
+                    let offset = next_offset.lock().unwrap().take();
+                    let offset = match offset {
+                        Some(offset) => offset,
+                        None => match endpoint
+                            .query_resumable_upload(&session, bytes.len())
+                            .await?
+                        {
+                            Some(offset) => {
+                                tracing::info!(
+                                    object_path = self.object_path(key),
+                                    offset = offset,
+                                    "[Indexer File] Resuming the upload."
+                                );
+                                offset
+                            },
+                            None => return Ok(None),
+                        },
+                    };
+                    let end = bytes.len().min(offset + self.resumable_upload_chunk_size);
+                    endpoint
+                        .upload_resumable_chunk(&session, &bytes[offset..end], offset, bytes.len())
+                        .await
+                })
+                .await?;
+            match persisted_size {
+                Some(offset) => *next_offset.lock().unwrap() = Some(offset),
+                None => return Ok(()),
+            }
+        }
+    }
+
+    async fn download_object(
+        &self,
+        operation: &'static str,
+        key: &str,
+    ) -> Result<Vec<u8>, cloud_storage::Error> {
+        self.with_retries(operation, key, || async {
+            match self.object_endpoint() {
+                Some(endpoint) => endpoint.download(&self.bucket_name, key).await,
+                None => Object::download(self.bucket_name.as_str(), key).await,
+            }
+        })
+        .await
+    }
+
+    /// Opens the download of the object; only opening it is retried. Objects are always streamed
+    /// through the JSON API, since the `cloud_storage` client streams them byte by byte.
+    async fn download_object_stream(
+        &self,
+        operation: &'static str,
+        key: &str,
+    ) -> Result<BlobByteStream, cloud_storage::Error> {
+        self.with_retries(operation, key, || {
+            self.json_api_endpoint()
+                .download_stream(&self.bucket_name, key)
+        })
+        .await
+    }
+
+    fn blob_download_error(
+        &self,
+        file_entry_key: &str,
+        err: cloud_storage::Error,
+    ) -> anyhow::Error {
+        match err {
+            cloud_storage::Error::Other(err) if err.contains("No such object: ") => {
+                anyhow::anyhow!("[Indexer File] Transactions file not found. Gap might happen between cache and file store. {}", err)
+            },
+            cloud_storage::Error::Other(err) => anyhow::anyhow!(
+                "[Indexer File] Error happens when downloading transaction file {}. {}",
+                self.object_path(file_entry_key),
+                err
+            ),
+            err => anyhow::Error::new(err).context(format!(
+                "[Indexer File] Error happens when downloading transaction file {}.",
+                self.object_path(file_entry_key)
+            )),
+        }
+    }
+
+    /// Enables client-side encryption of the blobs.
+    pub fn with_cipher(mut self, cipher: BlobCipher) -> Self {
+        self.cipher = Some(cipher);
+        self
+    }
+
+    /// Uploads a blob written with `encode_transaction_stream`, unless it's already there.
+    async fn upload_encoded_batch(
+        &self,
+        batch: EncodedBatch<Vec<u8>>,
+    ) -> anyhow::Result<(u64, u64, usize)> {
+        if !is_encoded_blob_already_uploaded(
+            self,
+            batch.start_version,
+            &batch.digest,
+            self.compression_level,
+        )
+        .await?
+        {
+            self.upload_blob(
+                batch.start_version,
+                FileEntry::new(batch.sink, self.storage_format),
+            )
+            .await?;
+        }
+        Ok((batch.start_version, batch.end_version, batch.size_in_bytes))
+    }
+
+    /// Uploads the blob of the batch starting at `start_version`, then its digest, so the digest
+    /// never refers to a missing blob. Returns the size of the encoded blob in bytes.
+    async fn upload_blob(
+        &self,
+        start_version: u64,
+        file_entry: FileEntry,
+    ) -> anyhow::Result<usize> {
+        let bytes = file_entry.into_inner();
+        let size_in_bytes = bytes.len();
+        let digest = compute_blob_digest(&bytes);
+        let bytes = encrypt_blob(self.cipher.as_ref(), bytes)?;
+        self.create_object_with_metadata(
+            "upload_blob",
+            bytes,
+            self.blob_key(start_version).await?.as_str(),
+            JSON_FILE_TYPE,
+            &self.blob_metadata,
+        )
+        .await?;
+        self.create_object_with_metadata(
+            "upload_blob_digest",
+            digest.into_bytes(),
+            self.blob_digest_key(start_version).await?.as_str(),
+            TEXT_FILE_TYPE,
+            &self.blob_metadata,
+        )
+        .await?;
+        Ok(size_in_bytes)
     }
 }
 
@@ -52,82 +575,146 @@ impl FileStoreOperator for GcsFileStoreOperator {
             "Before file store operator starts, verify the bucket exists."
         );
         // Verifies the bucket exists.
-        Bucket::read(&self.bucket_name)
+        if let Err(err) = self
+            .with_retries("read_bucket", "", || async {
+                match &self.endpoint {
+                    Some(endpoint) => endpoint.read_bucket(&self.bucket_name).await,
+                    None => Bucket::read(&self.bucket_name).await.map(|_| ()),
+                }
+            })
             .await
-            .expect("Failed to read bucket.");
+        {
+            panic!("Failed to read bucket {}. {}", self.bucket_name, err);
+        }
     }
 
     fn storage_format(&self) -> StorageFormat {
         self.storage_format
     }
 
+    fn encryption_scheme(&self) -> EncryptionScheme {
+        if self.cipher.is_some() {
+            EncryptionScheme::Aes256Gcm
+        } else {
+            EncryptionScheme::None
+        }
+    }
+
+    fn existing_blob_check(&self) -> ExistingBlobCheck {
+        self.existing_blob_check
+    }
+
     fn store_name(&self) -> &str {
         "GCS"
     }
 
+    fn key_layout_tracker(&self) -> &KeyLayoutTracker {
+        &self.key_layout
+    }
+
+    fn blob_size_tracker(&self) -> &BlobSizeTracker {
+        &self.blob_size
+    }
+
+    fn blob_digests_tracker(&self) -> &BlobDigestsTracker {
+        &self.blob_digests
+    }
+
+    fn blob_format_tracker(&self) -> &BlobFormatTracker {
+        &self.blob_format
+    }
+
     async fn get_raw_file(&self, version: u64) -> anyhow::Result<Vec<u8>> {
-        let file_entry_key = FileEntry::build_key(version, self.storage_format).to_string();
-        match Object::download(&self.bucket_name, file_entry_key.as_str()).await {
-            Ok(file) => Ok(file),
-            Err(cloud_storage::Error::Other(err)) => {
-                if err.contains("No such object: ") {
-                    anyhow::bail!("[Indexer File] Transactions file not found. Gap might happen between cache and file store. {}", err)
-                } else {
-                    anyhow::bail!(
-                        "[Indexer File] Error happens when downloading transaction file. {}",
-                        err
-                    );
-                }
-            },
-            Err(err) => {
-                anyhow::bail!(
-                    "[Indexer File] Error happens when transaction file. {}",
-                    err
-                );
-            },
+        let file_entry_key = self.blob_key(version).await?;
+        match self
+            .download_object("download_blob", file_entry_key.as_str())
+            .await
+        {
+            Ok(file) => decrypt_blob(self.cipher.as_ref(), file),
+            Err(err) => Err(self.blob_download_error(&file_entry_key, err)),
         }
     }
 
-    /// Gets the metadata from the file store. Operator will panic if error happens when accessing the metadata file(except not found).
-    async fn get_file_store_metadata(&self) -> Option<FileStoreMetadata> {
-        match Object::download(&self.bucket_name, METADATA_FILE_NAME).await {
-            Ok(metadata) => {
-                let metadata: FileStoreMetadata =
-                    serde_json::from_slice(&metadata).expect("Expected metadata to be valid JSON.");
-                Some(metadata)
-            },
-            Err(cloud_storage::Error::Other(err)) => {
-                if err.contains("No such object: ") {
-                    // Metadata is not found.
-                    None
-                } else {
-                    panic!(
-                        "[Indexer File] Error happens when accessing metadata file. {}",
-                        err
-                    );
-                }
-            },
-            Err(e) => {
-                panic!(
-                    "[Indexer File] Error happens when accessing metadata file. {}",
-                    e
-                );
-            },
+    async fn get_legacy_raw_file(
+        &self,
+        version: u64,
+    ) -> anyhow::Result<Option<(StorageFormat, Vec<u8>)>> {
+        for (storage_format, key) in self.legacy_blob_keys(version).await? {
+            match self.download_object("download_blob", key.as_str()).await {
+                Ok(file) => {
+                    return Ok(Some((
+                        storage_format,
+                        decrypt_blob(self.cipher.as_ref(), file)?,
+                    )))
+                },
+                Err(cloud_storage::Error::Other(err)) if err.contains("No such object: ") => {},
+                Err(err) => return Err(self.blob_download_error(&key, err)),
+            }
         }
+        Ok(None)
+    }
+
+    async fn get_raw_file_stream(&self, version: u64) -> anyhow::Result<BlobByteStream> {
+        // Encrypted blobs are decrypted as a whole.
+        if self.cipher.is_some() {
+            return Ok(blob_byte_stream_from_bytes(
+                self.get_raw_file(version).await?,
+            ));
+        }
+        let file_entry_key = self.blob_key(version).await?;
+        self.download_object_stream("download_blob", file_entry_key.as_str())
+            .await
+            .map_err(|err| self.blob_download_error(&file_entry_key, err))
+    }
+
+    async fn try_get_file_store_metadata(&self) -> anyhow::Result<Option<FileStoreMetadata>> {
+        let metadata = match self
+            .download_object("download_metadata", METADATA_FILE_NAME)
+            .await
+        {
+            Ok(metadata) => serde_json::from_slice::<FileStoreMetadata>(&metadata)
+                .expect("Expected metadata to be valid JSON."),
+            // Metadata is not found.
+            Err(cloud_storage::Error::Other(err)) if err.contains("No such object: ") => {
+                return Ok(None)
+            },
+            Err(err) => bail!(
+                "[Indexer File] Error happens when accessing metadata file {}. {}",
+                self.object_path(METADATA_FILE_NAME),
+                err
+            ),
+        };
+        self.metadata_revision.observe(&metadata)?;
+        self.key_layout.observe(&metadata);
+        self.blob_size.observe(&metadata);
+        self.blob_digests.observe(&metadata);
+        self.blob_format.observe(&metadata);
+        Ok(Some(metadata))
     }
 
     /// If the file store is empty, the metadata will be created; otherwise, return the existing metadata.
     async fn update_file_store_metadata_with_timeout(
         &mut self,
-        expected_chain_id: u64,
+        expected_chain_id: ChainId,
         version: u64,
     ) -> anyhow::Result<()> {
         if let Some(metadata) = self.get_file_store_metadata().await {
-            assert_eq!(metadata.chain_id, expected_chain_id, "Chain ID mismatch.");
+            if let Err(err) = metadata.chain_id.ensure_matches(
+                "the file store",
+                expected_chain_id,
+                "the metadata update",
+            ) {
+                panic!("{}", err);
+            }
             assert_eq!(
                 metadata.storage_format, self.storage_format,
                 "Storage format mismatch."
             );
+            assert_eq!(
+                metadata.encryption_scheme,
+                self.encryption_scheme(),
+                "Encryption scheme mismatch."
+            );
         }
         if self.file_store_metadata_last_updated.elapsed().as_millis()
             < FILE_STORE_METADATA_TIMEOUT_MILLIS
@@ -142,13 +729,23 @@ impl FileStoreOperator for GcsFileStoreOperator {
     /// Updates the file store metadata. This is only performed by the operator when new file transactions are uploaded.
     async fn update_file_store_metadata_internal(
         &mut self,
-        chain_id: u64,
+        chain_id: ChainId,
         version: u64,
     ) -> anyhow::Result<()> {
-        let metadata = FileStoreMetadata::new(chain_id, version, self.storage_format);
+        let metadata = FileStoreMetadata::new(
+            chain_id,
+            version,
+            self.storage_format,
+            self.encryption_scheme(),
+        )
+        .with_revision(self.metadata_revision.next_revision())
+        .with_key_layout(self.key_layout().await?)
+        .with_blob_size(self.blob_size().await?)
+        .with_blob_digests_since_version(self.blob_digests_since_version_for_update(version).await?)
+        .with_blob_format_version(self.blob_format_version().await?);
         // If the metadata is not updated, the indexer will be restarted.
-        Object::create(
-            self.bucket_name.as_str(),
+        self.create_object(
+            "upload_metadata",
             serde_json::to_vec(&metadata).unwrap(),
             METADATA_FILE_NAME,
             JSON_FILE_TYPE,
@@ -158,51 +755,1657 @@ impl FileStoreOperator for GcsFileStoreOperator {
         Ok(())
     }
 
-    /// Uploads the transactions to the file store. The transactions are grouped into batches of BLOB_STORAGE_SIZE.
+    async fn get_processing_progress(&self) -> anyhow::Result<Option<FileStoreProgress>> {
+        let endpoint = self.json_api_endpoint();
+        match self
+            .with_retries("download_progress", PROGRESS_FILE_NAME, || {
+                endpoint.download_with_generation(&self.bucket_name, PROGRESS_FILE_NAME)
+            })
+            .await
+        {
+            Ok((progress, generation)) => {
+                *self.progress_generation.lock().unwrap() = Some(generation);
+                Ok(Some(serde_json::from_slice(&progress)?))
+            },
+            Err(cloud_storage::Error::Other(err)) if err.contains("No such object: ") => {
+                *self.progress_generation.lock().unwrap() = Some(0);
+                Ok(None)
+            },
+            Err(err) => Err(err).with_context(|| {
+                format!(
+                    "[Indexer File] Error happens when downloading progress {}.",
+                    self.object_path(PROGRESS_FILE_NAME)
+                )
+            }),
+        }
+    }
+
+    /// Writes the progress over the generation last read or written, so a concurrent write isn't
+    /// silently overwritten.
+    async fn update_processing_progress(
+        &mut self,
+        progress: FileStoreProgress,
+    ) -> anyhow::Result<()> {
+        let known_generation = *self.progress_generation.lock().unwrap();
+        let generation = match known_generation {
+            Some(generation) => generation,
+            None => {
+                self.get_processing_progress().await?;
+                self.progress_generation.lock().unwrap().unwrap_or_default()
+            },
+        };
+        let bytes = serde_json::to_vec(&progress)?;
+        let endpoint = self.json_api_endpoint();
+        match self
+            .with_retries("upload_progress", PROGRESS_FILE_NAME, || {
+                endpoint.create_if_generation_matches(
+                    &self.bucket_name,
+                    bytes.clone(),
+                    PROGRESS_FILE_NAME,
+                    JSON_FILE_TYPE,
+                    generation,
+                )
+            })
+            .await
+        {
+            Ok(generation) => {
+                *self.progress_generation.lock().unwrap() = Some(generation);
+                Ok(())
+            },
+            Err(cloud_storage::Error::Google(response))
+                if response.error.code == PRECONDITION_FAILED_STATUS =>
+            {
+                // The generation is read again by the next update, e.g., if this write is the
+                // retry of one that succeeded.
+                *self.progress_generation.lock().unwrap() = None;
+                bail!(
+                    "[Indexer File] {} was updated by another writer.",
+                    self.object_path(PROGRESS_FILE_NAME)
+                )
+            },
+            Err(err) => Err(err).with_context(|| {
+                format!(
+                    "[Indexer File] Failed to upload {}.",
+                    self.object_path(PROGRESS_FILE_NAME)
+                )
+            }),
+        }
+    }
+
+    /// Uploads the transactions to the file store, as a single blob of `blob_size` transactions.
     /// Updates the file store metadata after the upload.
     async fn upload_transaction_batch(
         &mut self,
-        _chain_id: u64,
+        _chain_id: ChainId,
         transactions: Vec<Transaction>,
-    ) -> anyhow::Result<(u64, u64)> {
+    ) -> anyhow::Result<(u64, u64, usize)> {
         let start_version = transactions.first().unwrap().version;
         let end_version = transactions.last().unwrap().version;
         let batch_size = transactions.len();
+        let blob_size = self.blob_size().await?;
         anyhow::ensure!(
-            start_version % FILE_ENTRY_TRANSACTION_COUNT == 0,
-            "Starting version has to be a multiple of BLOB_STORAGE_SIZE."
+            start_version % blob_size == 0,
+            "Starting version has to be a multiple of the blob size {}.",
+            blob_size
         );
         anyhow::ensure!(
-            batch_size == FILE_ENTRY_TRANSACTION_COUNT as usize,
-            "The number of transactions to upload has to be multiplier of BLOB_STORAGE_SIZE."
+            batch_size as u64 == blob_size,
+            "The number of transactions to upload has to be the blob size {}.",
+            blob_size
         );
         let start_time = std::time::Instant::now();
-        let bucket_name = self.bucket_name.clone();
-        let file_entry = FileEntry::from_transactions(transactions, self.storage_format);
-        let file_entry_key = FileEntry::build_key(start_version, self.storage_format).to_string();
+        let file_entry = FileEntry::from_filtered_transactions(
+            start_version,
+            transactions.clone(),
+            self.storage_format,
+            self.compression_level,
+            self.blob_format_version().await?,
+        );
         log_grpc_step(
             "file_worker",
             IndexerGrpcStep::FileStoreEncodedTxns,
             Some(start_version as i64),
-            Some((start_version + FILE_ENTRY_TRANSACTION_COUNT - 1) as i64),
+            Some((start_version + blob_size - 1) as i64),
             None,
             None,
             Some(start_time.elapsed().as_secs_f64()),
             None,
-            Some(FILE_ENTRY_TRANSACTION_COUNT as i64),
+            Some(blob_size as i64),
             None,
         );
-        Object::create(
-            bucket_name.clone().as_str(),
-            file_entry.into_inner(),
-            file_entry_key.as_str(),
-            JSON_FILE_TYPE,
-        )
-        .await?;
-        Ok((start_version, end_version))
+        let bytes = file_entry.into_inner();
+        let size_in_bytes = bytes.len();
+        if !is_blob_already_uploaded(self, start_version, &bytes, &transactions).await? {
+            self.upload_blob(start_version, FileEntry::new(bytes, self.storage_format))
+                .await?;
+        }
+        Ok((start_version, end_version, size_in_bytes))
+    }
+
+    /// Only the compressed blob is buffered, for the upload.
+    async fn upload_transaction_stream<'a>(
+        &'a mut self,
+        chain_id: ChainId,
+        transactions: Box<dyn Iterator<Item = Transaction> + Send + 'a>,
+    ) -> anyhow::Result<(u64, u64, usize)> {
+        if !self.storage_format.supports_incremental_encoding() {
+            return self
+                .upload_transaction_batch(chain_id, transactions.collect())
+                .await;
+        }
+        let mut transactions = transactions.peekable();
+        let start_version = peek_start_version(&mut transactions)?;
+        let batch = encode_transaction_stream(
+            Vec::new(),
+            start_version,
+            transactions,
+            self.blob_size().await?,
+            self.storage_format,
+            self.compression_level,
+            self.blob_format_version().await?,
+        )?;
+        self.upload_encoded_batch(batch).await
+    }
+
+    async fn upload_encoded_transaction_batch(
+        &mut self,
+        chain_id: ChainId,
+        start_version: u64,
+        encoded_transactions: &[Vec<u8>],
+    ) -> anyhow::Result<(u64, u64, usize)> {
+        if !self.storage_format.supports_incremental_encoding() {
+            let transactions = decode_encoded_transactions(start_version, encoded_transactions)?;
+            return self.upload_transaction_batch(chain_id, transactions).await;
+        }
+        let batch = encode_encoded_transactions(
+            Vec::new(),
+            start_version,
+            encoded_transactions,
+            self.blob_size().await?,
+            self.storage_format,
+            self.compression_level,
+            self.blob_format_version().await?,
+        )?;
+        self.upload_encoded_batch(batch).await
+    }
+
+    async fn upload_filtered_transaction_batch(
+        &mut self,
+        start_version: u64,
+        transactions: Vec<Transaction>,
+    ) -> anyhow::Result<()> {
+        let blob_size = self.blob_size().await?;
+        anyhow::ensure!(
+            start_version % blob_size == 0,
+            "Starting version has to be a multiple of the blob size {}.",
+            blob_size
+        );
+        let file_entry = FileEntry::from_filtered_transactions(
+            start_version,
+            transactions,
+            self.storage_format,
+            self.compression_level,
+            self.blob_format_version().await?,
+        );
+        self.upload_blob(start_version, file_entry).await?;
+        Ok(())
+    }
+
+    async fn delete_blob(&mut self, version: u64) -> anyhow::Result<()> {
+        for key in [
+            self.blob_key(version).await?,
+            self.blob_digest_key(version).await?,
+        ] {
+            match self
+                .with_retries("delete_blob", key.as_str(), || async {
+                    match &self.endpoint {
+                        Some(endpoint) => endpoint.delete(&self.bucket_name, key.as_str()).await,
+                        None => Object::delete(self.bucket_name.as_str(), key.as_str()).await,
+                    }
+                })
+                .await
+            {
+                Ok(()) => {},
+                Err(cloud_storage::Error::Other(err)) if err.contains("No such object: ") => {},
+                Err(err) => {
+                    return Err(err).with_context(|| {
+                        format!(
+                            "[Indexer File] Error happens when deleting {}.",
+                            self.object_path(&key)
+                        )
+                    })
+                },
+            }
+        }
+        Ok(())
+    }
+
+    async fn get_recorded_blob_digest(&self, version: u64) -> anyhow::Result<Option<String>> {
+        let digest_key = self.blob_digest_key(version).await?;
+        match self
+            .download_object("download_blob_digest", digest_key.as_str())
+            .await
+        {
+            Ok(digest) => Ok(Some(String::from_utf8(digest)?)),
+            Err(cloud_storage::Error::Other(err)) if err.contains("No such object: ") => Ok(None),
+            Err(err) => Err(err).with_context(|| {
+                format!(
+                    "[Indexer File] Error happens when downloading blob digest {}.",
+                    self.object_path(&digest_key)
+                )
+            }),
+        }
     }
 
     fn clone_box(&self) -> Box<dyn FileStoreOperator> {
         Box::new(self.clone())
     }
 }
+
+/// A client of the GCS JSON API at a custom endpoint, e.g., a fake-gcs-server in tests. Errors
+/// are mapped to the ones of the `cloud_storage` client, so callers handle both the same way.
+#[derive(Clone)]
+struct GcsEndpoint {
+    url: Url,
+    credentials: GcsCredentials,
+    client: reqwest::Client,
+    server_side_encryption: Option<GcsServerSideEncryption>,
+}
+
+impl GcsEndpoint {
+    fn new(
+        url: &str,
+        credentials: GcsCredentials,
+        server_side_encryption: Option<GcsServerSideEncryption>,
+    ) -> Self {
+        Self {
+            url: Url::parse(url).expect("Invalid GCS endpoint."),
+            credentials,
+            client: reqwest::Client::new(),
+            server_side_encryption,
+        }
+    }
+
+    /// Adds the customer-supplied key, which GCS needs to write or read an object encrypted with
+    /// it.
+    fn with_encryption_key(&self, request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        match &self.server_side_encryption {
+            Some(GcsServerSideEncryption::CustomerSupplied { key, key_sha256 }) => request
+                .header("x-goog-encryption-algorithm", "AES256")
+                .header("x-goog-encryption-key", key)
+                .header("x-goog-encryption-key-sha256", key_sha256),
+            _ => request,
+        }
+    }
+
+    /// Names the KMS key new objects are encrypted with.
+    fn append_kms_key_name(&self, url: &mut Url) {
+        if let Some(GcsServerSideEncryption::Kms { key_name }) = &self.server_side_encryption {
+            url.query_pairs_mut().append_pair("kmsKeyName", key_name);
+        }
+    }
+
+    fn build_url(&self, segments: &[&str]) -> Url {
+        let mut url = self.url.clone();
+        url.path_segments_mut()
+            .expect("GCS endpoint cannot be a base URL.")
+            .pop_if_empty()
+            .extend(segments);
+        url
+    }
+
+    async fn send(
+        &self,
+        request: reqwest::RequestBuilder,
+    ) -> Result<reqwest::Response, cloud_storage::Error> {
+        let request = match self.credentials.access_token(&self.client).await {
+            Ok(Some(token)) => request.bearer_auth(token),
+            Ok(None) => request,
+            Err(err) => {
+                return Err(cloud_storage::Error::Other(format!(
+                    "Failed to get a GCS access token from {}: {}",
+                    self.credentials.description(),
+                    err
+                )))
+            },
+        };
+        Ok(request.send().await?)
+    }
+
+    /// Maps an unsuccessful response; a missing object is reported like the `cloud_storage`
+    /// client does, i.e., with "No such object: ".
+    async fn error_from_response(
+        response: reqwest::Response,
+        object: Option<(&str, &str)>,
+    ) -> cloud_storage::Error {
+        let code = response.status().as_u16();
+        let message = response.text().await.unwrap_or_default();
+        match object {
+            Some((bucket_name, key)) if code == 404 => {
+                cloud_storage::Error::Other(format!("No such object: {}/{}", bucket_name, key))
+            },
+            _ => cloud_storage::Error::Google(cloud_storage::GoogleErrorResponse {
+                error: cloud_storage::ErrorList {
+                    errors: vec![],
+                    code,
+                    message,
+                },
+            }),
+        }
+    }
+
+    async fn read_bucket(&self, bucket_name: &str) -> Result<(), cloud_storage::Error> {
+        let url = self.build_url(&["storage", "v1", "b", bucket_name]);
+        let response = self.send(self.client.get(url)).await?;
+        if !response.status().is_success() {
+            return Err(Self::error_from_response(response, None).await);
+        }
+        Ok(())
+    }
+
+    async fn download(
+        &self,
+        bucket_name: &str,
+        key: &str,
+    ) -> Result<Vec<u8>, cloud_storage::Error> {
+        let mut url = self.build_url(&["storage", "v1", "b", bucket_name, "o", key]);
+        url.query_pairs_mut().append_pair("alt", "media");
+        let response = self
+            .send(self.with_encryption_key(self.client.get(url)))
+            .await?;
+        if !response.status().is_success() {
+            return Err(Self::error_from_response(response, Some((bucket_name, key))).await);
+        }
+        Ok(response.bytes().await?.to_vec())
+    }
+
+    async fn download_stream(
+        &self,
+        bucket_name: &str,
+        key: &str,
+    ) -> Result<BlobByteStream, cloud_storage::Error> {
+        let mut url = self.build_url(&["storage", "v1", "b", bucket_name, "o", key]);
+        url.query_pairs_mut().append_pair("alt", "media");
+        let response = self
+            .send(self.with_encryption_key(self.client.get(url)))
+            .await?;
+        if !response.status().is_success() {
+            return Err(Self::error_from_response(response, Some((bucket_name, key))).await);
+        }
+        Ok(response
+            .bytes_stream()
+            .map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err))
+            .boxed())
+    }
+
+    /// Downloads the object along with its generation.
+    async fn download_with_generation(
+        &self,
+        bucket_name: &str,
+        key: &str,
+    ) -> Result<(Vec<u8>, i64), cloud_storage::Error> {
+        let mut url = self.build_url(&["storage", "v1", "b", bucket_name, "o", key]);
+        url.query_pairs_mut().append_pair("alt", "media");
+        let response = self
+            .send(self.with_encryption_key(self.client.get(url)))
+            .await?;
+        if !response.status().is_success() {
+            return Err(Self::error_from_response(response, Some((bucket_name, key))).await);
+        }
+        let generation = response
+            .headers()
+            .get("x-goog-generation")
+            .and_then(|generation| generation.to_str().ok())
+            .and_then(|generation| generation.parse().ok())
+            .ok_or_else(|| {
+                cloud_storage::Error::Other(format!("Object {} without a generation.", key))
+            })?;
+        Ok((response.bytes().await?.to_vec(), generation))
+    }
+
+    async fn create(
+        &self,
+        bucket_name: &str,
+        bytes: Vec<u8>,
+        key: &str,
+        mime_type: &str,
+        metadata: &BTreeMap<String, String>,
+    ) -> Result<(), cloud_storage::Error> {
+        self.send_create(bucket_name, bytes, key, mime_type, metadata, None)
+            .await
+            .map(|_| ())
+    }
+
+    /// Creates the object only if its current generation is `generation`, 0 meaning it doesn't
+    /// exist; GCS answers with 412 otherwise. Returns the generation of the new object.
+    async fn create_if_generation_matches(
+        &self,
+        bucket_name: &str,
+        bytes: Vec<u8>,
+        key: &str,
+        mime_type: &str,
+        generation: i64,
+    ) -> Result<i64, cloud_storage::Error> {
+        let response = self
+            .send_create(
+                bucket_name,
+                bytes,
+                key,
+                mime_type,
+                &BTreeMap::new(),
+                Some(generation),
+            )
+            .await?;
+        let object: serde_json::Value = serde_json::from_slice(&response.bytes().await?)
+            .map_err(|e| cloud_storage::Error::Other(e.to_string()))?;
+        object["generation"]
+            .as_str()
+            .and_then(|generation| generation.parse().ok())
+            .ok_or_else(|| {
+                cloud_storage::Error::Other(format!("Object {} created without a generation.", key))
+            })
+    }
+
+    /// Sends a single request upload; with custom `metadata`, as a multipart upload of the object
+    /// resource and the media, since a media upload can't carry it.
+    async fn send_create(
+        &self,
+        bucket_name: &str,
+        bytes: Vec<u8>,
+        key: &str,
+        mime_type: &str,
+        metadata: &BTreeMap<String, String>,
+        if_generation_match: Option<i64>,
+    ) -> Result<reqwest::Response, cloud_storage::Error> {
+        let mut url = self.build_url(&["upload", "storage", "v1", "b", bucket_name, "o"]);
+        let upload_type = if metadata.is_empty() {
+            "media"
+        } else {
+            "multipart"
+        };
+        url.query_pairs_mut()
+            .append_pair("uploadType", upload_type)
+            .append_pair("name", key);
+        if let Some(generation) = if_generation_match {
+            url.query_pairs_mut()
+                .append_pair("ifGenerationMatch", &generation.to_string());
+        }
+        self.append_kms_key_name(&mut url);
+        let request = if metadata.is_empty() {
+            self.client
+                .post(url)
+                .header(reqwest::header::CONTENT_TYPE, mime_type)
+                .body(bytes)
+        } else {
+            let resource = serde_json::json!({ "name": key, "metadata": metadata });
+            let mut body = format!(
+                "--{boundary}\r\nContent-Type: application/json; charset=UTF-8\r\n\r\n{}\r\n\
+                 --{boundary}\r\nContent-Type: {}\r\n\r\n",
+                resource,
+                mime_type,
+                boundary = MULTIPART_BOUNDARY,
+            )
+            .into_bytes();
+            body.extend_from_slice(&bytes);
+            body.extend_from_slice(format!("\r\n--{}--\r\n", MULTIPART_BOUNDARY).as_bytes());
+            self.client
+                .post(url)
+                .header(
+                    reqwest::header::CONTENT_TYPE,
+                    format!("multipart/related; boundary={}", MULTIPART_BOUNDARY),
+                )
+                .body(body)
+        };
+        let request = self.with_encryption_key(request);
+        let response = self.send(request).await?;
+        if !response.status().is_success() {
+            return Err(Self::error_from_response(response, None).await);
+        }
+        Ok(response)
+    }
+
+    /// Starts a resumable upload of `size` bytes, with custom `metadata` if any; returns the URI of
+    /// the upload session.
+    async fn start_resumable_upload(
+        &self,
+        bucket_name: &str,
+        key: &str,
+        mime_type: &str,
+        size: usize,
+        metadata: &BTreeMap<String, String>,
+    ) -> Result<Url, cloud_storage::Error> {
+        let mut url = self.build_url(&["upload", "storage", "v1", "b", bucket_name, "o"]);
+        url.query_pairs_mut()
+            .append_pair("uploadType", "resumable")
+            .append_pair("name", key);
+        self.append_kms_key_name(&mut url);
+        let request = self
+            .client
+            .post(url)
+            .header("X-Upload-Content-Type", mime_type)
+            .header("X-Upload-Content-Length", size);
+        let request = self.with_encryption_key(if metadata.is_empty() {
+            request.header(reqwest::header::CONTENT_LENGTH, 0)
+        } else {
+            request.json(&serde_json::json!({ "metadata": metadata }))
+        });
+        let response = self.send(request).await?;
+        if !response.status().is_success() {
+            return Err(Self::error_from_response(response, None).await);
+        }
+        response
+            .headers()
+            .get(reqwest::header::LOCATION)
+            .and_then(|location| location.to_str().ok())
+            .and_then(|location| Url::parse(location).ok())
+            .ok_or_else(|| {
+                cloud_storage::Error::Other(
+                    "Resumable upload started without a session URI.".to_string(),
+                )
+            })
+    }
+
+    /// Sends `chunk`, starting at `offset` of the upload. Returns the number of bytes persisted
+    /// so far, or `None` once the upload is complete.
+    async fn upload_resumable_chunk(
+        &self,
+        session: &Url,
+        chunk: &[u8],
+        offset: usize,
+        size: usize,
+    ) -> Result<Option<usize>, cloud_storage::Error> {
+        let content_range = format!(
+            "bytes {}-{}/{}",
+            offset,
+            (offset + chunk.len()).saturating_sub(1),
+            size
+        );
+        let request = self.with_encryption_key(
+            self.client
+                .put(session.clone())
+                .header(reqwest::header::CONTENT_RANGE, content_range)
+                .body(chunk.to_vec()),
+        );
+        Self::resumable_upload_progress(self.send(request).await?).await
+    }
+
+    /// Asks how much of the upload was persisted, e.g., after a chunk failed midway. Returns the
+    /// number of bytes persisted so far, or `None` if the upload is complete.
+    async fn query_resumable_upload(
+        &self,
+        session: &Url,
+        size: usize,
+    ) -> Result<Option<usize>, cloud_storage::Error> {
+        let request = self.with_encryption_key(
+            self.client
+                .put(session.clone())
+                .header(reqwest::header::CONTENT_RANGE, format!("bytes */{}", size))
+                .header(reqwest::header::CONTENT_LENGTH, 0),
+        );
+        Self::resumable_upload_progress(self.send(request).await?).await
+    }
+
+    async fn resumable_upload_progress(
+        response: reqwest::Response,
+    ) -> Result<Option<usize>, cloud_storage::Error> {
+        if response.status().is_success() {
+            return Ok(None);
+        }
+        if response.status().as_u16() != RESUME_INCOMPLETE_STATUS {
+            return Err(Self::error_from_response(response, None).await);
+        }
+        // The persisted bytes are reported as `bytes=0-<last byte>`; none are without the header.
+        match response.headers().get(reqwest::header::RANGE) {
+            Some(range) => range
+                .to_str()
+                .ok()
+                .and_then(|range| range.strip_prefix("bytes=0-"))
+                .and_then(|last_byte| last_byte.parse::<usize>().ok())
+                .map(|last_byte| Some(last_byte + 1))
+                .ok_or_else(|| {
+                    cloud_storage::Error::Other(format!(
+                        "Invalid range of a resumable upload: {:?}",
+                        range
+                    ))
+                }),
+            None => Ok(Some(0)),
+        }
+    }
+
+    async fn delete(&self, bucket_name: &str, key: &str) -> Result<(), cloud_storage::Error> {
+        let url = self.build_url(&["storage", "v1", "b", bucket_name, "o", key]);
+        let response = self.send(self.client.delete(url)).await?;
+        if !response.status().is_success() {
+            return Err(Self::error_from_response(response, Some((bucket_name, key))).await);
+        }
+        Ok(())
+    }
+}
+
+/// Credentials a `GcsEndpoint` authenticates its requests with.
+#[derive(Clone)]
+enum GcsCredentials {
+    Anonymous,
+    // A service account key file, at the path in `SERVICE_ACCOUNT` or
+    // `GOOGLE_APPLICATION_CREDENTIALS`, as the `cloud_storage` client reads it.
+    ServiceAccountKey(Arc<cloud_storage::Token>),
+    MetadataServer(Arc<MetadataServerToken>),
+    AccessToken(String),
+}
+
+impl GcsCredentials {
+    fn new(source: &GcsCredentialSource) -> Self {
+        match source {
+            GcsCredentialSource::KeyFile => {
+                Self::ServiceAccountKey(Arc::new(cloud_storage::Token::default()))
+            },
+            GcsCredentialSource::ApplicationDefault
+                if env::var_os(APPLICATION_CREDENTIALS_ENV_VAR).is_some() =>
+            {
+                Self::ServiceAccountKey(Arc::new(cloud_storage::Token::default()))
+            },
+            GcsCredentialSource::ApplicationDefault => {
+                Self::MetadataServer(Arc::new(MetadataServerToken::default()))
+            },
+            GcsCredentialSource::AccessToken(token) => Self::AccessToken(token.clone()),
+        }
+    }
+
+    fn service_account_key_description() -> String {
+        let path = env::var(SERVICE_ACCOUNT_ENV_VAR)
+            .or_else(|_| env::var(APPLICATION_CREDENTIALS_ENV_VAR))
+            .unwrap_or_default();
+        format!("the service account key file {:?}", path)
+    }
+
+    fn description(&self) -> String {
+        match self {
+            Self::Anonymous => "anonymous credentials".to_string(),
+            Self::ServiceAccountKey(_) => Self::service_account_key_description(),
+            Self::MetadataServer(_) => {
+                "the application default credentials of the metadata server".to_string()
+            },
+            Self::AccessToken(_) => "the access token of gcs_credential_source".to_string(),
+        }
+    }
+
+    /// Returns the token to send as bearer, none for anonymous requests.
+    async fn access_token(
+        &self,
+        client: &reqwest::Client,
+    ) -> Result<Option<String>, cloud_storage::Error> {
+        Ok(match self {
+            Self::Anonymous => None,
+            Self::ServiceAccountKey(token) => Some(token.get(client).await?),
+            Self::MetadataServer(token) => Some(token.get(client).await?),
+            Self::AccessToken(token) => Some(token.clone()),
+        })
+    }
+}
+
+/// Access token of the service account of the instance, e.g., the Google service account a
+/// Kubernetes service account is bound to with GKE workload identity. It is fetched from the
+/// metadata server and cached until shortly before it expires.
+#[derive(Default)]
+struct MetadataServerToken {
+    // The token and when to refresh it.
+    cached: tokio::sync::Mutex<Option<(String, Instant)>>,
+}
+
+#[derive(Deserialize)]
+struct MetadataServerTokenResponse {
+    access_token: String,
+    expires_in: u64,
+}
+
+impl MetadataServerToken {
+    async fn get(&self, client: &reqwest::Client) -> Result<String, cloud_storage::Error> {
+        let mut cached = self.cached.lock().await;
+        if let Some((token, refresh_time)) = &*cached {
+            if Instant::now() < *refresh_time {
+                return Ok(token.clone());
+            }
+        }
+        let host =
+            env::var(METADATA_HOST_ENV_VAR).unwrap_or_else(|_| DEFAULT_METADATA_HOST.to_string());
+        let response = client
+            .get(format!(
+                "http://{}/computeMetadata/v1/instance/service-accounts/default/token",
+                host
+            ))
+            .header("Metadata-Flavor", "Google")
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            return Err(GcsEndpoint::error_from_response(response, None).await);
+        }
+        let response: MetadataServerTokenResponse = response.json().await?;
+        let refresh_time = Instant::now()
+            + Duration::from_secs(response.expires_in)
+                .saturating_sub(METADATA_TOKEN_REFRESH_MARGIN);
+        *cached = Some((response.access_token.clone(), refresh_time));
+        Ok(response.access_token)
+    }
+}
+
+pub(crate) fn build_backoff(retry_config: &GcsRetryConfig) -> backoff::ExponentialBackoff {
+    backoff::ExponentialBackoff {
+        initial_interval: Duration::from_millis(retry_config.initial_backoff_in_millis),
+        current_interval: Duration::from_millis(retry_config.initial_backoff_in_millis),
+        max_interval: Duration::from_millis(retry_config.max_backoff_in_millis),
+        multiplier: 2.0,
+        // The number of attempts bounds the retries instead.
+        max_elapsed_time: None,
+        ..Default::default()
+    }
+}
+
+/// Points a request GCS rejected for a missing or wrong encryption key, e.g., a read of an object
+/// written with a customer-supplied key, or a write to a bucket that requires a KMS key, at the
+/// settings that provide the key.
+fn explain_encryption_key_error(err: cloud_storage::Error) -> cloud_storage::Error {
+    match &err {
+        cloud_storage::Error::Google(response)
+            if matches!(response.error.code, 400 | 403 | 412)
+                && ["encryption key", "kms"]
+                    .iter()
+                    .any(|pattern| response.error.message.to_lowercase().contains(pattern)) =>
+        {
+            cloud_storage::Error::Other(format!(
+                "GCS requires an encryption key the operator doesn't have; set \
+                 gcs_customer_supplied_encryption_key_path or gcs_kms_key_name to the key of the \
+                 bucket: {}",
+                response.error.message
+            ))
+        },
+        _ => err,
+    }
+}
+
+/// Names the credential source of a request GCS rejected as unauthenticated or unauthorized, so
+/// that, e.g., a service account missing from a workload identity binding is told apart from a
+/// missing key file.
+fn explain_credentials_error(
+    err: cloud_storage::Error,
+    credentials_description: &str,
+) -> cloud_storage::Error {
+    match &err {
+        cloud_storage::Error::Google(response) if matches!(response.error.code, 401 | 403) => {
+            cloud_storage::Error::Other(format!(
+                "GCS rejected the credentials from {}: {}",
+                credentials_description, response.error.message
+            ))
+        },
+        _ => err,
+    }
+}
+
+/// Whether a failed GCS request may succeed if it is sent again, i.e., it timed out, was
+/// throttled, or hit a server error.
+fn is_retryable_gcs_error(err: &cloud_storage::Error) -> bool {
+    fn is_retryable_status(code: u16) -> bool {
+        code == 408 || code == 429 || (500..600).contains(&code)
+    }
+    match err {
+        cloud_storage::Error::Google(response) => is_retryable_status(response.error.code),
+        cloud_storage::Error::Reqwest(err) => err
+            .status()
+            .map_or(true, |status| is_retryable_status(status.as_u16())),
+        cloud_storage::Error::Other(message) => !NON_RETRYABLE_ERROR_MESSAGES
+            .iter()
+            .any(|non_retryable| message.contains(non_retryable)),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compression_util::FILE_ENTRY_TRANSACTION_COUNT;
+    use std::{
+        collections::HashMap,
+        sync::{
+            atomic::{AtomicU32, Ordering},
+            Mutex,
+        },
+    };
+    use warp::Filter;
+
+    fn google_error(code: u16) -> cloud_storage::Error {
+        cloud_storage::Error::Google(cloud_storage::GoogleErrorResponse {
+            error: cloud_storage::ErrorList {
+                errors: vec![],
+                code,
+                message: "error".to_string(),
+            },
+        })
+    }
+
+    fn operator(max_attempts: u32) -> GcsFileStoreOperator {
+        GcsFileStoreOperator::new("bucket".to_string(), None, false, None).with_retry_config(
+            GcsRetryConfig {
+                max_attempts,
+                initial_backoff_in_millis: 1,
+                max_backoff_in_millis: 2,
+                request_timeout_in_secs: 1,
+            },
+        )
+    }
+
+    #[test]
+    fn only_transient_gcs_errors_are_retryable() {
+        assert!(is_retryable_gcs_error(&google_error(503)));
+        assert!(is_retryable_gcs_error(&google_error(429)));
+        assert!(is_retryable_gcs_error(&google_error(408)));
+        assert!(!is_retryable_gcs_error(&google_error(403)));
+        assert!(!is_retryable_gcs_error(&google_error(404)));
+        assert!(is_retryable_gcs_error(&cloud_storage::Error::Other(
+            "Backend Error".to_string()
+        )));
+        assert!(!is_retryable_gcs_error(&cloud_storage::Error::Other(
+            "No such object: bucket/metadata.json".to_string()
+        )));
+        assert!(!is_retryable_gcs_error(&cloud_storage::Error::Other(
+            "The specified bucket does not exist.".to_string()
+        )));
+    }
+
+    #[tokio::test]
+    async fn transient_errors_are_retried_until_success() {
+        let operator = operator(5);
+        let attempts = AtomicU32::new(0);
+        let retries_before = GCS_REQUEST_RETRIES
+            .with_label_values(&["test_transient"])
+            .get();
+        let result = operator
+            .with_retries("test_transient", "key", || async {
+                if attempts.fetch_add(1, Ordering::SeqCst) < 2 {
+                    Err(google_error(503))
+                } else {
+                    Ok(42)
+                }
+            })
+            .await;
+        assert_eq!(result.unwrap(), 42);
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+        assert_eq!(
+            GCS_REQUEST_RETRIES
+                .with_label_values(&["test_transient"])
+                .get()
+                - retries_before,
+            2
+        );
+    }
+
+    #[tokio::test]
+    async fn retries_stop_at_max_attempts_or_non_retryable_errors() {
+        let operator = operator(3);
+        let attempts = AtomicU32::new(0);
+        let result: Result<(), _> = operator
+            .with_retries("test_exhausted", "key", || async {
+                attempts.fetch_add(1, Ordering::SeqCst);
+                Err(google_error(500))
+            })
+            .await;
+        assert!(result.is_err());
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+
+        let attempts = AtomicU32::new(0);
+        let result: Result<(), _> = operator
+            .with_retries("test_forbidden", "key", || async {
+                attempts.fetch_add(1, Ordering::SeqCst);
+                Err(google_error(403))
+            })
+            .await;
+        assert!(result.is_err());
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn attempts_that_time_out_are_retried() {
+        let operator = operator(2);
+        let attempts = AtomicU32::new(0);
+        let result = operator
+            .with_retries("test_timeout", "key", || async {
+                if attempts.fetch_add(1, Ordering::SeqCst) == 0 {
+                    tokio::time::sleep(Duration::from_secs(5)).await;
+                }
+                Ok::<_, cloud_storage::Error>(())
+            })
+            .await;
+        assert!(result.is_ok());
+        assert_eq!(attempts.load(Ordering::SeqCst), 2);
+    }
+
+    fn decode_path_segment(segment: &str) -> String {
+        url::form_urlencoded::parse(format!("segment={}", segment).as_bytes())
+            .next()
+            .map(|(_, value)| value.into_owned())
+            .unwrap_or_default()
+    }
+
+    /// Splits the body of a multipart upload into the object resource and the media.
+    fn split_multipart_upload(content_type: &str, body: &[u8]) -> (serde_json::Value, Vec<u8>) {
+        let find = |haystack: &[u8], needle: &[u8]| {
+            haystack
+                .windows(needle.len())
+                .position(|window| window == needle)
+                .unwrap()
+        };
+        let boundary = content_type.split("boundary=").nth(1).unwrap();
+        let separator = format!("\r\n--{}\r\n", boundary);
+        let resource_start = find(body, b"\r\n\r\n") + 4;
+        let resource_end = find(body, separator.as_bytes());
+        let resource = serde_json::from_slice(&body[resource_start..resource_end]).unwrap();
+        let media = &body[resource_end + separator.len()..];
+        let media_start = find(media, b"\r\n\r\n") + 4;
+        let media_end = media.len() - format!("\r\n--{}--\r\n", boundary).len();
+        (resource, media[media_start..media_end].to_vec())
+    }
+
+    /// Serves the parts of the GCS JSON API used by the operator, for a single bucket. The
+    /// `failing_chunk`-th chunk of resumable uploads, counting from 0, fails once after half of it
+    /// is persisted. Objects written with a customer-supplied key can only be read with it. Single
+    /// request uploads honor `ifGenerationMatch`. Custom metadata of multipart and resumable
+    /// uploads is reported with the object.
+    fn start_fake_gcs_server(bucket_name: &'static str, failing_chunk: Option<usize>) -> String {
+        let objects: Arc<Mutex<HashMap<String, Vec<u8>>>> = Arc::default();
+        // Generation, encryption and custom metadata of the objects, as GCS reports it.
+        let object_metadata: Arc<Mutex<HashMap<String, serde_json::Value>>> = Arc::default();
+        // Resumable upload sessions: object name, total size, bytes persisted so far and
+        // encryption metadata.
+        let sessions: Arc<Mutex<Vec<(String, usize, Vec<u8>, serde_json::Value)>>> = Arc::default();
+        let chunk_count = Arc::new(AtomicU32::new(0));
+        let next_generation = Arc::new(AtomicU32::new(1));
+        let routes = warp::method()
+            .and(warp::path::full())
+            .and(warp::query::<HashMap<String, String>>())
+            .and(warp::header::headers_cloned())
+            .and(warp::body::bytes())
+            .map(
+                move |method: warp::http::Method,
+                      path: warp::path::FullPath,
+                      query: HashMap<String, String>,
+                      headers: warp::http::HeaderMap,
+                      body: warp::hyper::body::Bytes| {
+                    let segments: Vec<String> = path
+                        .as_str()
+                        .trim_start_matches('/')
+                        .split('/')
+                        .map(decode_path_segment)
+                        .collect();
+                    let segments: Vec<&str> = segments.iter().map(String::as_str).collect();
+                    let header = |name: &str| {
+                        headers
+                            .get(name)
+                            .and_then(|value| value.to_str().ok())
+                            .unwrap_or_default()
+                            .to_string()
+                    };
+                    let encryption_metadata = || {
+                        let mut metadata = serde_json::json!({});
+                        if let Some(key_name) = query.get("kmsKeyName") {
+                            metadata["kmsKeyName"] = serde_json::json!(key_name);
+                        }
+                        if !header("x-goog-encryption-key-sha256").is_empty() {
+                            metadata["customerEncryption"] = serde_json::json!({
+                                "encryptionAlgorithm": header("x-goog-encryption-algorithm"),
+                                "keySha256": header("x-goog-encryption-key-sha256"),
+                            });
+                        }
+                        metadata["generation"] = serde_json::json!(next_generation
+                            .fetch_add(1, Ordering::SeqCst)
+                            .to_string());
+                        metadata
+                    };
+                    let mut objects = objects.lock().unwrap();
+                    let mut object_metadata = object_metadata.lock().unwrap();
+                    let mut sessions = sessions.lock().unwrap();
+                    let mut response_header = None;
+                    let (status, body) = match (method.as_str(), segments.as_slice()) {
+                        ("GET", ["storage", "v1", "b", bucket]) if *bucket == bucket_name => {
+                            (200, b"{}".to_vec())
+                        },
+                        ("GET", ["storage", "v1", "b", bucket, "o", key])
+                            if *bucket == bucket_name
+                                && query.get("alt").map(String::as_str) == Some("media") =>
+                        {
+                            let key_sha256 = object_metadata
+                                .get(*key)
+                                .and_then(|metadata| {
+                                    metadata["customerEncryption"]["keySha256"].as_str()
+                                })
+                                .map(str::to_string);
+                            match objects.get(*key) {
+                                Some(_)
+                                    if key_sha256.map_or(false, |key_sha256| {
+                                        key_sha256 != header("x-goog-encryption-key-sha256")
+                                    }) =>
+                                {
+                                    (
+                                        400,
+                                        b"The target object is encrypted by a customer-supplied \
+                                          encryption key."
+                                            .to_vec(),
+                                    )
+                                },
+                                Some(bytes) => {
+                                    response_header = Some((
+                                        "x-goog-generation",
+                                        object_metadata[*key]["generation"]
+                                            .as_str()
+                                            .unwrap()
+                                            .to_string(),
+                                    ));
+                                    (200, bytes.clone())
+                                },
+                                None => (404, b"Not Found".to_vec()),
+                            }
+                        },
+                        ("GET", ["storage", "v1", "b", bucket, "o", key])
+                            if *bucket == bucket_name =>
+                        {
+                            match object_metadata.get(*key) {
+                                Some(metadata) => (200, metadata.to_string().into_bytes()),
+                                None => (404, b"Not Found".to_vec()),
+                            }
+                        },
+                        ("POST", ["upload", "storage", "v1", "b", bucket, "o"])
+                            if *bucket == bucket_name
+                                && query.get("uploadType").map(String::as_str)
+                                    == Some("resumable") =>
+                        {
+                            let size = header("x-upload-content-length").parse().unwrap();
+                            let mut metadata = encryption_metadata();
+                            if !body.is_empty() {
+                                let resource: serde_json::Value =
+                                    serde_json::from_slice(&body).unwrap();
+                                metadata["metadata"] = resource["metadata"].clone();
+                            }
+                            sessions.push((query["name"].clone(), size, vec![], metadata));
+                            response_header = Some((
+                                "location",
+                                format!(
+                                    "http://{}/upload/resumable/{}",
+                                    header("host"),
+                                    sessions.len() - 1
+                                ),
+                            ));
+                            (200, vec![])
+                        },
+                        ("POST", ["upload", "storage", "v1", "b", bucket, "o"])
+                            if *bucket == bucket_name =>
+                        {
+                            let generation = object_metadata
+                                .get(&query["name"])
+                                .map_or("0", |metadata| metadata["generation"].as_str().unwrap())
+                                .to_string();
+                            match query.get("ifGenerationMatch") {
+                                Some(expected) if *expected != generation => {
+                                    (412, b"Precondition Failed".to_vec())
+                                },
+                                _ => {
+                                    let mut metadata = encryption_metadata();
+                                    let media = match query.get("uploadType").map(String::as_str) {
+                                        Some("multipart") => {
+                                            let (resource, media) = split_multipart_upload(
+                                                &header("content-type"),
+                                                &body,
+                                            );
+                                            metadata["metadata"] = resource["metadata"].clone();
+                                            media
+                                        },
+                                        _ => body.to_vec(),
+                                    };
+                                    objects.insert(query["name"].clone(), media);
+                                    object_metadata.insert(query["name"].clone(), metadata.clone());
+                                    (200, metadata.to_string().into_bytes())
+                                },
+                            }
+                        },
+                        ("PUT", ["upload", "resumable", session]) => {
+                            let (name, size, persisted, metadata) =
+                                &mut sessions[session.parse::<usize>().unwrap()];
+                            let content_range = header("content-range");
+                            let range = content_range
+                                .strip_prefix("bytes ")
+                                .unwrap()
+                                .split('/')
+                                .next()
+                                .unwrap();
+                            let status = if range == "*" {
+                                // A status query.
+                                None
+                            } else if range.split('-').next().unwrap().parse::<usize>().unwrap()
+                                != persisted.len()
+                            {
+                                Some(400)
+                            } else if failing_chunk
+                                == Some(chunk_count.fetch_add(1, Ordering::SeqCst) as usize)
+                            {
+                                persisted.extend_from_slice(&body[..body.len() / 2]);
+                                Some(503)
+                            } else {
+                                persisted.extend_from_slice(&body);
+                                None
+                            };
+                            match status {
+                                Some(status) => (status, vec![]),
+                                None if persisted.len() == *size => {
+                                    objects.insert(name.clone(), persisted.clone());
+                                    object_metadata.insert(name.clone(), metadata.clone());
+                                    (200, b"{}".to_vec())
+                                },
+                                None => {
+                                    if !persisted.is_empty() {
+                                        response_header = Some((
+                                            "range",
+                                            format!("bytes=0-{}", persisted.len() - 1),
+                                        ));
+                                    }
+                                    (RESUME_INCOMPLETE_STATUS, vec![])
+                                },
+                            }
+                        },
+                        ("DELETE", ["storage", "v1", "b", bucket, "o", key])
+                            if *bucket == bucket_name =>
+                        {
+                            object_metadata.remove(*key);
+                            match objects.remove(*key) {
+                                Some(_) => (204, vec![]),
+                                None => (404, b"Not Found".to_vec()),
+                            }
+                        },
+                        _ => (404, b"Not Found".to_vec()),
+                    };
+                    let mut response = warp::http::Response::builder().status(status);
+                    if let Some((name, value)) = response_header {
+                        response = response.header(name, value);
+                    }
+                    response.body(body).unwrap()
+                },
+            );
+        let (address, server) = warp::serve(routes).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+        format!("http://{}", address)
+    }
+
+    #[tokio::test]
+    async fn operator_works_against_a_custom_endpoint() {
+        let endpoint = start_fake_gcs_server("bucket", None);
+        let mut operator = GcsFileStoreOperator::new("bucket".to_string(), None, true, None)
+            .with_endpoint(Some(endpoint.clone()), true);
+        operator.verify_storage_bucket_existence().await;
+
+        assert!(operator.get_file_store_metadata().await.is_none());
+        operator
+            .update_file_store_metadata_internal(ChainId(1), 0)
+            .await
+            .unwrap();
+        assert_eq!(operator.get_file_store_metadata().await.unwrap().version, 0);
+
+        let transactions: Vec<Transaction> = (0..FILE_ENTRY_TRANSACTION_COUNT)
+            .map(|version| Transaction {
+                version,
+                ..Transaction::default()
+            })
+            .collect();
+        operator
+            .upload_transaction_batch(ChainId(1), transactions.clone())
+            .await
+            .unwrap();
+        assert_eq!(operator.get_transactions(0, 1).await.unwrap(), transactions);
+        assert_eq!(operator.verify_blob_digest(0).await.unwrap(), Some(true));
+
+        operator.delete_blob(0).await.unwrap();
+        assert!(operator.get_raw_file(0).await.is_err());
+        assert_eq!(operator.get_blob_digest(0).await.unwrap(), None);
+
+        // Uploads to a missing bucket fail right away, naming the object.
+        let other_bucket = GcsFileStoreOperator::new("other".to_string(), None, true, None)
+            .with_endpoint(Some(endpoint), true);
+        let err = other_bucket
+            .create_object("test_missing_bucket", vec![1], "key", TEXT_FILE_TYPE)
+            .await
+            .unwrap_err();
+        assert!(format!("{:#}", err).contains("gs://other/key"));
+    }
+
+    #[tokio::test]
+    async fn ranges_landing_mid_blob_are_trimmed() {
+        let endpoint = start_fake_gcs_server("bucket", None);
+        let mut operator = GcsFileStoreOperator::new("bucket".to_string(), None, true, None)
+            .with_endpoint(Some(endpoint), true)
+            .with_retry_config(GcsRetryConfig {
+                max_attempts: 1,
+                ..GcsRetryConfig::default()
+            });
+        let transactions: Vec<Transaction> = (0..2 * FILE_ENTRY_TRANSACTION_COUNT)
+            .map(|version| Transaction {
+                version,
+                ..Transaction::default()
+            })
+            .collect();
+        for blob in transactions.chunks(FILE_ENTRY_TRANSACTION_COUNT as usize) {
+            operator
+                .upload_transaction_batch(ChainId(1), blob.to_vec())
+                .await
+                .unwrap();
+        }
+        assert_eq!(
+            operator
+                .get_transactions_in_range_concurrently(990, 20, 0, 2)
+                .await
+                .unwrap(),
+            transactions[990..1_010]
+        );
+        assert_eq!(
+            operator
+                .get_transactions_in_range(1_999, 1, 0)
+                .await
+                .unwrap(),
+            transactions[1_999..]
+        );
+
+        operator.delete_blob(1_000).await.unwrap();
+        let err = operator
+            .get_transactions_in_range_concurrently(990, 20, 0, 2)
+            .await
+            .unwrap_err();
+        assert!(format!("{:#}", err).contains("Failed to read the blob at 1000."));
+    }
+
+    #[tokio::test]
+    async fn streamed_batches_are_uploaded() {
+        let endpoint = start_fake_gcs_server("bucket", None);
+        let mut operator = GcsFileStoreOperator::new("bucket".to_string(), None, true, None)
+            .with_endpoint(Some(endpoint), true);
+        let transactions: Vec<Transaction> = (0..FILE_ENTRY_TRANSACTION_COUNT)
+            .map(|version| Transaction {
+                version,
+                ..Transaction::default()
+            })
+            .collect();
+        operator
+            .upload_transaction_stream(ChainId(1), Box::new(transactions.iter().cloned()))
+            .await
+            .unwrap();
+        assert_eq!(operator.get_transactions(0, 0).await.unwrap(), transactions);
+        assert_eq!(operator.verify_blob_digest(0).await.unwrap(), Some(true));
+
+        let mut conflicting_transactions = transactions.clone();
+        conflicting_transactions[10].epoch = 1;
+        let err = operator
+            .upload_transaction_stream(ChainId(1), Box::new(conflicting_transactions.into_iter()))
+            .await
+            .unwrap_err();
+        assert!(err
+            .downcast_ref::<crate::file_store_operator::BlobConflictError>()
+            .is_some());
+    }
+
+    #[tokio::test]
+    async fn progress_is_only_written_over_the_generation_last_seen() {
+        let endpoint = start_fake_gcs_server("bucket", None);
+        let mut operator = GcsFileStoreOperator::new("bucket".to_string(), None, true, None)
+            .with_endpoint(Some(endpoint.clone()), true);
+        assert_eq!(operator.get_processing_progress().await.unwrap(), None);
+        let progress = FileStoreProgress::new(ChainId(1), 1_000);
+        operator
+            .update_processing_progress(progress.clone())
+            .await
+            .unwrap();
+        assert_eq!(
+            operator.get_processing_progress().await.unwrap(),
+            Some(progress)
+        );
+
+        // Another writer updates the progress in between.
+        let mut other_writer = GcsFileStoreOperator::new("bucket".to_string(), None, true, None)
+            .with_endpoint(Some(endpoint), true);
+        other_writer
+            .update_processing_progress(FileStoreProgress::new(ChainId(1), 5_000))
+            .await
+            .unwrap();
+        let err = operator
+            .update_processing_progress(FileStoreProgress::new(ChainId(1), 2_000))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("updated by another writer"));
+        assert_eq!(
+            operator
+                .get_processing_progress()
+                .await
+                .unwrap()
+                .unwrap()
+                .version,
+            5_000
+        );
+        // Once the generation is read again, writes go through.
+        operator
+            .update_processing_progress(FileStoreProgress::new(ChainId(1), 6_000))
+            .await
+            .unwrap();
+        assert_eq!(
+            other_writer
+                .get_processing_progress()
+                .await
+                .unwrap()
+                .unwrap()
+                .version,
+            6_000
+        );
+    }
+
+    #[tokio::test]
+    async fn resumable_upload_resumes_after_a_failed_chunk() {
+        let endpoint = start_fake_gcs_server("bucket", Some(1));
+        let mut operator = GcsFileStoreOperator::new("bucket".to_string(), None, true, None)
+            .with_endpoint(Some(endpoint), true)
+            .with_retry_config(GcsRetryConfig {
+                initial_backoff_in_millis: 1,
+                ..GcsRetryConfig::default()
+            })
+            .with_resumable_upload_threshold(Some(1_000));
+        operator.resumable_upload_chunk_size = 256 * 1024;
+        let retries = GCS_REQUEST_RETRIES
+            .with_label_values(&["test_resumable_upload"])
+            .get();
+
+        // Three chunks; the second one fails halfway through, and the upload resumes from there.
+        let bytes: Vec<u8> = (0..700_000).map(|i| (i % 251) as u8).collect();
+        operator
+            .create_object(
+                "test_resumable_upload",
+                bytes.clone(),
+                "key",
+                TEXT_FILE_TYPE,
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            operator
+                .download_object("test_resumable_upload", "key")
+                .await
+                .unwrap(),
+            bytes
+        );
+        assert_eq!(
+            GCS_REQUEST_RETRIES
+                .with_label_values(&["test_resumable_upload"])
+                .get(),
+            retries + 1
+        );
+
+        // Objects up to the threshold are uploaded in a single request.
+        operator
+            .create_object(
+                "test_resumable_upload",
+                vec![1; 1_000],
+                "small",
+                TEXT_FILE_TYPE,
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            operator
+                .download_object("test_resumable_upload", "small")
+                .await
+                .unwrap(),
+            vec![1; 1_000]
+        );
+    }
+
+    #[tokio::test]
+    async fn server_side_encryption_keys_are_sent_with_writes_and_reads() {
+        let endpoint = start_fake_gcs_server("bucket", None);
+        let object_metadata = |key: &'static str| {
+            let url = format!("{}/storage/v1/b/bucket/o/{}", endpoint, key);
+            async move {
+                reqwest::get(url)
+                    .await
+                    .unwrap()
+                    .json::<serde_json::Value>()
+                    .await
+                    .unwrap()
+            }
+        };
+        let key_file = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(key_file.path(), hex::encode([7u8; 32])).unwrap();
+        let csek_operator = GcsFileStoreOperator::new("bucket".to_string(), None, true, None)
+            .with_endpoint(Some(endpoint.clone()), true)
+            .with_server_side_encryption(Some(
+                GcsServerSideEncryption::customer_supplied_from_key_file(key_file.path()).unwrap(),
+            ))
+            .with_resumable_upload_threshold(Some(1_000));
+
+        // Both single request and resumable uploads carry the key.
+        csek_operator
+            .create_object("test_csek", vec![1; 10], "small", TEXT_FILE_TYPE)
+            .await
+            .unwrap();
+        csek_operator
+            .create_object("test_csek", vec![2; 2_000], "large", TEXT_FILE_TYPE)
+            .await
+            .unwrap();
+        let key_sha256 = base64::encode(Sha256::digest(&[7u8; 32]));
+        for key in ["small", "large"] {
+            let metadata = object_metadata(key).await;
+            assert_eq!(metadata["customerEncryption"]["keySha256"], key_sha256);
+            assert_eq!(
+                metadata["customerEncryption"]["encryptionAlgorithm"],
+                "AES256"
+            );
+        }
+        assert_eq!(
+            csek_operator
+                .download_object("test_csek", "large")
+                .await
+                .unwrap(),
+            vec![2; 2_000]
+        );
+
+        // Without the key, reads fail right away and name the settings that provide it.
+        let operator_without_key =
+            GcsFileStoreOperator::new("bucket".to_string(), None, true, None)
+                .with_endpoint(Some(endpoint.clone()), true);
+        let err = operator_without_key
+            .download_object("test_csek", "small")
+            .await
+            .unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("gcs_customer_supplied_encryption_key_path"));
+
+        // Writes name the KMS key; reads need no key.
+        let key_name = "projects/p/locations/l/keyRings/r/cryptoKeys/k";
+        let kms_operator = GcsFileStoreOperator::new("bucket".to_string(), None, true, None)
+            .with_endpoint(Some(endpoint.clone()), true)
+            .with_server_side_encryption(Some(GcsServerSideEncryption::Kms {
+                key_name: key_name.to_string(),
+            }));
+        kms_operator
+            .create_object("test_kms", vec![3; 10], "kms", TEXT_FILE_TYPE)
+            .await
+            .unwrap();
+        assert_eq!(object_metadata("kms").await["kmsKeyName"], key_name);
+        assert_eq!(
+            operator_without_key
+                .download_object("test_kms", "kms")
+                .await
+                .unwrap(),
+            vec![3; 10]
+        );
+    }
+
+    #[tokio::test]
+    async fn blob_metadata_is_set_on_writes_and_ignored_on_reads() {
+        let endpoint = start_fake_gcs_server("bucket", None);
+        let object_metadata = |key: String| {
+            let url = format!("{}/storage/v1/b/bucket/o/{}", endpoint, key);
+            async move {
+                reqwest::get(url)
+                    .await
+                    .unwrap()
+                    .json::<serde_json::Value>()
+                    .await
+                    .unwrap()
+            }
+        };
+        let blob_metadata = BTreeMap::from([
+            ("retention-class".to_string(), "short-lived".to_string()),
+            ("expires-after-days".to_string(), "7".to_string()),
+        ]);
+        // Blobs are sent as resumable uploads, digests in a single request.
+        let mut operator = GcsFileStoreOperator::new("bucket".to_string(), None, true, None)
+            .with_endpoint(Some(endpoint.clone()), true)
+            .with_resumable_upload_threshold(Some(100))
+            .with_blob_metadata(blob_metadata.clone());
+        operator
+            .update_file_store_metadata_internal(ChainId(1), 0)
+            .await
+            .unwrap();
+        let transactions: Vec<Transaction> = (0..FILE_ENTRY_TRANSACTION_COUNT)
+            .map(|version| Transaction {
+                version,
+                ..Transaction::default()
+            })
+            .collect();
+        operator
+            .upload_transaction_batch(ChainId(1), transactions.clone())
+            .await
+            .unwrap();
+
+        let blob_key = operator.blob_key(0).await.unwrap();
+        let digest_key = operator.blob_digest_key(0).await.unwrap();
+        for key in [blob_key, digest_key] {
+            assert_eq!(
+                object_metadata(key).await["metadata"],
+                serde_json::json!(blob_metadata)
+            );
+        }
+        assert!(object_metadata(METADATA_FILE_NAME.to_string()).await["metadata"].is_null());
+
+        // Reads are the same, whether the reader sets the metadata or not.
+        let reader = GcsFileStoreOperator::new("bucket".to_string(), None, true, None)
+            .with_endpoint(Some(endpoint.clone()), true);
+        for operator in [&operator, &reader] {
+            assert_eq!(operator.get_transactions(0, 1).await.unwrap(), transactions);
+            assert_eq!(operator.verify_blob_digest(0).await.unwrap(), Some(true));
+        }
+    }
+
+    /// Starts a server that answers bucket reads authenticated with `Bearer <token>`, and 401
+    /// otherwise, and hands out `token` as a metadata server; returns its address and the number
+    /// of tokens handed out.
+    fn start_fake_authenticating_server(token: &'static str) -> (String, Arc<AtomicU32>) {
+        let tokens_handed_out = Arc::new(AtomicU32::new(0));
+        let counter = tokens_handed_out.clone();
+        let routes = warp::path::full().and(warp::header::headers_cloned()).map(
+            move |path: warp::path::FullPath, headers: warp::http::HeaderMap| {
+                let header = |name: &str| {
+                    headers
+                        .get(name)
+                        .and_then(|value| value.to_str().ok())
+                        .unwrap_or_default()
+                        .to_string()
+                };
+                let (status, body) = if path.as_str().starts_with("/computeMetadata/") {
+                    if header("metadata-flavor") == "Google" {
+                        counter.fetch_add(1, Ordering::SeqCst);
+                        let body = serde_json::json!({
+                            "access_token": token,
+                            "expires_in": 3600,
+                            "token_type": "Bearer",
+                        });
+                        (200, body.to_string())
+                    } else {
+                        (403, "Missing Metadata-Flavor".to_string())
+                    }
+                } else if header("authorization") == format!("Bearer {}", token) {
+                    (200, "{}".to_string())
+                } else {
+                    (401, "Invalid Credentials".to_string())
+                };
+                warp::http::Response::builder()
+                    .status(status)
+                    .body(body)
+                    .unwrap()
+            },
+        );
+        let (address, server) = warp::serve(routes).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+        (format!("http://{}", address), tokens_handed_out)
+    }
+
+    async fn read_bucket(operator: &GcsFileStoreOperator) -> Result<(), cloud_storage::Error> {
+        operator
+            .with_retries("read_bucket", "", || {
+                operator.json_api_endpoint().read_bucket("bucket")
+            })
+            .await
+    }
+
+    #[tokio::test]
+    async fn access_tokens_are_sent_as_bearer() {
+        let (endpoint, _) = start_fake_authenticating_server("token");
+        let operator = GcsFileStoreOperator::new("bucket".to_string(), None, false, None)
+            .with_endpoint(Some(endpoint.clone()), false)
+            .with_credential_source(GcsCredentialSource::AccessToken("token".to_string()));
+        read_bucket(&operator).await.unwrap();
+
+        // A rejected token is reported with its source.
+        let operator = GcsFileStoreOperator::new("bucket".to_string(), None, false, None)
+            .with_endpoint(Some(endpoint), false)
+            .with_credential_source(GcsCredentialSource::AccessToken("expired".to_string()));
+        let err = read_bucket(&operator).await.unwrap_err().to_string();
+        assert!(
+            err.contains(
+                "GCS rejected the credentials from the access token of gcs_credential_source"
+            ),
+            "{}",
+            err
+        );
+        assert!(!err.contains("expired"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn application_default_credentials_come_from_the_metadata_server() {
+        let (endpoint, tokens_handed_out) = start_fake_authenticating_server("workload-token");
+        env::set_var(
+            METADATA_HOST_ENV_VAR,
+            endpoint.trim_start_matches("http://"),
+        );
+        let operator = GcsFileStoreOperator::new("bucket".to_string(), None, false, None)
+            .with_endpoint(Some(endpoint), false)
+            .with_credential_source(GcsCredentialSource::ApplicationDefault);
+        assert!(matches!(
+            operator.endpoint.as_ref().unwrap().credentials,
+            GcsCredentials::MetadataServer(_)
+        ));
+        read_bucket(&operator).await.unwrap();
+        read_bucket(&operator).await.unwrap();
+        // The token is cached until it's about to expire.
+        assert_eq!(tokens_handed_out.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn credential_sources_other_than_a_key_file_bypass_the_cloud_storage_client() {
+        let operator = GcsFileStoreOperator::new("bucket".to_string(), None, false, None);
+        assert!(operator.endpoint.is_none());
+        let operator =
+            operator.with_credential_source(GcsCredentialSource::AccessToken("token".to_string()));
+        assert_eq!(
+            operator.endpoint.as_ref().unwrap().url.as_str(),
+            "https://storage.googleapis.com/"
+        );
+        // Anonymous requests stay anonymous.
+        let operator = GcsFileStoreOperator::new("bucket".to_string(), None, false, None)
+            .with_endpoint(None, true)
+            .with_credential_source(GcsCredentialSource::ApplicationDefault);
+        assert!(matches!(
+            operator.endpoint.as_ref().unwrap().credentials,
+            GcsCredentials::Anonymous
+        ));
+    }
+}
@@ -0,0 +1,581 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::{
+    compression_util::{FileStoreMetadata, KeyLayout, StorageFormat},
+    config::GcsRetryConfig,
+    counters::HTTP_FILE_STORE_REQUEST_RETRIES,
+    encryption_util::{check_encryption_key, decrypt_blob, BlobCipher, EncryptionScheme},
+    file_store_operator::{
+        blob_byte_stream_from_bytes, gcs::build_backoff, BlobByteStream, BlobDigestsTracker,
+        BlobFormatTracker, BlobSizeTracker, FileStoreOperator, FileStoreProgress, KeyLayoutTracker,
+        MetadataRevisionTracker, METADATA_FILE_NAME, PROGRESS_FILE_NAME,
+    },
+    types::ChainId,
+};
+use anyhow::{bail, Context};
+use aptos_protos::transaction::v1::Transaction;
+use backoff::backoff::Backoff;
+use futures::{StreamExt, TryStreamExt};
+use reqwest::{header, StatusCode};
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use url::Url;
+
+/// HttpFileStoreOperator reads a file store served over HTTP(S), e.g., a public archive behind a
+/// CDN, without cloud credentials. Objects are fetched at their key under the base URL; it can't
+/// write, so writes fail with a read-only error.
+#[derive(Clone)]
+pub struct HttpFileStoreOperator {
+    base_url: Url,
+    client: reqwest::Client,
+    storage_format: StorageFormat,
+    // If set, encrypted blobs are decrypted on read.
+    cipher: Option<BlobCipher>,
+    retry_config: GcsRetryConfig,
+    metadata_revision: MetadataRevisionTracker,
+    key_layout: KeyLayoutTracker,
+    blob_size: BlobSizeTracker,
+    blob_digests: BlobDigestsTracker,
+    blob_format: BlobFormatTracker,
+    // The metadata as last fetched, with its ETag, so that polling it sends conditional requests
+    // the server can answer with a 304.
+    cached_metadata: Arc<Mutex<Option<(String, FileStoreMetadata)>>>,
+}
+
+impl HttpFileStoreOperator {
+    pub fn new(
+        base_url: &str,
+        enable_compression: bool,
+        zstd_compression_level: Option<i32>,
+    ) -> Self {
+        let mut base_url = Url::parse(base_url).expect("Invalid HTTP file store base URL.");
+        // Keys are resolved relative to the base URL, which has to be a directory for that.
+        if !base_url.path().ends_with('/') {
+            base_url.set_path(&format!("{}/", base_url.path()));
+        }
+        Self {
+            base_url,
+            client: reqwest::Client::new(),
+            storage_format: StorageFormat::for_file_store(
+                enable_compression,
+                zstd_compression_level,
+            ),
+            cipher: None,
+            retry_config: GcsRetryConfig::default(),
+            metadata_revision: MetadataRevisionTracker::default(),
+            key_layout: KeyLayoutTracker::default(),
+            blob_size: BlobSizeTracker::default(),
+            blob_digests: BlobDigestsTracker::default(),
+            blob_format: BlobFormatTracker::default(),
+            cached_metadata: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Reads the blobs as Parquet files instead of the format derived from the compression
+    /// settings.
+    pub fn with_parquet(mut self, enable_parquet: bool) -> Self {
+        if enable_parquet {
+            self.storage_format = StorageFormat::Parquet;
+        }
+        self
+    }
+
+    pub fn with_cipher(mut self, cipher: BlobCipher) -> Self {
+        self.cipher = Some(cipher);
+        self
+    }
+
+    /// Keys the blobs with `key_layout` instead of the layout recorded in the metadata.
+    pub fn with_key_layout(mut self, key_layout: Option<KeyLayout>) -> Self {
+        self.key_layout = KeyLayoutTracker::new(key_layout);
+        self
+    }
+
+    pub fn with_retry_config(mut self, retry_config: GcsRetryConfig) -> Self {
+        self.retry_config = retry_config;
+        self
+    }
+
+    fn url(&self, key: &str) -> anyhow::Result<Url> {
+        self.base_url
+            .join(key)
+            .with_context(|| format!("Invalid key {} under {}.", key, self.base_url))
+    }
+
+    /// Sends a GET of `key`, conditional on `if_none_match` if set, until it gets a response that
+    /// retrying cannot change, or runs out of attempts. Connection errors, timeouts, throttling,
+    /// and server errors are retried; other responses, e.g., a 404, are returned as they are.
+    async fn get(
+        &self,
+        operation: &'static str,
+        key: &str,
+        if_none_match: Option<&str>,
+    ) -> anyhow::Result<reqwest::Response> {
+        let url = self.url(key)?;
+        let request_timeout = Duration::from_secs(self.retry_config.request_timeout_in_secs);
+        let mut backoff = build_backoff(&self.retry_config);
+        let mut attempt = 1;
+        loop {
+            let mut request = self.client.get(url.clone());
+            if let Some(etag) = if_none_match {
+                request = request.header(header::IF_NONE_MATCH, etag);
+            }
+            let err = match tokio::time::timeout(request_timeout, request.send()).await {
+                Ok(Ok(response)) if !is_retryable_http_status(response.status()) => {
+                    return Ok(response)
+                },
+                Ok(Ok(response)) => anyhow::anyhow!("Responded with {}.", response.status()),
+                Ok(Err(err)) => anyhow::Error::new(err),
+                Err(_) => anyhow::anyhow!("Request timed out after {:?}.", request_timeout),
+            };
+            if attempt >= self.retry_config.max_attempts {
+                return Err(err.context(format!(
+                    "[Indexer File] Failed to GET {} after {} attempts.",
+                    url, attempt
+                )));
+            }
+            tracing::warn!(
+                url = url.as_str(),
+                operation = operation,
+                attempt = attempt,
+                error = format!("{:#}", err),
+                "[Indexer File] HTTP request failed; retrying."
+            );
+            HTTP_FILE_STORE_REQUEST_RETRIES
+                .with_label_values(&[operation])
+                .inc();
+            let delay = backoff.next_backoff().unwrap_or(Duration::from_millis(
+                self.retry_config.max_backoff_in_millis,
+            ));
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+
+    /// Gets the object at `key`, or `None` if the server doesn't have it.
+    async fn get_object(
+        &self,
+        operation: &'static str,
+        key: &str,
+    ) -> anyhow::Result<Option<Vec<u8>>> {
+        let response = self.get(operation, key, None).await?;
+        match response.status() {
+            StatusCode::NOT_FOUND => Ok(None),
+            status if status.is_success() => Ok(Some(response.bytes().await?.to_vec())),
+            status => bail!(
+                "[Indexer File] Error happens when downloading {}. Responded with {}.",
+                response.url(),
+                status
+            ),
+        }
+    }
+}
+
+/// Whether a response may change if the request is sent again, i.e., it timed out, was
+/// throttled, or hit a server error.
+fn is_retryable_http_status(status: StatusCode) -> bool {
+    status == StatusCode::REQUEST_TIMEOUT
+        || status == StatusCode::TOO_MANY_REQUESTS
+        || status.is_server_error()
+}
+
+fn read_only_error(operation: &str) -> anyhow::Error {
+    anyhow::anyhow!(
+        "[Indexer File] The HTTP file store operator is read-only; it can't {}.",
+        operation
+    )
+}
+
+#[async_trait::async_trait]
+impl FileStoreOperator for HttpFileStoreOperator {
+    /// Checks that the metadata can be read, i.e., the server is reachable.
+    async fn verify_storage_bucket_existence(&self) {
+        tracing::info!(
+            base_url = self.base_url.as_str(),
+            "Before file store operator starts, verify the file store is reachable."
+        );
+        if let Err(err) = self.try_get_file_store_metadata().await {
+            panic!("Failed to read file store {}. {:#}", self.base_url, err);
+        }
+    }
+
+    fn storage_format(&self) -> StorageFormat {
+        self.storage_format
+    }
+
+    fn encryption_scheme(&self) -> EncryptionScheme {
+        if self.cipher.is_some() {
+            EncryptionScheme::Aes256Gcm
+        } else {
+            EncryptionScheme::None
+        }
+    }
+
+    fn store_name(&self) -> &str {
+        "HTTP"
+    }
+
+    fn key_layout_tracker(&self) -> &KeyLayoutTracker {
+        &self.key_layout
+    }
+
+    fn blob_size_tracker(&self) -> &BlobSizeTracker {
+        &self.blob_size
+    }
+
+    fn blob_digests_tracker(&self) -> &BlobDigestsTracker {
+        &self.blob_digests
+    }
+
+    fn blob_format_tracker(&self) -> &BlobFormatTracker {
+        &self.blob_format
+    }
+
+    async fn get_raw_file(&self, version: u64) -> anyhow::Result<Vec<u8>> {
+        let key = self.blob_key(version).await?;
+        match self.get_object("download_blob", &key).await? {
+            Some(bytes) => decrypt_blob(self.cipher.as_ref(), bytes),
+            None => bail!(
+                "[Indexer File] Transactions file not found. Gap might happen between cache and file store. {}",
+                self.url(&key)?
+            ),
+        }
+    }
+
+    async fn get_legacy_raw_file(
+        &self,
+        version: u64,
+    ) -> anyhow::Result<Option<(StorageFormat, Vec<u8>)>> {
+        for (storage_format, key) in self.legacy_blob_keys(version).await? {
+            if let Some(bytes) = self.get_object("download_blob", &key).await? {
+                return Ok(Some((
+                    storage_format,
+                    decrypt_blob(self.cipher.as_ref(), bytes)?,
+                )));
+            }
+        }
+        Ok(None)
+    }
+
+    async fn get_raw_file_stream(&self, version: u64) -> anyhow::Result<BlobByteStream> {
+        // Encrypted blobs are decrypted as a whole.
+        if self.cipher.is_some() {
+            return Ok(blob_byte_stream_from_bytes(
+                self.get_raw_file(version).await?,
+            ));
+        }
+        let key = self.blob_key(version).await?;
+        let response = self.get("download_blob", &key, None).await?;
+        match response.status() {
+            StatusCode::NOT_FOUND => bail!(
+                "[Indexer File] Transactions file not found. Gap might happen between cache and file store. {}",
+                response.url()
+            ),
+            status if status.is_success() => Ok(response
+                .bytes_stream()
+                .map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err))
+                .boxed()),
+            status => bail!(
+                "[Indexer File] Error happens when downloading transaction file {}. Responded with {}.",
+                response.url(),
+                status
+            ),
+        }
+    }
+
+    /// Polls the metadata with the ETag of the last read, if any, so an unchanged metadata isn't
+    /// downloaded again.
+    async fn try_get_file_store_metadata(&self) -> anyhow::Result<Option<FileStoreMetadata>> {
+        let cached_etag = self
+            .cached_metadata
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|(etag, _)| etag.clone());
+        let response = self
+            .get(
+                "download_metadata",
+                METADATA_FILE_NAME,
+                cached_etag.as_deref(),
+            )
+            .await?;
+        let metadata = match response.status() {
+            StatusCode::NOT_MODIFIED => match self.cached_metadata.lock().unwrap().as_ref() {
+                Some((_, metadata)) => metadata.clone(),
+                None => bail!(
+                    "[Indexer File] {} responded Not Modified to an unconditional request.",
+                    response.url()
+                ),
+            },
+            StatusCode::NOT_FOUND => {
+                *self.cached_metadata.lock().unwrap() = None;
+                return Ok(None);
+            },
+            status if status.is_success() => {
+                let etag = response
+                    .headers()
+                    .get(header::ETAG)
+                    .and_then(|etag| etag.to_str().ok())
+                    .map(str::to_string);
+                let url = response.url().clone();
+                let metadata: FileStoreMetadata = serde_json::from_slice(&response.bytes().await?)
+                    .with_context(|| format!("[Indexer File] Invalid metadata at {}.", url))?;
+                *self.cached_metadata.lock().unwrap() = etag.map(|etag| (etag, metadata.clone()));
+                metadata
+            },
+            status => bail!(
+                "[Indexer File] Error happens when accessing metadata file {}. Responded with {}.",
+                response.url(),
+                status
+            ),
+        };
+        check_encryption_key(
+            metadata.encryption_scheme,
+            metadata.encryption_key_id.as_deref(),
+            self.cipher.as_ref(),
+        )?;
+        self.metadata_revision.observe(&metadata)?;
+        self.key_layout.observe(&metadata);
+        self.blob_size.observe(&metadata);
+        self.blob_digests.observe(&metadata);
+        self.blob_format.observe(&metadata);
+        Ok(Some(metadata))
+    }
+
+    async fn update_file_store_metadata_with_timeout(
+        &mut self,
+        _expected_chain_id: ChainId,
+        _version: u64,
+    ) -> anyhow::Result<()> {
+        Err(read_only_error("update the metadata"))
+    }
+
+    async fn update_file_store_metadata_internal(
+        &mut self,
+        _chain_id: ChainId,
+        _version: u64,
+    ) -> anyhow::Result<()> {
+        Err(read_only_error("update the metadata"))
+    }
+
+    async fn get_processing_progress(&self) -> anyhow::Result<Option<FileStoreProgress>> {
+        match self
+            .get_object("download_progress", PROGRESS_FILE_NAME)
+            .await?
+        {
+            Some(progress) => Ok(Some(serde_json::from_slice(&progress)?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn update_processing_progress(
+        &mut self,
+        _progress: FileStoreProgress,
+    ) -> anyhow::Result<()> {
+        Err(read_only_error("update the processing progress"))
+    }
+
+    async fn upload_transaction_batch(
+        &mut self,
+        _chain_id: ChainId,
+        _transactions: Vec<Transaction>,
+    ) -> anyhow::Result<(u64, u64, usize)> {
+        Err(read_only_error("upload blobs"))
+    }
+
+    async fn upload_filtered_transaction_batch(
+        &mut self,
+        _start_version: u64,
+        _transactions: Vec<Transaction>,
+    ) -> anyhow::Result<()> {
+        Err(read_only_error("upload blobs"))
+    }
+
+    async fn delete_blob(&mut self, _version: u64) -> anyhow::Result<()> {
+        Err(read_only_error("delete blobs"))
+    }
+
+    async fn get_recorded_blob_digest(&self, version: u64) -> anyhow::Result<Option<String>> {
+        let digest_key = self.blob_digest_key(version).await?;
+        match self.get_object("download_blob_digest", &digest_key).await? {
+            Some(digest) => Ok(Some(String::from_utf8(digest)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn clone_box(&self) -> Box<dyn FileStoreOperator> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        compression_util::FILE_ENTRY_TRANSACTION_COUNT,
+        file_store_operator::{blob_transactions, compute_blob_digest, LocalFileStoreOperator},
+    };
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use warp::Filter;
+
+    #[derive(Default)]
+    struct RequestCounts {
+        not_modified: AtomicU32,
+        failures: AtomicU32,
+    }
+
+    /// Serves the files under `root` with a strong ETag, answering conditional requests with a
+    /// 304, after failing the first `failures` requests with a 503.
+    fn start_fake_http_server(
+        root: std::path::PathBuf,
+        failures: u32,
+    ) -> (String, Arc<RequestCounts>) {
+        let counts = Arc::new(RequestCounts::default());
+        let server_counts = counts.clone();
+        let routes = warp::path::full()
+            .and(warp::header::optional::<String>("if-none-match"))
+            .map(
+                move |path: warp::path::FullPath, if_none_match: Option<String>| {
+                    let response = warp::http::Response::builder();
+                    if server_counts.failures.fetch_add(1, Ordering::SeqCst) < failures {
+                        return response.status(503).body(vec![]).unwrap();
+                    }
+                    let path = path.as_str().trim_start_matches("/store/");
+                    match std::fs::read(root.join(path)) {
+                        Ok(bytes) => {
+                            let etag = format!("\"{}\"", compute_blob_digest(&bytes));
+                            if if_none_match.as_deref() == Some(etag.as_str()) {
+                                server_counts.not_modified.fetch_add(1, Ordering::SeqCst);
+                                return response.status(304).body(vec![]).unwrap();
+                            }
+                            response.header("etag", etag).body(bytes).unwrap()
+                        },
+                        Err(_) => response.status(404).body(vec![]).unwrap(),
+                    }
+                },
+            );
+        let (address, server) = warp::serve(routes).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+        (format!("http://{}/store", address), counts)
+    }
+
+    async fn local_file_store(blob_count: u64) -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        let mut operator = LocalFileStoreOperator::new(dir.path().to_path_buf(), true, None);
+        for i in 0..blob_count {
+            operator
+                .upload_transaction_batch(
+                    ChainId(1),
+                    blob_transactions(i * FILE_ENTRY_TRANSACTION_COUNT),
+                )
+                .await
+                .unwrap();
+        }
+        operator
+            .update_file_store_metadata_internal(
+                ChainId(1),
+                blob_count * FILE_ENTRY_TRANSACTION_COUNT,
+            )
+            .await
+            .unwrap();
+        dir
+    }
+
+    fn retry_config() -> GcsRetryConfig {
+        GcsRetryConfig {
+            max_attempts: 3,
+            initial_backoff_in_millis: 1,
+            max_backoff_in_millis: 2,
+            request_timeout_in_secs: 5,
+        }
+    }
+
+    #[tokio::test]
+    async fn blobs_and_ranges_are_read_over_http() {
+        let dir = local_file_store(2).await;
+        let (base_url, _) = start_fake_http_server(dir.path().to_path_buf(), 0);
+        let operator =
+            HttpFileStoreOperator::new(&base_url, true, None).with_retry_config(retry_config());
+        operator.verify_storage_bucket_existence().await;
+
+        assert_eq!(operator.get_latest_version().await, Some(2_000));
+        assert_eq!(
+            operator.get_transactions(1_000, 0).await.unwrap(),
+            blob_transactions(1_000)
+        );
+        assert_eq!(
+            operator
+                .get_transactions_in_range(990, 20, 0)
+                .await
+                .unwrap(),
+            [blob_transactions(0), blob_transactions(1_000)].concat()[990..1_010].to_vec()
+        );
+        assert_eq!(operator.verify_blob_digest(0).await.unwrap(), Some(true));
+        let err = operator.get_transactions(2_000, 0).await.unwrap_err();
+        assert!(
+            err.to_string().contains("Transactions file not found"),
+            "{:#}",
+            err
+        );
+    }
+
+    #[tokio::test]
+    async fn metadata_polls_are_conditional() {
+        let dir = local_file_store(1).await;
+        let (base_url, counts) = start_fake_http_server(dir.path().to_path_buf(), 0);
+        let operator = HttpFileStoreOperator::new(&base_url, true, None);
+        assert_eq!(operator.get_latest_version().await, Some(1_000));
+        assert_eq!(operator.get_latest_version().await, Some(1_000));
+        assert_eq!(counts.not_modified.load(Ordering::SeqCst), 1);
+
+        // A new metadata is downloaded.
+        let mut writer = LocalFileStoreOperator::new(dir.path().to_path_buf(), true, None);
+        writer
+            .upload_transaction_batch(ChainId(1), blob_transactions(1_000))
+            .await
+            .unwrap();
+        writer
+            .update_file_store_metadata_internal(ChainId(1), 2_000)
+            .await
+            .unwrap();
+        assert_eq!(operator.get_latest_version().await, Some(2_000));
+        assert_eq!(counts.not_modified.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn transient_errors_are_retried() {
+        let dir = local_file_store(1).await;
+        let (base_url, _) = start_fake_http_server(dir.path().to_path_buf(), 2);
+        let operator =
+            HttpFileStoreOperator::new(&base_url, true, None).with_retry_config(retry_config());
+        assert_eq!(operator.get_latest_version().await, Some(1_000));
+
+        let (base_url, _) = start_fake_http_server(dir.path().to_path_buf(), 3);
+        let operator =
+            HttpFileStoreOperator::new(&base_url, true, None).with_retry_config(retry_config());
+        let err = operator.try_get_file_store_metadata().await.unwrap_err();
+        assert!(
+            format!("{:#}", err).contains("after 3 attempts"),
+            "{:#}",
+            err
+        );
+    }
+
+    #[tokio::test]
+    async fn writes_are_rejected() {
+        let mut operator = HttpFileStoreOperator::new("http://127.0.0.1:1/store", true, None);
+        let err = operator
+            .upload_transaction_batch(ChainId(1), blob_transactions(0))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("read-only"), "{}", err);
+        assert!(operator.delete_blob(0).await.is_err());
+        assert!(operator
+            .update_file_store_metadata_internal(ChainId(1), 1_000)
+            .await
+            .is_err());
+    }
+}
@@ -0,0 +1,678 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::{
+    compression_util::{
+        blob_start_version, FileEntry, FileStoreMetadata, KeyLayout, StorageFormat,
+        DEFAULT_ZSTD_COMPRESSION_LEVEL, FILE_ENTRY_TRANSACTION_COUNT,
+    },
+    encryption_util::EncryptionScheme,
+    file_store_operator::{
+        compute_blob_digest, is_blob_already_uploaded, BlobDigestsTracker, BlobFormatTracker,
+        BlobSizeTracker, ExistingBlobCheck, FileStoreOperator, FileStoreProgress, KeyLayoutTracker,
+        MetadataRevisionTracker,
+    },
+    types::ChainId,
+};
+use anyhow::{bail, ensure};
+use aptos_protos::transaction::v1::Transaction;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::{Arc, Mutex},
+};
+
+#[derive(Default)]
+struct InMemoryFileStore {
+    // Blobs keyed by their starting version.
+    blobs: BTreeMap<u64, Vec<u8>>,
+    digests: BTreeMap<u64, String>,
+    // Bytes served instead of the stored blobs, keyed by their starting version.
+    read_overrides: BTreeMap<u64, Vec<u8>>,
+    metadata: Option<FileStoreMetadata>,
+    progress: Option<FileStoreProgress>,
+    // Versions of the metadata written so far, in order.
+    metadata_versions: Vec<u64>,
+    // Attempts so far, failed ones included, and the attempts to fail, counted from 1.
+    upload_count: u64,
+    failing_uploads: BTreeSet<u64>,
+    metadata_update_count: u64,
+    failing_metadata_updates: BTreeSet<u64>,
+}
+
+/// InMemoryFileStoreOperator keeps blobs and metadata in memory, for tests.
+/// Clones share the same store, like operators pointing to the same bucket. Blobs are keyed by
+/// their starting version whatever the key layout, which is only recorded in the metadata.
+#[derive(Clone)]
+pub struct InMemoryFileStoreOperator {
+    storage_format: StorageFormat,
+    store: Arc<Mutex<InMemoryFileStore>>,
+    metadata_revision: MetadataRevisionTracker,
+    key_layout: KeyLayoutTracker,
+    blob_size: BlobSizeTracker,
+    blob_digests: BlobDigestsTracker,
+    blob_format: BlobFormatTracker,
+    existing_blob_check: ExistingBlobCheck,
+}
+
+impl InMemoryFileStoreOperator {
+    pub fn new(enable_compression: bool, zstd_compression_level: Option<i32>) -> Self {
+        Self {
+            storage_format: StorageFormat::for_file_store(
+                enable_compression,
+                zstd_compression_level,
+            ),
+            store: Arc::new(Mutex::new(InMemoryFileStore::default())),
+            metadata_revision: MetadataRevisionTracker::default(),
+            key_layout: KeyLayoutTracker::default(),
+            blob_size: BlobSizeTracker::default(),
+            blob_digests: BlobDigestsTracker::default(),
+            blob_format: BlobFormatTracker::default(),
+            existing_blob_check: ExistingBlobCheck::default(),
+        }
+    }
+
+    /// Uses `key_layout` instead of the one recorded in the metadata.
+    pub fn with_key_layout(mut self, key_layout: Option<KeyLayout>) -> Self {
+        self.key_layout = KeyLayoutTracker::new(key_layout);
+        self
+    }
+
+    /// Writes blobs of `blob_size` versions if the store is new.
+    pub fn with_blob_size(mut self, blob_size: Option<u64>) -> Self {
+        self.blob_size = BlobSizeTracker::new(blob_size);
+        self
+    }
+
+    /// Checks whether a blob is already uploaded with `existing_blob_check` before writing it.
+    pub fn with_existing_blob_check(mut self, existing_blob_check: ExistingBlobCheck) -> Self {
+        self.existing_blob_check = existing_blob_check;
+        self
+    }
+
+    /// Starting versions of the stored blobs, in order.
+    pub fn blob_versions(&self) -> Vec<u64> {
+        self.store.lock().unwrap().blobs.keys().copied().collect()
+    }
+
+    /// Replaces the stored bytes of the blob at `version`, keeping its digest, to simulate a blob
+    /// corrupted at rest.
+    pub fn replace_blob(&self, version: u64, bytes: Vec<u8>) {
+        self.store.lock().unwrap().blobs.insert(version, bytes);
+    }
+
+    /// Fails the `n`th blob upload, counted from 1 across clones, failed ones included.
+    pub fn fail_upload(&self, n: u64) {
+        self.store.lock().unwrap().failing_uploads.insert(n);
+    }
+
+    /// Fails the `n`th metadata update, counted from 1 across clones, failed ones included.
+    pub fn fail_metadata_update(&self, n: u64) {
+        self.store
+            .lock()
+            .unwrap()
+            .failing_metadata_updates
+            .insert(n);
+    }
+
+    /// Number of blob uploads attempted, failed ones included.
+    pub fn upload_count(&self) -> u64 {
+        self.store.lock().unwrap().upload_count
+    }
+
+    /// Versions of the metadata written so far, in order; failed updates are left out.
+    pub fn metadata_versions(&self) -> Vec<u64> {
+        self.store.lock().unwrap().metadata_versions.clone()
+    }
+
+    /// Serves `bytes` for reads of the blob at `version`, whatever is uploaded there, to simulate a
+    /// store returning stale or corrupted data.
+    pub fn override_reads(&self, version: u64, bytes: Vec<u8>) {
+        self.store
+            .lock()
+            .unwrap()
+            .read_overrides
+            .insert(version, bytes);
+    }
+}
+
+#[async_trait::async_trait]
+impl FileStoreOperator for InMemoryFileStoreOperator {
+    async fn verify_storage_bucket_existence(&self) {}
+
+    fn storage_format(&self) -> StorageFormat {
+        self.storage_format
+    }
+
+    fn encryption_scheme(&self) -> EncryptionScheme {
+        EncryptionScheme::None
+    }
+
+    fn existing_blob_check(&self) -> ExistingBlobCheck {
+        self.existing_blob_check
+    }
+
+    fn store_name(&self) -> &str {
+        "in_memory"
+    }
+
+    fn key_layout_tracker(&self) -> &KeyLayoutTracker {
+        &self.key_layout
+    }
+
+    fn blob_size_tracker(&self) -> &BlobSizeTracker {
+        &self.blob_size
+    }
+
+    fn blob_digests_tracker(&self) -> &BlobDigestsTracker {
+        &self.blob_digests
+    }
+
+    fn blob_format_tracker(&self) -> &BlobFormatTracker {
+        &self.blob_format
+    }
+
+    async fn get_raw_file(&self, version: u64) -> anyhow::Result<Vec<u8>> {
+        let blob_version = blob_start_version(version, self.blob_size().await?);
+        let store = self.store.lock().unwrap();
+        match store
+            .read_overrides
+            .get(&blob_version)
+            .or_else(|| store.blobs.get(&blob_version))
+        {
+            Some(bytes) => Ok(bytes.clone()),
+            None => bail!(
+                "[Indexer File] Transactions file not found. Gap might happen between cache and file store. {}",
+                version
+            ),
+        }
+    }
+
+    async fn get_legacy_raw_file(
+        &self,
+        _version: u64,
+    ) -> anyhow::Result<Option<(StorageFormat, Vec<u8>)>> {
+        // Blobs are kept by version, whatever their storage format.
+        Ok(None)
+    }
+
+    async fn try_get_file_store_metadata(&self) -> anyhow::Result<Option<FileStoreMetadata>> {
+        let metadata = match self.store.lock().unwrap().metadata.clone() {
+            Some(metadata) => metadata,
+            None => return Ok(None),
+        };
+        self.metadata_revision.observe(&metadata)?;
+        self.key_layout.observe(&metadata);
+        self.blob_size.observe(&metadata);
+        self.blob_digests.observe(&metadata);
+        self.blob_format.observe(&metadata);
+        Ok(Some(metadata))
+    }
+
+    async fn update_file_store_metadata_with_timeout(
+        &mut self,
+        expected_chain_id: ChainId,
+        version: u64,
+    ) -> anyhow::Result<()> {
+        if let Some(metadata) = self.get_file_store_metadata().await {
+            metadata.chain_id.ensure_matches(
+                "the file store",
+                expected_chain_id,
+                "the metadata update",
+            )?;
+            ensure!(
+                metadata.storage_format == self.storage_format,
+                "Storage format mismatch."
+            );
+        }
+        self.update_file_store_metadata_internal(expected_chain_id, version)
+            .await
+    }
+
+    async fn update_file_store_metadata_internal(
+        &mut self,
+        chain_id: ChainId,
+        version: u64,
+    ) -> anyhow::Result<()> {
+        let key_layout = self.key_layout().await?;
+        let blob_size = self.blob_size().await?;
+        let blob_digests_since_version =
+            self.blob_digests_since_version_for_update(version).await?;
+        let blob_format_version = self.blob_format_version().await?;
+        let mut store = self.store.lock().unwrap();
+        store.metadata_update_count += 1;
+        if store
+            .failing_metadata_updates
+            .contains(&store.metadata_update_count)
+        {
+            bail!(
+                "Injected failure of metadata update {}.",
+                store.metadata_update_count
+            );
+        }
+        store.metadata_versions.push(version);
+        store.metadata = Some(
+            FileStoreMetadata::new(
+                chain_id,
+                version,
+                self.storage_format,
+                EncryptionScheme::None,
+            )
+            .with_revision(self.metadata_revision.next_revision())
+            .with_key_layout(key_layout)
+            .with_blob_size(blob_size)
+            .with_blob_digests_since_version(blob_digests_since_version)
+            .with_blob_format_version(blob_format_version),
+        );
+        Ok(())
+    }
+
+    async fn get_processing_progress(&self) -> anyhow::Result<Option<FileStoreProgress>> {
+        Ok(self.store.lock().unwrap().progress.clone())
+    }
+
+    async fn update_processing_progress(
+        &mut self,
+        progress: FileStoreProgress,
+    ) -> anyhow::Result<()> {
+        self.store.lock().unwrap().progress = Some(progress);
+        Ok(())
+    }
+
+    async fn upload_transaction_batch(
+        &mut self,
+        _chain_id: ChainId,
+        transactions: Vec<Transaction>,
+    ) -> anyhow::Result<(u64, u64, usize)> {
+        let start_version = transactions.first().unwrap().version;
+        let end_version = transactions.last().unwrap().version;
+        {
+            let mut store = self.store.lock().unwrap();
+            store.upload_count += 1;
+            if store.failing_uploads.contains(&store.upload_count) {
+                bail!("Injected failure of upload {}.", store.upload_count);
+            }
+        }
+        let blob_size = self.blob_size().await?;
+        ensure!(
+            start_version % blob_size == 0,
+            "Starting version has to be a multiple of the blob size {}.",
+            blob_size
+        );
+        ensure!(
+            transactions.len() as u64 == blob_size,
+            "The number of transactions to upload has to be the blob size {}.",
+            blob_size
+        );
+        let bytes = FileEntry::from_filtered_transactions(
+            start_version,
+            transactions.clone(),
+            self.storage_format,
+            DEFAULT_ZSTD_COMPRESSION_LEVEL,
+            self.blob_format_version().await?,
+        )
+        .into_inner();
+        let size_in_bytes = bytes.len();
+        if is_blob_already_uploaded(self, start_version, &bytes, &transactions).await? {
+            return Ok((start_version, end_version, size_in_bytes));
+        }
+        let digest = compute_blob_digest(&bytes);
+        let mut store = self.store.lock().unwrap();
+        store.blobs.insert(start_version, bytes);
+        store.digests.insert(start_version, digest);
+        Ok((start_version, end_version, size_in_bytes))
+    }
+
+    async fn upload_filtered_transaction_batch(
+        &mut self,
+        start_version: u64,
+        transactions: Vec<Transaction>,
+    ) -> anyhow::Result<()> {
+        let blob_size = self.blob_size().await?;
+        ensure!(
+            start_version % blob_size == 0,
+            "Starting version has to be a multiple of the blob size {}.",
+            blob_size
+        );
+        let bytes = FileEntry::from_filtered_transactions(
+            start_version,
+            transactions,
+            self.storage_format,
+            DEFAULT_ZSTD_COMPRESSION_LEVEL,
+            self.blob_format_version().await?,
+        )
+        .into_inner();
+        let digest = compute_blob_digest(&bytes);
+        let mut store = self.store.lock().unwrap();
+        store.blobs.insert(start_version, bytes);
+        store.digests.insert(start_version, digest);
+        Ok(())
+    }
+
+    async fn delete_blob(&mut self, version: u64) -> anyhow::Result<()> {
+        let mut store = self.store.lock().unwrap();
+        store.blobs.remove(&version);
+        store.digests.remove(&version);
+        Ok(())
+    }
+
+    async fn get_recorded_blob_digest(&self, version: u64) -> anyhow::Result<Option<String>> {
+        Ok(self.store.lock().unwrap().digests.get(&version).cloned())
+    }
+
+    fn clone_box(&self) -> Box<dyn FileStoreOperator> {
+        Box::new(self.clone())
+    }
+}
+
+/// Transactions of the blob starting at `start_version`, with only their versions set.
+pub fn blob_transactions(start_version: u64) -> Vec<Transaction> {
+    (start_version..start_version + FILE_ENTRY_TRANSACTION_COUNT)
+        .map(|version| Transaction {
+            version,
+            ..Default::default()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transactions(start_version: u64, count: u64) -> Vec<Transaction> {
+        (start_version..start_version + count)
+            .map(|version| Transaction {
+                version,
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn blobs_round_trip_through_clones() {
+        let mut operator = InMemoryFileStoreOperator::new(false, None);
+        let mut clone = operator.clone_box();
+        clone
+            .upload_transaction_batch(
+                ChainId(1),
+                transactions(1_000, FILE_ENTRY_TRANSACTION_COUNT),
+            )
+            .await
+            .unwrap();
+        operator
+            .update_file_store_metadata_with_timeout(ChainId(1), 2_000)
+            .await
+            .unwrap();
+
+        assert_eq!(operator.blob_versions(), vec![1_000]);
+        assert_eq!(
+            operator.get_transactions(1_000, 0).await.unwrap(),
+            transactions(1_000, FILE_ENTRY_TRANSACTION_COUNT)
+        );
+        assert_eq!(clone.get_latest_version().await, Some(2_000));
+        assert_eq!(
+            operator.verify_blob_digest(1_000).await.unwrap(),
+            Some(true)
+        );
+        assert!(operator.get_transactions(2_000, 0).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn latest_version_tells_empty_stores_from_errors() {
+        let mut operator = InMemoryFileStoreOperator::new(false, None);
+        assert_eq!(operator.try_get_latest_version().await.unwrap(), None);
+        operator
+            .update_file_store_metadata_with_timeout(ChainId(1), 0)
+            .await
+            .unwrap();
+        operator
+            .upload_transaction_batch(ChainId(1), transactions(0, FILE_ENTRY_TRANSACTION_COUNT))
+            .await
+            .unwrap();
+        operator
+            .update_file_store_metadata_with_timeout(ChainId(1), 1_000)
+            .await
+            .unwrap();
+        assert_eq!(
+            operator.try_get_latest_version().await.unwrap(),
+            Some(1_000)
+        );
+
+        // Metadata older than the one already read, e.g., from another writer, is an error.
+        operator.store.lock().unwrap().metadata = Some(FileStoreMetadata::new(
+            ChainId(1),
+            0,
+            StorageFormat::JsonBase64UncompressedProto,
+            EncryptionScheme::None,
+        ));
+        assert!(operator.try_get_latest_version().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn injected_failures_hit_the_nth_attempt() {
+        let mut operator = InMemoryFileStoreOperator::new(false, None);
+        operator.fail_upload(2);
+        operator.fail_metadata_update(1);
+        assert!(operator
+            .update_file_store_metadata_with_timeout(ChainId(1), 0)
+            .await
+            .is_err());
+        operator
+            .update_file_store_metadata_with_timeout(ChainId(1), 0)
+            .await
+            .unwrap();
+        for result in [true, false, true] {
+            assert_eq!(
+                operator
+                    .upload_transaction_batch(
+                        ChainId(1),
+                        transactions(0, FILE_ENTRY_TRANSACTION_COUNT)
+                    )
+                    .await
+                    .is_ok(),
+                result
+            );
+        }
+        assert_eq!(operator.upload_count(), 3);
+        assert_eq!(operator.metadata_versions(), vec![0]);
+    }
+
+    #[tokio::test]
+    async fn existing_blobs_are_checked_as_configured() {
+        // The blob is corrupted at rest, but keeps its digest.
+        for (existing_blob_check, skipped) in [
+            (ExistingBlobCheck::Contents, false),
+            (ExistingBlobCheck::Digest, true),
+            (ExistingBlobCheck::Disabled, false),
+        ] {
+            let mut operator = InMemoryFileStoreOperator::new(false, None)
+                .with_existing_blob_check(existing_blob_check);
+            for corrupt in [true, false] {
+                operator
+                    .upload_transaction_batch(
+                        ChainId(1),
+                        transactions(0, FILE_ENTRY_TRANSACTION_COUNT),
+                    )
+                    .await
+                    .unwrap();
+                if corrupt {
+                    operator.replace_blob(0, b"corrupted".to_vec());
+                }
+            }
+            assert_eq!(
+                operator.get_raw_file(0).await.unwrap() == b"corrupted",
+                skipped,
+                "{:?}",
+                existing_blob_check
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn misaligned_batches_are_rejected() {
+        let mut operator = InMemoryFileStoreOperator::new(false, None);
+        assert!(operator
+            .upload_transaction_batch(ChainId(1), transactions(500, FILE_ENTRY_TRANSACTION_COUNT))
+            .await
+            .is_err());
+        assert!(operator
+            .upload_transaction_batch(ChainId(1), transactions(0, 10))
+            .await
+            .is_err());
+        assert!(operator.blob_versions().is_empty());
+    }
+
+    #[tokio::test]
+    async fn metadata_chain_id_mismatch_is_rejected() {
+        let mut operator = InMemoryFileStoreOperator::new(false, None);
+        operator
+            .update_file_store_metadata_with_timeout(ChainId(1), 0)
+            .await
+            .unwrap();
+        assert!(operator
+            .update_file_store_metadata_with_timeout(ChainId(2), 1_000)
+            .await
+            .is_err());
+        assert_eq!(operator.get_latest_version().await, Some(0));
+    }
+
+    #[tokio::test]
+    async fn new_file_stores_are_sharded_and_existing_ones_keep_their_layout() {
+        let mut operator = InMemoryFileStoreOperator::new(false, None);
+        operator
+            .update_file_store_metadata_with_timeout(ChainId(1), 0)
+            .await
+            .unwrap();
+        assert_eq!(
+            operator.get_file_store_metadata().await.unwrap().key_layout,
+            KeyLayout::Sharded
+        );
+
+        // A store created before the layout was recorded.
+        let operator = InMemoryFileStoreOperator::new(false, None);
+        operator.store.lock().unwrap().metadata = Some(FileStoreMetadata::new(
+            ChainId(1),
+            0,
+            StorageFormat::JsonBase64UncompressedProto,
+            EncryptionScheme::None,
+        ));
+        let mut reader = operator.clone().with_key_layout(None);
+        assert_eq!(reader.key_layout().await.unwrap(), KeyLayout::Flat);
+        reader
+            .update_file_store_metadata_with_timeout(ChainId(1), 1_000)
+            .await
+            .unwrap();
+        assert_eq!(
+            operator.get_file_store_metadata().await.unwrap().key_layout,
+            KeyLayout::Flat
+        );
+
+        // Writing it with another layout has to go through the migrate tool.
+        let mut writer = operator.with_key_layout(Some(KeyLayout::Sharded));
+        assert!(writer.migrate_file_store_metadata().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn blob_size_is_recorded_and_kept() {
+        let mut operator = InMemoryFileStoreOperator::new(false, None).with_blob_size(Some(100));
+        for start_version in [0, 100] {
+            operator
+                .upload_transaction_batch(ChainId(1), transactions(start_version, 100))
+                .await
+                .unwrap();
+        }
+        operator
+            .update_file_store_metadata_with_timeout(ChainId(1), 200)
+            .await
+            .unwrap();
+        assert_eq!(
+            operator
+                .get_file_store_metadata()
+                .await
+                .unwrap()
+                .blob_size(),
+            100
+        );
+        assert_eq!(operator.blob_versions(), vec![0, 100]);
+
+        // Readers take the size from the metadata.
+        let reader = operator.clone().with_blob_size(None);
+        assert_eq!(
+            reader.get_transactions(150, 0).await.unwrap(),
+            transactions(150, 50)
+        );
+        assert_eq!(
+            reader.get_transactions_in_range(50, 100, 0).await.unwrap(),
+            transactions(50, 100)
+        );
+
+        // Writers configured with another size are refused.
+        let mut writer = operator.with_blob_size(Some(FILE_ENTRY_TRANSACTION_COUNT));
+        let err = writer.migrate_file_store_metadata().await.unwrap_err();
+        assert!(err.to_string().contains("blobs of 100 versions"), "{}", err);
+        assert!(writer
+            .upload_transaction_batch(
+                ChainId(1),
+                transactions(1_000, FILE_ENTRY_TRANSACTION_COUNT)
+            )
+            .await
+            .is_err());
+    }
+
+    async fn operator_with_blobs(blob_count: u64) -> InMemoryFileStoreOperator {
+        let mut operator = InMemoryFileStoreOperator::new(false, None);
+        for i in 0..blob_count {
+            operator
+                .upload_transaction_batch(
+                    ChainId(1),
+                    transactions(
+                        i * FILE_ENTRY_TRANSACTION_COUNT,
+                        FILE_ENTRY_TRANSACTION_COUNT,
+                    ),
+                )
+                .await
+                .unwrap();
+        }
+        operator
+    }
+
+    #[tokio::test]
+    async fn aligned_ranges_are_read_back() {
+        let operator = operator_with_blobs(3).await;
+        assert_eq!(
+            operator
+                .get_transactions_in_range(1_000, 2_000, 0)
+                .await
+                .unwrap(),
+            transactions(1_000, 2_000)
+        );
+        assert!(operator
+            .get_transactions_in_range(0, 0, 0)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn ranges_straddling_blobs_are_sliced() {
+        let operator = operator_with_blobs(2).await;
+        assert_eq!(
+            operator
+                .get_transactions_in_range(990, 20, 0)
+                .await
+                .unwrap(),
+            transactions(990, 20)
+        );
+        assert_eq!(
+            operator
+                .get_transactions_in_range(1_500, 1, 0)
+                .await
+                .unwrap(),
+            transactions(1_500, 1)
+        );
+        // The range runs past the last blob.
+        assert!(operator
+            .get_transactions_in_range(1_990, 20, 0)
+            .await
+            .is_err());
+    }
+}
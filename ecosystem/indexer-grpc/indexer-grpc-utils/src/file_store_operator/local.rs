@@ -2,36 +2,193 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use crate::{
-    compression_util::{FileEntry, FileStoreMetadata, StorageFormat, FILE_ENTRY_TRANSACTION_COUNT},
+    compression_util::{
+        FileEntry, FileStoreMetadata, KeyLayout, StorageFormat, DEFAULT_ZSTD_COMPRESSION_LEVEL,
+    },
+    counters::LOCAL_FILE_STORE_FSYNC_COUNT,
+    encryption_util::{
+        check_encryption_key, decrypt_blob, encrypt_blob, BlobCipher, EncryptionScheme,
+    },
     file_store_operator::{
-        FileStoreOperator, FILE_STORE_UPDATE_FREQUENCY_SECS, METADATA_FILE_NAME,
+        blob_byte_stream_from_bytes, compute_blob_digest, decode_encoded_transactions,
+        encode_encoded_transactions, encode_transaction_stream, is_blob_already_uploaded,
+        is_encoded_blob_already_uploaded, peek_start_version, BlobByteStream, BlobDigestsTracker,
+        BlobFormatTracker, BlobSizeTracker, EncodedBatch, ExistingBlobCheck, FileStoreOperator,
+        FileStoreProgress, KeyLayoutTracker, MetadataRevisionTracker,
+        FILE_STORE_UPDATE_FREQUENCY_SECS, METADATA_FILE_NAME, PROGRESS_FILE_NAME,
     },
+    types::ChainId,
 };
 use aptos_protos::transaction::v1::Transaction;
+use bytes::Bytes;
+use futures::StreamExt;
 use itertools::{any, Itertools};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tracing::info;
 
+// Suffix of the files being written; they are renamed to their final path once complete.
+const TEMP_FILE_SUFFIX: &str = ".tmp";
+// Size of the chunks blob files are streamed in.
+const READ_CHUNK_SIZE_IN_BYTES: usize = 64 * 1024;
+
+// Writer of the temporary file a streamed blob is encoded into.
+type BlobFileWriter = std::io::BufWriter<std::fs::File>;
+
 #[derive(Clone)]
 pub struct LocalFileStoreOperator {
     path: PathBuf,
     /// The timestamp of the latest metadata update; this is to avoid too frequent metadata update.
     latest_metadata_update_timestamp: Option<std::time::Instant>,
     storage_format: StorageFormat,
+    compression_level: i32,
+    // If set, blobs are encrypted before upload and decrypted on read.
+    cipher: Option<BlobCipher>,
+    // If set, writes are fsynced so they survive an OS crash.
+    fsync: bool,
+    metadata_revision: MetadataRevisionTracker,
+    key_layout: KeyLayoutTracker,
+    blob_size: BlobSizeTracker,
+    blob_digests: BlobDigestsTracker,
+    blob_format: BlobFormatTracker,
+    existing_blob_check: ExistingBlobCheck,
 }
 
 impl LocalFileStoreOperator {
-    pub fn new(path: PathBuf, enable_compression: bool) -> Self {
-        let storage_format = if enable_compression {
-            StorageFormat::GzipCompressedProto
-        } else {
-            StorageFormat::JsonBase64UncompressedProto
-        };
+    pub fn new(
+        path: PathBuf,
+        enable_compression: bool,
+        zstd_compression_level: Option<i32>,
+    ) -> Self {
+        let storage_format =
+            StorageFormat::for_file_store(enable_compression, zstd_compression_level);
         Self {
             path,
             latest_metadata_update_timestamp: None,
             storage_format,
+            compression_level: zstd_compression_level.unwrap_or(DEFAULT_ZSTD_COMPRESSION_LEVEL),
+            cipher: None,
+            fsync: false,
+            metadata_revision: MetadataRevisionTracker::default(),
+            key_layout: KeyLayoutTracker::default(),
+            blob_size: BlobSizeTracker::default(),
+            blob_digests: BlobDigestsTracker::default(),
+            blob_format: BlobFormatTracker::default(),
+            existing_blob_check: ExistingBlobCheck::default(),
+        }
+    }
+
+    /// Keys the blobs with `key_layout` instead of the layout recorded in the metadata.
+    pub fn with_key_layout(mut self, key_layout: Option<KeyLayout>) -> Self {
+        self.key_layout = KeyLayoutTracker::new(key_layout);
+        self
+    }
+
+    /// Writes blobs of `blob_size` versions if the file store is new; an existing file store keeps
+    /// the blob size recorded in its metadata.
+    pub fn with_blob_size(mut self, blob_size: Option<u64>) -> Self {
+        self.blob_size = BlobSizeTracker::new(blob_size);
+        self
+    }
+
+    /// Checks whether a blob is already uploaded with `existing_blob_check` before writing it.
+    pub fn with_existing_blob_check(mut self, existing_blob_check: ExistingBlobCheck) -> Self {
+        self.existing_blob_check = existing_blob_check;
+        self
+    }
+
+    /// Enables client-side encryption of the blobs.
+    pub fn with_cipher(mut self, cipher: BlobCipher) -> Self {
+        self.cipher = Some(cipher);
+        self
+    }
+
+    /// Writes the blobs as Parquet files instead of the format derived from the compression
+    /// settings.
+    pub fn with_parquet(mut self, enable_parquet: bool) -> Self {
+        if enable_parquet {
+            self.storage_format = StorageFormat::Parquet;
         }
+        self
+    }
+
+    /// Makes every write durable before it returns, at the cost of write latency.
+    pub fn with_fsync(mut self, fsync: bool) -> Self {
+        self.fsync = fsync;
+        self
+    }
+
+    /// Updates the metadata to `version` on the first upload, then at most every
+    /// FILE_STORE_UPDATE_FREQUENCY_SECS.
+    async fn update_metadata_periodically(
+        &mut self,
+        chain_id: ChainId,
+        version: u64,
+    ) -> anyhow::Result<()> {
+        if let Some(ts) = self.latest_metadata_update_timestamp {
+            // a periodic metadata update
+            if (std::time::Instant::now() - ts).as_secs() > FILE_STORE_UPDATE_FREQUENCY_SECS {
+                self.update_file_store_metadata_internal(chain_id, version)
+                    .await?;
+            }
+        } else {
+            // the first metadata update
+            self.update_file_store_metadata_internal(chain_id, version)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Writes the blob of the batch starting at `start_version` with `encode` to a temporary file,
+    /// then moves it into place along with its digest, unless the blob is already there.
+    async fn upload_encoded_blob(
+        &mut self,
+        chain_id: ChainId,
+        start_version: u64,
+        encode: impl FnOnce(BlobFileWriter) -> anyhow::Result<EncodedBatch<BlobFileWriter>> + Send,
+    ) -> anyhow::Result<(u64, u64, usize)> {
+        let txns_path = self.path.join(self.blob_key(start_version).await?);
+        let digest_path = self.path.join(self.blob_digest_key(start_version).await?);
+        tokio::fs::create_dir_all(txns_path.parent().unwrap()).await?;
+        let temp_path = temp_file_path(&txns_path);
+        let file = std::io::BufWriter::new(std::fs::File::create(&temp_path)?);
+        let batch = match encode(file).and_then(|batch| {
+            let file = batch.sink.into_inner().map_err(|err| err.into_error())?;
+            if self.fsync {
+                file.sync_all()?;
+                LOCAL_FILE_STORE_FSYNC_COUNT
+                    .with_label_values(&["file"])
+                    .inc();
+            }
+            Ok(EncodedBlob {
+                digest: batch.digest,
+                end_version: batch.end_version,
+                size_in_bytes: batch.size_in_bytes,
+            })
+        }) {
+            Ok(batch) => batch,
+            Err(err) => {
+                let _ = tokio::fs::remove_file(&temp_path).await;
+                return Err(err);
+            },
+        };
+        let already_uploaded = is_encoded_blob_already_uploaded(
+            self,
+            start_version,
+            &batch.digest,
+            self.compression_level,
+        )
+        .await;
+        if !matches!(already_uploaded, Ok(false)) {
+            let _ = tokio::fs::remove_file(&temp_path).await;
+        }
+        if !already_uploaded? {
+            rename_into_place(&temp_path, &txns_path, self.fsync).await?;
+            write_file_atomically(&digest_path, batch.digest.into_bytes(), self.fsync).await?;
+        }
+        self.update_metadata_periodically(chain_id, batch.end_version + 1)
+            .await?;
+        Ok((start_version, batch.end_version, batch.size_in_bytes))
     }
 }
 
@@ -45,70 +202,156 @@ impl FileStoreOperator for LocalFileStoreOperator {
         if !self.path.exists() {
             panic!("File store path does not exist.");
         }
+        // Leftovers of writes interrupted by a crash.
+        remove_temp_files(&self.path).expect("Failed to remove temporary files.");
     }
 
     fn storage_format(&self) -> StorageFormat {
         self.storage_format
     }
 
+    fn encryption_scheme(&self) -> EncryptionScheme {
+        if self.cipher.is_some() {
+            EncryptionScheme::Aes256Gcm
+        } else {
+            EncryptionScheme::None
+        }
+    }
+
+    fn existing_blob_check(&self) -> ExistingBlobCheck {
+        self.existing_blob_check
+    }
+
     fn store_name(&self) -> &str {
         "local"
     }
 
+    fn key_layout_tracker(&self) -> &KeyLayoutTracker {
+        &self.key_layout
+    }
+
+    fn blob_size_tracker(&self) -> &BlobSizeTracker {
+        &self.blob_size
+    }
+
+    fn blob_digests_tracker(&self) -> &BlobDigestsTracker {
+        &self.blob_digests
+    }
+
+    fn blob_format_tracker(&self) -> &BlobFormatTracker {
+        &self.blob_format
+    }
+
     async fn get_raw_file(&self, version: u64) -> anyhow::Result<Vec<u8>> {
-        let file_entry_key = FileEntry::build_key(version, self.storage_format).to_string();
+        let file_entry_key = self.blob_key(version).await?;
         let file_path = self.path.join(file_entry_key);
         match tokio::fs::read(file_path).await {
-            Ok(file) => Ok(file),
-            Err(err) => {
-                if err.kind() == std::io::ErrorKind::NotFound {
-                    anyhow::bail!("[Indexer File] Transactions file not found. Gap might happen between cache and file store. {}", err)
-                } else {
-                    anyhow::bail!(
-                        "[Indexer File] Error happens when transaction file. {}",
-                        err
-                    );
-                }
-            },
+            Ok(file) => decrypt_blob(self.cipher.as_ref(), file),
+            Err(err) => Err(blob_read_error(err)),
         }
     }
 
-    async fn get_file_store_metadata(&self) -> Option<FileStoreMetadata> {
-        let metadata_path = self.path.join(METADATA_FILE_NAME);
-        match tokio::fs::read(metadata_path).await {
-            Ok(metadata) => Some(FileStoreMetadata::from_bytes(metadata)),
-            Err(err) => {
-                if err.kind() == std::io::ErrorKind::NotFound {
-                    // Metadata is not found.
-                    None
-                } else {
-                    panic!(
-                        "[Indexer File] Error happens when accessing metadata file. {}",
-                        err
-                    );
-                }
-            },
+    async fn get_legacy_raw_file(
+        &self,
+        version: u64,
+    ) -> anyhow::Result<Option<(StorageFormat, Vec<u8>)>> {
+        for (storage_format, key) in self.legacy_blob_keys(version).await? {
+            match tokio::fs::read(self.path.join(key)).await {
+                Ok(file) => {
+                    return Ok(Some((
+                        storage_format,
+                        decrypt_blob(self.cipher.as_ref(), file)?,
+                    )))
+                },
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => {},
+                Err(err) => return Err(blob_read_error(err)),
+            }
+        }
+        Ok(None)
+    }
+
+    async fn get_raw_file_stream(&self, version: u64) -> anyhow::Result<BlobByteStream> {
+        // Encrypted blobs are decrypted as a whole.
+        if self.cipher.is_some() {
+            return Ok(blob_byte_stream_from_bytes(
+                self.get_raw_file(version).await?,
+            ));
         }
+        let file_entry_key = self.blob_key(version).await?;
+        let file = tokio::fs::File::open(self.path.join(file_entry_key))
+            .await
+            .map_err(blob_read_error)?;
+        Ok(futures::stream::try_unfold(file, |mut file| async move {
+            let mut chunk = vec![0; READ_CHUNK_SIZE_IN_BYTES];
+            let length = file.read(&mut chunk).await?;
+            if length == 0 {
+                return Ok(None);
+            }
+            chunk.truncate(length);
+            Ok(Some((Bytes::from(chunk), file)))
+        })
+        .boxed())
+    }
+
+    async fn try_get_file_store_metadata(&self) -> anyhow::Result<Option<FileStoreMetadata>> {
+        let metadata_path = self.path.join(METADATA_FILE_NAME);
+        let metadata = match tokio::fs::read(metadata_path).await {
+            Ok(metadata) => FileStoreMetadata::from_bytes(metadata),
+            // Metadata is not found.
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => anyhow::bail!(
+                "[Indexer File] Error happens when accessing metadata file. {}",
+                err
+            ),
+        };
+        check_encryption_key(
+            metadata.encryption_scheme,
+            metadata.encryption_key_id.as_deref(),
+            self.cipher.as_ref(),
+        )?;
+        self.metadata_revision.observe(&metadata)?;
+        self.key_layout.observe(&metadata);
+        self.blob_size.observe(&metadata);
+        self.blob_digests.observe(&metadata);
+        self.blob_format.observe(&metadata);
+        Ok(Some(metadata))
     }
 
     async fn update_file_store_metadata_with_timeout(
         &mut self,
-        expected_chain_id: u64,
-        _version: u64,
+        expected_chain_id: ChainId,
+        version: u64,
     ) -> anyhow::Result<()> {
         let metadata_path = self.path.join(METADATA_FILE_NAME);
         match tokio::fs::read(metadata_path).await {
             Ok(metadata) => {
                 let metadata: FileStoreMetadata =
                     serde_json::from_slice(&metadata).expect("Expected metadata to be valid JSON.");
-                anyhow::ensure!(metadata.chain_id == expected_chain_id, "Chain ID mismatch.");
-                Ok(())
+                metadata.chain_id.ensure_matches(
+                    "the file store",
+                    expected_chain_id,
+                    "the metadata update",
+                )?;
+                check_encryption_key(
+                    metadata.encryption_scheme,
+                    metadata.encryption_key_id.as_deref(),
+                    self.cipher.as_ref(),
+                )?;
+                anyhow::ensure!(
+                    metadata.encryption_scheme == self.encryption_scheme(),
+                    "Encryption scheme mismatch."
+                );
+                self.key_layout.observe(&metadata);
+                self.blob_size.observe(&metadata);
+                self.blob_digests.observe(&metadata);
+                self.blob_format.observe(&metadata);
+                self.metadata_revision.observe(&metadata)
             },
             Err(err) => {
                 if err.kind() == std::io::ErrorKind::NotFound {
                     // If the metadata is not found, it means the file store is empty.
                     info!("File store is empty. Creating metadata file.");
-                    self.update_file_store_metadata_internal(expected_chain_id, 0)
+                    self.update_file_store_metadata_internal(expected_chain_id, version)
                         .await
                         .expect("[Indexer File] Update metadata failed.");
                     Ok(())
@@ -125,10 +368,27 @@ impl FileStoreOperator for LocalFileStoreOperator {
 
     async fn update_file_store_metadata_internal(
         &mut self,
-        chain_id: u64,
+        chain_id: ChainId,
         version: u64,
     ) -> anyhow::Result<()> {
-        let metadata = FileStoreMetadata::new(chain_id, version, self.storage_format);
+        let blob_digests_since_version =
+            self.blob_digests_since_version_for_update(version).await?;
+        let metadata = FileStoreMetadata::new(
+            chain_id,
+            version,
+            self.storage_format,
+            self.encryption_scheme(),
+        )
+        .with_revision(self.metadata_revision.next_revision())
+        .with_key_layout(self.key_layout().await?)
+        .with_blob_size(self.blob_size().await?)
+        .with_blob_digests_since_version(blob_digests_since_version)
+        .with_blob_format_version(self.blob_format_version().await?)
+        .with_encryption_key_id(
+            self.cipher
+                .as_ref()
+                .map(|cipher| cipher.key_id().to_string()),
+        );
         // If the metadata is not updated, the indexer will be restarted.
         let metadata_path = self.path.join(METADATA_FILE_NAME);
         info!(
@@ -136,7 +396,13 @@ impl FileStoreOperator for LocalFileStoreOperator {
             metadata_path.display(),
             version
         );
-        match tokio::fs::write(metadata_path, serde_json::to_vec(&metadata).unwrap()).await {
+        match write_file_atomically(
+            &metadata_path,
+            serde_json::to_vec(&metadata).unwrap(),
+            self.fsync,
+        )
+        .await
+        {
             Ok(_) => {
                 self.latest_metadata_update_timestamp = Some(std::time::Instant::now());
                 Ok(())
@@ -145,32 +411,72 @@ impl FileStoreOperator for LocalFileStoreOperator {
         }
     }
 
+    async fn get_processing_progress(&self) -> anyhow::Result<Option<FileStoreProgress>> {
+        match tokio::fs::read(self.path.join(PROGRESS_FILE_NAME)).await {
+            Ok(progress) => Ok(Some(serde_json::from_slice(&progress)?)),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    async fn update_processing_progress(
+        &mut self,
+        progress: FileStoreProgress,
+    ) -> anyhow::Result<()> {
+        write_file_atomically(
+            &self.path.join(PROGRESS_FILE_NAME),
+            serde_json::to_vec(&progress)?,
+            self.fsync,
+        )
+        .await?;
+        Ok(())
+    }
+
     /// TODO: rewrite this function to be similar to the general version
     async fn upload_transaction_batch(
         &mut self,
-        chain_id: u64,
+        chain_id: ChainId,
         transactions: Vec<Transaction>,
-    ) -> anyhow::Result<(u64, u64)> {
+    ) -> anyhow::Result<(u64, u64, usize)> {
         let start_version = transactions.first().unwrap().version;
         let batch_size = transactions.len();
+        let blob_size = self.blob_size().await?;
         anyhow::ensure!(
-            start_version % FILE_ENTRY_TRANSACTION_COUNT == 0,
-            "Starting version has to be a multiple of BLOB_STORAGE_SIZE."
+            start_version % blob_size == 0,
+            "Starting version has to be a multiple of the blob size {}.",
+            blob_size
         );
         anyhow::ensure!(
-            batch_size % FILE_ENTRY_TRANSACTION_COUNT as usize == 0,
-            "The number of transactions to upload has to be multiplier of BLOB_STORAGE_SIZE."
+            batch_size as u64 % blob_size == 0,
+            "The number of transactions to upload has to be a multiple of the blob size {}.",
+            blob_size
         );
+        let blob_format_version = self.blob_format_version().await?;
         let mut tasks = vec![];
+        let mut size_in_bytes = 0;
 
-        // Split the transactions into batches of BLOB_STORAGE_SIZE.
-        for i in transactions.chunks(FILE_ENTRY_TRANSACTION_COUNT as usize) {
+        // Split the transactions into blobs.
+        for i in transactions.chunks(blob_size as usize) {
             let current_batch = i.iter().cloned().collect_vec();
             let starting_version = current_batch.first().unwrap().version;
-            let file_entry = FileEntry::from_transactions(current_batch, self.storage_format);
-            let file_entry_key =
-                FileEntry::build_key(starting_version, self.storage_format).to_string();
-            let txns_path = self.path.join(file_entry_key.as_str());
+            let file_entry = FileEntry::from_filtered_transactions(
+                starting_version,
+                current_batch,
+                self.storage_format,
+                self.compression_level,
+                blob_format_version,
+            );
+            let bytes = file_entry.into_inner();
+            size_in_bytes += bytes.len();
+            if is_blob_already_uploaded(self, starting_version, &bytes, i).await? {
+                continue;
+            }
+            let digest = compute_blob_digest(&bytes);
+            let bytes = encrypt_blob(self.cipher.as_ref(), bytes)?;
+            let txns_path = self.path.join(self.blob_key(starting_version).await?);
+            let digest_path = self
+                .path
+                .join(self.blob_digest_key(starting_version).await?);
             let parent_dir = txns_path.parent().unwrap();
             if !parent_dir.exists() {
                 tracing::debug!("Creating parent dir: {parent_dir:?}.");
@@ -181,12 +487,13 @@ impl FileStoreOperator for LocalFileStoreOperator {
                 "Uploading transactions to {:?}",
                 txns_path.to_str().unwrap()
             );
-            let task = tokio::spawn(async move {
-                match tokio::fs::write(txns_path, file_entry.into_inner()).await {
-                    Ok(_) => Ok(()),
-                    Err(err) => Err(anyhow::Error::from(err)),
-                }
-            });
+            let task = tokio::spawn(write_blob_with_digest(
+                txns_path,
+                digest_path,
+                bytes,
+                digest,
+                self.fsync,
+            ));
             tasks.push(task);
         }
         let results = match futures::future::try_join_all(tasks).await {
@@ -202,26 +509,1034 @@ impl FileStoreOperator for LocalFileStoreOperator {
         if any(results, |x| x.is_err()) {
             anyhow::bail!("Uploading transactions failed.");
         }
+        self.update_metadata_periodically(chain_id, start_version + batch_size as u64)
+            .await?;
 
-        if let Some(ts) = self.latest_metadata_update_timestamp {
-            // a periodic metadata update
-            if (std::time::Instant::now() - ts).as_secs() > FILE_STORE_UPDATE_FREQUENCY_SECS {
-                self.update_file_store_metadata_internal(
-                    chain_id,
-                    start_version + batch_size as u64,
-                )
-                .await?;
+        Ok((
+            start_version,
+            start_version + batch_size as u64 - 1,
+            size_in_bytes,
+        ))
+    }
+
+    /// The blob is written to its file as the transactions are read, with blocking writes.
+    async fn upload_transaction_stream<'a>(
+        &'a mut self,
+        chain_id: ChainId,
+        transactions: Box<dyn Iterator<Item = Transaction> + Send + 'a>,
+    ) -> anyhow::Result<(u64, u64, usize)> {
+        // Encryption needs the whole blob.
+        if self.cipher.is_some() || !self.storage_format.supports_incremental_encoding() {
+            return self
+                .upload_transaction_batch(chain_id, transactions.collect())
+                .await;
+        }
+        let mut transactions = transactions.peekable();
+        let start_version = peek_start_version(&mut transactions)?;
+        let blob_size = self.blob_size().await?;
+        let storage_format = self.storage_format;
+        let compression_level = self.compression_level;
+        let blob_format_version = self.blob_format_version().await?;
+        self.upload_encoded_blob(chain_id, start_version, move |file| {
+            encode_transaction_stream(
+                file,
+                start_version,
+                transactions,
+                blob_size,
+                storage_format,
+                compression_level,
+                blob_format_version,
+            )
+        })
+        .await
+    }
+
+    async fn upload_encoded_transaction_batch(
+        &mut self,
+        chain_id: ChainId,
+        start_version: u64,
+        encoded_transactions: &[Vec<u8>],
+    ) -> anyhow::Result<(u64, u64, usize)> {
+        // Encryption needs the whole blob.
+        if self.cipher.is_some() || !self.storage_format.supports_incremental_encoding() {
+            let transactions = decode_encoded_transactions(start_version, encoded_transactions)?;
+            return self.upload_transaction_batch(chain_id, transactions).await;
+        }
+        let blob_size = self.blob_size().await?;
+        let storage_format = self.storage_format;
+        let compression_level = self.compression_level;
+        let blob_format_version = self.blob_format_version().await?;
+        self.upload_encoded_blob(chain_id, start_version, move |file| {
+            encode_encoded_transactions(
+                file,
+                start_version,
+                encoded_transactions,
+                blob_size,
+                storage_format,
+                compression_level,
+                blob_format_version,
+            )
+        })
+        .await
+    }
+
+    async fn upload_filtered_transaction_batch(
+        &mut self,
+        start_version: u64,
+        transactions: Vec<Transaction>,
+    ) -> anyhow::Result<()> {
+        let blob_size = self.blob_size().await?;
+        anyhow::ensure!(
+            start_version % blob_size == 0,
+            "Starting version has to be a multiple of the blob size {}.",
+            blob_size
+        );
+        let file_entry = FileEntry::from_filtered_transactions(
+            start_version,
+            transactions,
+            self.storage_format,
+            self.compression_level,
+            self.blob_format_version().await?,
+        );
+        let bytes = file_entry.into_inner();
+        let digest = compute_blob_digest(&bytes);
+        let bytes = encrypt_blob(self.cipher.as_ref(), bytes)?;
+        let txns_path = self.path.join(self.blob_key(start_version).await?);
+        let digest_path = self.path.join(self.blob_digest_key(start_version).await?);
+        tokio::fs::create_dir_all(txns_path.parent().unwrap()).await?;
+        write_blob_with_digest(txns_path, digest_path, bytes, digest, self.fsync).await
+    }
+
+    async fn delete_blob(&mut self, version: u64) -> anyhow::Result<()> {
+        for path in [
+            self.path.join(self.blob_key(version).await?),
+            self.path.join(self.blob_digest_key(version).await?),
+        ] {
+            match tokio::fs::remove_file(path).await {
+                Ok(()) => {},
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => {},
+                Err(err) => return Err(err.into()),
             }
-        } else {
-            // the first metadata update
-            self.update_file_store_metadata_internal(chain_id, start_version + batch_size as u64)
-                .await?;
         }
+        Ok(())
+    }
 
-        Ok((start_version, start_version + batch_size as u64 - 1))
+    async fn get_recorded_blob_digest(&self, version: u64) -> anyhow::Result<Option<String>> {
+        let digest_path = self.path.join(self.blob_digest_key(version).await?);
+        match tokio::fs::read_to_string(digest_path).await {
+            Ok(digest) => Ok(Some(digest)),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
     }
 
     fn clone_box(&self) -> Box<dyn FileStoreOperator> {
         Box::new(self.clone())
     }
 }
+
+/// Writes the blob, then its digest, so the digest never refers to a missing blob.
+async fn write_blob_with_digest(
+    txns_path: PathBuf,
+    digest_path: PathBuf,
+    bytes: Vec<u8>,
+    digest: String,
+    fsync: bool,
+) -> anyhow::Result<()> {
+    write_file_atomically(&txns_path, bytes, fsync).await?;
+    write_file_atomically(&digest_path, digest.into_bytes(), fsync).await?;
+    Ok(())
+}
+
+/// Blob written by `upload_transaction_stream`, once its file is closed.
+struct EncodedBlob {
+    digest: String,
+    end_version: u64,
+    size_in_bytes: usize,
+}
+
+fn blob_read_error(err: std::io::Error) -> anyhow::Error {
+    if err.kind() == std::io::ErrorKind::NotFound {
+        anyhow::anyhow!("[Indexer File] Transactions file not found. Gap might happen between cache and file store. {}", err)
+    } else {
+        anyhow::anyhow!(
+            "[Indexer File] Error happens when transaction file. {}",
+            err
+        )
+    }
+}
+
+fn temp_file_path(path: &Path) -> PathBuf {
+    let mut file_name = path.file_name().unwrap().to_os_string();
+    file_name.push(TEMP_FILE_SUFFIX);
+    path.with_file_name(file_name)
+}
+
+/// Writes to a temporary file in the same directory and renames it, so `path` never holds
+/// partial data. With `fsync`, the file is synced before the rename and its directory after, so
+/// the write also survives an OS crash.
+async fn write_file_atomically(path: &Path, bytes: Vec<u8>, fsync: bool) -> std::io::Result<()> {
+    let temp_path = temp_file_path(path);
+    if !fsync {
+        tokio::fs::write(&temp_path, bytes).await?;
+        return tokio::fs::rename(&temp_path, path).await;
+    }
+    let mut file = tokio::fs::File::create(&temp_path).await?;
+    file.write_all(&bytes).await?;
+    file.sync_all().await?;
+    LOCAL_FILE_STORE_FSYNC_COUNT
+        .with_label_values(&["file"])
+        .inc();
+    rename_into_place(&temp_path, path, fsync).await
+}
+
+/// Renames the complete temporary file to `path`; with `fsync`, the directory is synced after.
+async fn rename_into_place(temp_path: &Path, path: &Path, fsync: bool) -> std::io::Result<()> {
+    tokio::fs::rename(temp_path, path).await?;
+    if fsync {
+        tokio::fs::File::open(path.parent().unwrap())
+            .await?
+            .sync_all()
+            .await?;
+        LOCAL_FILE_STORE_FSYNC_COUNT
+            .with_label_values(&["directory"])
+            .inc();
+    }
+    Ok(())
+}
+
+fn remove_temp_files(dir: &Path) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            remove_temp_files(&path)?;
+        } else if path.to_string_lossy().ends_with(TEMP_FILE_SUFFIX) {
+            tracing::warn!(path = ?path, "Removing the leftover of an interrupted write.");
+            std::fs::remove_file(&path)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        compression_util::{
+            BlobHeader, CacheEntry, KeyTemplate, BLOB_FORMAT_VERSION, FILE_ENTRY_TRANSACTION_COUNT,
+            FILE_STORE_METADATA_SCHEMA_VERSION, LEGACY_BLOB_FORMAT_VERSION,
+        },
+        file_store_operator::{blob_transactions, BlobConflictError},
+    };
+    use futures::TryStreamExt;
+
+    #[tokio::test]
+    async fn encrypted_blobs_round_trip() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let cipher = BlobCipher::new(&[7u8; 32]).unwrap();
+        let mut operator = LocalFileStoreOperator::new(tmp_dir.path().to_path_buf(), true, None)
+            .with_cipher(cipher);
+        operator
+            .upload_transaction_batch(ChainId(1), blob_transactions(0))
+            .await
+            .unwrap();
+
+        let downloaded = operator.get_transactions(0, 0).await.unwrap();
+        assert_eq!(downloaded, blob_transactions(0));
+        // The metadata stays plaintext, so the file store can be identified without the key.
+        let metadata: FileStoreMetadata = serde_json::from_slice(
+            &std::fs::read(tmp_dir.path().join(METADATA_FILE_NAME)).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(metadata.encryption_scheme, EncryptionScheme::Aes256Gcm);
+        assert_eq!(
+            metadata.encryption_key_id.as_deref(),
+            Some(BlobCipher::new(&[7u8; 32]).unwrap().key_id())
+        );
+    }
+
+    #[tokio::test]
+    async fn encrypted_blobs_are_not_readable_without_key() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let cipher = BlobCipher::new(&[7u8; 32]).unwrap();
+        let mut operator = LocalFileStoreOperator::new(tmp_dir.path().to_path_buf(), true, None)
+            .with_cipher(cipher);
+        operator
+            .upload_transaction_batch(ChainId(1), blob_transactions(0))
+            .await
+            .unwrap();
+
+        let operator_without_key =
+            LocalFileStoreOperator::new(tmp_dir.path().to_path_buf(), true, None);
+        assert!(operator_without_key.get_transactions(0, 0).await.is_err());
+        let operator_with_wrong_key =
+            LocalFileStoreOperator::new(tmp_dir.path().to_path_buf(), true, None)
+                .with_cipher(BlobCipher::new(&[8u8; 32]).unwrap());
+        assert!(operator_with_wrong_key
+            .get_transactions(0, 0)
+            .await
+            .is_err());
+
+        // Readers fail on the metadata already, before decoding any blob.
+        assert_eq!(
+            operator_without_key
+                .try_get_file_store_metadata()
+                .await
+                .unwrap_err()
+                .to_string(),
+            "The file store is encrypted, but no encryption key is provided."
+        );
+        assert!(operator_with_wrong_key
+            .try_get_file_store_metadata()
+            .await
+            .unwrap_err()
+            .to_string()
+            .starts_with("The file store is encrypted with key "));
+    }
+
+    #[tokio::test]
+    async fn blobs_of_other_storage_formats_are_readable() {
+        // Gzip and JSON file stores, read after switching to zstd.
+        for enable_compression in [true, false] {
+            let tmp_dir = tempfile::tempdir().unwrap();
+            let mut operator =
+                LocalFileStoreOperator::new(tmp_dir.path().to_path_buf(), enable_compression, None);
+            operator
+                .upload_transaction_batch(ChainId(1), blob_transactions(0))
+                .await
+                .unwrap();
+
+            let zstd_operator =
+                LocalFileStoreOperator::new(tmp_dir.path().to_path_buf(), true, Some(3));
+            assert_eq!(
+                zstd_operator.get_transactions(0, 0).await.unwrap(),
+                blob_transactions(0)
+            );
+            let streamed: Vec<Transaction> = zstd_operator
+                .get_transaction_stream(10, 0)
+                .await
+                .unwrap()
+                .try_collect()
+                .await
+                .unwrap();
+            assert_eq!(streamed, blob_transactions(0)[10..]);
+            // Blobs missing in every storage format are still missing.
+            assert!(zstd_operator
+                .get_transactions(FILE_ENTRY_TRANSACTION_COUNT, 0)
+                .await
+                .is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn blob_digest_is_recorded_and_verified() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let mut operator = LocalFileStoreOperator::new(tmp_dir.path().to_path_buf(), true, None);
+        operator
+            .upload_transaction_batch(ChainId(1), blob_transactions(0))
+            .await
+            .unwrap();
+        assert!(operator.get_blob_digest(0).await.unwrap().is_some());
+        assert_eq!(operator.verify_blob_digest(0).await.unwrap(), Some(true));
+
+        let blob_path = tmp_dir.path().join(FileEntry::build_key_with_layout(
+            0,
+            operator.storage_format(),
+            &KeyLayout::Sharded,
+        ));
+        let mut bytes = std::fs::read(&blob_path).unwrap();
+        bytes[0] ^= 0xFF;
+        std::fs::write(&blob_path, bytes).unwrap();
+        assert_eq!(operator.verify_blob_digest(0).await.unwrap(), Some(false));
+    }
+
+    #[tokio::test]
+    async fn blob_without_digest_is_tolerated() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let mut operator = LocalFileStoreOperator::new(tmp_dir.path().to_path_buf(), true, None);
+        operator
+            .upload_transaction_batch(ChainId(1), blob_transactions(0))
+            .await
+            .unwrap();
+        std::fs::remove_file(
+            tmp_dir
+                .path()
+                .join(operator.blob_digest_key(0).await.unwrap()),
+        )
+        .unwrap();
+        assert_eq!(operator.get_blob_digest(0).await.unwrap(), None);
+        assert_eq!(operator.verify_blob_digest(0).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn blobs_uploaded_before_digests_were_recorded_have_none() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        // A file store created before digests were recorded.
+        std::fs::write(
+            tmp_dir.path().join(METADATA_FILE_NAME),
+            br#"{"chain_id":1,"file_folder_size":1000,"version":0,"storage_format":"GzipCompressedProto"}"#,
+        )
+        .unwrap();
+        let mut operator = LocalFileStoreOperator::new(tmp_dir.path().to_path_buf(), true, None);
+        operator
+            .upload_transaction_batch(ChainId(1), blob_transactions(0))
+            .await
+            .unwrap();
+        // Digests are recorded from the version of the first metadata update on.
+        assert_eq!(
+            operator
+                .get_file_store_metadata()
+                .await
+                .unwrap()
+                .blob_digests_since_version,
+            Some(1_000)
+        );
+        operator
+            .upload_transaction_batch(ChainId(1), blob_transactions(1_000))
+            .await
+            .unwrap();
+        operator
+            .update_file_store_metadata_internal(ChainId(1), 2_000)
+            .await
+            .unwrap();
+
+        let reader = LocalFileStoreOperator::new(tmp_dir.path().to_path_buf(), true, None);
+        assert_eq!(
+            reader
+                .get_file_store_metadata()
+                .await
+                .unwrap()
+                .blob_digests_since_version,
+            Some(1_000)
+        );
+        // Blobs before it have no digest, rather than a missing one.
+        assert_eq!(reader.get_blob_digest(0).await.unwrap(), None);
+        assert_eq!(reader.verify_blob_digest(0).await.unwrap(), None);
+        assert_eq!(reader.verify_blob_digest(1_000).await.unwrap(), Some(true));
+    }
+
+    #[tokio::test]
+    async fn blobs_of_legacy_file_stores_have_no_header() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        // A file store created before blob headers, which older readers can't parse.
+        std::fs::write(
+            tmp_dir.path().join(METADATA_FILE_NAME),
+            br#"{"chain_id":1,"file_folder_size":1000,"version":0,"storage_format":"GzipCompressedProto"}"#,
+        )
+        .unwrap();
+        let mut operator = LocalFileStoreOperator::new(tmp_dir.path().to_path_buf(), true, None);
+        operator
+            .upload_transaction_batch(ChainId(1), blob_transactions(0))
+            .await
+            .unwrap();
+        operator
+            .update_file_store_metadata_internal(ChainId(1), 1_000)
+            .await
+            .unwrap();
+        let blob = operator.get_raw_file(0).await.unwrap();
+        assert_eq!(BlobHeader::parse(&blob).unwrap(), None);
+        assert_eq!(
+            operator
+                .get_file_store_metadata()
+                .await
+                .unwrap()
+                .blob_format_version,
+            LEGACY_BLOB_FORMAT_VERSION
+        );
+        assert_eq!(
+            operator.get_transactions(0, 0).await.unwrap(),
+            blob_transactions(0)
+        );
+
+        // New file stores are of the current blob format version.
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let mut operator = LocalFileStoreOperator::new(tmp_dir.path().to_path_buf(), true, None);
+        operator
+            .upload_transaction_batch(ChainId(1), blob_transactions(0))
+            .await
+            .unwrap();
+        operator
+            .update_file_store_metadata_internal(ChainId(1), 1_000)
+            .await
+            .unwrap();
+        let blob = operator.get_raw_file(0).await.unwrap();
+        assert!(BlobHeader::parse(&blob).unwrap().is_some());
+        assert_eq!(
+            operator
+                .get_file_store_metadata()
+                .await
+                .unwrap()
+                .blob_format_version,
+            BLOB_FORMAT_VERSION
+        );
+        assert_eq!(
+            operator.get_transactions(0, 0).await.unwrap(),
+            blob_transactions(0)
+        );
+    }
+
+    #[tokio::test]
+    async fn filtered_batches_round_trip() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let mut operator = LocalFileStoreOperator::new(tmp_dir.path().to_path_buf(), true, None);
+        let subset: Vec<Transaction> = blob_transactions(1_000).into_iter().step_by(100).collect();
+        operator
+            .upload_filtered_transaction_batch(1_000, subset.clone())
+            .await
+            .unwrap();
+        operator
+            .upload_filtered_transaction_batch(2_000, vec![])
+            .await
+            .unwrap();
+
+        assert_eq!(operator.get_transactions(1_000, 0).await.unwrap(), subset);
+        assert!(operator
+            .get_transactions(2_000, 0)
+            .await
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            operator.verify_blob_digest(2_000).await.unwrap(),
+            Some(true)
+        );
+        assert!(operator
+            .upload_filtered_transaction_batch(1_500, vec![])
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn interrupted_writes_never_leave_partial_blobs() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let mut operator = LocalFileStoreOperator::new(tmp_dir.path().to_path_buf(), true, None);
+        operator
+            .upload_transaction_batch(ChainId(1), blob_transactions(0))
+            .await
+            .unwrap();
+
+        // A crash while overwriting the blob leaves the previous one intact.
+        let blob_path = tmp_dir.path().join(FileEntry::build_key_with_layout(
+            0,
+            operator.storage_format,
+            &KeyLayout::Sharded,
+        ));
+        std::fs::write(temp_file_path(&blob_path), b"partial").unwrap();
+        assert_eq!(
+            operator.get_transactions(0, 0).await.unwrap(),
+            blob_transactions(0)
+        );
+        // A crash while writing a new blob leaves nothing at its path.
+        let new_blob_path = tmp_dir.path().join(FileEntry::build_key_with_layout(
+            1_000,
+            operator.storage_format,
+            &KeyLayout::Sharded,
+        ));
+        std::fs::write(temp_file_path(&new_blob_path), b"partial").unwrap();
+        assert!(!new_blob_path.exists());
+        assert!(operator.get_transactions(1_000, 0).await.is_err());
+
+        // Leftovers are removed on startup.
+        operator.verify_storage_bucket_existence().await;
+        assert!(!temp_file_path(&blob_path).exists());
+        assert!(!temp_file_path(&new_blob_path).exists());
+        assert!(blob_path.exists());
+    }
+
+    #[tokio::test]
+    async fn uploads_are_idempotent() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let mut operator = LocalFileStoreOperator::new(tmp_dir.path().to_path_buf(), true, None);
+        operator
+            .upload_transaction_batch(ChainId(1), blob_transactions(0))
+            .await
+            .unwrap();
+        let blob_path = tmp_dir.path().join(FileEntry::build_key_with_layout(
+            0,
+            operator.storage_format,
+            &KeyLayout::Sharded,
+        ));
+        let modified_time = std::fs::metadata(&blob_path).unwrap().modified().unwrap();
+
+        // Uploading the same transactions again leaves the blob as is.
+        assert_eq!(
+            operator
+                .upload_transaction_batch(ChainId(1), blob_transactions(0))
+                .await
+                .unwrap(),
+            (
+                0,
+                999,
+                std::fs::metadata(&blob_path).unwrap().len() as usize
+            )
+        );
+        assert_eq!(
+            std::fs::metadata(&blob_path).unwrap().modified().unwrap(),
+            modified_time
+        );
+
+        // Different transactions for the same versions are rejected.
+        let mut conflicting_transactions = blob_transactions(0);
+        conflicting_transactions[10].epoch = 1;
+        let err = operator
+            .upload_transaction_batch(ChainId(1), conflicting_transactions.clone())
+            .await
+            .unwrap_err();
+        assert_eq!(err.downcast_ref::<BlobConflictError>().unwrap().version, 0);
+        assert_eq!(
+            operator.get_transactions(0, 0).await.unwrap(),
+            blob_transactions(0)
+        );
+
+        // They can be written once the blob is deleted.
+        operator.delete_blob(0).await.unwrap();
+        assert!(operator.get_blob_digest(0).await.unwrap().is_none());
+        operator
+            .upload_transaction_batch(ChainId(1), conflicting_transactions.clone())
+            .await
+            .unwrap();
+        assert_eq!(
+            operator.get_transactions(0, 0).await.unwrap(),
+            conflicting_transactions
+        );
+        operator.delete_blob(1_000).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn metadata_is_migrated_to_the_current_schema() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let metadata_path = tmp_dir.path().join(METADATA_FILE_NAME);
+        let mut operator = LocalFileStoreOperator::new(tmp_dir.path().to_path_buf(), true, None);
+        std::fs::write(
+            &metadata_path,
+            br#"{"chain_id":1,"file_folder_size":1000,"version":5000,"storage_format":"GzipCompressedProto"}"#,
+        )
+        .unwrap();
+
+        operator.migrate_file_store_metadata().await.unwrap();
+        let metadata = operator.get_file_store_metadata().await.unwrap();
+        assert_eq!(metadata.schema_version, FILE_STORE_METADATA_SCHEMA_VERSION);
+        assert_eq!((metadata.chain_id, metadata.version), (ChainId(1), 5000));
+
+        // Metadata from a newer version of the code is left untouched.
+        let future_metadata = format!(
+            r#"{{"chain_id":1,"file_folder_size":1000,"version":5000,"storage_format":"GzipCompressedProto","schema_version":{},"revision":2}}"#,
+            FILE_STORE_METADATA_SCHEMA_VERSION + 1
+        );
+        std::fs::write(&metadata_path, &future_metadata).unwrap();
+        assert!(operator.migrate_file_store_metadata().await.is_err());
+        assert_eq!(
+            std::fs::read_to_string(&metadata_path).unwrap(),
+            future_metadata
+        );
+    }
+
+    #[tokio::test]
+    async fn blobs_are_keyed_with_the_layout_recorded_in_the_metadata() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let mut operator = LocalFileStoreOperator::new(tmp_dir.path().to_path_buf(), true, None);
+        operator
+            .upload_transaction_batch(ChainId(1), blob_transactions(0))
+            .await
+            .unwrap();
+        assert!(tmp_dir
+            .path()
+            .join(FileEntry::build_key_with_layout(
+                0,
+                operator.storage_format,
+                &KeyLayout::Sharded
+            ))
+            .exists());
+        assert_eq!(
+            operator.get_file_store_metadata().await.unwrap().key_layout,
+            KeyLayout::Sharded
+        );
+
+        // A file store created before the layout was recorded stays flat.
+        let flat_dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            flat_dir.path().join(METADATA_FILE_NAME),
+            br#"{"chain_id":1,"file_folder_size":1000,"version":0,"storage_format":"GzipCompressedProto"}"#,
+        )
+        .unwrap();
+        let mut operator = LocalFileStoreOperator::new(flat_dir.path().to_path_buf(), true, None);
+        operator
+            .upload_transaction_batch(ChainId(1), blob_transactions(0))
+            .await
+            .unwrap();
+        assert!(flat_dir
+            .path()
+            .join(FileEntry::build_key(0, operator.storage_format))
+            .exists());
+        assert_eq!(
+            operator.get_transactions(0, 0).await.unwrap(),
+            blob_transactions(0)
+        );
+        assert_eq!(
+            operator.get_file_store_metadata().await.unwrap().key_layout,
+            KeyLayout::Flat
+        );
+    }
+
+    #[tokio::test]
+    async fn writes_and_reads_agree_under_a_key_template() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let key_layout = KeyLayout::Template(KeyTemplate {
+            template: "{prefix}/{version_padded}.blob".to_string(),
+            prefix: "blobs".to_string(),
+            padding_width: 8,
+        });
+        let mut writer = LocalFileStoreOperator::new(tmp_dir.path().to_path_buf(), true, None)
+            .with_key_layout(Some(key_layout.clone()));
+        writer
+            .upload_transaction_batch(ChainId(1), blob_transactions(0))
+            .await
+            .unwrap();
+        writer
+            .upload_transaction_batch(ChainId(1), blob_transactions(1_000))
+            .await
+            .unwrap();
+        assert!(tmp_dir.path().join("blobs/00000000.blob").exists());
+        assert!(tmp_dir.path().join("blobs/00001000.blob").exists());
+        assert_eq!(
+            writer.get_file_store_metadata().await.unwrap().key_layout,
+            key_layout
+        );
+
+        // Readers pick the template up from the metadata.
+        let reader = LocalFileStoreOperator::new(tmp_dir.path().to_path_buf(), true, None);
+        assert_eq!(reader.key_layout().await.unwrap(), key_layout);
+        assert_eq!(
+            reader.get_transactions(1_500, 0).await.unwrap(),
+            blob_transactions(1_000)[500..].to_vec()
+        );
+        assert_eq!(reader.verify_blob_digest(0).await.unwrap(), Some(true));
+    }
+
+    #[tokio::test]
+    async fn ranges_landing_mid_blob_are_trimmed() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let mut operator = LocalFileStoreOperator::new(tmp_dir.path().to_path_buf(), true, None);
+        for version in [0, 1_000, 2_000] {
+            operator
+                .upload_transaction_batch(ChainId(1), blob_transactions(version))
+                .await
+                .unwrap();
+        }
+        let all_transactions: Vec<Transaction> = [0, 1_000, 2_000]
+            .into_iter()
+            .flat_map(transactions)
+            .collect();
+        for (start_version, count) in [(500, 2_000), (999, 2), (1_000, 1_000), (2_999, 1)] {
+            assert_eq!(
+                operator
+                    .get_transactions_in_range_concurrently(start_version, count, 0, 2)
+                    .await
+                    .unwrap(),
+                all_transactions[start_version as usize..(start_version + count) as usize]
+            );
+        }
+
+        // Missing and corrupt blobs are named.
+        operator.delete_blob(1_000).await.unwrap();
+        let err = operator
+            .get_transactions_in_range_concurrently(500, 1_000, 0, 2)
+            .await
+            .unwrap_err();
+        assert!(format!("{:#}", err).contains("Failed to read the blob at 1000."));
+        std::fs::write(
+            tmp_dir.path().join(FileEntry::build_key_with_layout(
+                2_000,
+                operator.storage_format,
+                &KeyLayout::Sharded,
+            )),
+            b"corrupt",
+        )
+        .unwrap();
+        let err = operator
+            .get_transactions_in_range(2_500, 10, 0)
+            .await
+            .unwrap_err();
+        assert!(format!("{:#}", err).contains("Failed to read the blob at 2000."));
+        // The range runs past the last blob.
+        assert!(operator
+            .get_transactions_in_range(2_990, 20, 0)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn ranges_are_streamed_blob_by_blob() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let mut operator = LocalFileStoreOperator::new(tmp_dir.path().to_path_buf(), true, Some(3));
+        for version in [0, 1_000, 2_000] {
+            operator
+                .upload_transaction_stream(
+                    ChainId(1),
+                    Box::new((version..version + 1_000).map(large_transaction)),
+                )
+                .await
+                .unwrap();
+        }
+        for (start_version, count) in [(500, 2_000), (999, 2), (0, 3_000), (2_999, 1), (10, 0)] {
+            let streamed: Vec<Transaction> = operator
+                .get_transaction_stream_in_range(start_version, count, 0)
+                .try_collect()
+                .await
+                .unwrap();
+            assert_eq!(
+                streamed,
+                (start_version..start_version + count)
+                    .map(large_transaction)
+                    .collect::<Vec<_>>()
+            );
+        }
+
+        // The stream fails at the missing blob, after the transactions before it.
+        operator.delete_blob(1_000).await.unwrap();
+        let results: Vec<_> = operator
+            .get_transaction_stream_in_range(900, 1_000, 0)
+            .collect()
+            .await;
+        assert_eq!(results.len(), 101);
+        assert!(results[..100].iter().all(|result| result.is_ok()));
+        let err = results[100].as_ref().unwrap_err();
+        assert!(format!("{:#}", err).contains("Failed to read the blob at 1000."));
+    }
+
+    fn large_transaction(version: u64) -> Transaction {
+        Transaction {
+            version,
+            info: Some(aptos_protos::transaction::v1::TransactionInfo {
+                hash: vec![version as u8; 16 * 1024],
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn streamed_batches_are_written_as_they_are_read() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let mut operator =
+            LocalFileStoreOperator::new(tmp_dir.path().to_path_buf(), false, Some(3));
+        // The transactions are generated as the blob is written; the batch is never in memory.
+        let (start_version, end_version, size_in_bytes) = operator
+            .upload_transaction_stream(ChainId(1), Box::new((1_000..2_000).map(large_transaction)))
+            .await
+            .unwrap();
+        assert_eq!((start_version, end_version), (1_000, 1_999));
+        let blob_path = tmp_dir.path().join(FileEntry::build_key_with_layout(
+            1_000,
+            operator.storage_format,
+            &KeyLayout::Sharded,
+        ));
+        assert_eq!(
+            std::fs::metadata(&blob_path).unwrap().len(),
+            size_in_bytes as u64
+        );
+        assert_eq!(
+            operator.verify_blob_digest(1_000).await.unwrap(),
+            Some(true)
+        );
+        assert_eq!(
+            operator.get_transactions(1_000, 0).await.unwrap(),
+            (1_000..2_000).map(large_transaction).collect::<Vec<_>>()
+        );
+        assert_eq!(operator.get_latest_version().await, Some(2_000));
+
+        // Streaming the same transactions again is a no-op; different ones are rejected.
+        let modified_time = std::fs::metadata(&blob_path).unwrap().modified().unwrap();
+        operator
+            .upload_transaction_stream(ChainId(1), Box::new((1_000..2_000).map(large_transaction)))
+            .await
+            .unwrap();
+        assert_eq!(
+            std::fs::metadata(&blob_path).unwrap().modified().unwrap(),
+            modified_time
+        );
+        let err = operator
+            .upload_transaction_stream(
+                ChainId(1),
+                Box::new((1_000..2_000).map(|version| Transaction {
+                    epoch: 1,
+                    ..large_transaction(version)
+                })),
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<BlobConflictError>().unwrap().version,
+            1_000
+        );
+
+        // A batch with a gap is rejected, without leaving its temporary file behind.
+        assert!(operator
+            .upload_transaction_stream(
+                ChainId(1),
+                Box::new(
+                    (2_000..3_001)
+                        .filter(|version| *version != 2_500)
+                        .map(large_transaction)
+                )
+            )
+            .await
+            .is_err());
+        assert!(
+            !temp_file_path(&tmp_dir.path().join(FileEntry::build_key_with_layout(
+                2_000,
+                operator.storage_format,
+                &KeyLayout::Sharded,
+            )))
+            .exists()
+        );
+    }
+
+    #[tokio::test]
+    async fn metadata_revision_going_backwards_is_rejected() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let mut operator = LocalFileStoreOperator::new(tmp_dir.path().to_path_buf(), true, None);
+        operator
+            .update_file_store_metadata_internal(ChainId(1), 0)
+            .await
+            .unwrap();
+        operator
+            .update_file_store_metadata_internal(ChainId(1), 1000)
+            .await
+            .unwrap();
+        let metadata = operator.get_file_store_metadata().await.unwrap();
+        assert_eq!(metadata.revision, 2);
+        assert!(metadata.last_updated_at_in_secs > 0);
+
+        // Another writer that never read the metadata starts over from revision 1.
+        let mut other_operator =
+            LocalFileStoreOperator::new(tmp_dir.path().to_path_buf(), true, None);
+        other_operator
+            .update_file_store_metadata_internal(ChainId(1), 0)
+            .await
+            .unwrap();
+        assert!(operator
+            .update_file_store_metadata_with_timeout(ChainId(1), 2000)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn progress_round_trips() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let mut operator = LocalFileStoreOperator::new(tmp_dir.path().to_path_buf(), true, None);
+        assert_eq!(operator.get_processing_progress().await.unwrap(), None);
+        for version in [1_000, 2_000] {
+            let progress = FileStoreProgress::new(ChainId(1), version);
+            operator
+                .update_processing_progress(progress.clone())
+                .await
+                .unwrap();
+            assert_eq!(
+                operator.get_processing_progress().await.unwrap(),
+                Some(progress)
+            );
+        }
+        // The progress doesn't touch the metadata.
+        assert!(operator.get_file_store_metadata().await.is_none());
+    }
+
+    fn fsync_count(target: &str) -> u64 {
+        LOCAL_FILE_STORE_FSYNC_COUNT
+            .with_label_values(&[target])
+            .get()
+    }
+
+    #[tokio::test]
+    async fn fsynced_writes_round_trip() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let mut operator =
+            LocalFileStoreOperator::new(tmp_dir.path().to_path_buf(), true, None).with_fsync(true);
+        operator
+            .update_file_store_metadata_with_timeout(ChainId(1), 0)
+            .await
+            .unwrap();
+        let (file_fsyncs, directory_fsyncs) = (fsync_count("file"), fsync_count("directory"));
+        operator
+            .upload_transaction_batch(ChainId(1), blob_transactions(0))
+            .await
+            .unwrap();
+        // The blob and its digest are synced, along with their directory, before the upload
+        // returns. Other tests may sync concurrently, hence the lower bounds.
+        assert!(fsync_count("file") >= file_fsyncs + 2);
+        assert!(fsync_count("directory") >= directory_fsyncs + 2);
+
+        assert_eq!(operator.get_latest_version().await, Some(0));
+        assert_eq!(
+            operator.get_transactions(0, 0).await.unwrap(),
+            blob_transactions(0)
+        );
+        assert_eq!(operator.verify_blob_digest(0).await.unwrap(), Some(true));
+    }
+
+    #[tokio::test]
+    async fn encoded_transactions_give_the_same_blobs_as_decoded_ones() {
+        let transactions: Vec<Transaction> = blob_transactions(1_000)
+            .into_iter()
+            .map(|transaction| Transaction {
+                epoch: transaction.version / 7,
+                timestamp: Some(aptos_protos::util::timestamp::Timestamp {
+                    seconds: transaction.version as i64,
+                    nanos: 0,
+                }),
+                ..transaction
+            })
+            .collect();
+        // As read from a compressed cache.
+        let encoded_transactions: Vec<Vec<u8>> = transactions
+            .iter()
+            .map(|transaction| {
+                CacheEntry::from_transaction(
+                    transaction.clone(),
+                    StorageFormat::ZstdCompressedProto,
+                )
+                .into_encoded_transaction()
+                .unwrap()
+            })
+            .collect();
+        // Gzip and zstd blobs are written incrementally, uncompressed ones from decoded transactions.
+        for (enable_compression, zstd_compression_level) in
+            [(true, None), (true, Some(3)), (false, None)]
+        {
+            let decoded_dir = tempfile::tempdir().unwrap();
+            let mut decoded_operator = LocalFileStoreOperator::new(
+                decoded_dir.path().to_path_buf(),
+                enable_compression,
+                zstd_compression_level,
+            );
+            decoded_operator
+                .upload_transaction_stream(ChainId(1), Box::new(transactions.iter().cloned()))
+                .await
+                .unwrap();
+            let encoded_dir = tempfile::tempdir().unwrap();
+            let mut encoded_operator = LocalFileStoreOperator::new(
+                encoded_dir.path().to_path_buf(),
+                enable_compression,
+                zstd_compression_level,
+            );
+            assert_eq!(
+                encoded_operator
+                    .upload_encoded_transaction_batch(ChainId(1), 1_000, &encoded_transactions)
+                    .await
+                    .unwrap(),
+                (
+                    1_000,
+                    1_999,
+                    decoded_operator.get_raw_file(1_000).await.unwrap().len()
+                )
+            );
+            assert_eq!(
+                encoded_operator.get_raw_file(1_000).await.unwrap(),
+                decoded_operator.get_raw_file(1_000).await.unwrap()
+            );
+            assert_eq!(
+                encoded_operator.verify_blob_digest(1_000).await.unwrap(),
+                Some(true)
+            );
+        }
+    }
+}
@@ -1,20 +1,699 @@
 // Copyright © Aptos Foundation
 // SPDX-License-Identifier: Apache-2.0
 
-use crate::compression_util::{
-    FileEntry, FileStoreMetadata, StorageFormat, FILE_ENTRY_TRANSACTION_COUNT,
+use crate::{
+    compression_util::{
+        blob_start_version, FileEntry, FileEntryReader, FileEntryWriter, FileStoreMetadata,
+        KeyLayout, StorageFormat, BLOB_FORMAT_VERSION, FILE_ENTRY_TRANSACTION_COUNT,
+        FILE_STORE_METADATA_SCHEMA_VERSION,
+    },
+    encryption_util::EncryptionScheme,
+    types::ChainId,
 };
-use anyhow::{Context, Result};
+use anyhow::{bail, ensure, Context, Result};
 use aptos_protos::transaction::v1::Transaction;
+use bytes::{Buf, Bytes};
+use futures::{stream::BoxStream, Future, StreamExt, TryStreamExt};
+use prost::Message;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    fmt,
+    io::{Read, Write},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, RwLock,
+    },
+};
+use tokio::sync::mpsc;
 
 pub mod gcs;
 pub use gcs::*;
+pub mod http;
+pub use http::*;
+#[cfg(any(test, feature = "testing"))]
+pub mod in_memory;
+#[cfg(any(test, feature = "testing"))]
+pub use in_memory::*;
 pub mod local;
 use crate::counters::TRANSACTION_STORE_FETCH_RETRIES;
 pub use local::*;
 
 const METADATA_FILE_NAME: &str = "metadata.json";
+// Small record of the processing progress, rewritten after every successful upload.
+const PROGRESS_FILE_NAME: &str = "progress.json";
 const FILE_STORE_UPDATE_FREQUENCY_SECS: u64 = 5;
+// Suffix of the sidecar object holding the digest of a blob.
+const BLOB_DIGEST_FILE_SUFFIX: &str = ".sha256";
+
+// Chunks of a blob, and decoded transactions, in flight between a streamed read and its decoding.
+const BLOB_STREAM_BUFFER_SIZE: usize = 1;
+
+/// Hex encoded SHA-256 digest of an encoded blob, i.e., before encryption.
+pub fn compute_blob_digest(bytes: &[u8]) -> String {
+    hex::encode(Sha256::digest(bytes))
+}
+
+/// Bytes of an encoded blob, in the chunks they are read or downloaded in.
+pub type BlobByteStream = BoxStream<'static, std::io::Result<Bytes>>;
+
+/// Transactions of one or more blobs, in order, as they are decoded.
+pub type TransactionStream = BoxStream<'static, Result<Transaction>>;
+
+/// A blob of one chunk, for blobs that are read as a whole.
+pub fn blob_byte_stream_from_bytes(bytes: Vec<u8>) -> BlobByteStream {
+    futures::stream::once(async move { Ok(Bytes::from(bytes)) }).boxed()
+}
+
+/// Digest of a blob computed as its bytes stream by; the same as `compute_blob_digest` of the
+/// whole blob once the stream is done.
+#[derive(Clone, Default)]
+pub struct StreamingBlobDigest(Arc<Mutex<Sha256>>);
+
+impl StreamingBlobDigest {
+    /// Passes `bytes` through, hashing them.
+    pub fn hash(&self, bytes: BlobByteStream) -> BlobByteStream {
+        let hasher = self.0.clone();
+        bytes
+            .inspect_ok(move |chunk| hasher.lock().unwrap().update(chunk))
+            .boxed()
+    }
+
+    /// Digest of the bytes streamed so far.
+    pub fn digest(&self) -> String {
+        hex::encode(self.0.lock().unwrap().clone().finalize())
+    }
+}
+
+/// Decodes the transactions of a blob as its bytes stream in, skipping the first `skip` of them.
+/// Compressed blobs are decoded on a blocking thread one transaction at a time, with at most
+/// `BLOB_STREAM_BUFFER_SIZE` chunks and transactions in flight; blobs in the other formats are
+/// decoded as a whole.
+pub fn decode_transaction_stream(
+    bytes: BlobByteStream,
+    storage_format: StorageFormat,
+    skip: usize,
+) -> TransactionStream {
+    if !storage_format.supports_incremental_encoding() {
+        return futures::stream::once(async move {
+            let bytes: Vec<u8> = bytes
+                .try_fold(Vec::new(), |mut blob, chunk| async move {
+                    blob.extend_from_slice(&chunk);
+                    Ok(blob)
+                })
+                .await?;
+            let transactions = tokio::task::spawn_blocking(move || {
+                FileEntry::new(bytes, storage_format).into_transactions_in_storage()
+            })
+            .await
+            .context("Converting storage bytes to FileEntry transactions thread panicked")?
+            .context("Failed to decode the blob.")?
+            .transactions;
+            Ok::<_, anyhow::Error>(futures::stream::iter(
+                transactions.into_iter().skip(skip).map(Ok),
+            ))
+        })
+        .try_flatten()
+        .boxed();
+    }
+
+    let (chunk_sender, chunk_receiver) = mpsc::channel(BLOB_STREAM_BUFFER_SIZE);
+    tokio::spawn(async move {
+        let mut bytes = bytes;
+        while let Some(chunk) = bytes.next().await {
+            // The decoding stopped.
+            if chunk_sender.send(chunk).await.is_err() {
+                break;
+            }
+        }
+    });
+    let (transaction_sender, transaction_receiver) = mpsc::channel(BLOB_STREAM_BUFFER_SIZE);
+    tokio::task::spawn_blocking(move || {
+        let reader = match FileEntryReader::new(ChunkReader {
+            receiver: chunk_receiver,
+            chunk: Bytes::new(),
+        }) {
+            Ok(reader) => reader,
+            Err(err) => {
+                let _ = transaction_sender.blocking_send(Err(
+                    anyhow::Error::new(err).context("Failed to decode the blob.")
+                ));
+                return;
+            },
+        };
+        for transaction in reader.skip(skip) {
+            let transaction = transaction
+                .map_err(|err| anyhow::Error::new(err).context("Failed to decode the blob."));
+            // The stream was dropped.
+            if transaction_sender.blocking_send(transaction).is_err() {
+                break;
+            }
+        }
+    });
+    futures::stream::unfold(transaction_receiver, |mut receiver| async move {
+        receiver
+            .recv()
+            .await
+            .map(|transaction| (transaction, receiver))
+    })
+    .boxed()
+}
+
+/// Reads the chunks of a streamed blob from a blocking thread.
+struct ChunkReader {
+    receiver: mpsc::Receiver<std::io::Result<Bytes>>,
+    chunk: Bytes,
+}
+
+impl Read for ChunkReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while !self.chunk.has_remaining() {
+            match self.receiver.blocking_recv() {
+                Some(chunk) => self.chunk = chunk?,
+                None => return Ok(0),
+            }
+        }
+        let length = buf.len().min(self.chunk.remaining());
+        self.chunk.copy_to_slice(&mut buf[..length]);
+        Ok(length)
+    }
+}
+
+/// Splits the `count` versions from `start_version` on into the ranges of the blobs of
+/// `blob_size` versions holding them, as `(blob_version, start_version, end_version)`.
+fn blob_ranges(
+    start_version: u64,
+    count: u64,
+    blob_size: u64,
+) -> Result<impl Iterator<Item = (u64, u64, u64)> + Send> {
+    let end_version = start_version
+        .checked_add(count)
+        .context("The version range overflows.")?;
+    let first_blob_version = if count == 0 {
+        // No blob to read.
+        end_version
+    } else {
+        blob_start_version(start_version, blob_size)
+    };
+    Ok((first_blob_version..end_version)
+        .step_by(blob_size as usize)
+        .map(move |blob_version| {
+            (
+                blob_version,
+                blob_version.max(start_version),
+                (blob_version + blob_size).min(end_version),
+            )
+        }))
+}
+
+/// Calls `fetch` until it succeeds, at most `retries` more times, counting the retries of
+/// `store_name`.
+async fn fetch_with_retries<T, F, Fut>(store_name: &str, retries: u8, fetch: F) -> Result<T>
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let mut retries = retries;
+    loop {
+        match fetch().await {
+            Ok(value) => return Ok(value),
+            Err(err) => {
+                TRANSACTION_STORE_FETCH_RETRIES
+                    .with_label_values(&[store_name])
+                    .inc_by(1);
+
+                if retries == 0 {
+                    return Err(err);
+                }
+                retries -= 1;
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            },
+        }
+    }
+}
+
+/// Version up to which every blob is confirmed uploaded, persisted right after each round of
+/// uploads. It's kept apart from the metadata, which may lag behind it, so that a restart resumes
+/// where the processor left off.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct FileStoreProgress {
+    pub chain_id: ChainId,
+    /// Next version to upload; every blob before it is uploaded.
+    pub version: u64,
+    pub updated_at_in_secs: u64,
+}
+
+impl FileStoreProgress {
+    pub fn new(chain_id: ChainId, version: u64) -> Self {
+        Self {
+            chain_id,
+            version,
+            updated_at_in_secs: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map_or(0, |duration| duration.as_secs()),
+        }
+    }
+}
+
+/// How an upload first checks whether its blob is already in the file store, e.g., after a restart
+/// from older metadata, so that it isn't written again.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub enum ExistingBlobCheck {
+    /// The blob already there is downloaded and compared, so a blob holding different
+    /// transactions fails the upload.
+    #[default]
+    Contents,
+    /// Only the recorded digest of the blob is compared, which is cheaper but doesn't catch
+    /// conflicting blobs; blobs without a digest are written again.
+    Digest,
+    /// Blobs are always written, e.g., for backends where reads before writes are expensive.
+    Disabled,
+}
+
+/// Tracks the highest metadata revision an operator has read or written; shared by its clones.
+/// Metadata whose revision goes backwards was written by another writer, e.g., a second processor
+/// pointed at the same file store.
+#[derive(Clone, Debug, Default)]
+pub struct MetadataRevisionTracker(Arc<AtomicU64>);
+
+impl MetadataRevisionTracker {
+    /// Fails if `metadata` is older than a revision read or written before.
+    pub fn observe(&self, metadata: &FileStoreMetadata) -> Result<()> {
+        let highest_revision = self.0.fetch_max(metadata.revision, Ordering::SeqCst);
+        ensure!(
+            metadata.revision >= highest_revision,
+            "[Indexer File] The file store metadata revision went backwards from {} to {}; it was last written by '{}'. Is another processor writing to the file store?",
+            highest_revision,
+            metadata.revision,
+            metadata.writer
+        );
+        Ok(())
+    }
+
+    /// Revision of the next metadata written.
+    pub fn next_revision(&self) -> u64 {
+        self.0.fetch_add(1, Ordering::SeqCst) + 1
+    }
+}
+
+/// Tracks the key layout of the file store of an operator; shared by its clones. A configured
+/// layout is always used; otherwise, the one recorded in the metadata last read.
+#[derive(Clone, Debug, Default)]
+pub struct KeyLayoutTracker {
+    configured: Option<KeyLayout>,
+    // `None` until the metadata is read.
+    observed: Arc<RwLock<Option<KeyLayout>>>,
+}
+
+impl KeyLayoutTracker {
+    pub fn new(configured: Option<KeyLayout>) -> Self {
+        Self {
+            configured,
+            observed: Arc::default(),
+        }
+    }
+
+    pub fn configured(&self) -> Option<&KeyLayout> {
+        self.configured.as_ref()
+    }
+
+    /// Records the layout of `metadata`.
+    pub fn observe(&self, metadata: &FileStoreMetadata) {
+        self.set(metadata.key_layout.clone());
+    }
+
+    fn set(&self, key_layout: KeyLayout) {
+        *self.observed.write().unwrap() = Some(key_layout);
+    }
+
+    /// The layout in use, or `None` until the metadata is read.
+    pub fn get(&self) -> Option<KeyLayout> {
+        self.configured
+            .clone()
+            .or_else(|| self.observed.read().unwrap().clone())
+    }
+}
+
+/// Tracks the blob size of the file store of an operator; shared by its clones. The size recorded
+/// in the metadata is always used; a configured size only applies to new file stores, and has to
+/// match the recorded one otherwise.
+#[derive(Clone, Debug, Default)]
+pub struct BlobSizeTracker {
+    configured: Option<u64>,
+    // `None` until the metadata is read.
+    observed: Arc<RwLock<Option<u64>>>,
+}
+
+impl BlobSizeTracker {
+    pub fn new(configured: Option<u64>) -> Self {
+        Self {
+            configured,
+            observed: Arc::default(),
+        }
+    }
+
+    pub fn configured(&self) -> Option<u64> {
+        self.configured
+    }
+
+    /// Records the blob size of `metadata`.
+    pub fn observe(&self, metadata: &FileStoreMetadata) {
+        self.set(metadata.blob_size());
+    }
+
+    fn set(&self, blob_size: u64) {
+        *self.observed.write().unwrap() = Some(blob_size);
+    }
+
+    /// The blob size in use, or `None` until the metadata is read.
+    pub fn get(&self) -> Option<u64> {
+        *self.observed.read().unwrap()
+    }
+}
+
+/// Tracks the first version of the blobs of an operator's file store that have a digest, as
+/// recorded in the metadata; shared by its clones.
+#[derive(Clone, Debug, Default)]
+pub struct BlobDigestsTracker {
+    // `None` until the metadata is read; then `Some(None)` if the file store has no digests.
+    observed: Arc<RwLock<Option<Option<u64>>>>,
+}
+
+impl BlobDigestsTracker {
+    /// Records the first version with a digest of `metadata`.
+    pub fn observe(&self, metadata: &FileStoreMetadata) {
+        self.set(metadata.blob_digests_since_version);
+    }
+
+    fn set(&self, blob_digests_since_version: Option<u64>) {
+        *self.observed.write().unwrap() = Some(blob_digests_since_version);
+    }
+
+    /// The first version with a digest, or `None` until the metadata is read.
+    pub fn get(&self) -> Option<Option<u64>> {
+        *self.observed.read().unwrap()
+    }
+}
+
+/// Tracks the blob format version of the file store of an operator, as recorded in the metadata;
+/// shared by its clones.
+#[derive(Clone, Debug, Default)]
+pub struct BlobFormatTracker {
+    // `None` until the metadata is read.
+    observed: Arc<RwLock<Option<u8>>>,
+}
+
+impl BlobFormatTracker {
+    /// Records the blob format version of `metadata`.
+    pub fn observe(&self, metadata: &FileStoreMetadata) {
+        self.set(metadata.blob_format_version);
+    }
+
+    fn set(&self, blob_format_version: u8) {
+        *self.observed.write().unwrap() = Some(blob_format_version);
+    }
+
+    /// The blob format version in use, or `None` until the metadata is read.
+    pub fn get(&self) -> Option<u8> {
+        *self.observed.read().unwrap()
+    }
+}
+
+/// An upload would replace a blob holding different transactions, e.g., written by another
+/// processor; retrying won't help.
+#[derive(Debug)]
+pub struct BlobConflictError {
+    pub version: u64,
+}
+
+impl fmt::Display for BlobConflictError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "[Indexer File] The blob at {} already holds different transactions.",
+            self.version
+        )
+    }
+}
+
+impl std::error::Error for BlobConflictError {}
+
+/// Whether the blob at `start_version` is already uploaded according to the checks that don't need
+/// its contents, i.e., `None` unless the existing blob check is `Digest` or `Disabled`.
+async fn is_blob_digest_already_uploaded<O: FileStoreOperator + ?Sized>(
+    operator: &O,
+    start_version: u64,
+    digest: &str,
+) -> Option<bool> {
+    match operator.existing_blob_check() {
+        ExistingBlobCheck::Contents => None,
+        // Like a missing blob, a digest that can't be read is written again.
+        ExistingBlobCheck::Digest => Some(matches!(
+            operator.get_blob_digest(start_version).await,
+            Ok(Some(existing_digest)) if existing_digest == digest
+        )),
+        ExistingBlobCheck::Disabled => Some(false),
+    }
+}
+
+/// Returns whether the blob at `start_version` already holds `bytes`, in which case uploading
+/// them again is a no-op. Missing or undecodable blobs, and blobs holding the same transactions in
+/// another encoding, are to be (re)written; blobs holding different transactions are a
+/// `BlobConflictError`. The existing blob check of the operator may make this cheaper.
+async fn is_blob_already_uploaded<O: FileStoreOperator + ?Sized>(
+    operator: &O,
+    start_version: u64,
+    bytes: &[u8],
+    transactions: &[Transaction],
+) -> Result<bool> {
+    if let Some(already_uploaded) =
+        is_blob_digest_already_uploaded(operator, start_version, &compute_blob_digest(bytes)).await
+    {
+        return Ok(already_uploaded);
+    }
+    // Not found is an error as well; anything else would fail the upload anyway.
+    let existing_bytes = match operator.get_raw_file(start_version).await {
+        Ok(existing_bytes) => existing_bytes,
+        Err(_) => return Ok(false),
+    };
+    if existing_bytes == bytes {
+        return Ok(true);
+    }
+    match operator.get_transactions(start_version, 0).await {
+        Ok(existing_transactions) if existing_transactions != transactions => {
+            Err(BlobConflictError {
+                version: start_version,
+            }
+            .into())
+        },
+        _ => Ok(false),
+    }
+}
+
+/// Passes the bytes written through to `W`, keeping their digest and size.
+struct DigestWriter<W: Write> {
+    sink: W,
+    hasher: Sha256,
+    size_in_bytes: usize,
+}
+
+impl<W: Write> Write for DigestWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.sink.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        self.size_in_bytes += written;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.sink.flush()
+    }
+}
+
+/// Blob of a batch written with `encode_transaction_stream`.
+struct EncodedBatch<W> {
+    sink: W,
+    start_version: u64,
+    end_version: u64,
+    digest: String,
+    size_in_bytes: usize,
+}
+
+/// Writes the blob of the batch starting at `start_version` to `sink` as `transactions` are read.
+/// The batch has to be `blob_size` transactions with consecutive versions.
+fn encode_transaction_stream<W: Write>(
+    sink: W,
+    start_version: u64,
+    transactions: impl Iterator<Item = Transaction>,
+    blob_size: u64,
+    storage_format: StorageFormat,
+    compression_level: i32,
+    blob_format_version: u8,
+) -> Result<EncodedBatch<W>> {
+    encode_batch(
+        sink,
+        start_version,
+        transactions,
+        blob_size,
+        storage_format,
+        compression_level,
+        blob_format_version,
+        |writer, transaction| {
+            let expected_version = start_version + writer.transaction_count();
+            ensure!(
+                transaction.version == expected_version,
+                "Expected version {} in the batch at {}, found {}.",
+                expected_version,
+                start_version,
+                transaction.version
+            );
+            Ok(writer.write_transaction(&transaction)?)
+        },
+    )
+}
+
+/// Like `encode_transaction_stream`, for the protobuf encodings of the transactions, which are
+/// written as they are. Their versions aren't checked, since they aren't decoded.
+fn encode_encoded_transactions<W: Write>(
+    sink: W,
+    start_version: u64,
+    encoded_transactions: &[Vec<u8>],
+    blob_size: u64,
+    storage_format: StorageFormat,
+    compression_level: i32,
+    blob_format_version: u8,
+) -> Result<EncodedBatch<W>> {
+    encode_batch(
+        sink,
+        start_version,
+        encoded_transactions.iter(),
+        blob_size,
+        storage_format,
+        compression_level,
+        blob_format_version,
+        |writer, encoded_transaction| Ok(writer.write_encoded_transaction(encoded_transaction)?),
+    )
+}
+
+/// Decodes the protobuf encodings of the transactions of the batch starting at `start_version`,
+/// for operators that can't write them as they are.
+fn decode_encoded_transactions(
+    start_version: u64,
+    encoded_transactions: &[Vec<u8>],
+) -> Result<Vec<Transaction>> {
+    let transactions = encoded_transactions
+        .iter()
+        .map(|encoded_transaction| Transaction::decode(encoded_transaction.as_slice()))
+        .collect::<Result<Vec<_>, _>>()?;
+    ensure!(
+        transactions.first().map(|transaction| transaction.version) == Some(start_version),
+        "The encoded batch doesn't start at version {}.",
+        start_version
+    );
+    Ok(transactions)
+}
+
+fn encode_batch<W: Write, T>(
+    sink: W,
+    start_version: u64,
+    transactions: impl Iterator<Item = T>,
+    blob_size: u64,
+    storage_format: StorageFormat,
+    compression_level: i32,
+    blob_format_version: u8,
+    mut write: impl FnMut(&mut FileEntryWriter<DigestWriter<W>>, T) -> Result<()>,
+) -> Result<EncodedBatch<W>> {
+    ensure!(
+        start_version % blob_size == 0,
+        "Starting version has to be a multiple of the blob size {}.",
+        blob_size
+    );
+    let sink = DigestWriter {
+        sink,
+        hasher: Sha256::new(),
+        size_in_bytes: 0,
+    };
+    let mut writer = FileEntryWriter::new(
+        sink,
+        start_version,
+        storage_format,
+        compression_level,
+        blob_format_version,
+    )?;
+    for transaction in transactions {
+        write(&mut writer, transaction)?;
+    }
+    ensure!(
+        writer.transaction_count() == blob_size,
+        "The number of transactions to upload has to be the blob size {}.",
+        blob_size
+    );
+    let sink = writer.finish()?;
+    Ok(EncodedBatch {
+        sink: sink.sink,
+        start_version,
+        end_version: start_version + blob_size - 1,
+        digest: hex::encode(sink.hasher.finalize()),
+        size_in_bytes: sink.size_in_bytes,
+    })
+}
+
+/// Like `is_blob_already_uploaded`, for a blob written with `encode_transaction_stream` and only
+/// known by its digest: the transactions of the blob already there are encoded the same way to
+/// tell whether they differ.
+async fn is_encoded_blob_already_uploaded<O: FileStoreOperator + ?Sized>(
+    operator: &O,
+    start_version: u64,
+    digest: &str,
+    compression_level: i32,
+) -> Result<bool> {
+    if let Some(already_uploaded) =
+        is_blob_digest_already_uploaded(operator, start_version, digest).await
+    {
+        return Ok(already_uploaded);
+    }
+    let existing_bytes = match operator.get_raw_file(start_version).await {
+        Ok(existing_bytes) => existing_bytes,
+        Err(_) => return Ok(false),
+    };
+    if compute_blob_digest(&existing_bytes) == digest {
+        return Ok(true);
+    }
+    let existing_transactions = match operator.get_transactions(start_version, 0).await {
+        Ok(existing_transactions) => existing_transactions,
+        Err(_) => return Ok(false),
+    };
+    let same_transactions = match encode_transaction_stream(
+        std::io::sink(),
+        start_version,
+        existing_transactions.into_iter(),
+        operator.blob_size().await?,
+        operator.storage_format(),
+        compression_level,
+        operator.blob_format_version().await?,
+    ) {
+        Ok(existing_batch) => existing_batch.digest == digest,
+        // E.g., a blob missing transactions.
+        Err(_) => false,
+    };
+    if !same_transactions {
+        return Err(BlobConflictError {
+            version: start_version,
+        }
+        .into());
+    }
+    Ok(false)
+}
+
+/// First version of a batch to be read from `transactions`.
+fn peek_start_version(
+    transactions: &mut std::iter::Peekable<impl Iterator<Item = Transaction>>,
+) -> Result<u64> {
+    match transactions.peek() {
+        Some(transaction) => Ok(transaction.version),
+        None => bail!("Cannot upload an empty batch."),
+    }
+}
 
 #[async_trait::async_trait]
 pub trait FileStoreOperator: Send + Sync {
@@ -23,6 +702,14 @@ pub trait FileStoreOperator: Send + Sync {
 
     fn storage_format(&self) -> StorageFormat;
 
+    /// Client-side encryption of the blobs written by this operator.
+    fn encryption_scheme(&self) -> EncryptionScheme;
+
+    /// How uploads check whether their blob is already in the file store.
+    fn existing_blob_check(&self) -> ExistingBlobCheck {
+        ExistingBlobCheck::Contents
+    }
+
     /// The name of the store, for logging. Ex: "GCS", "Redis", etc
     fn store_name(&self) -> &str;
 
@@ -34,26 +721,175 @@ pub trait FileStoreOperator: Send + Sync {
         Ok(transactions)
     }
 
+    /// Gets the `count` transactions starting at `start_version`, which doesn't have to be a
+    /// multiple of BLOB_STORAGE_SIZE; the range may span several blobs.
+    async fn get_transactions_in_range(
+        &self,
+        start_version: u64,
+        count: u64,
+        retries: u8,
+    ) -> Result<Vec<Transaction>> {
+        self.get_transactions_in_range_concurrently(start_version, count, retries, 1)
+            .await
+    }
+
+    /// Like `get_transactions_in_range`, fetching up to `concurrency` blobs at a time. Errors name
+    /// the blob that is missing or corrupt.
+    async fn get_transactions_in_range_concurrently(
+        &self,
+        start_version: u64,
+        count: u64,
+        retries: u8,
+        concurrency: usize,
+    ) -> Result<Vec<Transaction>> {
+        ensure!(concurrency > 0, "Concurrency has to be positive.");
+        let blob_size = self.blob_size().await?;
+        let blobs = futures::stream::iter(blob_ranges(start_version, count, blob_size)?.map(
+            |(blob_version, version, end_version)| async move {
+                self.get_transaction_stream_in_blob(blob_version, version, end_version, retries)
+                    .await
+                    .try_collect::<Vec<_>>()
+                    .await
+            },
+        ))
+        .buffered(concurrency)
+        .try_collect::<Vec<_>>()
+        .await?;
+        Ok(blobs.into_iter().flatten().collect())
+    }
+
+    /// Streams the `count` transactions starting at `start_version`, like
+    /// `get_transactions_in_range` returns them, reading one blob at a time. Only the transaction
+    /// being decoded is held in memory, whatever the size of the range.
+    fn get_transaction_stream_in_range(
+        &self,
+        start_version: u64,
+        count: u64,
+        retries: u8,
+    ) -> BoxStream<'_, Result<Transaction>> {
+        futures::stream::once(async move {
+            let blob_ranges = blob_ranges(start_version, count, self.blob_size().await?)?;
+            Ok::<_, anyhow::Error>(
+                futures::stream::iter(blob_ranges)
+                    .then(move |(blob_version, version, end_version)| {
+                        self.get_transaction_stream_in_blob(
+                            blob_version,
+                            version,
+                            end_version,
+                            retries,
+                        )
+                    })
+                    .flatten(),
+            )
+        })
+        .try_flatten()
+        .boxed()
+    }
+
+    /// Streams the transactions `[version, end_version)` of the blob at `blob_version`, checking
+    /// their versions. Errors name the blob.
+    async fn get_transaction_stream_in_blob(
+        &self,
+        blob_version: u64,
+        version: u64,
+        end_version: u64,
+        retries: u8,
+    ) -> TransactionStream {
+        let context = move || format!("Failed to read the blob at {}.", blob_version);
+        let transactions = match self.get_transaction_stream(version, retries).await {
+            Ok(transactions) => transactions,
+            Err(err) => {
+                return futures::stream::once(async move { Err(err.context(context())) }).boxed()
+            },
+        };
+        futures::stream::unfold(
+            (transactions, version),
+            move |(mut transactions, version)| async move {
+                if version == end_version {
+                    // Drops the rest of the blob.
+                    return None;
+                }
+                let transaction = match transactions.next().await {
+                    Some(Ok(transaction)) if transaction.version == version => Ok(transaction),
+                    Some(Ok(transaction)) => Err(anyhow::anyhow!(
+                        "Expected version {} in the blob at {}, found {}.",
+                        version,
+                        blob_version,
+                        transaction.version
+                    )),
+                    Some(Err(err)) => Err(err.context(context())),
+                    None => Err(anyhow::anyhow!(
+                        "The blob at {} ends before version {}.",
+                        blob_version,
+                        end_version
+                    )),
+                };
+                // Stops at the first error.
+                let next_version = if transaction.is_ok() {
+                    version + 1
+                } else {
+                    end_version
+                };
+                Some((transaction, (transactions, next_version)))
+            },
+        )
+        .boxed()
+    }
+
     async fn get_raw_file(&self, version: u64) -> Result<Vec<u8>>;
 
+    /// Gets the blob holding `version` if it was written in another storage format, e.g., gzip
+    /// blobs of a file store since switched to zstd, with its storage format; `None` if it's under
+    /// none of the `legacy_blob_keys`.
+    async fn get_legacy_raw_file(&self, version: u64) -> Result<Option<(StorageFormat, Vec<u8>)>>;
+
     async fn get_raw_file_with_retries(&self, version: u64, retries: u8) -> Result<Vec<u8>> {
-        let mut retries = retries;
-        loop {
-            match self.get_raw_file(version).await {
-                Ok(bytes) => return Ok(bytes),
-                Err(err) => {
-                    TRANSACTION_STORE_FETCH_RETRIES
-                        .with_label_values(&[self.store_name()])
-                        .inc_by(1);
-
-                    if retries == 0 {
-                        return Err(err);
-                    }
-                    retries -= 1;
-                    tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        fetch_with_retries(self.store_name(), retries, || self.get_raw_file(version)).await
+    }
+
+    /// Streams the bytes of the blob holding `version`, as `get_raw_file` returns them. Defaults
+    /// to the whole blob in one chunk; operators that can read a blob incrementally override it.
+    async fn get_raw_file_stream(&self, version: u64) -> Result<BlobByteStream> {
+        Ok(blob_byte_stream_from_bytes(
+            self.get_raw_file(version).await?,
+        ))
+    }
+
+    /// Retries opening the stream of the blob; errors in the middle of the stream are returned
+    /// as its items instead.
+    async fn get_raw_file_stream_with_retries(
+        &self,
+        version: u64,
+        retries: u8,
+    ) -> Result<BlobByteStream> {
+        fetch_with_retries(self.store_name(), retries, || {
+            self.get_raw_file_stream(version)
+        })
+        .await
+    }
+
+    /// Streams the transactions of the blob holding `version`, from `version` on, decoding them
+    /// as the blob is read; see `decode_transaction_stream`.
+    async fn get_transaction_stream(&self, version: u64, retries: u8) -> Result<TransactionStream> {
+        let blob_size = self.blob_size().await?;
+        // Legacy blobs are read as a whole.
+        let (bytes, storage_format) = match self
+            .get_raw_file_stream_with_retries(version, retries)
+            .await
+        {
+            Ok(bytes) => (bytes, self.storage_format()),
+            Err(err) => match self.get_legacy_raw_file(version).await? {
+                Some((storage_format, bytes)) => {
+                    (blob_byte_stream_from_bytes(bytes), storage_format)
                 },
-            }
-        }
+                None => return Err(err),
+            },
+        };
+        Ok(decode_transaction_stream(
+            bytes,
+            storage_format,
+            (version % blob_size) as usize,
+        ))
     }
 
     async fn get_transactions_with_durations(
@@ -61,57 +897,394 @@ pub trait FileStoreOperator: Send + Sync {
         version: u64,
         retries: u8,
     ) -> Result<(Vec<Transaction>, f64, f64)> {
+        let blob_size = self.blob_size().await?;
         let io_start_time = std::time::Instant::now();
-        let bytes = self.get_raw_file_with_retries(version, retries).await?;
+        let (storage_format, bytes) = match self.get_raw_file_with_retries(version, retries).await {
+            Ok(bytes) => (self.storage_format(), bytes),
+            Err(err) => self.get_legacy_raw_file(version).await?.ok_or(err)?,
+        };
         let io_duration = io_start_time.elapsed().as_secs_f64();
         let decoding_start_time = std::time::Instant::now();
-        let storage_format = self.storage_format();
 
         let transactions_in_storage = tokio::task::spawn_blocking(move || {
             FileEntry::new(bytes, storage_format).into_transactions_in_storage()
         })
         .await
-        .context("Converting storage bytes to FileEntry transactions thread panicked")?;
+        .context("Converting storage bytes to FileEntry transactions thread panicked")?
+        .context("Failed to decode the blob.")?;
 
         let decoding_duration = decoding_start_time.elapsed().as_secs_f64();
         Ok((
             transactions_in_storage
                 .transactions
                 .into_iter()
-                .skip((version % FILE_ENTRY_TRANSACTION_COUNT) as usize)
+                .skip((version % blob_size) as usize)
                 .collect(),
             io_duration,
             decoding_duration,
         ))
     }
+    fn key_layout_tracker(&self) -> &KeyLayoutTracker;
+
+    /// Layout of the blob keys: the configured one, else the one recorded in the metadata. A file
+    /// store without metadata is new, and sharded.
+    async fn key_layout(&self) -> Result<KeyLayout> {
+        if let Some(key_layout) = self.key_layout_tracker().get() {
+            return Ok(key_layout);
+        }
+        // Reading the metadata records its layout.
+        let key_layout = match self.try_get_file_store_metadata().await? {
+            Some(metadata) => metadata.key_layout,
+            None => KeyLayout::Sharded,
+        };
+        self.key_layout_tracker().set(key_layout.clone());
+        Ok(key_layout)
+    }
+
+    fn blob_size_tracker(&self) -> &BlobSizeTracker;
+
+    fn blob_digests_tracker(&self) -> &BlobDigestsTracker;
+
+    fn blob_format_tracker(&self) -> &BlobFormatTracker;
+
+    /// Format version of the blobs written to the file store: the one recorded in the metadata,
+    /// so that older readers of an existing file store can still read it. A file store without
+    /// metadata is new, and gets `BLOB_FORMAT_VERSION`.
+    async fn blob_format_version(&self) -> Result<u8> {
+        if let Some(blob_format_version) = self.blob_format_tracker().get() {
+            return Ok(blob_format_version);
+        }
+        // Reading the metadata records its blob format version.
+        let blob_format_version = match self.try_get_file_store_metadata().await? {
+            Some(metadata) => metadata.blob_format_version,
+            None => BLOB_FORMAT_VERSION,
+        };
+        self.blob_format_tracker().set(blob_format_version);
+        Ok(blob_format_version)
+    }
+
+    /// First version of the blobs uploaded with a digest, as recorded in the metadata; `None` if
+    /// the file store was written before digests were recorded. A new file store has digests of
+    /// all its blobs.
+    async fn blob_digests_since_version(&self) -> Result<Option<u64>> {
+        if let Some(blob_digests_since_version) = self.blob_digests_tracker().get() {
+            return Ok(blob_digests_since_version);
+        }
+        // Reading the metadata records the first version with a digest.
+        let blob_digests_since_version = match self.try_get_file_store_metadata().await? {
+            Some(metadata) => metadata.blob_digests_since_version,
+            None => Some(0),
+        };
+        self.blob_digests_tracker().set(blob_digests_since_version);
+        Ok(blob_digests_since_version)
+    }
+
+    /// First version with a digest to record in metadata updated to `version`: the one recorded
+    /// already, else `version`, since blobs before it may have been uploaded without digests.
+    async fn blob_digests_since_version_for_update(&self, version: u64) -> Result<u64> {
+        let blob_digests_since_version =
+            self.blob_digests_since_version().await?.unwrap_or(version);
+        self.blob_digests_tracker()
+            .set(Some(blob_digests_since_version));
+        Ok(blob_digests_since_version)
+    }
+
+    /// Number of versions of each blob: the one recorded in the metadata. A file store without
+    /// metadata is new, and gets the configured size, else `FILE_ENTRY_TRANSACTION_COUNT`.
+    async fn blob_size(&self) -> Result<u64> {
+        if let Some(blob_size) = self.blob_size_tracker().get() {
+            return Ok(blob_size);
+        }
+        // Reading the metadata records its blob size.
+        let blob_size = match self.try_get_file_store_metadata().await? {
+            Some(metadata) => metadata.blob_size(),
+            None => self
+                .blob_size_tracker()
+                .configured()
+                .unwrap_or(FILE_ENTRY_TRANSACTION_COUNT),
+        };
+        self.blob_size_tracker().set(blob_size);
+        Ok(blob_size)
+    }
+
+    /// Key of the blob holding `version`, after the key layout and blob size of the file store.
+    async fn blob_key(&self, version: u64) -> Result<String> {
+        let blob_version = blob_start_version(version, self.blob_size().await?);
+        Ok(FileEntry::build_blob_key(
+            blob_version,
+            self.storage_format(),
+            &self.key_layout().await?,
+        ))
+    }
+
+    /// Keys of the blob holding `version` in the other storage formats, with their formats; see
+    /// `FileEntry::build_legacy_blob_keys`.
+    async fn legacy_blob_keys(&self, version: u64) -> Result<Vec<(StorageFormat, String)>> {
+        let blob_version = blob_start_version(version, self.blob_size().await?);
+        Ok(FileEntry::build_legacy_blob_keys(
+            blob_version,
+            self.storage_format(),
+            &self.key_layout().await?,
+        ))
+    }
+
+    /// Key of the digest of the blob holding `version`.
+    async fn blob_digest_key(&self, version: u64) -> Result<String> {
+        Ok(format!(
+            "{}{}",
+            self.blob_key(version).await?,
+            BLOB_DIGEST_FILE_SUFFIX
+        ))
+    }
+
+    /// Gets the metadata from the file store, or `None` if there is none yet.
+    async fn try_get_file_store_metadata(&self) -> Result<Option<FileStoreMetadata>>;
+
     /// Gets the metadata from the file store. Operator will panic if error happens when accessing the metadata file(except not found).
-    async fn get_file_store_metadata(&self) -> Option<FileStoreMetadata>;
+    async fn get_file_store_metadata(&self) -> Option<FileStoreMetadata> {
+        self.try_get_file_store_metadata()
+            .await
+            .unwrap_or_else(|err| panic!("{:#}", err))
+    }
     /// If the file store is empty, the metadata will be created; otherwise, return the existing metadata.
     async fn update_file_store_metadata_with_timeout(
         &mut self,
-        expected_chain_id: u64,
+        expected_chain_id: ChainId,
         version: u64,
     ) -> anyhow::Result<()>;
     /// Updates the file store metadata. This is only performed by the operator when new file transactions are uploaded.
     async fn update_file_store_metadata_internal(
         &mut self,
-        chain_id: u64,
+        chain_id: ChainId,
         version: u64,
     ) -> anyhow::Result<()>;
-    /// Uploads the transactions to the file store. Single batch of 1000
-    /// Returns start and end version of the batch, inclusive
+    /// Gets the processing progress, or `None` if none was recorded yet.
+    async fn get_processing_progress(&self) -> Result<Option<FileStoreProgress>>;
+
+    /// Replaces the processing progress atomically; readers see either the old or the new one.
+    async fn update_processing_progress(&mut self, progress: FileStoreProgress) -> Result<()>;
+
+    /// Uploads the transactions to the file store. Single batch of `blob_size` transactions.
+    /// Returns start and end version of the batch, inclusive, and the size of the encoded blobs in bytes
+    /// Uploading a blob that's already there is a no-op; replacing one holding different
+    /// transactions fails with a `BlobConflictError`.
     async fn upload_transaction_batch(
         &mut self,
-        chain_id: u64,
+        chain_id: ChainId,
         batch: Vec<Transaction>,
-    ) -> anyhow::Result<(u64, u64)>;
+    ) -> anyhow::Result<(u64, u64, usize)>;
+
+    /// Same as `upload_transaction_batch`, encoding the transactions as they're read, so that the
+    /// batch isn't copied in memory. Operators that can't encode incrementally collect them.
+    async fn upload_transaction_stream<'a>(
+        &'a mut self,
+        chain_id: ChainId,
+        transactions: Box<dyn Iterator<Item = Transaction> + Send + 'a>,
+    ) -> Result<(u64, u64, usize)> {
+        self.upload_transaction_batch(chain_id, transactions.collect())
+            .await
+    }
+
+    /// Same as `upload_transaction_batch`, for the protobuf encodings of the transactions of the
+    /// batch starting at `start_version`, e.g., as read from the cache. Operators that encode
+    /// incrementally write them into the blob without decoding them, which gives the same blob;
+    /// others decode them.
+    async fn upload_encoded_transaction_batch(
+        &mut self,
+        chain_id: ChainId,
+        start_version: u64,
+        encoded_transactions: &[Vec<u8>],
+    ) -> Result<(u64, u64, usize)> {
+        let transactions = decode_encoded_transactions(start_version, encoded_transactions)?;
+        self.upload_transaction_batch(chain_id, transactions).await
+    }
+
+    /// Uploads a subset of the batch starting at `start_version`, e.g., after filtering, as the
+    /// blob of that batch. The subset may be empty; metadata is left to the caller.
+    async fn upload_filtered_transaction_batch(
+        &mut self,
+        start_version: u64,
+        transactions: Vec<Transaction>,
+    ) -> anyhow::Result<()>;
+
+    /// Deletes the blob starting at `version` and its digest; deleting a missing blob is a no-op.
+    async fn delete_blob(&mut self, version: u64) -> Result<()>;
+
+    /// Gets the digest of the blob starting at `version`, recorded when it was uploaded.
+    /// Returns `None` for blobs uploaded before digests were recorded, without looking them up.
+    async fn get_blob_digest(&self, version: u64) -> Result<Option<String>> {
+        match self.blob_digests_since_version().await? {
+            Some(blob_digests_since_version) if version >= blob_digests_since_version => {
+                self.get_recorded_blob_digest(version).await
+            },
+            _ => Ok(None),
+        }
+    }
+
+    /// Reads the digest of the blob starting at `version`; `None` if it's missing.
+    async fn get_recorded_blob_digest(&self, version: u64) -> Result<Option<String>>;
+
+    /// Checks the blob starting at `version` against its recorded digest without decoding it.
+    /// Returns `None` if no digest was recorded for the blob.
+    async fn verify_blob_digest(&self, version: u64) -> Result<Option<bool>> {
+        let expected_digest = match self.get_blob_digest(version).await? {
+            Some(digest) => digest,
+            None => return Ok(None),
+        };
+        let bytes = self.get_raw_file(version).await?;
+        Ok(Some(compute_blob_digest(&bytes) == expected_digest))
+    }
+
+    /// Upgrades metadata with an older schema version in place; it's rewritten with the current
+    /// one. Fails if the metadata has a newer schema than supported, or doesn't match the storage
+    /// format, encryption, or configured key layout or blob size of this operator.
+    async fn migrate_file_store_metadata(&mut self) -> Result<()> {
+        let metadata = match self.get_file_store_metadata().await {
+            Some(metadata) => metadata,
+            None => return Ok(()),
+        };
+        metadata.check_schema_version()?;
+        if let Some(key_layout) = self.key_layout_tracker().configured() {
+            ensure!(
+                &metadata.key_layout == key_layout,
+                "Key layout mismatch; the file store is {:?}, convert it with the migrate tool.",
+                metadata.key_layout
+            );
+        }
+        if let Some(blob_size) = self.blob_size_tracker().configured() {
+            metadata.check_blob_size(blob_size)?;
+        }
+        if metadata.schema_version == FILE_STORE_METADATA_SCHEMA_VERSION {
+            return Ok(());
+        }
+        ensure!(
+            metadata.storage_format == self.storage_format(),
+            "Storage format mismatch."
+        );
+        ensure!(
+            metadata.encryption_scheme == self.encryption_scheme(),
+            "Encryption scheme mismatch."
+        );
+        tracing::info!(
+            from_schema_version = metadata.schema_version,
+            to_schema_version = FILE_STORE_METADATA_SCHEMA_VERSION,
+            "Upgrading the file store metadata."
+        );
+        self.update_file_store_metadata_internal(metadata.chain_id, metadata.version)
+            .await
+    }
+
+    /// Version every blob before which is in the file store, from the metadata; `None` if the file
+    /// store is empty. Read-only, unlike `update_file_store_metadata_with_timeout`, so it's cheap
+    /// enough for health checks.
+    async fn try_get_latest_version(&self) -> Result<Option<u64>> {
+        Ok(self
+            .try_get_file_store_metadata()
+            .await?
+            .map(|metadata| metadata.version))
+    }
 
     /// This is updated by the filestore worker whenever it updates the filestore metadata
     async fn get_latest_version(&self) -> Option<u64> {
-        let metadata = self.get_file_store_metadata().await;
-        metadata.map(|metadata| metadata.version)
+        self.try_get_latest_version()
+            .await
+            .unwrap_or_else(|err| panic!("{:#}", err))
     }
 
     /// Get a clone for the file store operator.
     fn clone_box(&self) -> Box<dyn FileStoreOperator>;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAYLOAD_SIZE: usize = 128 * 1024;
+    const CHUNK_SIZE: usize = 64 * 1024;
+
+    /// A zstd blob of transactions of `PAYLOAD_SIZE` pseudo-random bytes, i.e., that don't
+    /// compress, so that positions in the blob follow the transactions.
+    fn incompressible_blob(transaction_count: u64) -> (Vec<Transaction>, Vec<u8>) {
+        let mut state = 0x2545_F491_4F6C_DD1Du64;
+        let transactions: Vec<Transaction> = (0..transaction_count)
+            .map(|version| {
+                let mut hash = Vec::with_capacity(PAYLOAD_SIZE);
+                while hash.len() < PAYLOAD_SIZE {
+                    state ^= state << 13;
+                    state ^= state >> 7;
+                    state ^= state << 17;
+                    hash.extend_from_slice(&state.to_le_bytes());
+                }
+                Transaction {
+                    version,
+                    info: Some(aptos_protos::transaction::v1::TransactionInfo {
+                        hash,
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                }
+            })
+            .collect();
+        let mut writer = FileEntryWriter::new(
+            Vec::new(),
+            0,
+            StorageFormat::ZstdCompressedProto,
+            1,
+            BLOB_FORMAT_VERSION,
+        )
+        .unwrap();
+        for transaction in &transactions {
+            writer.write_transaction(transaction).unwrap();
+        }
+        (transactions, writer.finish().unwrap())
+    }
+
+    /// Streams `blob` in chunks, counting the bytes streamed.
+    fn counting_stream(blob: Vec<u8>, bytes_read: Arc<AtomicU64>) -> BlobByteStream {
+        let chunks: Vec<Bytes> = blob
+            .chunks(CHUNK_SIZE)
+            .map(Bytes::copy_from_slice)
+            .collect();
+        futures::stream::iter(chunks)
+            .map(move |chunk| {
+                bytes_read.fetch_add(chunk.len() as u64, Ordering::SeqCst);
+                Ok(chunk)
+            })
+            .boxed()
+    }
+
+    #[tokio::test]
+    async fn streamed_blobs_are_decoded_one_transaction_at_a_time() {
+        let (transactions, blob) = incompressible_blob(24);
+        let blob_size = blob.len() as u64;
+        let bytes_read = Arc::new(AtomicU64::new(0));
+        let digest = StreamingBlobDigest::default();
+        let mut stream = decode_transaction_stream(
+            digest.hash(counting_stream(blob.clone(), bytes_read.clone())),
+            StorageFormat::ZstdCompressedProto,
+            0,
+        );
+        for (i, transaction) in transactions.iter().enumerate() {
+            assert_eq!(&stream.next().await.unwrap().unwrap(), transaction);
+            // At most two more transactions and a few chunks are in flight, on top of what the
+            // decompressor reads ahead.
+            let bound = (i + 3) * PAYLOAD_SIZE + 3 * CHUNK_SIZE + 512 * 1024;
+            assert!(bytes_read.load(Ordering::SeqCst) <= bound as u64);
+        }
+        assert!(stream.next().await.is_none());
+        assert_eq!(bytes_read.load(Ordering::SeqCst), blob_size);
+        assert_eq!(digest.digest(), compute_blob_digest(&blob));
+
+        // Dropping the stream stops reading the blob.
+        let bytes_read = Arc::new(AtomicU64::new(0));
+        let mut stream = decode_transaction_stream(
+            counting_stream(blob, bytes_read.clone()),
+            StorageFormat::ZstdCompressedProto,
+            1,
+        );
+        assert_eq!(stream.next().await.unwrap().unwrap(), transactions[1]);
+        drop(stream);
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        assert!(bytes_read.load(Ordering::SeqCst) < blob_size / 2);
+    }
+}
@@ -6,7 +6,13 @@ pub mod compression_util;
 pub mod config;
 pub mod constants;
 pub mod counters;
+pub mod encryption_util;
 pub mod file_store_operator;
+pub mod moving_average;
+pub mod parquet_util;
+pub mod rate_limiter;
+pub mod redis_cluster;
+pub mod redis_tls;
 pub mod types;
 
 use anyhow::{Context, Result};
@@ -0,0 +1,357 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+use std::collections::{BTreeMap, VecDeque};
+
+// Values are exact below 2^PERCENTILE_PRECISION_BITS; above, they're bucketed with this many bits
+// of mantissa, i.e., percentiles are within 1/2^(PERCENTILE_PRECISION_BITS + 1) of the value.
+const PERCENTILE_PRECISION_BITS: u32 = 5;
+
+/// MovingAverage tracks the values ticked over a sliding window of time, e.g., the number of
+/// transactions processed, to compute their rate and distribution.
+pub struct MovingAverage {
+    window_millis: u64,
+    // (timestamp_millis, value)
+    values: VecDeque<(u64, u64)>,
+    sum: u64,
+    // Number of values in the window by percentile bucket.
+    percentile_buckets: BTreeMap<u64, u64>,
+    // Number of values ever evicted from the window, i.e., index of the first value in it.
+    evicted_count: u64,
+    // (index, value) of the values in the window that no later value is smaller, resp. larger,
+    // than; so values are increasing, resp. decreasing, and the first one is the min, resp. max.
+    min_candidates: VecDeque<(u64, u64)>,
+    max_candidates: VecDeque<(u64, u64)>,
+}
+
+impl MovingAverage {
+    pub fn new(window_millis: u64) -> Self {
+        Self {
+            window_millis,
+            values: VecDeque::new(),
+            sum: 0,
+            percentile_buckets: BTreeMap::new(),
+            evicted_count: 0,
+            min_candidates: VecDeque::new(),
+            max_candidates: VecDeque::new(),
+        }
+    }
+
+    pub fn tick_now(&mut self, value: u64) {
+        let now = chrono::Utc::now().naive_utc().timestamp_millis() as u64;
+        self.tick(now, value);
+    }
+
+    pub fn tick(&mut self, timestamp_millis: u64, value: u64) -> f64 {
+        let index = self.evicted_count + self.values.len() as u64;
+        while matches!(self.min_candidates.back(), Some(&(_, min)) if min >= value) {
+            self.min_candidates.pop_back();
+        }
+        self.min_candidates.push_back((index, value));
+        while matches!(self.max_candidates.back(), Some(&(_, max)) if max <= value) {
+            self.max_candidates.pop_back();
+        }
+        self.max_candidates.push_back((index, value));
+        self.values.push_back((timestamp_millis, value));
+        self.sum += value;
+        *self
+            .percentile_buckets
+            .entry(percentile_bucket(value))
+            .or_default() += 1;
+        self.evict_values_before(timestamp_millis);
+        self.avg()
+    }
+
+    pub fn window_millis(&self) -> u64 {
+        self.window_millis
+    }
+
+    /// Changes the window of a live instance. Shrinking it drops the values that fall out of it,
+    /// relative to the last one; growing it keeps all values, and older ones are evicted later.
+    pub fn set_window_millis(&mut self, window_millis: u64) {
+        self.window_millis = window_millis;
+        if let Some(&(last_timestamp_millis, _)) = self.values.back() {
+            self.evict_values_before(last_timestamp_millis);
+        }
+    }
+
+    /// Sum of the values in the window per millisecond between the first and the last of them.
+    pub fn avg(&self) -> f64 {
+        if self.values.len() < 2 {
+            0.0
+        } else {
+            let elapsed = self.values.back().unwrap().0 - self.values.front().unwrap().0;
+            self.sum as f64 / elapsed as f64
+        }
+    }
+
+    pub fn sum(&self) -> u64 {
+        self.sum
+    }
+
+    /// Smallest value in the window, if any.
+    pub fn min(&self) -> Option<u64> {
+        self.min_candidates.front().map(|&(_, value)| value)
+    }
+
+    /// Largest value in the window, if any.
+    pub fn max(&self) -> Option<u64> {
+        self.max_candidates.front().map(|&(_, value)| value)
+    }
+
+    /// Approximate `q` quantile of the values in the window, e.g., 0.99 for p99, with `q` in
+    /// [0, 1]. Values are counted in buckets, so it's exact for small values and otherwise within
+    /// about 1.6% of the actual value. Returns 0 if the window is empty.
+    pub fn percentile(&self, q: f64) -> f64 {
+        let count = self.values.len() as u64;
+        if count == 0 {
+            return 0.0;
+        }
+        // Nearest-rank: the smallest value with at least `q` of the values at or below it.
+        let rank = ((q.clamp(0.0, 1.0) * count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (&bucket, &bucket_count) in &self.percentile_buckets {
+            seen += bucket_count;
+            if seen >= rank {
+                return percentile_bucket_value(bucket);
+            }
+        }
+        unreachable!("The buckets count every value in the window.")
+    }
+
+    /// Drops the values older than the window ending at `timestamp_millis`.
+    fn evict_values_before(&mut self, timestamp_millis: u64) {
+        while let Some(&(ts, val)) = self.values.front() {
+            if timestamp_millis - ts <= self.window_millis {
+                break;
+            }
+            self.sum -= val;
+            self.values.pop_front();
+            self.remove_from_percentile_buckets(val);
+            let index = self.evicted_count;
+            self.evicted_count += 1;
+            for candidates in [&mut self.min_candidates, &mut self.max_candidates] {
+                if matches!(candidates.front(), Some(&(first_index, _)) if first_index == index) {
+                    candidates.pop_front();
+                }
+            }
+        }
+    }
+
+    fn remove_from_percentile_buckets(&mut self, value: u64) {
+        let bucket = percentile_bucket(value);
+        if let Some(bucket_count) = self.percentile_buckets.get_mut(&bucket) {
+            *bucket_count -= 1;
+            if *bucket_count == 0 {
+                self.percentile_buckets.remove(&bucket);
+            }
+        }
+    }
+}
+
+/// Bucket of `value`, ordered like the values: small values have their own bucket, larger ones
+/// share it with the values having the same exponent and leading mantissa bits.
+fn percentile_bucket(value: u64) -> u64 {
+    if value < 1 << PERCENTILE_PRECISION_BITS {
+        return value;
+    }
+    let exponent = 63 - value.leading_zeros();
+    let shift = exponent - PERCENTILE_PRECISION_BITS;
+    let mantissa = (value >> shift) & ((1 << PERCENTILE_PRECISION_BITS) - 1);
+    (((shift + 1) as u64) << PERCENTILE_PRECISION_BITS) | mantissa
+}
+
+/// Middle of the values in `bucket`.
+fn percentile_bucket_value(bucket: u64) -> f64 {
+    let shift = bucket >> PERCENTILE_PRECISION_BITS;
+    if shift == 0 {
+        return bucket as f64;
+    }
+    let mantissa = bucket & ((1 << PERCENTILE_PRECISION_BITS) - 1);
+    let lower = ((1 << PERCENTILE_PRECISION_BITS) | mantissa) << (shift - 1);
+    let width = 1u64 << (shift - 1);
+    lower as f64 + (width - 1) as f64 / 2.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_within(actual: f64, expected: f64, tolerance: f64) {
+        assert!(
+            (actual - expected).abs() <= expected * tolerance,
+            "{} is not within {} of {}",
+            actual,
+            tolerance,
+            expected
+        );
+    }
+
+    #[test]
+    fn avg_is_the_rate_over_the_window() {
+        let mut ma = MovingAverage::new(1_000);
+        assert_eq!(ma.tick(0, 100), 0.0);
+        assert_eq!(ma.tick(500, 100), 0.4);
+        assert_eq!(ma.tick(1_000, 100), 0.3);
+        // The first value leaves the window.
+        assert_eq!(ma.tick(1_500, 100), 0.3);
+        assert_eq!(ma.sum(), 300);
+    }
+
+    #[test]
+    fn shrinking_the_window_evicts_the_oldest_values() {
+        let mut ma = MovingAverage::new(10_000);
+        for ts in 0..10 {
+            ma.tick(ts * 1_000, 100);
+        }
+        assert_eq!(ma.sum(), 1_000);
+        ma.set_window_millis(3_000);
+        // Values at 6s to 9s are within 3s of the last one.
+        assert_eq!(ma.sum(), 400);
+        assert_eq!(ma.avg(), 400.0 / 3_000.0);
+        assert_eq!(
+            ma.percentile_buckets.values().sum::<u64>(),
+            ma.values.len() as u64
+        );
+        // Ticks keep evicting with the new window.
+        ma.tick(10_000, 100);
+        assert_eq!(ma.sum(), 400);
+    }
+
+    #[test]
+    fn growing_the_window_keeps_the_values() {
+        let mut ma = MovingAverage::new(2_000);
+        for ts in 0..5 {
+            ma.tick(ts * 1_000, 100);
+        }
+        assert_eq!(ma.sum(), 300);
+        let avg = ma.avg();
+        ma.set_window_millis(10_000);
+        assert_eq!(ma.window_millis(), 10_000);
+        assert_eq!(ma.avg(), avg);
+        // Nothing is evicted until values are older than the new window.
+        for ts in 5..10 {
+            ma.tick(ts * 1_000, 100);
+        }
+        assert_eq!(ma.sum(), 800);
+        assert_eq!(ma.avg(), 800.0 / 7_000.0);
+    }
+
+    #[test]
+    fn min_and_max_follow_the_window() {
+        let mut ma = MovingAverage::new(2_000);
+        assert_eq!((ma.min(), ma.max()), (None, None));
+        ma.tick(0, 50);
+        assert_eq!((ma.min(), ma.max()), (Some(50), Some(50)));
+        ma.tick(1_000, 10);
+        ma.tick(2_000, 90);
+        assert_eq!((ma.min(), ma.max()), (Some(10), Some(90)));
+        // 50 leaves the window; the min and max are still in it.
+        ma.tick(3_000, 30);
+        assert_eq!((ma.min(), ma.max()), (Some(10), Some(90)));
+        // Then 10 leaves.
+        ma.tick(4_000, 40);
+        assert_eq!((ma.min(), ma.max()), (Some(30), Some(90)));
+        // Then 90, along with the others on a gap.
+        ma.tick(10_000, 20);
+        assert_eq!((ma.min(), ma.max()), (Some(20), Some(20)));
+    }
+
+    #[test]
+    fn min_and_max_with_repeated_values() {
+        let mut ma = MovingAverage::new(1_000);
+        for ts in 0..5 {
+            ma.tick(ts * 500, 7);
+        }
+        assert_eq!((ma.min(), ma.max()), (Some(7), Some(7)));
+        ma.tick(2_500, 3);
+        ma.tick(3_000, 9);
+        assert_eq!((ma.min(), ma.max()), (Some(3), Some(9)));
+        ma.tick(3_500, 5);
+        assert_eq!((ma.min(), ma.max()), (Some(3), Some(9)));
+        ma.tick(4_000, 5);
+        assert_eq!((ma.min(), ma.max()), (Some(5), Some(9)));
+    }
+
+    #[test]
+    fn min_and_max_match_a_scan_of_the_window() {
+        let mut ma = MovingAverage::new(5_000);
+        // Deterministic pseudo-random values and intervals.
+        let mut seed = 17u64;
+        let mut ts = 0;
+        for _ in 0..1_000 {
+            seed = seed.wrapping_mul(6_364_136_223_846_793_005).wrapping_add(1);
+            ts += (seed >> 60) * 200;
+            ma.tick(ts, (seed >> 33) % 1_000);
+            let values = ma.values.iter().map(|&(_, value)| value);
+            assert_eq!(ma.min(), values.clone().min());
+            assert_eq!(ma.max(), values.max());
+        }
+    }
+
+    #[test]
+    fn percentiles_of_a_uniform_distribution() {
+        let mut ma = MovingAverage::new(u64::MAX);
+        for value in 1..=10_000 {
+            ma.tick(value, value);
+        }
+        assert_within(ma.percentile(0.5), 5_000.0, 0.02);
+        assert_within(ma.percentile(0.9), 9_000.0, 0.02);
+        assert_within(ma.percentile(0.99), 9_900.0, 0.02);
+        assert_within(ma.percentile(1.0), 10_000.0, 0.02);
+        assert_eq!(ma.percentile(0.0), 1.0);
+    }
+
+    #[test]
+    fn percentiles_of_a_skewed_distribution() {
+        let mut ma = MovingAverage::new(u64::MAX);
+        // 98 fast values and 2 slow ones, e.g., latencies with a tail.
+        for i in 0..100 {
+            ma.tick(i, if i % 50 == 49 { 5_000 } else { 10 + i % 7 });
+        }
+        assert!(ma.percentile(0.5) <= 16.0);
+        assert!(ma.percentile(0.9) <= 16.0);
+        assert_within(ma.percentile(0.99), 5_000.0, 0.02);
+    }
+
+    #[test]
+    fn small_values_are_exact() {
+        let mut ma = MovingAverage::new(u64::MAX);
+        for value in [3, 1, 2, 0, 4] {
+            ma.tick(0, value);
+        }
+        assert_eq!(ma.percentile(0.5), 2.0);
+        assert_eq!(ma.percentile(1.0), 4.0);
+    }
+
+    #[test]
+    fn percentiles_only_cover_the_window() {
+        let mut ma = MovingAverage::new(1_000);
+        assert_eq!(ma.percentile(0.5), 0.0);
+        for ts in 0..10 {
+            ma.tick(ts * 100, 1_000_000);
+        }
+        // Every large value is out of the window by then.
+        for ts in 20..30 {
+            ma.tick(ts * 100, 10);
+        }
+        assert_eq!(ma.percentile(0.99), 10.0);
+        assert_eq!(
+            ma.percentile_buckets.values().sum::<u64>(),
+            ma.values.len() as u64
+        );
+    }
+
+    #[test]
+    fn buckets_are_ordered_like_values() {
+        let mut previous_bucket = 0;
+        for exponent in 0..63 {
+            for value in [1u64 << exponent, (1u64 << exponent) * 3 / 2 + 1] {
+                let bucket = percentile_bucket(value);
+                assert!(bucket >= previous_bucket);
+                assert_within(percentile_bucket_value(bucket), value as f64, 0.016);
+                previous_bucket = bucket;
+            }
+        }
+    }
+}
@@ -0,0 +1,647 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+//! Reads and writes batches of transactions as Parquet files, so query engines can read the file
+//! store directly.
+//!
+//! Each file holds one row group with one row per transaction, in order. The columns are
+//! `PARQUET_COLUMNS`; all of them are required and PLAIN encoded, in zstd compressed pages. The
+//! `encoded_transaction` column holds the protobuf encoded transaction, which is what is decoded
+//! when the file is read back; the other columns are there for queries. The starting version of
+//! the batch is kept in the `starting_version` key-value metadata, since a filtered batch may
+//! not hold its first transaction.
+
+use anyhow::{bail, ensure, Context, Result};
+use aptos_protos::{
+    indexer::v1::TransactionsInStorage,
+    transaction::v1::{transaction::TransactionType, Transaction},
+};
+use prost::Message;
+
+const PARQUET_MAGIC_BYTES: &[u8; 4] = b"PAR1";
+const STARTING_VERSION_KEY: &str = "starting_version";
+const CREATED_BY: &str = "aptos-indexer-grpc";
+const ENCODED_TRANSACTION_COLUMN: &str = "encoded_transaction";
+
+// Parquet physical types.
+const INT64: i32 = 2;
+const BYTE_ARRAY: i32 = 6;
+// Parquet converted types.
+const UTF8: i32 = 0;
+const TIMESTAMP_MICROS: i32 = 10;
+const UINT_64: i32 = 14;
+const REQUIRED: i32 = 0;
+const PLAIN_ENCODING: i32 = 0;
+const RLE_ENCODING: i32 = 3;
+const ZSTD_CODEC: i32 = 6;
+const DATA_PAGE: i32 = 0;
+
+/// A column of the Parquet schema: its name, physical type, and converted type.
+pub struct ParquetColumn {
+    pub name: &'static str,
+    physical_type: i32,
+    converted_type: Option<i32>,
+}
+
+/// The schema of the Parquet files, in column order. Columns are only ever appended.
+pub const PARQUET_COLUMNS: [ParquetColumn; 6] = [
+    // The version of the transaction.
+    ParquetColumn {
+        name: "version",
+        physical_type: INT64,
+        converted_type: Some(UINT_64),
+    },
+    ParquetColumn {
+        name: "block_height",
+        physical_type: INT64,
+        converted_type: Some(UINT_64),
+    },
+    ParquetColumn {
+        name: "epoch",
+        physical_type: INT64,
+        converted_type: Some(UINT_64),
+    },
+    // Microseconds since the unix epoch; 0 if the transaction has no timestamp.
+    ParquetColumn {
+        name: "timestamp",
+        physical_type: INT64,
+        converted_type: Some(TIMESTAMP_MICROS),
+    },
+    // The transaction type, e.g., `TRANSACTION_TYPE_USER`.
+    ParquetColumn {
+        name: "type",
+        physical_type: BYTE_ARRAY,
+        converted_type: Some(UTF8),
+    },
+    // The protobuf encoded transaction.
+    ParquetColumn {
+        name: ENCODED_TRANSACTION_COLUMN,
+        physical_type: BYTE_ARRAY,
+        converted_type: None,
+    },
+];
+
+/// Encodes the transactions of the batch starting at `starting_version` into a Parquet file.
+pub fn encode_parquet(
+    starting_version: u64,
+    transactions: &[Transaction],
+    compression_level: i32,
+) -> Vec<u8> {
+    let mut file = PARQUET_MAGIC_BYTES.to_vec();
+    let mut column_chunks = Vec::new();
+    let mut total_byte_size = 0;
+    for column in &PARQUET_COLUMNS {
+        let values = encode_column(column.name, transactions);
+        let compressed = zstd::stream::encode_all(values.as_slice(), compression_level)
+            .expect("Zstd compression failed.");
+        let mut page_header = ThriftWriter::new();
+        page_header.i32_field(1, DATA_PAGE);
+        page_header.i32_field(2, values.len() as i32);
+        page_header.i32_field(3, compressed.len() as i32);
+        page_header.struct_field_begin(5);
+        page_header.i32_field(1, transactions.len() as i32);
+        page_header.i32_field(2, PLAIN_ENCODING);
+        page_header.i32_field(3, RLE_ENCODING);
+        page_header.i32_field(4, RLE_ENCODING);
+        page_header.struct_end();
+        let page_header = page_header.finish();
+
+        let data_page_offset = file.len() as i64;
+        let uncompressed_size = (page_header.len() + values.len()) as i64;
+        let compressed_size = (page_header.len() + compressed.len()) as i64;
+        total_byte_size += uncompressed_size;
+        file.extend_from_slice(&page_header);
+        file.extend_from_slice(&compressed);
+        column_chunks.push((column, data_page_offset, uncompressed_size, compressed_size));
+    }
+
+    let mut footer = ThriftWriter::new();
+    footer.i32_field(1, 1);
+    footer.list_field_begin(2, THRIFT_STRUCT, PARQUET_COLUMNS.len() + 1);
+    footer.binary_field(4, b"schema");
+    footer.i32_field(5, PARQUET_COLUMNS.len() as i32);
+    footer.struct_end();
+    for column in &PARQUET_COLUMNS {
+        footer.i32_field(1, column.physical_type);
+        footer.i32_field(3, REQUIRED);
+        footer.binary_field(4, column.name.as_bytes());
+        if let Some(converted_type) = column.converted_type {
+            footer.i32_field(6, converted_type);
+        }
+        footer.struct_end();
+    }
+    footer.i64_field(3, transactions.len() as i64);
+    footer.list_field_begin(4, THRIFT_STRUCT, 1);
+    footer.list_field_begin(1, THRIFT_STRUCT, column_chunks.len());
+    for (column, data_page_offset, uncompressed_size, compressed_size) in &column_chunks {
+        footer.i64_field(2, *data_page_offset);
+        footer.struct_field_begin(3);
+        footer.i32_field(1, column.physical_type);
+        footer.list_field_begin(2, THRIFT_I32, 2);
+        footer.varint(zigzag(PLAIN_ENCODING as i64));
+        footer.varint(zigzag(RLE_ENCODING as i64));
+        footer.list_field_begin(3, THRIFT_BINARY, 1);
+        footer.binary(column.name.as_bytes());
+        footer.i32_field(4, ZSTD_CODEC);
+        footer.i64_field(5, transactions.len() as i64);
+        footer.i64_field(6, *uncompressed_size);
+        footer.i64_field(7, *compressed_size);
+        footer.i64_field(9, *data_page_offset);
+        footer.struct_end();
+        footer.struct_end();
+    }
+    footer.i64_field(2, total_byte_size);
+    footer.i64_field(3, transactions.len() as i64);
+    footer.struct_end();
+    footer.list_field_begin(5, THRIFT_STRUCT, 1);
+    footer.binary_field(1, STARTING_VERSION_KEY.as_bytes());
+    footer.binary_field(2, starting_version.to_string().as_bytes());
+    footer.struct_end();
+    footer.binary_field(6, CREATED_BY.as_bytes());
+    let footer = footer.finish();
+
+    file.extend_from_slice(&footer);
+    file.extend_from_slice(&(footer.len() as u32).to_le_bytes());
+    file.extend_from_slice(PARQUET_MAGIC_BYTES);
+    file
+}
+
+/// Decodes a Parquet file written by `encode_parquet`.
+pub fn decode_parquet(bytes: &[u8]) -> Result<TransactionsInStorage> {
+    ensure!(
+        bytes.len() >= 12
+            && bytes.starts_with(PARQUET_MAGIC_BYTES)
+            && bytes.ends_with(PARQUET_MAGIC_BYTES),
+        "Not a Parquet file."
+    );
+    let footer_len_offset = bytes.len() - 8;
+    let footer_len =
+        u32::from_le_bytes(bytes[footer_len_offset..footer_len_offset + 4].try_into()?) as usize;
+    ensure!(
+        footer_len + 12 <= bytes.len(),
+        "Parquet footer length out of range."
+    );
+    let footer = ThriftReader::new(&bytes[footer_len_offset - footer_len..footer_len_offset])
+        .read_struct()?;
+
+    let starting_version = footer
+        .list(5)
+        .unwrap_or_default()
+        .iter()
+        .filter_map(ThriftValue::as_struct)
+        .find(|key_value| key_value.binary(1) == Some(STARTING_VERSION_KEY.as_bytes()))
+        .and_then(|key_value| key_value.binary(2))
+        .context("Parquet file has no starting version.")?;
+    let starting_version: u64 = std::str::from_utf8(starting_version)?.parse()?;
+
+    let mut transactions = Vec::new();
+    for row_group in footer.list(4).context("Parquet file has no row groups.")? {
+        let row_group = row_group.as_struct().context("Invalid row group.")?;
+        let column_chunk = row_group
+            .list(1)
+            .context("Row group has no columns.")?
+            .iter()
+            .filter_map(|column_chunk| column_chunk.as_struct()?.get(3)?.as_struct())
+            .find(|column_metadata| {
+                column_metadata
+                    .list(3)
+                    .and_then(|path| path.first()?.as_binary())
+                    == Some(ENCODED_TRANSACTION_COLUMN.as_bytes())
+            })
+            .context("Row group has no encoded transaction column.")?;
+        ensure!(
+            column_chunk.i64(4) == Some(ZSTD_CODEC as i64),
+            "Unsupported Parquet compression codec."
+        );
+        let data_page_offset = column_chunk
+            .i64(9)
+            .context("Column chunk has no data page.")? as usize;
+        let num_values = column_chunk.i64(5).context("Column chunk has no values.")? as usize;
+        ensure!(data_page_offset < bytes.len(), "Data page out of range.");
+        let mut page_reader = ThriftReader::new(&bytes[data_page_offset..]);
+        let page_header = page_reader.read_struct()?;
+        let compressed_page_size = page_header
+            .i64(3)
+            .context("Page header has no compressed size.")?
+            as usize;
+        let page_data = page_reader.read_bytes(compressed_page_size)?;
+        let values = zstd::stream::decode_all(page_data)
+            .context("Failed to decompress the Parquet page.")?;
+
+        let mut values = values.as_slice();
+        for _ in 0..num_values {
+            ensure!(values.len() >= 4, "Truncated Parquet page.");
+            let len = u32::from_le_bytes(values[..4].try_into()?) as usize;
+            ensure!(values.len() >= 4 + len, "Truncated Parquet page.");
+            transactions.push(Transaction::decode(&values[4..4 + len])?);
+            values = &values[4 + len..];
+        }
+    }
+    Ok(TransactionsInStorage {
+        starting_version: Some(starting_version),
+        transactions,
+    })
+}
+
+/// PLAIN encodes the values of a column.
+fn encode_column(name: &str, transactions: &[Transaction]) -> Vec<u8> {
+    let mut values = Vec::new();
+    for transaction in transactions {
+        match name {
+            "version" => values.extend_from_slice(&transaction.version.to_le_bytes()),
+            "block_height" => values.extend_from_slice(&transaction.block_height.to_le_bytes()),
+            "epoch" => values.extend_from_slice(&transaction.epoch.to_le_bytes()),
+            "timestamp" => {
+                let micros = transaction.timestamp.as_ref().map_or(0, |timestamp| {
+                    timestamp.seconds * 1_000_000 + timestamp.nanos as i64 / 1_000
+                });
+                values.extend_from_slice(&micros.to_le_bytes());
+            },
+            "type" => {
+                let transaction_type = TransactionType::try_from(transaction.r#type)
+                    .map_or("TRANSACTION_TYPE_UNSPECIFIED", |t| t.as_str_name());
+                extend_with_byte_array(&mut values, transaction_type.as_bytes());
+            },
+            ENCODED_TRANSACTION_COLUMN => {
+                extend_with_byte_array(&mut values, &transaction.encode_to_vec());
+            },
+            _ => unreachable!("Unknown Parquet column {}.", name),
+        }
+    }
+    values
+}
+
+fn extend_with_byte_array(values: &mut Vec<u8>, bytes: &[u8]) {
+    values.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+    values.extend_from_slice(bytes);
+}
+
+// Thrift compact protocol types used by the Parquet metadata.
+const THRIFT_BOOLEAN_TRUE: u8 = 1;
+const THRIFT_BOOLEAN_FALSE: u8 = 2;
+const THRIFT_BYTE: u8 = 3;
+const THRIFT_I16: u8 = 4;
+const THRIFT_I32: u8 = 5;
+const THRIFT_I64: u8 = 6;
+const THRIFT_DOUBLE: u8 = 7;
+const THRIFT_BINARY: u8 = 8;
+const THRIFT_LIST: u8 = 9;
+const THRIFT_SET: u8 = 10;
+const THRIFT_MAP: u8 = 11;
+const THRIFT_STRUCT: u8 = 12;
+
+fn zigzag(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+fn unzigzag(value: u64) -> i64 {
+    ((value >> 1) as i64) ^ -((value & 1) as i64)
+}
+
+/// Writes Parquet metadata with the thrift compact protocol. Lists of structs are written by
+/// opening the list and then writing each element's fields followed by `struct_end`.
+struct ThriftWriter {
+    bytes: Vec<u8>,
+    // The id of the last field written in each open struct.
+    last_field_ids: Vec<i16>,
+}
+
+impl ThriftWriter {
+    fn new() -> Self {
+        Self {
+            bytes: Vec::new(),
+            last_field_ids: vec![0],
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        self.bytes.push(0);
+        self.bytes
+    }
+
+    fn varint(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.bytes.push((value as u8 & 0x7F) | 0x80);
+            value >>= 7;
+        }
+        self.bytes.push(value as u8);
+    }
+
+    fn binary(&mut self, value: &[u8]) {
+        self.varint(value.len() as u64);
+        self.bytes.extend_from_slice(value);
+    }
+
+    fn field_header(&mut self, field_id: i16, field_type: u8) {
+        let last_field_id = self.last_field_ids.last_mut().unwrap();
+        let delta = field_id - *last_field_id;
+        *last_field_id = field_id;
+        if (1..=15).contains(&delta) {
+            self.bytes.push(((delta as u8) << 4) | field_type);
+        } else {
+            self.bytes.push(field_type);
+            self.varint(zigzag(field_id as i64));
+        }
+    }
+
+    fn i32_field(&mut self, field_id: i16, value: i32) {
+        self.field_header(field_id, THRIFT_I32);
+        self.varint(zigzag(value as i64));
+    }
+
+    fn i64_field(&mut self, field_id: i16, value: i64) {
+        self.field_header(field_id, THRIFT_I64);
+        self.varint(zigzag(value));
+    }
+
+    fn binary_field(&mut self, field_id: i16, value: &[u8]) {
+        self.field_header(field_id, THRIFT_BINARY);
+        self.binary(value);
+    }
+
+    fn struct_field_begin(&mut self, field_id: i16) {
+        self.field_header(field_id, THRIFT_STRUCT);
+        self.last_field_ids.push(0);
+    }
+
+    /// Opens a list; the elements of a list of structs are closed with `struct_end`.
+    fn list_field_begin(&mut self, field_id: i16, element_type: u8, size: usize) {
+        self.field_header(field_id, THRIFT_LIST);
+        if size < 15 {
+            self.bytes.push(((size as u8) << 4) | element_type);
+        } else {
+            self.bytes.push(0xF0 | element_type);
+            self.varint(size as u64);
+        }
+        if element_type == THRIFT_STRUCT {
+            self.last_field_ids.extend(std::iter::repeat(0).take(size));
+        }
+    }
+
+    fn struct_end(&mut self) {
+        self.bytes.push(0);
+        self.last_field_ids.pop();
+    }
+}
+
+/// A decoded thrift value; only the types used by the Parquet metadata are kept.
+#[derive(Debug)]
+enum ThriftValue {
+    Bool(bool),
+    Int(i64),
+    Double(f64),
+    Binary(Vec<u8>),
+    List(Vec<ThriftValue>),
+    Map(Vec<(ThriftValue, ThriftValue)>),
+    Struct(ThriftStruct),
+}
+
+impl ThriftValue {
+    fn as_struct(&self) -> Option<&ThriftStruct> {
+        match self {
+            ThriftValue::Struct(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    fn as_binary(&self) -> Option<&[u8]> {
+        match self {
+            ThriftValue::Binary(value) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct ThriftStruct(Vec<(i16, ThriftValue)>);
+
+impl ThriftStruct {
+    fn get(&self, field_id: i16) -> Option<&ThriftValue> {
+        self.0
+            .iter()
+            .find(|(id, _)| *id == field_id)
+            .map(|(_, value)| value)
+    }
+
+    fn i64(&self, field_id: i16) -> Option<i64> {
+        match self.get(field_id)? {
+            ThriftValue::Int(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    fn binary(&self, field_id: i16) -> Option<&[u8]> {
+        self.get(field_id)?.as_binary()
+    }
+
+    fn list(&self, field_id: i16) -> Option<&[ThriftValue]> {
+        match self.get(field_id)? {
+            ThriftValue::List(values) => Some(values),
+            _ => None,
+        }
+    }
+}
+
+/// Reads thrift compact protocol values, keeping unknown fields so newer metadata still parses.
+struct ThriftReader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> ThriftReader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes }
+    }
+
+    fn read_bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        ensure!(self.bytes.len() >= len, "Truncated Parquet metadata.");
+        let (bytes, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(bytes)
+    }
+
+    fn read_byte(&mut self) -> Result<u8> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    fn read_varint(&mut self) -> Result<u64> {
+        let mut value = 0;
+        for shift in (0..64).step_by(7) {
+            let byte = self.read_byte()?;
+            value |= ((byte & 0x7F) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        bail!("Invalid varint in Parquet metadata.")
+    }
+
+    fn read_struct(&mut self) -> Result<ThriftStruct> {
+        let mut fields = Vec::new();
+        let mut last_field_id = 0;
+        loop {
+            let header = self.read_byte()?;
+            if header == 0 {
+                return Ok(ThriftStruct(fields));
+            }
+            let field_type = header & 0x0F;
+            let delta = (header >> 4) as i16;
+            let field_id = if delta == 0 {
+                unzigzag(self.read_varint()?) as i16
+            } else {
+                last_field_id + delta
+            };
+            last_field_id = field_id;
+            let value = match field_type {
+                THRIFT_BOOLEAN_TRUE => ThriftValue::Bool(true),
+                THRIFT_BOOLEAN_FALSE => ThriftValue::Bool(false),
+                _ => self.read_value(field_type)?,
+            };
+            fields.push((field_id, value));
+        }
+    }
+
+    fn read_value(&mut self, value_type: u8) -> Result<ThriftValue> {
+        Ok(match value_type {
+            // Booleans in lists are one byte each.
+            THRIFT_BOOLEAN_TRUE | THRIFT_BOOLEAN_FALSE => {
+                ThriftValue::Bool(self.read_byte()? == THRIFT_BOOLEAN_TRUE)
+            },
+            THRIFT_BYTE => ThriftValue::Int(self.read_byte()? as i8 as i64),
+            THRIFT_I16 | THRIFT_I32 | THRIFT_I64 => ThriftValue::Int(unzigzag(self.read_varint()?)),
+            THRIFT_DOUBLE => {
+                ThriftValue::Double(f64::from_le_bytes(self.read_bytes(8)?.try_into()?))
+            },
+            THRIFT_BINARY => {
+                let len = self.read_varint()? as usize;
+                ThriftValue::Binary(self.read_bytes(len)?.to_vec())
+            },
+            THRIFT_LIST | THRIFT_SET => {
+                let header = self.read_byte()?;
+                let element_type = header & 0x0F;
+                let size = match header >> 4 {
+                    15 => self.read_varint()? as usize,
+                    size => size as usize,
+                };
+                let mut values = Vec::with_capacity(size.min(1024));
+                for _ in 0..size {
+                    values.push(self.read_value(element_type)?);
+                }
+                ThriftValue::List(values)
+            },
+            THRIFT_MAP => {
+                let size = self.read_varint()? as usize;
+                let mut entries = Vec::with_capacity(size.min(1024));
+                if size > 0 {
+                    let types = self.read_byte()?;
+                    for _ in 0..size {
+                        let key = self.read_value(types >> 4)?;
+                        let value = self.read_value(types & 0x0F)?;
+                        entries.push((key, value));
+                    }
+                }
+                ThriftValue::Map(entries)
+            },
+            THRIFT_STRUCT => ThriftValue::Struct(self.read_struct()?),
+            _ => bail!("Unknown thrift type {} in Parquet metadata.", value_type),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aptos_protos::util::timestamp::Timestamp;
+
+    fn transactions(starting_version: u64, count: u64) -> Vec<Transaction> {
+        (starting_version..starting_version + count)
+            .map(|version| Transaction {
+                version,
+                epoch: 1,
+                block_height: version / 2,
+                timestamp: Some(Timestamp {
+                    seconds: version as i64,
+                    nanos: 500_000,
+                }),
+                r#type: if version % 2 == 0 {
+                    TransactionType::User as i32
+                } else {
+                    TransactionType::BlockMetadata as i32
+                },
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn parquet_round_trip() {
+        let transactions = transactions(2_000, 20);
+        let bytes = encode_parquet(2_000, &transactions, 3);
+        assert!(bytes.starts_with(PARQUET_MAGIC_BYTES));
+        assert!(bytes.ends_with(PARQUET_MAGIC_BYTES));
+        let decoded = decode_parquet(&bytes).unwrap();
+        assert_eq!(decoded.starting_version, Some(2_000));
+        assert_eq!(decoded.transactions, transactions);
+
+        let empty = decode_parquet(&encode_parquet(3_000, &[], 3)).unwrap();
+        assert_eq!(empty.starting_version, Some(3_000));
+        assert!(empty.transactions.is_empty());
+    }
+
+    #[test]
+    fn parquet_footer_describes_the_schema() {
+        let transactions = transactions(0, 3);
+        let bytes = encode_parquet(0, &transactions, 3);
+        let footer_len_offset = bytes.len() - 8;
+        let footer_len = u32::from_le_bytes(
+            bytes[footer_len_offset..footer_len_offset + 4]
+                .try_into()
+                .unwrap(),
+        ) as usize;
+        let footer = ThriftReader::new(&bytes[footer_len_offset - footer_len..footer_len_offset])
+            .read_struct()
+            .unwrap();
+        assert_eq!(footer.i64(3), Some(3));
+        let names: Vec<&[u8]> = footer
+            .list(2)
+            .unwrap()
+            .iter()
+            .skip(1)
+            .map(|element| element.as_struct().unwrap().binary(4).unwrap())
+            .collect();
+        let expected: Vec<&[u8]> = PARQUET_COLUMNS
+            .iter()
+            .map(|column| column.name.as_bytes())
+            .collect();
+        assert_eq!(names, expected);
+
+        // The version column holds the versions, PLAIN encoded.
+        let row_group = footer.list(4).unwrap()[0].as_struct().unwrap();
+        let version_column = row_group.list(1).unwrap()[0]
+            .as_struct()
+            .unwrap()
+            .get(3)
+            .unwrap()
+            .as_struct()
+            .unwrap();
+        let mut page_reader = ThriftReader::new(&bytes[version_column.i64(9).unwrap() as usize..]);
+        let page_header = page_reader.read_struct().unwrap();
+        let page = page_reader
+            .read_bytes(page_header.i64(3).unwrap() as usize)
+            .unwrap();
+        let values = zstd::stream::decode_all(page).unwrap();
+        let versions: Vec<u64> = values
+            .chunks(8)
+            .map(|value| u64::from_le_bytes(value.try_into().unwrap()))
+            .collect();
+        assert_eq!(versions, vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn invalid_parquet_files_are_rejected() {
+        assert!(decode_parquet(b"not parquet").is_err());
+        let mut bytes = encode_parquet(0, &transactions(0, 3), 3);
+        let len = bytes.len();
+        bytes[len - 8..len - 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(decode_parquet(&bytes).is_err());
+    }
+}
@@ -0,0 +1,191 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+use serde::{Deserialize, Serialize};
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Limits of the requests sent to a backend, e.g., the writes to a file store bucket.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimitConfig {
+    #[serde(default)]
+    pub max_requests_per_sec: Option<f64>,
+    #[serde(default)]
+    pub max_bytes_per_sec: Option<u64>,
+}
+
+impl RateLimitConfig {
+    /// Reasons the config can't work, e.g., a zero rate; empty if it's valid.
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = vec![];
+        if self
+            .max_requests_per_sec
+            .map_or(false, |rate| rate.is_nan() || rate <= 0.0)
+        {
+            problems.push("max_requests_per_sec must be positive".to_string());
+        }
+        if self.max_bytes_per_sec == Some(0) {
+            problems.push("max_bytes_per_sec must be positive".to_string());
+        }
+        problems
+    }
+}
+
+/// Token bucket refilling at `rate_per_sec` tokens per second, up to `capacity` tokens. Tokens
+/// missing for an acquisition are taken on credit: the bucket goes negative, and the caller waits
+/// until it's refilled back to zero. So any amount can be acquired, and callers are served in order.
+pub struct TokenBucket {
+    rate_per_sec: f64,
+    capacity: f64,
+    // (tokens, last_refill_time)
+    state: Mutex<(f64, Instant)>,
+}
+
+impl TokenBucket {
+    /// Creates a full bucket.
+    pub fn new(rate_per_sec: f64, capacity: f64) -> Self {
+        Self {
+            rate_per_sec,
+            capacity,
+            state: Mutex::new((capacity, Instant::now())),
+        }
+    }
+
+    /// Takes `amount` tokens, waiting until the bucket is back to zero if they're taken on credit.
+    /// Returns the time waited.
+    pub async fn acquire(&self, amount: f64) -> Duration {
+        let wait = self.reserve(amount, Instant::now());
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+        wait
+    }
+
+    /// Takes `amount` tokens at `now` without waiting; returns how long the caller has to wait for
+    /// the bucket to be back to zero.
+    fn reserve(&self, amount: f64, now: Instant) -> Duration {
+        let mut state = self.state.lock().unwrap();
+        let (tokens, last_refill_time) = &mut *state;
+        let elapsed = now.saturating_duration_since(*last_refill_time);
+        *tokens = (*tokens + elapsed.as_secs_f64() * self.rate_per_sec).min(self.capacity);
+        *last_refill_time = now.max(*last_refill_time);
+        *tokens -= amount;
+        if *tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-*tokens / self.rate_per_sec)
+        }
+    }
+}
+
+/// Rate limiter of the requests to a backend, by count and by size. Sizes are often only known
+/// once a request is sent, e.g., of compressed uploads, so they're recorded afterwards and the next
+/// requests wait them out. Bursts of up to a second's worth of either are let through.
+pub struct RateLimiter {
+    requests: Option<TokenBucket>,
+    bytes: Option<TokenBucket>,
+}
+
+impl RateLimiter {
+    pub fn new(config: &RateLimitConfig) -> Self {
+        Self {
+            requests: config
+                .max_requests_per_sec
+                .map(|rate| TokenBucket::new(rate, rate.max(1.0))),
+            bytes: config
+                .max_bytes_per_sec
+                .map(|rate| TokenBucket::new(rate as f64, rate as f64)),
+        }
+    }
+
+    /// Waits until a request can be sent. Returns the time waited, zero if the limits weren't hit.
+    pub async fn acquire_request(&self) -> Duration {
+        let mut waited = Duration::ZERO;
+        if let Some(requests) = &self.requests {
+            waited += requests.acquire(1.0).await;
+        }
+        if let Some(bytes) = &self.bytes {
+            waited += bytes.acquire(0.0).await;
+        }
+        waited
+    }
+
+    /// Accounts for the bytes of a sent request.
+    pub fn record_bytes(&self, size_in_bytes: u64) {
+        if let Some(bytes) = &self.bytes {
+            bytes.reserve(size_in_bytes as f64, Instant::now());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bucket_lets_bursts_through_then_waits_for_the_refill() {
+        let bucket = TokenBucket::new(10.0, 10.0);
+        let start = Instant::now();
+        for _ in 0..10 {
+            assert_eq!(bucket.reserve(1.0, start), Duration::ZERO);
+        }
+        assert_eq!(bucket.reserve(1.0, start), Duration::from_millis(100));
+        // The next caller waits behind the previous one.
+        assert_eq!(bucket.reserve(1.0, start), Duration::from_millis(200));
+        // Refilled back to 8 tokens after a second.
+        assert_eq!(
+            bucket.reserve(8.0, start + Duration::from_secs(1)),
+            Duration::ZERO
+        );
+    }
+
+    #[test]
+    fn bucket_refills_up_to_its_capacity() {
+        let bucket = TokenBucket::new(10.0, 5.0);
+        let start = Instant::now();
+        assert_eq!(bucket.reserve(5.0, start), Duration::ZERO);
+        let later = start + Duration::from_secs(60);
+        assert_eq!(bucket.reserve(5.0, later), Duration::ZERO);
+        assert_eq!(bucket.reserve(1.0, later), Duration::from_millis(100));
+    }
+
+    #[test]
+    fn large_amounts_are_taken_on_credit() {
+        let bucket = TokenBucket::new(100.0, 100.0);
+        let start = Instant::now();
+        assert_eq!(bucket.reserve(300.0, start), Duration::from_secs(2));
+        assert_eq!(
+            bucket.reserve(0.0, start + Duration::from_secs(1)),
+            Duration::from_secs(1)
+        );
+    }
+
+    #[tokio::test]
+    async fn requests_wait_out_the_recorded_bytes() {
+        let limiter = RateLimiter::new(&RateLimitConfig {
+            max_requests_per_sec: None,
+            max_bytes_per_sec: Some(1_000_000),
+        });
+        assert_eq!(limiter.acquire_request().await, Duration::ZERO);
+        // A second's worth of bytes is the burst, so 100ms are left to wait.
+        limiter.record_bytes(1_100_000);
+        let waited = limiter.acquire_request().await;
+        assert!(
+            waited > Duration::from_millis(50) && waited <= Duration::from_millis(100),
+            "{:?}",
+            waited
+        );
+    }
+
+    #[test]
+    fn zero_rates_are_rejected() {
+        let config = RateLimitConfig {
+            max_requests_per_sec: Some(0.0),
+            max_bytes_per_sec: Some(0),
+        };
+        assert_eq!(config.problems().len(), 2);
+    }
+}
@@ -0,0 +1,453 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::{
+    redis_tls::{explain_connection_error, RedisTlsConfig, TlsConnection},
+    types::RedisUrl,
+};
+use anyhow::{ensure, Context};
+use futures::future::{try_join_all, FutureExt};
+use redis::{
+    aio::{ConnectionLike, ConnectionManager},
+    cluster::{ClusterClient, TlsMode},
+    cluster_async::ClusterConnection,
+    Arg, Cmd, ErrorKind, Pipeline, RedisError, RedisFuture, RedisResult, Value,
+};
+use std::collections::BTreeMap;
+
+/// Number of hash slots in a Redis Cluster.
+pub const CLUSTER_SLOT_COUNT: u16 = 16384;
+
+/// Hash slot of `key`. If the key has a hash tag, i.e., a non-empty part between its first `{`
+/// and the next `}`, only the tag is hashed, so that keys sharing a tag share a slot.
+pub fn key_slot(key: &[u8]) -> u16 {
+    let hashed = key
+        .iter()
+        .position(|byte| *byte == b'{')
+        .and_then(|open| {
+            let tag = &key[open + 1..];
+            tag.iter()
+                .position(|byte| *byte == b'}')
+                .filter(|close| *close > 0)
+                .map(|close| &tag[..close])
+        })
+        .unwrap_or(key);
+    crc16(hashed) % CLUSTER_SLOT_COUNT
+}
+
+// CRC16-CCITT (XMODEM), the checksum Redis Cluster hashes keys with.
+fn crc16(bytes: &[u8]) -> u16 {
+    let mut crc: u16 = 0;
+    for byte in bytes {
+        crc ^= (*byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+/// Connection to a Redis Cluster, over the cluster client of the `redis` crate.
+///
+/// The client sends every command to the primary serving its keys, follows `MOVED` and `ASK`
+/// redirections, and reloads the slot map as the cluster is resharded or fails over. It doesn't
+/// split requests over keys of several slots, which the cluster rejects with `CROSSSLOT`, so MGET
+/// and DEL over keys of several slots, and pipelines of commands for several slots, are split here
+/// into one request per slot, and their replies merged back in order.
+#[derive(Clone)]
+pub struct RedisClusterConnection<C = ClusterConnection> {
+    conn: C,
+}
+
+impl RedisClusterConnection {
+    /// Connects to the cluster through the first reachable seed. The other nodes are reached with
+    /// the scheme and credentials of the seeds. Over TLS, the cluster client only trusts the CAs of
+    /// the system, so `insecure_skip_verify` is the only option of `tls_config` that applies.
+    pub async fn connect(
+        seed_addresses: &[RedisUrl],
+        tls_config: &RedisTlsConfig,
+    ) -> anyhow::Result<Self> {
+        ensure!(
+            !seed_addresses.is_empty(),
+            "At least one Redis Cluster seed address is required."
+        );
+        ensure!(
+            seed_addresses
+                .iter()
+                .all(|seed| seed.is_tls() == seed_addresses[0].is_tls()),
+            "Redis Cluster seeds have to use the same scheme."
+        );
+        ensure!(
+            tls_config.ca_cert_path.is_none(),
+            "redis_tls_config.ca_cert_path can't be used with a Redis Cluster."
+        );
+        let mut builder = ClusterClient::builder(seed_addresses.iter().map(|seed| seed.0.as_str()));
+        if tls_config.insecure_skip_verify {
+            builder = builder.tls(TlsMode::Insecure);
+        }
+        let conn = builder.build()?.get_async_connection().await?;
+        Ok(Self::new(conn))
+    }
+}
+
+impl<C> RedisClusterConnection<C>
+where
+    C: ConnectionLike + Clone + Send + Sync + 'static,
+{
+    /// Wraps `conn`, which sends every command to the node serving its slot.
+    pub fn new(conn: C) -> Self {
+        Self { conn }
+    }
+
+    async fn send_command(&mut self, cmd: &Cmd) -> RedisResult<Value> {
+        let args = command_args(cmd);
+        let name = args
+            .first()
+            .map(|name| name.to_ascii_uppercase())
+            .unwrap_or_default();
+        if matches!(name.as_slice(), b"MGET" | b"DEL") {
+            // Positions of the keys in every slot.
+            let mut keys_per_slot: BTreeMap<u16, Vec<usize>> = BTreeMap::new();
+            for (index, key) in args[1..].iter().enumerate() {
+                keys_per_slot.entry(key_slot(key)).or_default().push(index);
+            }
+            if keys_per_slot.len() > 1 {
+                return self
+                    .send_split_across_slots(&name, &args[1..], keys_per_slot)
+                    .await;
+            }
+        }
+        self.conn.req_packed_command(cmd).await
+    }
+
+    async fn send_split_across_slots(
+        &self,
+        name: &[u8],
+        keys: &[&[u8]],
+        keys_per_slot: BTreeMap<u16, Vec<usize>>,
+    ) -> RedisResult<Value> {
+        let commands: Vec<Cmd> = keys_per_slot
+            .values()
+            .map(|indices| {
+                let mut cmd = redis::cmd(&String::from_utf8_lossy(name));
+                for index in indices {
+                    cmd.arg(keys[*index]);
+                }
+                cmd
+            })
+            .collect();
+        let replies = try_join_all(commands.iter().map(|cmd| {
+            let mut conn = self.conn.clone();
+            async move { conn.req_packed_command(cmd).await }
+        }))
+        .await?;
+        if name == b"DEL" {
+            let mut deleted = 0;
+            for reply in replies {
+                match reply {
+                    Value::Int(count) => deleted += count,
+                    _ => return Err((ErrorKind::TypeError, "Invalid DEL reply").into()),
+                }
+            }
+            return Ok(Value::Int(deleted));
+        }
+        let mut values = vec![Value::Nil; keys.len()];
+        for (indices, reply) in keys_per_slot.values().zip(replies) {
+            match reply {
+                Value::Bulk(slot_values) if slot_values.len() == indices.len() => {
+                    for (index, value) in indices.iter().zip(slot_values) {
+                        values[*index] = value;
+                    }
+                },
+                _ => return Err((ErrorKind::TypeError, "Invalid MGET reply").into()),
+            }
+        }
+        Ok(Value::Bulk(values))
+    }
+
+    async fn send_pipeline(
+        &mut self,
+        pipeline: &Pipeline,
+        offset: usize,
+        count: usize,
+    ) -> RedisResult<Vec<Value>> {
+        // Positions in the pipeline of the commands for every slot.
+        let mut commands_per_slot: BTreeMap<Option<u16>, Vec<usize>> = BTreeMap::new();
+        for (index, cmd) in pipeline.cmd_iter().enumerate() {
+            commands_per_slot
+                .entry(command_slot(&command_args(cmd)))
+                .or_default()
+                .push(index);
+        }
+        // A transaction can't span slots, so it is sent whole, like pipelines for a single slot.
+        if offset > 0 || commands_per_slot.len() <= 1 {
+            return self.conn.req_packed_commands(pipeline, offset, count).await;
+        }
+        let commands: Vec<&Cmd> = pipeline.cmd_iter().collect();
+        let slot_futures = commands_per_slot.into_values().map(|indices| {
+            let mut conn = self.conn.clone();
+            let commands = &commands;
+            async move {
+                let mut slot_pipeline = Pipeline::with_capacity(indices.len());
+                for index in &indices {
+                    slot_pipeline.add_command(commands[*index].clone());
+                }
+                let values = conn
+                    .req_packed_commands(&slot_pipeline, 0, indices.len())
+                    .await?;
+                Ok::<_, RedisError>(indices.into_iter().zip(values).collect::<Vec<_>>())
+            }
+        });
+        let mut replies = vec![Value::Nil; commands.len()];
+        for (index, value) in try_join_all(slot_futures).await?.into_iter().flatten() {
+            replies[index] = value;
+        }
+        Ok(replies)
+    }
+}
+
+impl<C> ConnectionLike for RedisClusterConnection<C>
+where
+    C: ConnectionLike + Clone + Send + Sync + 'static,
+{
+    fn req_packed_command<'a>(&'a mut self, cmd: &'a Cmd) -> RedisFuture<'a, Value> {
+        async move { self.send_command(cmd).await }.boxed()
+    }
+
+    fn req_packed_commands<'a>(
+        &'a mut self,
+        cmd: &'a Pipeline,
+        offset: usize,
+        count: usize,
+    ) -> RedisFuture<'a, Vec<Value>> {
+        async move { self.send_pipeline(cmd, offset, count).await }.boxed()
+    }
+
+    fn get_db(&self) -> i64 {
+        // A Redis Cluster only has database 0.
+        0
+    }
+}
+
+fn command_args(cmd: &Cmd) -> Vec<&[u8]> {
+    cmd.args_iter()
+        .filter_map(|arg| match arg {
+            Arg::Simple(arg) => Some(arg),
+            Arg::Cursor => None,
+        })
+        .collect()
+}
+
+/// Slot of the first key of a command, if it has keys.
+fn command_slot(args: &[&[u8]]) -> Option<u16> {
+    let name = args
+        .first()
+        .map(|name| name.to_ascii_uppercase())
+        .unwrap_or_default();
+    match name.as_slice() {
+        b"SCRIPT" | b"FLUSHALL" | b"FLUSHDB" | b"PING" | b"INFO" | b"CLUSTER" => None,
+        // EVAL and EVALSHA take the script, the number of keys and then the keys.
+        b"EVAL" | b"EVALSHA" => match args.get(2) {
+            Some(key_count) if *key_count != b"0" => args.get(3).map(|key| key_slot(key)),
+            _ => None,
+        },
+        _ => args.get(1).map(|key| key_slot(key)),
+    }
+}
+
+/// Connection to the cache, which is either a single Redis instance, over TLS or not, or a Redis
+/// Cluster.
+#[derive(Clone)]
+pub enum CacheConnection {
+    Standalone(ConnectionManager),
+    Tls(TlsConnection),
+    Cluster(RedisClusterConnection),
+}
+
+impl CacheConnection {
+    /// Connects to the instance at `address`, or, if `cluster_seed_addresses` is not empty, to the
+    /// Redis Cluster reached through `address` and those seeds. `rediss://` addresses are reached
+    /// over TLS with `tls_config`.
+    pub async fn connect(
+        address: &RedisUrl,
+        cluster_seed_addresses: &[RedisUrl],
+        tls_config: &RedisTlsConfig,
+    ) -> anyhow::Result<Self> {
+        if cluster_seed_addresses.is_empty() {
+            return Self::connect_instance(address, tls_config)
+                .await
+                .map_err(|e| explain_connection_error(address, e));
+        }
+        let mut seeds = vec![address.clone()];
+        seeds.extend(cluster_seed_addresses.iter().cloned());
+        let conn = RedisClusterConnection::connect(&seeds, tls_config)
+            .await
+            .with_context(|| format!("Create redis cluster connection to {} failed.", address.0))?;
+        Ok(Self::Cluster(conn))
+    }
+
+    async fn connect_instance(
+        address: &RedisUrl,
+        tls_config: &RedisTlsConfig,
+    ) -> RedisResult<Self> {
+        if address.is_tls() {
+            return Ok(Self::Tls(
+                TlsConnection::connect(address, tls_config).await?,
+            ));
+        }
+        let conn = redis::Client::open(address.0.clone())?
+            .get_tokio_connection_manager()
+            .await?;
+        Ok(Self::Standalone(conn))
+    }
+}
+
+impl ConnectionLike for CacheConnection {
+    fn req_packed_command<'a>(&'a mut self, cmd: &'a Cmd) -> RedisFuture<'a, Value> {
+        match self {
+            Self::Standalone(conn) => conn.req_packed_command(cmd),
+            Self::Tls(conn) => conn.req_packed_command(cmd),
+            Self::Cluster(conn) => conn.req_packed_command(cmd),
+        }
+    }
+
+    fn req_packed_commands<'a>(
+        &'a mut self,
+        cmd: &'a Pipeline,
+        offset: usize,
+        count: usize,
+    ) -> RedisFuture<'a, Vec<Value>> {
+        match self {
+            Self::Standalone(conn) => conn.req_packed_commands(cmd, offset, count),
+            Self::Tls(conn) => conn.req_packed_commands(cmd, offset, count),
+            Self::Cluster(conn) => conn.req_packed_commands(cmd, offset, count),
+        }
+    }
+
+    fn get_db(&self) -> i64 {
+        match self {
+            Self::Standalone(conn) => conn.get_db(),
+            Self::Tls(conn) => conn.get_db(),
+            Self::Cluster(conn) => conn.get_db(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use redis::AsyncCommands;
+    use redis_test::{MockCmd, MockRedisConnection};
+
+    // Slots of the keys of the tests: "{baz}" keys are in slot 4813, "{bar}" keys in slot 5061
+    // and "{foo}" keys in slot 12182.
+    fn connect(cmds: Vec<MockCmd>) -> RedisClusterConnection<MockRedisConnection> {
+        RedisClusterConnection::new(MockRedisConnection::new(cmds))
+    }
+
+    #[test]
+    fn key_slot_hashes_the_hash_tag_only() {
+        assert_eq!(crc16(b"123456789"), 0x31C3);
+        assert_eq!(key_slot(b"foo"), 12182);
+        assert_eq!(key_slot(b"bar"), 5061);
+        assert_eq!(key_slot(b"baz"), 4813);
+        assert_eq!(
+            key_slot(b"{user1000}.following"),
+            key_slot(b"{user1000}.followers")
+        );
+        assert_eq!(key_slot(b"foo{bar}"), key_slot(b"bar"));
+        // An empty tag doesn't count, so the whole key is hashed.
+        assert_eq!(
+            key_slot(b"foo{}{bar}"),
+            crc16(b"foo{}{bar}") % CLUSTER_SLOT_COUNT
+        );
+    }
+
+    #[tokio::test]
+    async fn mget_across_slots_is_split_per_slot_and_merged_in_order() {
+        let mut conn = connect(vec![
+            MockCmd::new(
+                redis::cmd("MGET").arg("{baz}1"),
+                Ok(Value::Bulk(vec![Value::Data(b"z1".to_vec())])),
+            ),
+            MockCmd::new(
+                redis::cmd("MGET").arg("{bar}1").arg("{bar}2"),
+                Ok(Value::Bulk(vec![Value::Data(b"b1".to_vec()), Value::Nil])),
+            ),
+            MockCmd::new(
+                redis::cmd("MGET").arg("{foo}1").arg("{foo}2"),
+                Ok(Value::Bulk(vec![
+                    Value::Data(b"f1".to_vec()),
+                    Value::Data(b"f2".to_vec()),
+                ])),
+            ),
+            // Keys of a single slot are sent as they are.
+            MockCmd::new(
+                redis::cmd("MGET").arg("{foo}1").arg("{foo}2"),
+                Ok(Value::Bulk(vec![Value::Nil, Value::Nil])),
+            ),
+        ]);
+
+        let values: Vec<Option<Vec<u8>>> = conn
+            .mget(&["{foo}1", "{bar}1", "{baz}1", "{foo}2", "{bar}2"])
+            .await
+            .unwrap();
+        assert_eq!(values, vec![
+            Some(b"f1".to_vec()),
+            Some(b"b1".to_vec()),
+            Some(b"z1".to_vec()),
+            Some(b"f2".to_vec()),
+            None,
+        ]);
+        let values: Vec<Option<Vec<u8>>> = conn.mget(&["{foo}1", "{foo}2"]).await.unwrap();
+        assert_eq!(values, vec![None, None]);
+    }
+
+    #[tokio::test]
+    async fn del_across_slots_sums_the_deleted_keys() {
+        let mut conn = connect(vec![
+            MockCmd::new(redis::cmd("DEL").arg("bar"), Ok(1)),
+            MockCmd::new(redis::cmd("DEL").arg("foo"), Ok(1)),
+        ]);
+
+        let deleted: i64 = conn.del(&["foo", "bar"]).await.unwrap();
+        assert_eq!(deleted, 2);
+    }
+
+    #[tokio::test]
+    async fn pipeline_is_split_per_slot() {
+        let mut conn = connect(vec![
+            MockCmd::with_values(redis::pipe().cmd("SET").arg("baz").arg(3), Ok(vec!["OK"])),
+            MockCmd::with_values(
+                redis::pipe()
+                    .cmd("SET")
+                    .arg("bar")
+                    .arg(1)
+                    .cmd("GET")
+                    .arg("bar"),
+                Ok(vec!["OK", "1"]),
+            ),
+            MockCmd::with_values(redis::pipe().cmd("DEL").arg("foo"), Ok(vec![1])),
+        ]);
+
+        let replies: (String, i64, String, u64) = redis::pipe()
+            .cmd("SET")
+            .arg("bar")
+            .arg(1)
+            .cmd("DEL")
+            .arg("foo")
+            .cmd("SET")
+            .arg("baz")
+            .arg(3)
+            .cmd("GET")
+            .arg("bar")
+            .query_async(&mut conn)
+            .await
+            .unwrap();
+        assert_eq!(replies, ("OK".to_string(), 1, "OK".to_string(), 1));
+    }
+}
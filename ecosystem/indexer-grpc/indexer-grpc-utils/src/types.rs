@@ -9,18 +9,51 @@ use std::{
 };
 use url::Url;
 
-/// A URL that only allows the redis:// scheme.
+/// Port of a Redis URL without one.
+pub const DEFAULT_REDIS_PORT: u16 = 6379;
+
+/// A URL that only allows the redis:// and rediss:// (TLS) schemes.
 #[derive(Clone, Debug, Eq, PartialEq, Serialize)]
 pub struct RedisUrl(pub Url);
 
+impl RedisUrl {
+    /// Whether the connection is over TLS, i.e., the scheme is rediss://.
+    pub fn is_tls(&self) -> bool {
+        self.scheme() == "rediss"
+    }
+
+    /// `host:port` of the instance, without the credentials of the URL.
+    pub fn endpoint(&self) -> String {
+        format!(
+            "{}:{}",
+            self.host_str().unwrap_or_default(),
+            self.port().unwrap_or(DEFAULT_REDIS_PORT)
+        )
+    }
+
+    /// Checks the scheme and host of a URL that wasn't parsed as a `RedisUrl`, e.g., one built as
+    /// `RedisUrl(url)`.
+    pub fn check(&self) -> anyhow::Result<()> {
+        Self::validate(&self.0)
+    }
+
+    fn validate(url: &Url) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            url.scheme() == "redis" || url.scheme() == "rediss",
+            "Invalid scheme: {}; expected redis:// or rediss:// for TLS",
+            url.scheme()
+        );
+        anyhow::ensure!(url.has_host(), "Redis URL {} has no host", url);
+        Ok(())
+    }
+}
+
 impl FromStr for RedisUrl {
     type Err = anyhow::Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         let url = Url::parse(s)?;
-        if url.scheme() != "redis" {
-            return Err(anyhow::anyhow!("Invalid scheme: {}", url.scheme()));
-        }
+        Self::validate(&url)?;
         Ok(RedisUrl(url))
     }
 }
@@ -31,12 +64,7 @@ impl<'de> Deserialize<'de> for RedisUrl {
         D: serde::Deserializer<'de>,
     {
         let url = Url::deserialize(deserializer)?;
-        if url.scheme() != "redis" {
-            return Err(serde::de::Error::custom(format!(
-                "Invalid scheme: {}",
-                url.scheme()
-            )));
-        }
+        Self::validate(&url).map_err(serde::de::Error::custom)?;
         Ok(Self(url))
     }
 }
@@ -60,3 +88,74 @@ impl Display for RedisUrl {
         write!(f, "{}", self.0)
     }
 }
+
+/// Chain id of the transactions in the cache and the file store, e.g., 1 for mainnet. It's
+/// serialized as a bare number, like before it had its own type, but can't be mixed up with a
+/// version.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(transparent)]
+pub struct ChainId(pub u64);
+
+impl ChainId {
+    /// Fails with both chain ids and where they come from, e.g., the cache and the file store,
+    /// unless they match.
+    pub fn ensure_matches(
+        self,
+        source: &str,
+        other: ChainId,
+        other_source: &str,
+    ) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            self == other,
+            "Chain ID mismatch: {} has chain id {}, but {} has chain id {}.",
+            source,
+            self,
+            other_source,
+            other
+        );
+        Ok(())
+    }
+}
+
+impl From<u64> for ChainId {
+    fn from(chain_id: u64) -> Self {
+        Self(chain_id)
+    }
+}
+
+impl From<ChainId> for u64 {
+    fn from(chain_id: ChainId) -> Self {
+        chain_id.0
+    }
+}
+
+impl Display for ChainId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chain_id_is_serialized_as_a_number() {
+        assert_eq!(serde_json::to_string(&ChainId(1)).unwrap(), "1");
+        assert_eq!(serde_json::from_str::<ChainId>("2").unwrap(), ChainId(2));
+    }
+
+    #[test]
+    fn chain_id_mismatch_names_both_sources() {
+        assert!(ChainId(1)
+            .ensure_matches("the config", ChainId(1), "the cache")
+            .is_ok());
+        assert_eq!(
+            ChainId(1)
+                .ensure_matches("the config", ChainId(2), "the cache")
+                .unwrap_err()
+                .to_string(),
+            "Chain ID mismatch: the config has chain id 1, but the cache has chain id 2."
+        );
+    }
+}
@@ -1,6 +1,267 @@
 // Copyright © Aptos Foundation
 
 // @generated
+impl serde::Serialize for GetStatusRequest {
+    #[allow(deprecated)]
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        use serde::ser::SerializeStruct;
+        let len = 0;
+        let struct_ser = serializer.serialize_struct("aptos.indexer.v1.GetStatusRequest", len)?;
+        struct_ser.end()
+    }
+}
+impl<'de> serde::Deserialize<'de> for GetStatusRequest {
+    #[allow(deprecated)]
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        const FIELDS: &[&str] = &[
+        ];
+
+        #[allow(clippy::enum_variant_names)]
+        enum GeneratedField {
+        }
+        impl<'de> serde::Deserialize<'de> for GeneratedField {
+            fn deserialize<D>(deserializer: D) -> std::result::Result<GeneratedField, D::Error>
+            where
+                D: serde::Deserializer<'de>,
+            {
+                struct GeneratedVisitor;
+
+                impl<'de> serde::de::Visitor<'de> for GeneratedVisitor {
+                    type Value = GeneratedField;
+
+                    fn expecting(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                        write!(formatter, "expected one of: {:?}", &FIELDS)
+                    }
+
+                    #[allow(unused_variables)]
+                    fn visit_str<E>(self, value: &str) -> std::result::Result<GeneratedField, E>
+                    where
+                        E: serde::de::Error,
+                    {
+                            Err(serde::de::Error::unknown_field(value, FIELDS))
+                    }
+                }
+                deserializer.deserialize_identifier(GeneratedVisitor)
+            }
+        }
+        struct GeneratedVisitor;
+        impl<'de> serde::de::Visitor<'de> for GeneratedVisitor {
+            type Value = GetStatusRequest;
+
+            fn expecting(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                formatter.write_str("struct aptos.indexer.v1.GetStatusRequest")
+            }
+
+            fn visit_map<V>(self, mut map: V) -> std::result::Result<GetStatusRequest, V::Error>
+                where
+                    V: serde::de::MapAccess<'de>,
+            {
+                while map.next_key::<GeneratedField>()?.is_some() {
+                    let _ = map.next_value::<serde::de::IgnoredAny>()?;
+                }
+                Ok(GetStatusRequest {
+                })
+            }
+        }
+        deserializer.deserialize_struct("aptos.indexer.v1.GetStatusRequest", FIELDS, GeneratedVisitor)
+    }
+}
+impl serde::Serialize for GetStatusResponse {
+    #[allow(deprecated)]
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        use serde::ser::SerializeStruct;
+        let mut len = 0;
+        if self.chain_id.is_some() {
+            len += 1;
+        }
+        if self.file_store_version.is_some() {
+            len += 1;
+        }
+        if self.cache_latest_version.is_some() {
+            len += 1;
+        }
+        if self.cache_storage_format.is_some() {
+            len += 1;
+        }
+        if self.file_store_storage_format.is_some() {
+            len += 1;
+        }
+        if self.secs_since_last_upload.is_some() {
+            len += 1;
+        }
+        let mut struct_ser = serializer.serialize_struct("aptos.indexer.v1.GetStatusResponse", len)?;
+        if let Some(v) = self.chain_id.as_ref() {
+            struct_ser.serialize_field("chainId", ToString::to_string(&v).as_str())?;
+        }
+        if let Some(v) = self.file_store_version.as_ref() {
+            struct_ser.serialize_field("fileStoreVersion", ToString::to_string(&v).as_str())?;
+        }
+        if let Some(v) = self.cache_latest_version.as_ref() {
+            struct_ser.serialize_field("cacheLatestVersion", ToString::to_string(&v).as_str())?;
+        }
+        if let Some(v) = self.cache_storage_format.as_ref() {
+            struct_ser.serialize_field("cacheStorageFormat", v)?;
+        }
+        if let Some(v) = self.file_store_storage_format.as_ref() {
+            struct_ser.serialize_field("fileStoreStorageFormat", v)?;
+        }
+        if let Some(v) = self.secs_since_last_upload.as_ref() {
+            struct_ser.serialize_field("secsSinceLastUpload", ToString::to_string(&v).as_str())?;
+        }
+        struct_ser.end()
+    }
+}
+impl<'de> serde::Deserialize<'de> for GetStatusResponse {
+    #[allow(deprecated)]
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        const FIELDS: &[&str] = &[
+            "chain_id",
+            "chainId",
+            "file_store_version",
+            "fileStoreVersion",
+            "cache_latest_version",
+            "cacheLatestVersion",
+            "cache_storage_format",
+            "cacheStorageFormat",
+            "file_store_storage_format",
+            "fileStoreStorageFormat",
+            "secs_since_last_upload",
+            "secsSinceLastUpload",
+        ];
+
+        #[allow(clippy::enum_variant_names)]
+        enum GeneratedField {
+            ChainId,
+            FileStoreVersion,
+            CacheLatestVersion,
+            CacheStorageFormat,
+            FileStoreStorageFormat,
+            SecsSinceLastUpload,
+        }
+        impl<'de> serde::Deserialize<'de> for GeneratedField {
+            fn deserialize<D>(deserializer: D) -> std::result::Result<GeneratedField, D::Error>
+            where
+                D: serde::Deserializer<'de>,
+            {
+                struct GeneratedVisitor;
+
+                impl<'de> serde::de::Visitor<'de> for GeneratedVisitor {
+                    type Value = GeneratedField;
+
+                    fn expecting(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                        write!(formatter, "expected one of: {:?}", &FIELDS)
+                    }
+
+                    #[allow(unused_variables)]
+                    fn visit_str<E>(self, value: &str) -> std::result::Result<GeneratedField, E>
+                    where
+                        E: serde::de::Error,
+                    {
+                        match value {
+                            "chainId" | "chain_id" => Ok(GeneratedField::ChainId),
+                            "fileStoreVersion" | "file_store_version" => Ok(GeneratedField::FileStoreVersion),
+                            "cacheLatestVersion" | "cache_latest_version" => Ok(GeneratedField::CacheLatestVersion),
+                            "cacheStorageFormat" | "cache_storage_format" => Ok(GeneratedField::CacheStorageFormat),
+                            "fileStoreStorageFormat" | "file_store_storage_format" => Ok(GeneratedField::FileStoreStorageFormat),
+                            "secsSinceLastUpload" | "secs_since_last_upload" => Ok(GeneratedField::SecsSinceLastUpload),
+                            _ => Err(serde::de::Error::unknown_field(value, FIELDS)),
+                        }
+                    }
+                }
+                deserializer.deserialize_identifier(GeneratedVisitor)
+            }
+        }
+        struct GeneratedVisitor;
+        impl<'de> serde::de::Visitor<'de> for GeneratedVisitor {
+            type Value = GetStatusResponse;
+
+            fn expecting(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                formatter.write_str("struct aptos.indexer.v1.GetStatusResponse")
+            }
+
+            fn visit_map<V>(self, mut map: V) -> std::result::Result<GetStatusResponse, V::Error>
+                where
+                    V: serde::de::MapAccess<'de>,
+            {
+                let mut chain_id__ = None;
+                let mut file_store_version__ = None;
+                let mut cache_latest_version__ = None;
+                let mut cache_storage_format__ = None;
+                let mut file_store_storage_format__ = None;
+                let mut secs_since_last_upload__ = None;
+                while let Some(k) = map.next_key()? {
+                    match k {
+                        GeneratedField::ChainId => {
+                            if chain_id__.is_some() {
+                                return Err(serde::de::Error::duplicate_field("chainId"));
+                            }
+                            chain_id__ =
+                                map.next_value::<::std::option::Option<::pbjson::private::NumberDeserialize<_>>>()?.map(|x| x.0)
+                            ;
+                        }
+                        GeneratedField::FileStoreVersion => {
+                            if file_store_version__.is_some() {
+                                return Err(serde::de::Error::duplicate_field("fileStoreVersion"));
+                            }
+                            file_store_version__ =
+                                map.next_value::<::std::option::Option<::pbjson::private::NumberDeserialize<_>>>()?.map(|x| x.0)
+                            ;
+                        }
+                        GeneratedField::CacheLatestVersion => {
+                            if cache_latest_version__.is_some() {
+                                return Err(serde::de::Error::duplicate_field("cacheLatestVersion"));
+                            }
+                            cache_latest_version__ =
+                                map.next_value::<::std::option::Option<::pbjson::private::NumberDeserialize<_>>>()?.map(|x| x.0)
+                            ;
+                        }
+                        GeneratedField::CacheStorageFormat => {
+                            if cache_storage_format__.is_some() {
+                                return Err(serde::de::Error::duplicate_field("cacheStorageFormat"));
+                            }
+                            cache_storage_format__ = map.next_value()?;
+                        }
+                        GeneratedField::FileStoreStorageFormat => {
+                            if file_store_storage_format__.is_some() {
+                                return Err(serde::de::Error::duplicate_field("fileStoreStorageFormat"));
+                            }
+                            file_store_storage_format__ = map.next_value()?;
+                        }
+                        GeneratedField::SecsSinceLastUpload => {
+                            if secs_since_last_upload__.is_some() {
+                                return Err(serde::de::Error::duplicate_field("secsSinceLastUpload"));
+                            }
+                            secs_since_last_upload__ =
+                                map.next_value::<::std::option::Option<::pbjson::private::NumberDeserialize<_>>>()?.map(|x| x.0)
+                            ;
+                        }
+                    }
+                }
+                Ok(GetStatusResponse {
+                    chain_id: chain_id__,
+                    file_store_version: file_store_version__,
+                    cache_latest_version: cache_latest_version__,
+                    cache_storage_format: cache_storage_format__,
+                    file_store_storage_format: file_store_storage_format__,
+                    secs_since_last_upload: secs_since_last_upload__,
+                })
+            }
+        }
+        deserializer.deserialize_struct("aptos.indexer.v1.GetStatusResponse", FIELDS, GeneratedVisitor)
+    }
+}
 impl serde::Serialize for GetTransactionsRequest {
     #[allow(deprecated)]
     fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>